@@ -0,0 +1,87 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Time intersecting rays with a height map, to compare builds with and
+//! without the `simd` feature. Run with `cargo bench --bench intersection`,
+//! adding `--features simd` for the other build.
+
+extern crate peaks;
+
+use peaks::{AffineTransform, HeightMap, Primitive, Ray, Texture, Vec3};
+
+use std::time::{Duration, Instant};
+
+const SIZE: usize = 1025;
+const RAYS: usize = 512;
+const RUNS: usize = 5;
+
+/// Rolling hills with ridges across them
+fn terrain() -> HeightMap {
+    let mut heights = Texture::blank(SIZE, SIZE);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let (fx, fy) = (x as f64, y as f64);
+            let height = 40.0 * (fx / 37.0).sin() * (fy / 53.0).cos()
+                + 15.0 * ((fx + fy) / 11.0).sin()
+                + 5.0 * (fx / 3.0).cos() * (fy / 5.0).sin();
+            heights.write1x1(x, y, height);
+        }
+    }
+    HeightMap::new(AffineTransform::new(0.0, 0.0, 1.0, 1.0), &heights)
+}
+
+/// Rays from a point above a corner of the terrain, looking across it
+fn rays() -> Vec<Ray> {
+    let origin = Vec3::new(-100.0, 200.0, -100.0);
+    let mut rays = Vec::with_capacity(RAYS * RAYS);
+    for j in 0..RAYS {
+        for i in 0..RAYS {
+            let target = Vec3::new(i as f64 * 2.0, 0.0, j as f64 * 2.0);
+            rays.push(Ray::new(origin, Vec3::normalize(target - origin)));
+        }
+    }
+    rays
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1e3 + f64::from(duration.subsec_nanos()) / 1e6
+}
+
+fn main() {
+    let (terrain, rays) = (terrain(), rays());
+    let mut best = None;
+    let mut checksum = (0, 0.0);
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let hits: Vec<f64> = rays
+            .iter()
+            .filter_map(|&ray| terrain.intersects(ray).map(|hit| hit.t))
+            .collect();
+        let elapsed = millis(start.elapsed());
+        best = Some(best.map_or(elapsed, |best: f64| best.min(elapsed)));
+        checksum = (hits.len(), hits.iter().sum());
+    }
+
+    let simd = if cfg!(feature = "simd") { "on" } else { "off" };
+    println!(
+        "{} rays, simd {}: best of {} runs {:.1} ms, {} hits, sum of t {}",
+        rays.len(),
+        simd,
+        RUNS,
+        best.unwrap(),
+        checksum.0,
+        checksum.1
+    );
+}
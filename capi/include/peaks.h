@@ -0,0 +1,61 @@
+/* This file is part of Peaks.
+ *
+ * Peaks is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Peaks is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#ifndef PEAKS_H
+#define PEAKS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct PeaksScene PeaksScene;
+
+/* Called with the pixels completed, the total pixels and the user data */
+typedef void (*PeaksProgressFn)(size_t completed, size_t total,
+                                void *user_data);
+
+/* Message of the last error on this thread, or NULL */
+const char *peaks_last_error(void);
+
+/* Load a scene from a JSON string of its options, NULL on failure */
+PeaksScene *peaks_scene_from_json(const char *json);
+
+void peaks_scene_free(PeaksScene *scene);
+
+/* Width and height in pixels of the image of a scene, zero on success */
+int peaks_scene_size(const PeaksScene *scene, size_t *width, size_t *height);
+
+/* Render 8 bit sRGB pixels into a buffer of at least width * height * 3
+ * bytes, calling progress, if not NULL, on the calling thread. A threads or
+ * tile_size of zero is chosen for the machine. Returns zero on success */
+int peaks_render(const PeaksScene *scene, size_t samples, size_t threads,
+                 size_t tile_size, uint8_t *rgb, size_t length,
+                 PeaksProgressFn progress, void *user_data);
+
+/* Whether two points of three doubles in scene coordinates see each other,
+ * one if they do and zero if not, writing the distance from the first to
+ * where the line is blocked to distance if not NULL. Returns -1 on failure */
+int peaks_line_of_sight(const PeaksScene *scene, const double *from,
+                        const double *to, double *distance);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,235 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! C interface for embedding the renderer, declared in `include/peaks.h`
+
+extern crate peaks;
+extern crate serde_json;
+
+use peaks::{
+    encode_srgb, render_threaded, RenderConfig, Renderer, Scene, SceneOpts,
+    Texture, TileOrder, Vec3,
+};
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+/// Called as a render progresses, with the number of pixels completed, the
+/// total number of pixels and the user data passed to `peaks_render`
+pub type ProgressFn = extern "C" fn(usize, usize, *mut c_void);
+
+/// A scene loaded from its options
+pub struct PeaksScene {
+    scene: Scene,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+fn set_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|error| *error.borrow_mut() = Some(message));
+}
+
+/// Run a function, recording its error or panic message for
+/// `peaks_last_error`
+fn guard<T, F>(fallback: T, func: F) -> T
+where
+    F: FnOnce() -> Result<T, String>,
+{
+    match catch_unwind(AssertUnwindSafe(func)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(message)) => {
+            set_error(message);
+            fallback
+        }
+        Err(panic) => {
+            let message = match panic.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => match panic.downcast_ref::<String>() {
+                    Some(message) => message.clone(),
+                    None => String::from("Unknown error"),
+                },
+            };
+            set_error(message);
+            fallback
+        }
+    }
+}
+
+/// Return the message of the last error on this thread, or null, valid until
+/// the next call into the library
+#[no_mangle]
+pub extern "C" fn peaks_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| match *error.borrow() {
+        Some(ref message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Load a scene from a nul terminated JSON string of its options, returning
+/// null on failure
+#[no_mangle]
+pub unsafe extern "C" fn peaks_scene_from_json(
+    json: *const c_char,
+) -> *mut PeaksScene {
+    guard(ptr::null_mut(), || {
+        if json.is_null() {
+            return Err(String::from("Scene JSON is null"));
+        }
+        let json = CStr::from_ptr(json).to_str().map_err(|e| e.to_string())?;
+        let options: SceneOpts =
+            serde_json::from_str(json).map_err(|e| e.to_string())?;
+        options.validate()?;
+        let scene = Scene::new(options);
+        Ok(Box::into_raw(Box::new(PeaksScene { scene })))
+    })
+}
+
+/// Free a scene returned by `peaks_scene_from_json`
+#[no_mangle]
+pub unsafe extern "C" fn peaks_scene_free(scene: *mut PeaksScene) {
+    if !scene.is_null() {
+        drop(Box::from_raw(scene));
+    }
+}
+
+/// Write the width and height in pixels of the image of a scene
+#[no_mangle]
+pub unsafe extern "C" fn peaks_scene_size(
+    scene: *const PeaksScene,
+    width: *mut usize,
+    height: *mut usize,
+) -> c_int {
+    if scene.is_null() || width.is_null() || height.is_null() {
+        set_error(String::from("Scene or size is null"));
+        return -1;
+    }
+    let (w, h) = (*scene).scene.camera.view_plane();
+    *width = w;
+    *height = h;
+    0
+}
+
+/// Render a scene into a buffer of `length` bytes, holding 8 bit sRGB pixels
+/// row by row, calling `progress`, if not null, on this thread as tiles
+/// complete. Returns zero on success
+#[no_mangle]
+pub unsafe extern "C" fn peaks_render(
+    scene: *const PeaksScene,
+    samples: usize,
+    threads: usize,
+    tile_size: usize,
+    rgb: *mut u8,
+    length: usize,
+    progress: Option<ProgressFn>,
+    user_data: *mut c_void,
+) -> c_int {
+    guard(-1, || {
+        if scene.is_null() || rgb.is_null() {
+            return Err(String::from("Scene or buffer is null"));
+        }
+        let scene = &(*scene).scene;
+        let (width, height) = scene.camera.view_plane();
+        if length < width * height * 3 {
+            return Err(format!(
+                "Buffer of {} bytes is too small for {}x{} pixels",
+                length, width, height
+            ));
+        }
+
+        let config = RenderConfig::detect(width, height);
+        let auto = |value: usize| if value == 0 { None } else { Some(value) };
+        let config = config.with(auto(threads), auto(tile_size));
+        let renderer = Renderer::new(samples.max(1), scene.clone());
+        let mut surface = Texture::blank(width, height);
+        let mut report = |completed, total| {
+            if let Some(progress) = progress {
+                progress(completed, total, user_data);
+            }
+        };
+        render_threaded(
+            &mut surface,
+            &renderer,
+            config.threads,
+            config.tile_size,
+            TileOrder::Scanline,
+            &mut report,
+        );
+
+        let output = slice::from_raw_parts_mut(rgb, width * height * 3);
+        for (pixel, value) in output.chunks_mut(3).zip(&surface.buffer) {
+            let color = encode_srgb(*value);
+            pixel.copy_from_slice(&[color.r, color.g, color.b]);
+        }
+        Ok(0)
+    })
+}
+
+/// Test whether two points, each of three doubles in scene coordinates, see
+/// each other, writing the distance from the first to where the line between
+/// them is blocked to `distance`, if not null. Returns one if the points see
+/// each other, zero if they do not and -1 on failure
+#[no_mangle]
+pub unsafe extern "C" fn peaks_line_of_sight(
+    scene: *const PeaksScene,
+    from: *const f64,
+    to: *const f64,
+    distance: *mut f64,
+) -> c_int {
+    guard(-1, || {
+        if scene.is_null() || from.is_null() || to.is_null() {
+            return Err(String::from("Scene or point is null"));
+        }
+        let point = |p: *const f64| {
+            let p = slice::from_raw_parts(p, 3);
+            Vec3::new(p[0], p[1], p[2])
+        };
+        let renderer = Renderer::new(1, (*scene).scene.clone());
+        match renderer.first_blocker(point(from), point(to)) {
+            Some(t) => {
+                if !distance.is_null() {
+                    *distance = t;
+                }
+                Ok(0)
+            }
+            None => Ok(1),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reporting_load_errors() {
+        let json = CString::new("{\"background\": [0, 0, 0]}").unwrap();
+        unsafe {
+            assert!(peaks_scene_from_json(json.as_ptr()).is_null());
+            let message = CStr::from_ptr(peaks_last_error());
+            assert!(message.to_str().unwrap().contains("camera"));
+
+            assert!(peaks_scene_from_json(ptr::null()).is_null());
+            let message = CStr::from_ptr(peaks_last_error());
+            assert_eq!(message.to_str().unwrap(), "Scene JSON is null");
+        }
+    }
+}
@@ -0,0 +1,270 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Python module for reading rasters, processing them and rendering scenes
+
+// Code generated by the pyo3 macros refers to `::core`, which the 2015
+// edition only finds at the crate root
+extern crate core;
+extern crate peaks;
+extern crate pyo3;
+extern crate serde_json;
+
+use peaks::{
+    encode_srgb, render_threaded, AffineTransform, ChunkSink, ChunkSource,
+    ConsoleProgress, RasterReader, RasterWriter, RenderConfig, Renderer, Scene,
+    SceneOpts, TileOrder,
+};
+use pyo3::exceptions::{PyIOError, PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+/// A raster of values, such as heights
+#[pyclass]
+#[derive(Clone)]
+struct Texture {
+    inner: peaks::Texture<f64>,
+}
+
+#[pymethods]
+impl Texture {
+    /// Create a texture from a flat list of values, row by row
+    #[new]
+    #[pyo3(signature = (width, height, values=None))]
+    fn new(
+        width: usize,
+        height: usize,
+        values: Option<Vec<f64>>,
+    ) -> PyResult<Self> {
+        let values = values.unwrap_or_else(|| vec![0.0; width * height]);
+        if values.len() != width * height {
+            return Err(PyValueError::new_err(format!(
+                "Expected {} values for a {}x{} texture, got {}",
+                width * height,
+                width,
+                height,
+                values.len()
+            )));
+        }
+        Ok(Texture {
+            inner: peaks::Texture::new(width, height, values),
+        })
+    }
+
+    #[getter]
+    fn width(&self) -> usize {
+        self.inner.width
+    }
+
+    #[getter]
+    fn height(&self) -> usize {
+        self.inner.height
+    }
+
+    fn get(&self, x: usize, y: usize) -> PyResult<f64> {
+        self.check(x, y)?;
+        Ok(self.inner.lookup1x1(x, y))
+    }
+
+    fn set(&mut self, x: usize, y: usize, value: f64) -> PyResult<()> {
+        self.check(x, y)?;
+        self.inner.write1x1(x, y, value);
+        Ok(())
+    }
+
+    /// Return the values as a flat list, row by row
+    fn values(&self) -> Vec<f64> {
+        self.inner.buffer.clone()
+    }
+
+    /// Return the minimum, maximum, mean and standard deviation of the
+    /// values, ignoring NaNs
+    fn statistics<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let stats = peaks::statistics(&self.inner);
+        let dict = PyDict::new_bound(py);
+        dict.set_item("min", stats.min)?;
+        dict.set_item("max", stats.max)?;
+        dict.set_item("mean", stats.mean)?;
+        dict.set_item("stddev", stats.stddev)?;
+        dict.set_item("percentiles", stats.percentiles)?;
+        dict.set_item("histogram", stats.histogram)?;
+        Ok(dict)
+    }
+
+    /// Return a copy with values between a low and high percentile scaled
+    /// from 0 to 1
+    fn stretch(&self, low: f64, high: f64) -> Texture {
+        let mut output =
+            peaks::Texture::blank(self.inner.width, self.inner.height);
+        peaks::stretch(&self.inner, &mut output, low, high);
+        Texture { inner: output }
+    }
+
+    /// Return a copy with each value the mean of those within a radius of
+    /// pixels
+    fn smooth(&self, radius: usize) -> Texture {
+        self.map(|input, output| peaks::smooth(input, output, radius))
+    }
+
+    /// Return a copy with each value the median of those within a radius of
+    /// pixels
+    fn generalise(&self, radius: usize) -> Texture {
+        self.map(|input, output| peaks::generalise(input, output, radius))
+    }
+
+    /// Return a copy with values multiplied by a factor about a base value
+    #[pyo3(signature = (factor, base=0.0))]
+    fn exaggerate(&self, factor: f64, base: f64) -> Texture {
+        self.map(|input, output| peaks::exaggerate(input, output, factor, base))
+    }
+
+    /// Return the shading of the values as heights, lit by the sun at an
+    /// azimuth and elevation in degrees, given the width and height of a
+    /// pixel in the units of the heights
+    #[pyo3(signature = (azimuth=315.0, elevation=45.0, cell_size=(1.0, 1.0)))]
+    fn hillshade(
+        &self,
+        azimuth: f64,
+        elevation: f64,
+        cell_size: (f64, f64),
+    ) -> Texture {
+        let transform =
+            AffineTransform::new(0.0, 0.0, cell_size.0, cell_size.1);
+        self.map(|input, output| {
+            peaks::hillshade(input, output, &transform, azimuth, elevation)
+        })
+    }
+}
+
+impl Texture {
+    /// Return a texture of the same size written by an operation
+    fn map<F>(&self, operation: F) -> Texture
+    where
+        F: FnOnce(&peaks::Texture<f64>, &mut peaks::Texture<f64>),
+    {
+        let mut output =
+            peaks::Texture::blank(self.inner.width, self.inner.height);
+        operation(&self.inner, &mut output);
+        Texture { inner: output }
+    }
+
+    fn check(&self, x: usize, y: usize) -> PyResult<()> {
+        if x >= self.inner.width || y >= self.inner.height {
+            Err(PyIndexError::new_err(format!("No pixel at {}, {}", x, y)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Read a band of a raster readable by GDAL
+#[pyfunction]
+fn read_raster(path: &str, band: usize) -> PyResult<Texture> {
+    let mut reader = RasterReader::open(path, band)
+        .map_err(|err| PyIOError::new_err(err.to_string()))?;
+    let (width, height) = ChunkSource::<f64>::size(&reader);
+    let inner = reader
+        .read(0, 0, width, height)
+        .map_err(|err| PyIOError::new_err(err.to_string()))?;
+    Ok(Texture { inner })
+}
+
+/// Write a texture to a single band GeoTIFF, with the size, transform and
+/// projection of another raster
+#[pyfunction]
+fn write_raster(path: &str, like: &str, texture: &Texture) -> PyResult<()> {
+    let reader = RasterReader::open(like, 1)
+        .map_err(|err| PyIOError::new_err(err.to_string()))?;
+    let (width, height) = ChunkSource::<f64>::size(&reader);
+    if (width, height) != (texture.inner.width, texture.inner.height) {
+        return Err(PyValueError::new_err(format!(
+            "Texture is not the size of {}, {}x{}",
+            like, width, height
+        )));
+    }
+    let mut writer = RasterWriter::create(path, &reader)
+        .map_err(|err| PyIOError::new_err(err.to_string()))?;
+    writer
+        .write(0, 0, &texture.inner)
+        .map_err(|err| PyIOError::new_err(err.to_string()))
+}
+
+/// Render a scene from a JSON string of its options, returning its width,
+/// height and 8 bit sRGB pixels, row by row
+#[pyfunction]
+#[pyo3(signature = (scene, samples=4, threads=None, tile_size=None, tile_order="scanline", progress=false))]
+fn render(
+    py: Python,
+    scene: &str,
+    samples: usize,
+    threads: Option<usize>,
+    tile_size: Option<usize>,
+    tile_order: &str,
+    progress: bool,
+) -> PyResult<(usize, usize, Py<PyBytes>)> {
+    let options: SceneOpts = serde_json::from_str(scene)
+        .map_err(|err| PyValueError::new_err(err.to_string()))?;
+    options.validate().map_err(PyValueError::new_err)?;
+    let order: TileOrder = tile_order.parse().map_err(PyValueError::new_err)?;
+    let surface = py.allow_threads(|| {
+        let scene = Scene::new(options);
+        let (width, height) = scene.camera.view_plane();
+        let config = RenderConfig::detect(width, height);
+        let RenderConfig { threads, tile_size } =
+            config.with(threads, tile_size);
+        let renderer = Renderer::new(samples.max(1), scene);
+        let mut surface = peaks::Texture::blank(width, height);
+        if progress {
+            let mut sink = ConsoleProgress::new(30);
+            render_threaded(
+                &mut surface,
+                &renderer,
+                threads,
+                tile_size,
+                order,
+                &mut sink,
+            );
+        } else {
+            let mut sink = |_, _| {};
+            render_threaded(
+                &mut surface,
+                &renderer,
+                threads,
+                tile_size,
+                order,
+                &mut sink,
+            );
+        }
+        surface
+    });
+
+    let mut bytes = Vec::with_capacity(surface.buffer.len() * 3);
+    for value in &surface.buffer {
+        let color = encode_srgb(*value);
+        bytes.extend_from_slice(&[color.r, color.g, color.b]);
+    }
+    let bytes = PyBytes::new_bound(py, &bytes).unbind();
+    Ok((surface.width, surface.height, bytes))
+}
+
+#[pymodule]
+#[pyo3(name = "peaks")]
+fn peaks_module(module: &Bound<PyModule>) -> PyResult<()> {
+    module.add_class::<Texture>()?;
+    module.add_function(wrap_pyfunction!(read_raster, module)?)?;
+    module.add_function(wrap_pyfunction!(write_raster, module)?)?;
+    module.add_function(wrap_pyfunction!(render, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,96 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use math::Vec3;
+use textures::Texture;
+
+/// Running totals of samples for each pixel of an image
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccumulationBuffer {
+    pub width: usize,
+    pub height: usize,
+    sums: Texture<Vec3>,
+    weights: Texture<f64>,
+}
+
+impl AccumulationBuffer {
+    pub fn new(width: usize, height: usize) -> AccumulationBuffer {
+        AccumulationBuffer {
+            width,
+            height,
+            sums: Texture::blank(width, height),
+            weights: Texture::blank(width, height),
+        }
+    }
+
+    /// Add a weighted sample to a pixel
+    pub fn add(&mut self, x: usize, y: usize, color: Vec3, weight: f64) {
+        let sum = self.sums.lookup1x1(x, y);
+        let total = self.weights.lookup1x1(x, y);
+        self.sums.write1x1(x, y, sum + color * weight);
+        self.weights.write1x1(x, y, total + weight);
+    }
+
+    /// Return the total weight of the samples added to a pixel
+    pub fn weight(&self, x: usize, y: usize) -> f64 {
+        self.weights.lookup1x1(x, y)
+    }
+
+    /// Return the weighted mean of the samples added to a pixel
+    pub fn mean(&self, x: usize, y: usize) -> Vec3 {
+        let weight = self.weights.lookup1x1(x, y);
+        if weight > 0.0 {
+            self.sums.lookup1x1(x, y) / weight
+        } else {
+            Vec3::zeros()
+        }
+    }
+
+    /// Write the mean of each pixel to a texture
+    pub fn resolve(&self, output: &mut Texture<Vec3>) {
+        assert_eq!(self.width, output.width);
+        assert_eq!(self.height, output.height);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                output.write1x1(x, y, self.mean(x, y));
+            }
+        }
+    }
+
+    /// Remove all samples
+    pub fn clear(&mut self) {
+        self.sums = Texture::blank(self.width, self.height);
+        self.weights = Texture::blank(self.width, self.height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulating_samples() {
+        let mut buffer = AccumulationBuffer::new(2, 1);
+        buffer.add(1, 0, Vec3::new(1.0, 0.0, 0.0), 1.0);
+        buffer.add(1, 0, Vec3::new(0.0, 1.0, 0.0), 3.0);
+
+        let mut output = Texture::blank(2, 1);
+        buffer.resolve(&mut output);
+        assert_eq!(output.buffer[0], Vec3::zeros());
+        assert_eq!(output.buffer[1], Vec3::new(0.25, 0.75, 0.0));
+        assert_eq!(buffer.weight(1, 0), 4.0);
+    }
+}
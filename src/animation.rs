@@ -0,0 +1,244 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Sequences of frames from one scene, with any of its options keyframed by
+//! their dotted paths, as given to overrides, so a sun can cross the sky or a
+//! sea rise without a scene file for each frame.
+
+use batch::set_path;
+use math::Vec3;
+use options::{AnimationOpts, Interpolation, KeyframeOpts, TrackOpts};
+use serde_json::Value;
+
+/// Return the numbers of a number or array of numbers
+fn numbers(value: &Value) -> Option<Vec<f64>> {
+    match *value {
+        Value::Number(ref number) => number.as_f64().map(|n| vec![n]),
+        Value::Array(ref items) => items.iter().map(Value::as_f64).collect(),
+        _ => None,
+    }
+}
+
+/// Return numbers in the shape of a value, rounded if it holds integers
+fn shaped(template: &Value, numbers: Vec<f64>) -> Value {
+    let number = |n: f64, integer: bool| {
+        if integer {
+            json!(n.round() as i64)
+        } else {
+            json!(n)
+        }
+    };
+    match *template {
+        Value::Array(ref items) => Value::Array(
+            items
+                .iter()
+                .zip(numbers)
+                .map(|(item, n)| number(n, item.is_i64() || item.is_u64()))
+                .collect(),
+        ),
+        _ => number(numbers[0], template.is_i64() || template.is_u64()),
+    }
+}
+
+/// Return a direction turned a fraction of the way from one to another, with
+/// a length between theirs
+fn slerp(a: Vec3, b: Vec3, t: f64) -> Vec3 {
+    let (la, lb) = (Vec3::length(a), Vec3::length(b));
+    let (ua, ub) = (a / la, b / lb);
+    let angle = Vec3::dot(ua, ub).max(-1.0).min(1.0).acos();
+    let direction = if angle.sin().abs() < 1e-9 {
+        ua * (1.0 - t) + ub * t
+    } else {
+        (ua * ((1.0 - t) * angle).sin() + ub * (t * angle).sin()) / angle.sin()
+    };
+    Vec3::normalize(direction) * (la * (1.0 - t) + lb * t)
+}
+
+/// Return the value a fraction of the way between two keyframes
+fn between(
+    a: &KeyframeOpts,
+    b: &KeyframeOpts,
+    t: f64,
+    interpolation: Interpolation,
+    path: &str,
+) -> Result<Value, String> {
+    if interpolation == Interpolation::Step {
+        return Ok(a.value.clone());
+    }
+
+    let message = || format!("Keyframes of {} are not alike numbers", path);
+    let (va, vb) = match (numbers(&a.value), numbers(&b.value)) {
+        (Some(va), Some(vb)) if va.len() == vb.len() && !va.is_empty() => {
+            (va, vb)
+        }
+        _ => return Err(message()),
+    };
+
+    let values = match interpolation {
+        Interpolation::Spherical => {
+            if va.len() != 3 {
+                let message =
+                    format!("Keyframes of {} are not directions", path);
+                return Err(message);
+            }
+            let turned = slerp(
+                Vec3::new(va[0], va[1], va[2]),
+                Vec3::new(vb[0], vb[1], vb[2]),
+                t,
+            );
+            vec![turned.x, turned.y, turned.z]
+        }
+        _ => {
+            let t = if interpolation == Interpolation::Smooth {
+                t * t * (3.0 - 2.0 * t)
+            } else {
+                t
+            };
+            va.iter().zip(&vb).map(|(a, b)| a + (b - a) * t).collect()
+        }
+    };
+    Ok(shaped(&a.value, values))
+}
+
+/// Return the value of a track at a frame, holding its first and last values
+/// before and after its keyframes
+pub fn track_value(track: &TrackOpts, frame: f64) -> Result<Value, String> {
+    let mut keyframes: Vec<&KeyframeOpts> = track.keyframes.iter().collect();
+    keyframes.sort_by(|a, b| a.frame.partial_cmp(&b.frame).unwrap());
+
+    let (first, last) = match (keyframes.first(), keyframes.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Err(format!("Track of {} has no keyframes", track.path)),
+    };
+    if frame <= first.frame {
+        return Ok(first.value.clone());
+    }
+    if frame >= last.frame {
+        return Ok(last.value.clone());
+    }
+
+    let i = keyframes
+        .windows(2)
+        .position(|pair| frame < pair[1].frame)
+        .unwrap();
+    let (a, b) = (keyframes[i], keyframes[i + 1]);
+    let t = (frame - a.frame) / (b.frame - a.frame);
+    between(a, b, t, track.interpolation, &track.path)
+}
+
+/// Return a scene document as it is at a frame of an animation, with the
+/// animation itself removed
+pub fn frame_scene(
+    scene: &Value,
+    animation: &AnimationOpts,
+    frame: usize,
+) -> Result<Value, String> {
+    let mut scene = scene.clone();
+    if let Value::Object(ref mut fields) = scene {
+        fields.remove("animation");
+    }
+    for track in &animation.tracks {
+        let value = try!(track_value(track, frame as f64));
+        try!(set_path(&mut scene, &track.path, value));
+    }
+    Ok(scene)
+}
+
+/// Return the path of the image of a frame, from a pattern with `{}` in
+/// place of the frame number, padded to the same width for every frame
+pub fn frame_path(
+    pattern: &str,
+    frame: usize,
+    frames: usize,
+) -> Result<String, String> {
+    if !pattern.contains("{}") {
+        return Err(format!("Expected {{}} in {} for the frame", pattern));
+    }
+    let width = frames.saturating_sub(1).to_string().len();
+    Ok(pattern.replace("{}", &format!("{:01$}", frame, width)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn track(value: Value) -> TrackOpts {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn interpolating_keyframes() {
+        let level = track(json!({
+            "path": "primitives.1.position",
+            "keyframes": [
+                {"frame": 10, "value": [0.0, 20.0, 0.0]},
+                {"frame": 0, "value": [0.0, 10.0, 0.0]},
+            ],
+        }));
+        assert_eq!(track_value(&level, -5.0), Ok(json!([0.0, 10.0, 0.0])));
+        assert_eq!(track_value(&level, 5.0), Ok(json!([0.0, 15.0, 0.0])));
+        assert_eq!(track_value(&level, 15.0), Ok(json!([0.0, 20.0, 0.0])));
+
+        let width = track(json!({
+            "path": "camera.width",
+            "keyframes": [{"frame": 0, "value": 10}, {"frame": 4, "value": 20}],
+            "interpolation": "smooth",
+        }));
+        assert_eq!(track_value(&width, 1.0), Ok(json!(12)));
+
+        let sun = track(json!({
+            "path": "lights.0.direction",
+            "keyframes": [
+                {"frame": 0, "value": [1.0, 0.0, 0.0]},
+                {"frame": 2, "value": [0.0, 0.0, 2.0]},
+            ],
+            "interpolation": "spherical",
+        }));
+        let turned = numbers(&track_value(&sun, 1.0).unwrap()).unwrap();
+        let half = 1.5 * 0.5f64.sqrt();
+        assert!((turned[0] - half).abs() < 1e-9);
+        assert!((turned[2] - half).abs() < 1e-9);
+
+        let name = track(json!({
+            "path": "name",
+            "keyframes": [{"frame": 0, "value": "a"}, {"frame": 2, "value": "b"}],
+        }));
+        assert!(track_value(&name, 1.0).is_err());
+    }
+
+    #[test]
+    fn animating_scenes() {
+        let scene = json!({
+            "lights": [{"intensity": 1.0}],
+            "animation": {
+                "frames": 3,
+                "tracks": [{
+                    "path": "lights.0.intensity",
+                    "keyframes": [
+                        {"frame": 0, "value": 0.0},
+                        {"frame": 2, "value": 1.0},
+                    ],
+                }],
+            },
+        });
+        let animation = serde_json::from_value(scene["animation"].clone());
+        let frame = frame_scene(&scene, &animation.unwrap(), 1).unwrap();
+        assert_eq!(frame, json!({"lights": [{"intensity": 0.5}]}));
+
+        assert_eq!(frame_path("day-{}.png", 7, 120), Ok("day-007.png".into()));
+        assert!(frame_path("day.png", 7, 120).is_err());
+    }
+}
@@ -0,0 +1,453 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Baking scenes into packages holding everything their loaders read, and
+//! the height maps built from it, so they render without the original files.
+
+use io::cache::{self, scope, LoaderCache};
+use io::package::{self, PackageWriter};
+use labels::names;
+use lighting::{bake_diffuse, bake_occlusion, OCCLUSION_DIRECTIONS};
+use math::{AffineTransform, Vec3};
+use options::{
+    BakedLightingOpts, BakedLoader, ExtrusionOpts, HeightMapOpts, LightOpts,
+    Loader, PrimitiveOpts, SceneOpts, ShaderOpts,
+};
+use primitives::{attribute_values, content_hash, load_height_map, HeightMap};
+use scene::{flatten_shaders, height_maps_mut, place_curvature};
+use textures::Texture;
+
+use serde::Serialize;
+use serde_json::{self, Value};
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+use std::path::Path;
+
+/// Name of the entry of a package holding its scene
+const SCENE_ENTRY: &str = "scene.json";
+
+/// Adds the data of loaders to a package, once for each distinct loader
+struct Baker<'a> {
+    package: &'a mut PackageWriter,
+    entries: HashMap<String, String>,
+    height_maps: usize,
+    lighting: usize,
+}
+
+impl<'a> Baker<'a> {
+    fn new(package: &'a mut PackageWriter) -> Baker<'a> {
+        Baker {
+            package,
+            entries: HashMap::new(),
+            height_maps: 0,
+            lighting: 0,
+        }
+    }
+
+    /// Add the raster or shapes of a loader, returning the name of its entry
+    fn data(&mut self, loader: &Loader) -> Result<String> {
+        let key = try!(serde_json::to_string(loader));
+        if let Some(entry) = self.entries.get(&key) {
+            return Ok(entry.clone());
+        }
+
+        let bytes = if loader.is_raster() {
+            package::encode_raster(&*try!(cache::heights(loader)))
+        } else {
+            try!(serde_json::to_vec(&*try!(cache::shapes(loader))))
+        };
+        let entry = format!("data/{}", self.entries.len());
+        self.package.add(&entry, bytes);
+        self.entries.insert(key, entry.clone());
+        Ok(entry)
+    }
+
+    /// Add the heights of a height map and the quadtree built from them
+    fn height_map(&mut self, options: &mut HeightMapOpts) -> Result<()> {
+        if let Loader::Baked(_) = options.data {
+            return Ok(());
+        }
+
+        let entry = try!(self.data(&options.data));
+        let (transform, texture) = load_height_map(options);
+        let hash = content_hash(&transform, &texture);
+        let size = (texture.width, texture.height);
+        let mut bytes = vec![];
+        try!(HeightMap::new(transform, &texture).save(&mut bytes, size, hash));
+
+        let name = format!("height_maps/{}", self.height_maps);
+        self.height_maps += 1;
+        self.package.add(&name, bytes);
+        options.data = Loader::Baked(BakedLoader {
+            package: String::new(),
+            entry,
+            height_map: Some(name),
+        });
+        options.cache = None;
+        Ok(())
+    }
+
+    /// Add the values read by a function from an attribute of the shapes of a
+    /// loader, replacing it with a loader reading them
+    fn values<T, F>(
+        &mut self,
+        loader: &mut Loader,
+        field: &str,
+        read: F,
+    ) -> Result<()>
+    where
+        T: Serialize,
+        F: FnOnce(&Loader, &str) -> Result<T>,
+    {
+        if let Loader::Baked(_) = *loader {
+            return Ok(());
+        }
+
+        let key = format!("{}#{}", try!(serde_json::to_string(loader)), field);
+        let entry = match self.entries.get(&key) {
+            Some(entry) => entry.clone(),
+            None => {
+                let bytes =
+                    try!(serde_json::to_vec(&try!(read(loader, field))));
+                let entry = format!("data/{}", self.entries.len());
+                self.package.add(&entry, bytes);
+                self.entries.insert(key, entry.clone());
+                entry
+            }
+        };
+        *loader = Loader::Baked(BakedLoader {
+            package: String::new(),
+            entry,
+            height_map: None,
+        });
+        Ok(())
+    }
+
+    /// Add the heights of extrusions and the names of labels read from
+    /// attributes of their shapes
+    fn attributes(&mut self, options: &mut SceneOpts) -> Result<()> {
+        for primitive in &mut options.primitives {
+            if let PrimitiveOpts::Extrusion(ExtrusionOpts {
+                ref mut data,
+                attribute: Some(ref field),
+                ..
+            }) = *primitive
+            {
+                try!(self.values(data, field, attribute_values));
+            }
+        }
+        for label in &mut options.labels {
+            try!(self.values(&mut label.data, &label.attribute, names));
+        }
+        Ok(())
+    }
+
+    /// Add a raster in world space, returning a loader reading it
+    fn raster(
+        &mut self,
+        transform: &AffineTransform,
+        texture: Texture<f64>,
+    ) -> Loader {
+        let entry = format!("lighting/{}", self.lighting);
+        self.lighting += 1;
+        let raster = (String::new(), *transform, texture);
+        self.package.add(&entry, package::encode_raster(&raster));
+        Loader::Baked(BakedLoader {
+            package: String::new(),
+            entry,
+            height_map: None,
+        })
+    }
+
+    /// Add the light received by the first height map of a scene for each of
+    /// its phong shaders, and the sky reaching it up to a distance, which the
+    /// shaders only use for that height map
+    fn lighting(
+        &mut self,
+        options: &mut SceneOpts,
+        distance: f64,
+    ) -> Result<()> {
+        let terrain =
+            options
+                .primitives
+                .iter()
+                .enumerate()
+                .find_map(|(i, p)| match *p {
+                    PrimitiveOpts::HeightMap(ref opts) => {
+                        Some((i, opts.clone()))
+                    }
+                    _ => None,
+                });
+        let (primitive, terrain) = try!(terrain.ok_or_else(|| {
+            let message = "Baking lighting needs a height map";
+            Error::new(ErrorKind::InvalidInput, message)
+        }));
+        let (transform, mut heights) = load_height_map(&terrain);
+        for height in &mut heights.buffer {
+            *height *= terrain.exaggeration;
+        }
+
+        let visible = bake_occlusion(
+            &transform,
+            &heights,
+            OCCLUSION_DIRECTIONS,
+            distance,
+        );
+        let occlusion = self.raster(&transform, visible);
+
+        // Shaders lit by the same lights share their baked light
+        let mut baked: HashMap<Vec<usize>, Loader> = HashMap::new();
+        let shaders = mem::replace(&mut options.shaders, vec![]);
+        options.shaders = flatten_shaders(shaders);
+        let scene_lights = &options.lights;
+        for shader in &mut options.shaders {
+            let opts = match *shader {
+                ShaderOpts::Phong(ref mut opts) => opts,
+                _ => continue,
+            };
+            if !baked.contains_key(&opts.lights) {
+                let lights: Vec<(Vec3, f64)> = opts
+                    .lights
+                    .iter()
+                    .filter_map(|&i| match scene_lights.get(i) {
+                        Some(&LightOpts::Directional(ref light)) => {
+                            Some((From::from(light.direction), light.intensity))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let diffuse = bake_diffuse(&transform, &heights, &lights);
+                let loader = self.raster(&transform, diffuse);
+                baked.insert(opts.lights.clone(), loader);
+            }
+            opts.baked = Some(BakedLightingOpts {
+                diffuse: baked[&opts.lights].clone(),
+                occlusion: occlusion.clone(),
+                primitive,
+            });
+        }
+        Ok(())
+    }
+
+    /// Replace the loaders of a scene with loaders reading from the package
+    fn loaders(&mut self, value: &mut Value) -> Result<()> {
+        match *value {
+            Value::Object(ref mut fields) => {
+                // Shapes read with their attributes are added by
+                // `attributes`, and only left here if not found by it
+                let attribute =
+                    fields.get("attribute").map_or(false, |v| !v.is_null());
+                if let Some(data) = fields.get_mut("data") {
+                    let loader = serde_json::from_value(data.clone()).ok();
+                    match loader {
+                        Some(Loader::Baked(_)) | None => {}
+                        Some(_) if attribute => {}
+                        Some(loader) => {
+                            let entry = try!(self.data(&loader));
+                            *data = try!(serde_json::to_value(Loader::Baked(
+                                BakedLoader {
+                                    package: String::new(),
+                                    entry,
+                                    height_map: None,
+                                }
+                            )));
+                        }
+                    }
+                }
+                for (_, field) in fields.iter_mut() {
+                    try!(self.loaders(field));
+                }
+            }
+            Value::Array(ref mut items) => {
+                for item in items {
+                    try!(self.loaders(item));
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Write a scene to a package with the data of its loaders, which must still
+/// be readable, and its height maps built ahead of time
+pub fn bake<P: AsRef<Path>>(options: SceneOpts, path: P) -> Result<()> {
+    bake_package(options, path, None)
+}
+
+/// Write a scene to a package as `bake` does, also baking the light received
+/// by its terrain for its phong shaders, with the sky searched for terrain
+/// blocking it up to a distance, so its lights and terrain must not change
+pub fn bake_with_lighting<P: AsRef<Path>>(
+    options: SceneOpts,
+    path: P,
+    distance: f64,
+) -> Result<()> {
+    bake_package(options, path, Some(distance))
+}
+
+fn bake_package<P: AsRef<Path>>(
+    mut options: SceneOpts,
+    path: P,
+    lighting: Option<f64>,
+) -> Result<()> {
+    place_curvature(
+        &mut options.primitives,
+        &mut options.lights,
+        &options.camera,
+    );
+
+    let mut package = PackageWriter::new();
+    let mut loaders = LoaderCache::default();
+    let scene = try!(scope(&mut loaders, || -> Result<Value> {
+        let mut baker = Baker::new(&mut package);
+        if let Some(distance) = lighting {
+            try!(baker.lighting(&mut options, distance));
+        }
+        let height_maps =
+            height_maps_mut(&mut options.primitives, &mut options.lights);
+        for height_map in height_maps {
+            try!(baker.height_map(height_map));
+        }
+        try!(baker.attributes(&mut options));
+        let mut scene = try!(serde_json::to_value(&options));
+        try!(baker.loaders(&mut scene));
+        Ok(scene)
+    }));
+
+    package.add(SCENE_ENTRY, try!(serde_json::to_vec(&scene)));
+    package.save(path)
+}
+
+/// Point the loaders of a scene at the package it was read from
+fn locate(value: &mut Value, path: &str) {
+    match *value {
+        Value::Object(ref mut fields) => {
+            if fields.get("type") == Some(&json!("baked")) {
+                fields.insert("package".to_owned(), json!(path));
+            }
+            for (_, field) in fields.iter_mut() {
+                locate(field, path);
+            }
+        }
+        Value::Array(ref mut items) => {
+            for item in items {
+                locate(item, path);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Read the scene of a package written by `bake`
+pub fn open_package<P: AsRef<Path>>(path: P) -> Result<Value> {
+    let path = path.as_ref();
+    let bytes = try!(package::open_entry(path, SCENE_ENTRY));
+    let mut scene = try!(serde_json::from_slice(&bytes));
+    locate(&mut scene, &path.to_string_lossy());
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    #[test]
+    fn baking_loaders() {
+        let dir = env::temp_dir().join("peaks-baking-loaders");
+        fs::create_dir_all(&dir).unwrap();
+        let geojson = dir.join("points.json");
+        let points = json!({
+            "type": "MultiPoint",
+            "coordinates": [[1.0, 2.0], [3.0, 4.0]]
+        });
+        File::create(&geojson)
+            .unwrap()
+            .write_all(points.to_string().as_bytes())
+            .unwrap();
+
+        let loader = json!({"type": "geojson", "filepath": geojson});
+        let mut scene = json!({
+            "linework": [{"data": loader}, {"data": loader}],
+            "primitives": [{"data": loader, "attribute": "height"}]
+        });
+        let mut package = PackageWriter::new();
+        scope(&mut LoaderCache::default(), || {
+            Baker::new(&mut package).loaders(&mut scene).unwrap();
+        });
+        package.add(SCENE_ENTRY, serde_json::to_vec(&scene).unwrap());
+        let path = dir.join("scene.peakspkg");
+        package.save(&path).unwrap();
+
+        let scene = open_package(&path).unwrap();
+        let data = &scene["linework"][0]["data"];
+        assert_eq!(data["entry"], json!("data/0"));
+        assert_eq!(scene["linework"][1]["data"], *data);
+        assert_eq!(scene["primitives"][0]["data"], loader);
+
+        let baked: Loader = serde_json::from_value(data.clone()).unwrap();
+        let expected: Loader = serde_json::from_value(loader).unwrap();
+        let shapes = cache::shapes(&baked).unwrap();
+        assert_eq!(shapes, cache::shapes(&expected).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn baking_attributes() {
+        let dir = env::temp_dir().join("peaks-baking-attributes");
+        fs::create_dir_all(&dir).unwrap();
+        let geojson = dir.join("peaks.json");
+        let features = json!({
+            "type": "FeatureCollection",
+            "features": [{
+                "type": "Feature",
+                "geometry": {"type": "Point", "coordinates": [1.0, 2.0]},
+                "properties": {"name": "Ben Nevis"}
+            }]
+        });
+        File::create(&geojson)
+            .unwrap()
+            .write_all(features.to_string().as_bytes())
+            .unwrap();
+
+        let original: Loader = serde_json::from_value(
+            json!({"type": "geojson", "filepath": geojson}),
+        )
+        .unwrap();
+        let mut loader = original.clone();
+        let mut package = PackageWriter::new();
+        let mut baker = Baker::new(&mut package);
+        baker.values(&mut loader, "name", names).unwrap();
+        let path = dir.join("scene.peakspkg");
+        package.save(&path).unwrap();
+
+        let loader = match loader {
+            Loader::Baked(opts) => Loader::Baked(BakedLoader {
+                package: path.to_string_lossy().into_owned(),
+                ..opts
+            }),
+            _ => panic!("Names were not baked"),
+        };
+        let expected = names(&original, "name").unwrap();
+        assert_eq!(expected.len(), 1);
+        assert_eq!(names(&loader, "name").unwrap(), expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use math::Vec3;
+use options::{BatchJobOpts, SceneOpts};
+use serde_json::{self, Value};
+
+/// Apply a JSON merge patch (RFC 7386) to a document
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+
+    if !target.is_object() {
+        *target = json!({});
+    }
+
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(
+                target.entry(key.clone()).or_insert(Value::Null),
+                value,
+            );
+        }
+    }
+}
+
+/// Set a value in a document from an assignment of a dotted path, such as
+/// `shaders.3.color=[1,0,0]`, to a JSON value, where values that are not
+/// valid JSON are taken as strings
+pub fn apply_override(
+    target: &mut Value,
+    assignment: &str,
+) -> Result<(), String> {
+    let mut parts = assignment.splitn(2, '=');
+    let path = parts.next().unwrap_or("");
+    let value = match parts.next() {
+        Some(value) => serde_json::from_str(value)
+            .unwrap_or_else(|_| Value::String(value.to_string())),
+        None => return Err(format!("Override {} has no value", assignment)),
+    };
+    set_path(target, path, value)
+}
+
+/// Set a value in a document at a dotted path, creating missing objects
+/// along it but not missing array items
+pub fn set_path(
+    target: &mut Value,
+    path: &str,
+    value: Value,
+) -> Result<(), String> {
+    let mut node = target;
+    for key in path.split('.') {
+        let index = key.parse::<usize>().ok();
+        node = match node {
+            Value::Object(map) => {
+                map.entry(key.to_string()).or_insert(json!({}))
+            }
+            Value::Array(items) => match index {
+                Some(i) if i < items.len() => &mut items[i],
+                _ => return Err(format!("No index {} in {}", key, path)),
+            },
+            _ => return Err(format!("Cannot set {} in {}", key, path)),
+        };
+    }
+    *node = value;
+    Ok(())
+}
+
+/// Return the options for the scene of a batch job, with its overrides
+pub fn scene_options(
+    job: &BatchJobOpts,
+    mut scene: Value,
+) -> serde_json::Result<SceneOpts> {
+    if let Some(ref overrides) = job.overrides {
+        merge_patch(&mut scene, overrides);
+    }
+    serde_json::from_value(scene)
+}
+
+/// Return copies of a scene viewed from evenly spaced compass bearings around
+/// the camera's look at point, starting from its own, paired with the bearing
+/// in degrees
+pub fn orbit_views(
+    scene: &SceneOpts,
+    views: usize,
+    elevation: Option<f64>,
+) -> Vec<(f64, SceneOpts)> {
+    let (start, current) = scene.camera.angles();
+    let elevation = elevation.unwrap_or(current);
+    (0..views)
+        .map(|view| {
+            let azimuth = (start + 360.0 * view as f64 / views as f64) % 360.0;
+            let mut scene = scene.clone();
+            scene.camera.orbit(azimuth, elevation);
+            // Framing points the camera itself, so is told the angles too
+            if let Some(ref mut frame) = scene.frame {
+                frame.azimuth = Some(azimuth);
+                frame.elevation = Some(elevation);
+            }
+            (azimuth, scene)
+        })
+        .collect()
+}
+
+/// Return copies of a scene for the left and right eye, with cameras moved
+/// sideways by half an interocular distance and turned in to converge at a
+/// distance along the view, or on the look at point. Framing moves each eye
+/// independently, so should not be combined with stereo views.
+pub fn stereo_views(
+    scene: &SceneOpts,
+    interocular: f64,
+    convergence: Option<f64>,
+) -> (SceneOpts, SceneOpts) {
+    let eye = |side: f64| {
+        let mut scene = scene.clone();
+        let up = Vec3::from(scene.camera.up());
+        {
+            let (position, look_at) = scene.camera.placement_mut();
+            let (eye, target) = (Vec3::from(*position), Vec3::from(*look_at));
+            let direction = Vec3::normalize(target - eye);
+            let right = Vec3::normalize(Vec3::cross(direction, up));
+            let target = match convergence {
+                Some(distance) => eye + direction * distance,
+                None => target,
+            };
+            let eye = eye + right * (side * interocular / 2.0);
+            *position = [eye.x, eye.y, eye.z];
+            *look_at = [target.x, target.y, target.z];
+        }
+        scene
+    };
+    (eye(-1.0), eye(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use options::CameraOpts;
+
+    fn scene() -> SceneOpts {
+        serde_json::from_value(json!({
+            "background": [0, 0, 0],
+            "camera": {
+                "type": "perspective",
+                "width": 10,
+                "height": 10,
+                "position": [0, 0, 10],
+                "look_at": [0, 0, 0],
+                "fov": 0.5,
+                "view_distance": 1,
+                "up": [0, 1, 0],
+            },
+            "shaders": [],
+            "lights": [],
+            "primitives": [],
+            "objects": [],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn merging_patches() {
+        let mut scene = json!({
+            "camera": {"position": [0, 0, 0], "fov": 45},
+            "background": [0, 0, 0],
+            "edges": {"width": 1},
+        });
+        merge_patch(
+            &mut scene,
+            &json!({"camera": {"position": [1, 2, 3]}, "edges": null}),
+        );
+        assert_eq!(
+            scene,
+            json!({
+                "camera": {"position": [1, 2, 3], "fov": 45},
+                "background": [0, 0, 0],
+            })
+        );
+    }
+
+    #[test]
+    fn applying_overrides() {
+        let mut scene = json!({
+            "camera": {"width": 100},
+            "shaders": [{"color": [0, 0, 0]}],
+        });
+        apply_override(&mut scene, "camera.width=3840").unwrap();
+        apply_override(&mut scene, "shaders.0.color=[1,0,0]").unwrap();
+        apply_override(&mut scene, "frame.margin=0.1").unwrap();
+        apply_override(&mut scene, "name=alps").unwrap();
+        assert_eq!(
+            scene,
+            json!({
+                "camera": {"width": 3840},
+                "shaders": [{"color": [1, 0, 0]}],
+                "frame": {"margin": 0.1},
+                "name": "alps",
+            })
+        );
+
+        assert!(apply_override(&mut scene, "shaders.1.color=[0,0,0]").is_err());
+        assert!(apply_override(&mut scene, "camera.width.x=1").is_err());
+        assert!(apply_override(&mut scene, "camera.width").is_err());
+    }
+
+    #[test]
+    fn orbiting_views() {
+        let views = orbit_views(&scene(), 4, Some(30.0));
+        let azimuths: Vec<f64> = views.iter().map(|view| view.0).collect();
+        assert_eq!(azimuths, vec![0.0, 90.0, 180.0, 270.0]);
+
+        let (azimuth, elevation) = views[1].1.camera.angles();
+        assert!((azimuth - 90.0).abs() < 1e-9);
+        assert!((elevation - 30.0).abs() < 1e-9);
+        let [x, y, _] = views[1].1.camera.position();
+        assert!((x - 10.0 * 30f64.to_radians().cos()).abs() < 1e-9);
+        assert!((y - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn stereo_pairs() {
+        let (left, right) = stereo_views(&scene(), 2.0, Some(5.0));
+        assert_eq!(left.camera.position(), [-1.0, 0.0, 10.0]);
+        assert_eq!(right.camera.position(), [1.0, 0.0, 10.0]);
+        match right.camera {
+            CameraOpts::Perspective(ref opts) => {
+                assert_eq!(opts.look_at, [0.0, 0.0, 5.0])
+            }
+            _ => unreachable!(),
+        }
+    }
+}
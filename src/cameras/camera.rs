@@ -13,9 +13,78 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
-use math::Ray;
+use math::{Ray, Vec3};
+use primitives::Aabb;
+
+use std::f64::INFINITY;
+use std::sync::Arc;
+
+/// Length below which a cross product is taken to be of parallel vectors
+const PARALLEL: f64 = 1e-9;
 
 pub trait Camera {
     fn view_plane(&self) -> (usize, usize);
     fn cast_ray(&self, x: f64, y: f64) -> Ray;
+    /// Return the view plane coordinates of a point in world space
+    fn project(&self, point: Vec3) -> Option<(f64, f64)>;
+    /// Return a camera looking in the same direction, placed to fit a box in
+    /// view with a margin as a fraction of the view plane
+    fn frame_bounds(&self, bounds: &Aabb, margin: f64) -> Arc<Camera>;
+    /// Return the range of distances along a ray cast by the camera within
+    /// which surfaces are visible
+    fn clip_range(&self, _ray: &Ray) -> (f64, f64) {
+        (0.0, INFINITY)
+    }
+}
+
+/// Return the view direction of a camera at a position looking at a point,
+/// and an axis for it to keep upright about. A camera at its look at point
+/// looks down, and a camera looking along its up axis keeps north, or -Z, up
+pub fn orientation(
+    position: Vec3,
+    look_at: Vec3,
+    up_axis: Vec3,
+) -> (Vec3, Vec3) {
+    let offset = look_at - position;
+    let direction = if Vec3::length(offset) > 0.0 {
+        Vec3::normalize(offset)
+    } else {
+        Vec3::new(0.0, -1.0, 0.0)
+    };
+
+    let fallbacks =
+        [up_axis, Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0)];
+    let up_axis = fallbacks
+        .iter()
+        .cloned()
+        .find(|&axis| Vec3::length(Vec3::cross(direction, axis)) > PARALLEL)
+        .unwrap_or(up_axis);
+    (direction, up_axis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orienting_degenerate_cameras() {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let origin = Vec3::zeros();
+        let above = Vec3::new(0.0, 10.0, 0.0);
+
+        let (direction, axis) = orientation(above, origin, up);
+        assert_eq!(direction, Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(axis, Vec3::new(0.0, 0.0, -1.0));
+
+        let (direction, axis) = orientation(origin, origin, up);
+        assert_eq!(direction, Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(axis, Vec3::new(0.0, 0.0, -1.0));
+
+        let north = Vec3::new(0.0, 0.0, -1.0);
+        let (_, axis) = orientation(origin, north, north);
+        assert_eq!(axis, up);
+
+        let (_, axis) = orientation(Vec3::new(0.0, 10.0, 10.0), origin, up);
+        assert_eq!(axis, up);
+    }
 }
@@ -0,0 +1,166 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use super::camera::{orientation, Camera};
+use math::{Ray, Vec3};
+use options::EquirectangularCameraOpts;
+use primitives::Aabb;
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// A camera capturing every direction around its position, mapping longitude
+/// to the horizontal axis and latitude to the vertical, as used by panorama
+/// viewers
+#[derive(Copy, Clone, Debug)]
+pub struct EquirectangularCamera {
+    position: Vec3,
+    up_axis: Vec3,
+    width: usize,
+    height: usize,
+    forward: Vec3,
+    right: Vec3,
+    up: Vec3,
+}
+
+impl EquirectangularCamera {
+    /// Create a camera, with the look at point in the center of the image
+    pub fn new(
+        width: usize,
+        height: usize,
+        position: Vec3,
+        look_at: Vec3,
+        up_axis: Vec3,
+    ) -> EquirectangularCamera {
+        let (forward, axis) = orientation(position, look_at, up_axis);
+        let right = Vec3::normalize(Vec3::cross(forward, axis));
+        let up = Vec3::cross(right, forward);
+
+        EquirectangularCamera {
+            position,
+            up_axis,
+            width,
+            height,
+            forward,
+            right,
+            up,
+        }
+    }
+
+    /// Return a ray for a point on the view plane, without differentials
+    fn primary_ray(&self, x: f64, y: f64) -> Ray {
+        // Longitude wraps around the horizontal axis, so the left and right
+        // edges of the image meet, while latitude is clamped at the poles
+        let longitude = (x / self.width as f64 - 0.5) * 2.0 * PI;
+        let latitude = (0.5 - y / self.height as f64).max(-0.5).min(0.5) * PI;
+
+        let direction = self.forward * (latitude.cos() * longitude.cos())
+            + self.right * (latitude.cos() * longitude.sin())
+            + self.up * latitude.sin();
+        Ray::new(self.position, Vec3::normalize(direction))
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn view_plane(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn cast_ray(&self, x: f64, y: f64) -> Ray {
+        let rx = self.primary_ray(x + 1.0, y);
+        let ry = self.primary_ray(x, y + 1.0);
+        self.primary_ray(x, y).with_differentials(rx, ry)
+    }
+
+    fn project(&self, point: Vec3) -> Option<(f64, f64)> {
+        let offset = point - self.position;
+        let distance = Vec3::length(offset);
+        if distance == 0.0 {
+            return None;
+        }
+
+        let direction = offset / distance;
+        let longitude = Vec3::dot(direction, self.right)
+            .atan2(Vec3::dot(direction, self.forward));
+        let latitude = Vec3::dot(direction, self.up).max(-1.0).min(1.0).asin();
+
+        let x = (longitude / (2.0 * PI) + 0.5) * self.width as f64;
+        let y = (0.5 - latitude / PI) * self.height as f64;
+        Some((x, y))
+    }
+
+    fn frame_bounds(&self, bounds: &Aabb, _margin: f64) -> Arc<Camera> {
+        // Everything is in view already, so only turn to face the bounds
+        Arc::new(EquirectangularCamera::new(
+            self.width,
+            self.height,
+            self.position,
+            bounds.center(),
+            self.up_axis,
+        ))
+    }
+}
+
+impl From<EquirectangularCameraOpts> for EquirectangularCamera {
+    fn from(options: EquirectangularCameraOpts) -> EquirectangularCamera {
+        EquirectangularCamera::new(
+            options.width,
+            options.height,
+            From::from(options.position),
+            From::from(options.look_at),
+            From::from(options.up),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> EquirectangularCamera {
+        EquirectangularCamera::new(
+            360,
+            180,
+            Vec3::new(0.0, 10.0, 0.0),
+            Vec3::new(0.0, 10.0, -10.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        )
+    }
+
+    #[test]
+    fn projecting_points() {
+        let camera = camera();
+        let ray = camera.cast_ray(20.0, 30.0);
+        let point = ray.origin + ray.direction * 5.0;
+        let (x, y) = camera.project(point).unwrap();
+        assert!((x - 20.0).abs() < 1e-9);
+        assert!((y - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn poles_and_seams() {
+        let camera = camera();
+        let ray = camera.cast_ray(180.0, 90.0);
+        assert!(
+            Vec3::distance(ray.direction, Vec3::new(0.0, 0.0, -1.0)) < 1e-9
+        );
+
+        let ray = camera.cast_ray(100.0, 0.0);
+        assert!(Vec3::distance(ray.direction, Vec3::new(0.0, 1.0, 0.0)) < 1e-9);
+
+        let (a, b) = (camera.cast_ray(0.0, 45.0), camera.cast_ray(360.0, 45.0));
+        assert!(Vec3::distance(a.direction, b.direction) < 1e-9);
+    }
+}
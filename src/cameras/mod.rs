@@ -14,9 +14,11 @@
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
 mod camera;
+mod equirectangular;
 mod orthographic;
 mod pinhole;
 
 pub use self::camera::Camera;
+pub use self::equirectangular::EquirectangularCamera;
 pub use self::orthographic::OrthographicCamera;
 pub use self::pinhole::PinholeCamera;
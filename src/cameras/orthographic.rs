@@ -13,9 +13,13 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
-use super::camera::Camera;
+use super::camera::{orientation, Camera};
 use math::{Ray, Vec3};
 use options::OrthographicCameraOpts;
+use primitives::Aabb;
+
+use std::f64::INFINITY;
+use std::sync::Arc;
 
 #[derive(Copy, Clone, Debug)]
 pub struct OrthographicCamera {
@@ -29,6 +33,8 @@ pub struct OrthographicCamera {
     u: Vec3,
     v: Vec3,
     w: Vec3,
+    near: f64,
+    far: f64,
     view_plane_size: f64,
 }
 
@@ -42,11 +48,11 @@ impl OrthographicCamera {
         up_axis: Vec3,
         view_plane_size: f64,
     ) -> OrthographicCamera {
-        let direction = Vec3::normalize(look_at - position);
-        let right = Vec3::cross(direction, up_axis);
+        let (direction, axis) = orientation(position, look_at, up_axis);
+        let right = Vec3::cross(direction, axis);
         let up = Vec3::cross(right, direction);
 
-        let w = Vec3::normalize(position - look_at);
+        let w = -direction;
         let u = Vec3::cross(up, w);
         let v = Vec3::cross(w, u);
 
@@ -70,17 +76,28 @@ impl OrthographicCamera {
             u,
             v,
             w,
+            near: 0.0,
+            far: INFINITY,
             view_plane_size,
         }
     }
-}
 
-impl Camera for OrthographicCamera {
-    fn view_plane(&self) -> (usize, usize) {
-        (self.width, self.height)
+    /// Return the camera hiding surfaces nearer or further than distances
+    /// along its view direction
+    pub fn with_clipping(
+        self,
+        near: f64,
+        far: Option<f64>,
+    ) -> OrthographicCamera {
+        OrthographicCamera {
+            near,
+            far: far.unwrap_or(INFINITY),
+            ..self
+        }
     }
 
-    fn cast_ray(&self, x: f64, y: f64) -> Ray {
+    /// Return a ray for a point on the view plane, without differentials
+    fn primary_ray(&self, x: f64, y: f64) -> Ray {
         let mut px = x / self.width as f64 * 2.0 - 1.0;
         let mut py = 1.0 - y / self.height as f64 * 2.0;
 
@@ -92,6 +109,69 @@ impl Camera for OrthographicCamera {
     }
 }
 
+impl Camera for OrthographicCamera {
+    fn view_plane(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn cast_ray(&self, x: f64, y: f64) -> Ray {
+        let rx = self.primary_ray(x + 1.0, y);
+        let ry = self.primary_ray(x, y + 1.0);
+        self.primary_ray(x, y).with_differentials(rx, ry)
+    }
+
+    fn project(&self, point: Vec3) -> Option<(f64, f64)> {
+        let offset = point - self.position;
+        if Vec3::dot(offset, self.w) >= 0.0 {
+            return None;
+        }
+
+        let size = self.view_plane_size;
+        let px = Vec3::dot(offset, self.u) / Vec3::dot(self.u, self.u);
+        let py = Vec3::dot(offset, self.v) / Vec3::dot(self.v, self.v);
+        let px = px / (self.aspect.x * size);
+        let py = py / (self.aspect.y * size);
+
+        let x = (px + 1.0) / 2.0 * self.width as f64;
+        let y = (1.0 - py) / 2.0 * self.height as f64;
+        Some((x, y))
+    }
+
+    fn frame_bounds(&self, bounds: &Aabb, margin: f64) -> Arc<Camera> {
+        let center = bounds.center();
+        let direction = -self.w;
+
+        // Size the view plane to the widest corner, and back away from the
+        // nearest so that the box is entirely in front of the camera
+        let mut size: f64 = 0.0;
+        let mut distance: f64 = 0.0;
+        for &corner in &bounds.corners() {
+            let offset = corner - center;
+            let px = Vec3::dot(offset, self.u) / Vec3::dot(self.u, self.u);
+            let py = Vec3::dot(offset, self.v) / Vec3::dot(self.v, self.v);
+            size = size
+                .max(px.abs() / self.aspect.x)
+                .max(py.abs() / self.aspect.y);
+            distance = distance.max(-Vec3::dot(offset, direction));
+        }
+
+        let camera = OrthographicCamera::new(
+            self.width,
+            self.height,
+            center - direction * (distance + self.view_distance),
+            center,
+            self.view_distance,
+            self.up_axis,
+            size * (1.0 + margin),
+        );
+        Arc::new(camera.with_clipping(self.near, Some(self.far)))
+    }
+
+    fn clip_range(&self, _: &Ray) -> (f64, f64) {
+        (self.near, self.far)
+    }
+}
+
 impl From<OrthographicCameraOpts> for OrthographicCamera {
     fn from(options: OrthographicCameraOpts) -> OrthographicCamera {
         OrthographicCamera::new(
@@ -103,5 +183,58 @@ impl From<OrthographicCameraOpts> for OrthographicCamera {
             From::from(options.up),
             options.view_plane_size,
         )
+        .with_clipping(options.near, options.far)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projecting_points() {
+        let camera = OrthographicCamera::new(
+            200,
+            100,
+            Vec3::new(0.0, 10.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            1.0,
+            Vec3::new(0.0, 0.0, -1.0),
+            50.0,
+        );
+        let ray = camera.cast_ray(20.0, 30.0);
+        let point = ray.origin + ray.direction * 5.0;
+        let (x, y) = camera.project(point).unwrap();
+        assert!((x - 20.0).abs() < 1e-9);
+        assert!((y - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn framing_bounds() {
+        let camera = OrthographicCamera::new(
+            200,
+            100,
+            Vec3::new(0.0, 10.0, 10.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            1.0,
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+        );
+        let bounds =
+            Aabb::new(Vec3::new(90.0, 0.0, 40.0), Vec3::new(110.0, 5.0, 60.0));
+        let camera = camera.frame_bounds(&bounds, 0.0);
+
+        let (mut min_x, mut min_y) = (200.0_f64, 100.0_f64);
+        let mut max_y: f64 = 0.0;
+        for &corner in &bounds.corners() {
+            let (x, y) = camera.project(corner).unwrap();
+            assert!(x >= -1e-9 && x <= 200.0 + 1e-9);
+            assert!(y >= -1e-9 && y <= 100.0 + 1e-9);
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_y = max_y.max(y);
+        }
+        assert!(min_x > 1.0);
+        assert!(min_y.abs() < 1e-9 && (max_y - 100.0).abs() < 1e-9);
     }
 }
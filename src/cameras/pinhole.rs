@@ -13,9 +13,13 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
-use super::camera::Camera;
+use super::camera::{orientation, Camera};
 use math::{Ray, Vec3};
 use options::PerspectiveCameraOpts;
+use primitives::Aabb;
+
+use std::f64::INFINITY;
+use std::sync::Arc;
 
 #[derive(Copy, Clone, Debug)]
 pub struct PinholeCamera {
@@ -30,6 +34,8 @@ pub struct PinholeCamera {
     u: Vec3,
     v: Vec3,
     w: Vec3,
+    near: f64,
+    far: f64,
 }
 
 impl PinholeCamera {
@@ -43,12 +49,12 @@ impl PinholeCamera {
         up_axis: Vec3,
     ) -> PinholeCamera {
         // Calculate the correct up vector for the up axis
-        let direction = Vec3::normalize(look_at - position);
-        let right = Vec3::cross(direction, up_axis);
+        let (direction, axis) = orientation(position, look_at, up_axis);
+        let right = Vec3::cross(direction, axis);
         let up = Vec3::cross(right, direction);
 
         // Create the cameras coordinate system (ONB)
-        let w = Vec3::normalize(position - look_at);
+        let w = -direction;
         let u = Vec3::cross(up, w);
         let v = Vec3::cross(w, u);
 
@@ -74,16 +80,23 @@ impl PinholeCamera {
             u,
             v,
             w,
+            near: 0.0,
+            far: INFINITY,
         }
     }
-}
 
-impl Camera for PinholeCamera {
-    fn view_plane(&self) -> (usize, usize) {
-        (self.width, self.height)
+    /// Return the camera hiding surfaces nearer or further than distances
+    /// along its view direction
+    pub fn with_clipping(self, near: f64, far: Option<f64>) -> PinholeCamera {
+        PinholeCamera {
+            near,
+            far: far.unwrap_or(INFINITY),
+            ..self
+        }
     }
 
-    fn cast_ray(&self, x: f64, y: f64) -> Ray {
+    /// Return a ray for a point on the view plane, without differentials
+    fn primary_ray(&self, x: f64, y: f64) -> Ray {
         // Raster to NDC space
         let mut px = x / self.width as f64 * 2.0 - 1.0;
         let mut py = 1.0 - y / self.height as f64 * 2.0;
@@ -97,6 +110,77 @@ impl Camera for PinholeCamera {
     }
 }
 
+impl Camera for PinholeCamera {
+    fn view_plane(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn cast_ray(&self, x: f64, y: f64) -> Ray {
+        let rx = self.primary_ray(x + 1.0, y);
+        let ry = self.primary_ray(x, y + 1.0);
+        self.primary_ray(x, y).with_differentials(rx, ry)
+    }
+
+    fn project(&self, point: Vec3) -> Option<(f64, f64)> {
+        let dir = point - self.position;
+        let depth = -Vec3::dot(dir, self.w);
+        if depth <= 0.0 {
+            return None;
+        }
+
+        // Scale onto the view plane then undo the aspect ratio and fov, the
+        // basis vectors `u` and `v` are not necessarily of unit length
+        let scale = self.view_distance / depth;
+        let px = Vec3::dot(dir, self.u) / Vec3::dot(self.u, self.u) * scale;
+        let py = Vec3::dot(dir, self.v) / Vec3::dot(self.v, self.v) * scale;
+        let px = px / (self.aspect.x * self.fov);
+        let py = py / (self.aspect.y * self.fov);
+
+        let x = (px + 1.0) / 2.0 * self.width as f64;
+        let y = (1.0 - py) / 2.0 * self.height as f64;
+        Some((x, y))
+    }
+
+    fn frame_bounds(&self, bounds: &Aabb, margin: f64) -> Arc<Camera> {
+        let center = bounds.center();
+        let direction = -self.w;
+
+        // Find the distance from the center at which every corner projects
+        // within the view plane, offset by the depth of the corner
+        let mut distance: f64 = 0.0;
+        for &corner in &bounds.corners() {
+            let offset = corner - center;
+            let px = Vec3::dot(offset, self.u) / Vec3::dot(self.u, self.u);
+            let py = Vec3::dot(offset, self.v) / Vec3::dot(self.v, self.v);
+            let extent = (px.abs() / self.aspect.x)
+                .max(py.abs() / self.aspect.y)
+                * self.view_distance
+                * (1.0 + margin)
+                / self.fov;
+            let depth = Vec3::dot(offset, direction);
+            distance =
+                distance.max(extent - depth).max(self.view_distance - depth);
+        }
+
+        let camera = PinholeCamera::new(
+            self.width,
+            self.height,
+            center - direction * distance,
+            center,
+            self.fov,
+            self.view_distance,
+            self.up_axis,
+        );
+        Arc::new(camera.with_clipping(self.near, Some(self.far)))
+    }
+
+    fn clip_range(&self, ray: &Ray) -> (f64, f64) {
+        // Distance along the ray per unit of depth along the view direction
+        let scale = 1.0 / -Vec3::dot(ray.direction, self.w);
+        (self.near * scale, self.far * scale)
+    }
+}
+
 impl From<PerspectiveCameraOpts> for PinholeCamera {
     fn from(options: PerspectiveCameraOpts) -> PinholeCamera {
         PinholeCamera::new(
@@ -108,5 +192,55 @@ impl From<PerspectiveCameraOpts> for PinholeCamera {
             options.view_distance,
             From::from(options.up),
         )
+        .with_clipping(options.near, options.far)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projecting_points() {
+        let camera = PinholeCamera::new(
+            200,
+            100,
+            Vec3::new(0.0, 10.0, 10.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            0.5,
+            1.0,
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        let ray = camera.cast_ray(20.0, 30.0);
+        let point = ray.origin + ray.direction * 5.0;
+        let (x, y) = camera.project(point).unwrap();
+        assert!((x - 20.0).abs() < 1e-9);
+        assert!((y - 30.0).abs() < 1e-9);
+        assert_eq!(camera.project(Vec3::new(0.0, 20.0, 20.0)), None);
+    }
+
+    #[test]
+    fn framing_bounds() {
+        let camera = PinholeCamera::new(
+            200,
+            100,
+            Vec3::new(0.0, 10.0, 10.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            0.5,
+            1.0,
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        let bounds =
+            Aabb::new(Vec3::new(90.0, 0.0, 40.0), Vec3::new(110.0, 5.0, 60.0));
+        let camera = camera.frame_bounds(&bounds, 0.1);
+
+        let mut edge: f64 = 0.0;
+        for &corner in &bounds.corners() {
+            let (x, y) = camera.project(corner).unwrap();
+            assert!(x > 0.0 && x < 200.0 && y > 0.0 && y < 100.0);
+            let (nx, ny) = (x / 100.0 - 1.0, y / 50.0 - 1.0);
+            edge = edge.max(nx.abs()).max(ny.abs());
+        }
+        assert!((edge - 1.0 / 1.1).abs() < 1e-9);
     }
 }
@@ -0,0 +1,183 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use batch::merge_patch;
+use serde_json::{self, Map, Value};
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Fields of loaders holding paths, resolved relative to the catalog file
+const PATH_FIELDS: &[&str] = &["filepath", "cache"];
+
+/// Loader options of datasets by name, so that scenes can refer to data kept
+/// in different places on different machines
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Catalog {
+    entries: Map<String, Value>,
+}
+
+impl Catalog {
+    pub fn new(entries: Map<String, Value>) -> Catalog {
+        Catalog { entries }
+    }
+
+    /// Read a catalog file, a JSON object of loader options by name, with
+    /// relative paths resolved against the directory of the file
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Catalog> {
+        let mut text = String::new();
+        try!(try!(File::open(path.as_ref())).read_to_string(&mut text));
+        let mut entries: Map<String, Value> = try!(serde_json::from_str(&text));
+
+        let root = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+        for entry in entries.values_mut() {
+            relative_to(entry, root);
+        }
+        Ok(Catalog { entries })
+    }
+
+    /// Return the path of the catalog of the user, from `PEAKS_CATALOG` or
+    /// else `~/.config/peaks/catalog.json`, if it exists
+    pub fn user_path() -> Option<PathBuf> {
+        let path = match env::var_os("PEAKS_CATALOG") {
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(env::var_os("HOME")?)
+                .join(".config")
+                .join("peaks")
+                .join("catalog.json"),
+        };
+        if path.exists() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    /// Replace references to datasets of the form `{"catalog": name}` in
+    /// scene options with their loader options, overridden by any other
+    /// fields of the reference
+    pub fn resolve(&self, value: &mut Value) -> Result<(), String> {
+        let name = match value.get("catalog") {
+            Some(Value::String(name)) => Some(name.clone()),
+            _ => None,
+        };
+
+        if let Some(name) = name {
+            let mut entry = match self.entries.get(&name) {
+                Some(entry) => entry.clone(),
+                None => return Err(format!("Unknown dataset '{}'", name)),
+            };
+            let mut overrides = value.take();
+            overrides.as_object_mut().unwrap().remove("catalog");
+            merge_patch(&mut entry, &overrides);
+            *value = entry;
+            return Ok(());
+        }
+
+        match value {
+            Value::Object(fields) => {
+                for field in fields.values_mut() {
+                    try!(self.resolve(field));
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    try!(self.resolve(item));
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// Resolve relative paths in loader options against a directory
+fn relative_to(entry: &mut Value, root: &Path) {
+    let fields = match entry.as_object_mut() {
+        Some(fields) => fields,
+        None => return,
+    };
+    for name in PATH_FIELDS {
+        if let Some(Value::String(path)) = fields.get_mut(*name) {
+            if Path::new(path.as_str()).is_relative() && !path.contains("://") {
+                *path = root.join(path.as_str()).to_string_lossy().into_owned();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog() -> Catalog {
+        let entries = json!({
+            "eu_dem": {"type": "gdal", "filepath": "/data/eu_dem.tif", "band": 1},
+            "osm_water": {
+                "type": "osm",
+                "filepath": "/data/europe.osm.pbf",
+                "filter": "natural=water",
+            },
+        });
+        Catalog::new(entries.as_object().unwrap().clone())
+    }
+
+    #[test]
+    fn resolving_datasets() {
+        let mut scene = json!({
+            "primitives": [{"type": "height_map", "data": {"catalog": "eu_dem"}}],
+            "shaders": [{
+                "data": {"catalog": "osm_water", "layer": "multipolygons"},
+            }],
+        });
+        catalog().resolve(&mut scene).unwrap();
+        assert_eq!(
+            scene,
+            json!({
+                "primitives": [{
+                    "type": "height_map",
+                    "data": {"type": "gdal", "filepath": "/data/eu_dem.tif", "band": 1},
+                }],
+                "shaders": [{
+                    "data": {
+                        "type": "osm",
+                        "filepath": "/data/europe.osm.pbf",
+                        "filter": "natural=water",
+                        "layer": "multipolygons",
+                    },
+                }],
+            })
+        );
+
+        let mut scene = json!({"data": {"catalog": "missing"}});
+        assert!(catalog().resolve(&mut scene).is_err());
+    }
+
+    #[test]
+    fn relative_catalog_paths() {
+        let mut entry = json!({"filepath": "dem.tif", "cache": "/tmp/peaks"});
+        relative_to(&mut entry, Path::new("/data"));
+        assert_eq!(
+            entry,
+            json!({"filepath": "/data/dem.tif", "cache": "/tmp/peaks"})
+        );
+
+        let mut entry = json!({"url": "https://example.com/dem.tif"});
+        relative_to(&mut entry, Path::new("/data"));
+        assert_eq!(entry, json!({"url": "https://example.com/dem.tif"}));
+    }
+}
@@ -0,0 +1,201 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Execution of texture operations over overlapping chunks of rasters, so
+//! only a chunk of the input and output need be held in memory at once.
+
+use textures::Texture;
+
+use std::io::Result;
+
+/// A raster from which windows of pixels are read
+pub trait ChunkSource<T: Copy + Default> {
+    /// Return the width and height of the raster
+    fn size(&self) -> (usize, usize);
+    /// Read a window of the raster
+    fn read(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<Texture<T>>;
+}
+
+/// A raster to which windows of pixels are written
+pub trait ChunkSink<T: Copy + Default> {
+    /// Write a window of the raster at an offset
+    fn write(&mut self, x: usize, y: usize, chunk: &Texture<T>) -> Result<()>;
+}
+
+/// An operation on a texture, producing an output of the same size
+pub trait ChunkOp<I, O>
+where
+    I: Copy + Default,
+    O: Copy + Default,
+{
+    /// Distance in pixels around an output pixel on which its value depends
+    fn halo(&self) -> usize;
+    fn apply(&mut self, input: &Texture<I>, output: &mut Texture<O>);
+}
+
+/// Operations depending only on the pixel they produce
+impl<I, O, F> ChunkOp<I, O> for F
+where
+    I: Copy + Default,
+    O: Copy + Default,
+    F: FnMut(&Texture<I>, &mut Texture<O>),
+{
+    fn halo(&self) -> usize {
+        0
+    }
+
+    fn apply(&mut self, input: &Texture<I>, output: &mut Texture<O>) {
+        self(input, output)
+    }
+}
+
+/// An operation depending on pixels within a distance of those it produces
+pub struct WithHalo<F>(pub usize, pub F);
+
+impl<I, O, F> ChunkOp<I, O> for WithHalo<F>
+where
+    I: Copy + Default,
+    O: Copy + Default,
+    F: FnMut(&Texture<I>, &mut Texture<O>),
+{
+    fn halo(&self) -> usize {
+        self.0
+    }
+
+    fn apply(&mut self, input: &Texture<I>, output: &mut Texture<O>) {
+        (self.1)(input, output)
+    }
+}
+
+impl<T> ChunkSource<T> for Texture<T>
+where
+    T: Copy + Default,
+{
+    fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn read(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<Texture<T>> {
+        let mut chunk = Texture::blank(width, height);
+        for row in 0..height {
+            let offset = (y + row) * self.width + x;
+            chunk.buffer[row * width..(row + 1) * width]
+                .copy_from_slice(&self.buffer[offset..offset + width]);
+        }
+        Ok(chunk)
+    }
+}
+
+impl<T> ChunkSink<T> for Texture<T>
+where
+    T: Copy + Default,
+{
+    fn write(&mut self, x: usize, y: usize, chunk: &Texture<T>) -> Result<()> {
+        for row in 0..chunk.height {
+            let offset = (y + row) * self.width + x;
+            let source = &chunk.buffer[row * chunk.width..][..chunk.width];
+            self.buffer[offset..offset + chunk.width].copy_from_slice(source);
+        }
+        Ok(())
+    }
+}
+
+/// Apply an operation to a raster in chunks of a size, each read with a halo
+/// of surrounding pixels so the stitched output matches applying the
+/// operation to the whole raster
+pub fn process_chunks<I, O, S, K, F>(
+    source: &mut S,
+    sink: &mut K,
+    size: usize,
+    op: &mut F,
+) -> Result<()>
+where
+    I: Copy + Default,
+    O: Copy + Default,
+    S: ChunkSource<I>,
+    K: ChunkSink<O>,
+    F: ChunkOp<I, O>,
+{
+    let (width, height) = source.size();
+    let size = size.max(1);
+    let halo = op.halo();
+
+    for y in (0..height).step_by(size) {
+        for x in (0..width).step_by(size) {
+            let (w, h) = (size.min(width - x), size.min(height - y));
+            let (x0, y0) = (x.saturating_sub(halo), y.saturating_sub(halo));
+            let x1 = (x + w + halo).min(width);
+            let y1 = (y + h + halo).min(height);
+
+            let input = try!(source.read(x0, y0, x1 - x0, y1 - y0));
+            let mut output = Texture::blank(input.width, input.height);
+            op.apply(&input, &mut output);
+
+            let interior = try!(output.read(x - x0, y - y0, w, h));
+            try!(sink.write(x, y, &interior));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ops::{operator3x3, EdgePolicy};
+
+    fn blur(input: &Texture<f64>, output: &mut Texture<f64>) {
+        operator3x3(input, output, EdgePolicy::Mirror, |values| {
+            values.iter().sum::<f64>() / 9.0
+        });
+    }
+
+    #[test]
+    fn matching_whole_textures() {
+        let values = (0..70).map(|i| f64::from(i * i % 17)).collect();
+        let mut input = Texture::new(10, 7, values);
+        let mut expected = Texture::blank(10, 7);
+        blur(&input, &mut expected);
+
+        let mut output = Texture::blank(10, 7);
+        let mut op = WithHalo(1, blur);
+        process_chunks(&mut input, &mut output, 4, &mut op).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn pixel_wise_operations() {
+        let mut input = Texture::new(3, 2, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let mut output = Texture::blank(3, 2);
+        let mut op = |input: &Texture<f64>, output: &mut Texture<f64>| {
+            for (o, i) in output.buffer.iter_mut().zip(&input.buffer) {
+                *o = i * 2.0;
+            }
+        };
+        process_chunks(&mut input, &mut output, 2, &mut op).unwrap();
+        assert_eq!(output.buffer, vec![2.0, 4.0, 6.0, 8.0, 10.0, 12.0]);
+    }
+}
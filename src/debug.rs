@@ -0,0 +1,243 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use lights::Light;
+use math::{Ray, Vec3};
+use scene::Object;
+use shaders::{RayType, Shader, TraceInfo, Tracer};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A shader evaluated while shading a sample
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ShaderDebug {
+    /// Index of the shader in the scene
+    pub shader: usize,
+    /// Number of shaders wrapping this one
+    pub depth: usize,
+    /// The color returned by the shader
+    pub color: Vec3,
+}
+
+/// The surface hit by a sample
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HitDebug {
+    /// Index of the object in the scene
+    pub object: usize,
+    /// Index of the objects primitive
+    pub primitive: usize,
+    pub t: f64,
+    pub normal: Vec3,
+    pub position: Vec3,
+}
+
+/// Diagnostics for a single sample within a pixel
+#[derive(Clone, Debug, PartialEq)]
+pub struct SampleDebug {
+    /// Offset of the sample within the pixel
+    pub u: f64,
+    pub v: f64,
+    pub ray: Ray,
+    pub hit: Option<HitDebug>,
+    /// Shaders in the order they were called, outermost first
+    pub shaders: Vec<ShaderDebug>,
+    pub color: Vec3,
+}
+
+/// Diagnostics for all samples of a pixel
+#[derive(Clone, Debug, PartialEq)]
+pub struct PixelDebug {
+    pub x: usize,
+    pub y: usize,
+    pub samples: Vec<SampleDebug>,
+    pub color: Vec3,
+}
+
+#[derive(Default)]
+struct ShaderLog {
+    depth: usize,
+    entries: Vec<ShaderDebug>,
+}
+
+/// Records the color returned by a shader before passing it on
+struct RecordingShader<'a> {
+    index: usize,
+    inner: &'a Shader,
+    log: Rc<RefCell<ShaderLog>>,
+}
+
+impl<'a> Shader for RecordingShader<'a> {
+    fn shade(&self, tracer: &Tracer, info: &TraceInfo) -> Vec3 {
+        let entry = {
+            let mut log = self.log.borrow_mut();
+            let depth = log.depth;
+            log.depth += 1;
+            log.entries.push(ShaderDebug {
+                shader: self.index,
+                depth,
+                color: Vec3::zeros(),
+            });
+            log.entries.len() - 1
+        };
+
+        let color = self.inner.shade(tracer, info);
+
+        let mut log = self.log.borrow_mut();
+        log.depth -= 1;
+        log.entries[entry].color = color;
+        color
+    }
+}
+
+/// A tracer that records every shader called through it
+pub struct DebugTracer<'a> {
+    tracer: &'a Tracer,
+    shaders: Vec<RecordingShader<'a>>,
+    log: Rc<RefCell<ShaderLog>>,
+}
+
+impl<'a> DebugTracer<'a> {
+    pub fn new(tracer: &'a Tracer, shaders: &[&'a Shader]) -> DebugTracer<'a> {
+        let log = Rc::new(RefCell::new(ShaderLog::default()));
+        let shaders = shaders
+            .iter()
+            .enumerate()
+            .map(|(index, inner)| RecordingShader {
+                index,
+                inner: *inner,
+                log: log.clone(),
+            })
+            .collect();
+        DebugTracer {
+            tracer,
+            shaders,
+            log,
+        }
+    }
+
+    /// Return the shaders recorded since the last call
+    pub fn take(&self) -> Vec<ShaderDebug> {
+        let mut log = self.log.borrow_mut();
+        log.depth = 0;
+        log.entries.drain(..).collect()
+    }
+}
+
+impl<'a> Tracer for DebugTracer<'a> {
+    fn trace_pixel(&self, kind: RayType, x: f64, y: f64) -> Option<TraceInfo> {
+        self.tracer.trace_pixel(kind, x, y)
+    }
+
+    fn trace_ray(
+        &self,
+        kind: RayType,
+        ray: Ray,
+        x: f64,
+        y: f64,
+    ) -> Option<TraceInfo> {
+        self.tracer.trace_ray(kind, ray, x, y)
+    }
+
+    fn secondary_ray(&self, info: &TraceInfo, direction: Vec3) -> Ray {
+        self.tracer.secondary_ray(info, direction)
+    }
+
+    fn shader(&self, index: usize) -> Option<&Shader> {
+        self.shaders.get(index).map(|shader| shader as &Shader)
+    }
+
+    fn light(&self, index: usize) -> Option<&Light> {
+        self.tracer.light(index)
+    }
+
+    fn object(&self, index: usize) -> Option<&Object> {
+        self.tracer.object(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NullTracer;
+
+    impl Tracer for NullTracer {
+        fn trace_pixel(&self, _: RayType, _: f64, _: f64) -> Option<TraceInfo> {
+            None
+        }
+
+        fn trace_ray(
+            &self,
+            _: RayType,
+            _: Ray,
+            _: f64,
+            _: f64,
+        ) -> Option<TraceInfo> {
+            None
+        }
+
+        fn secondary_ray(&self, _: &TraceInfo, direction: Vec3) -> Ray {
+            Ray::new(Vec3::zeros(), direction)
+        }
+
+        fn shader(&self, _: usize) -> Option<&Shader> {
+            None
+        }
+
+        fn light(&self, _: usize) -> Option<&Light> {
+            None
+        }
+    }
+
+    struct Constant(Vec3);
+
+    impl Shader for Constant {
+        fn shade(&self, _: &Tracer, _: &TraceInfo) -> Vec3 {
+            self.0
+        }
+    }
+
+    struct Halve(usize);
+
+    impl Shader for Halve {
+        fn shade(&self, tracer: &Tracer, info: &TraceInfo) -> Vec3 {
+            tracer.shader(self.0).unwrap().shade(tracer, info) * 0.5
+        }
+    }
+
+    #[test]
+    fn records_shader_chain() {
+        let constant = Constant(Vec3::new(1.0, 1.0, 1.0));
+        let halve = Halve(0);
+        let tracer = DebugTracer::new(&NullTracer, &[&constant, &halve]);
+        let info = TraceInfo {
+            ray: Ray::default(),
+            intersection: Default::default(),
+            primitive: 0,
+            x: 0.0,
+            y: 0.0,
+        };
+
+        tracer.shader(1).unwrap().shade(&tracer, &info);
+        let shaders = tracer.take();
+        assert_eq!(shaders.len(), 2);
+        assert_eq!((shaders[0].shader, shaders[0].depth), (1, 0));
+        assert_eq!(shaders[0].color, Vec3::new(0.5, 0.5, 0.5));
+        assert_eq!((shaders[1].shader, shaders[1].depth), (0, 1));
+        assert_eq!(shaders[1].color, Vec3::new(1.0, 1.0, 1.0));
+        assert!(tracer.take().is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use lights::Light;
+use math::{Ray, Vec3};
+use scene::Object;
+use shaders::{RayType, Shader, TraceInfo, Tracer};
+
+use std::cell::Cell;
+use std::str::FromStr;
+
+/// Depth at which the depth mode fades to black
+pub const MAX_DEPTH: f64 = 1.0e6;
+
+/// Number of quadtree nodes visited that maps to the top of the heat scale
+pub const MAX_TRAVERSAL_COST: f64 = 4096.0;
+
+/// Number of rays traced for a sample that maps to the top of the heat scale
+pub const MAX_RAYS_PER_SAMPLE: f64 = 256.0;
+
+/// What the renderer outputs for each sample
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RenderMode {
+    /// Colors from the scene shaders
+    Shaded,
+    /// World space surface normals
+    Normals,
+    /// Distance along the camera ray, on a logarithmic scale
+    Depth,
+    /// A distinct color for each object
+    ObjectId,
+    /// Number of quadtree nodes visited by the camera ray
+    QuadtreeCost,
+    /// Number of rays traced while shading, including the camera ray
+    SampleHeatmap,
+}
+
+impl Default for RenderMode {
+    fn default() -> RenderMode {
+        RenderMode::Shaded
+    }
+}
+
+impl FromStr for RenderMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<RenderMode, String> {
+        match mode {
+            "shaded" => Ok(RenderMode::Shaded),
+            "normals" => Ok(RenderMode::Normals),
+            "depth" => Ok(RenderMode::Depth),
+            "object-id" => Ok(RenderMode::ObjectId),
+            "quadtree-cost" => Ok(RenderMode::QuadtreeCost),
+            "sample-heatmap" => Ok(RenderMode::SampleHeatmap),
+            _ => Err(format!("Unknown render mode '{}'", mode)),
+        }
+    }
+}
+
+/// Return a fully saturated color for a hue in the range 0 to 1
+pub fn hue(h: f64) -> Vec3 {
+    let h = h.fract() * 6.0;
+    let channel = |c: f64| c.min(1.0).max(0.0);
+    Vec3::new(
+        channel((h - 3.0).abs() - 1.0),
+        channel(2.0 - (h - 2.0).abs()),
+        channel(2.0 - (h - 4.0).abs()),
+    )
+}
+
+/// Return a color ranging from blue at 0 to red at 1
+pub fn heat(t: f64) -> Vec3 {
+    hue((1.0 - t.min(1.0).max(0.0)) * 2.0 / 3.0)
+}
+
+/// Return a color for a count on a logarithmic heat scale
+pub fn heat_log(count: usize, max: f64) -> Vec3 {
+    heat((count as f64).max(1.0).ln() / max.ln())
+}
+
+/// Return a color that is distinct from those of neighbouring indices
+pub fn index_color(index: usize) -> Vec3 {
+    hue(index as f64 * 0.618_033_988_749_895)
+}
+
+/// A tracer that counts the rays traced through it
+pub struct CountingTracer<'a> {
+    tracer: &'a Tracer,
+    pub rays: Cell<usize>,
+}
+
+impl<'a> CountingTracer<'a> {
+    pub fn new(tracer: &'a Tracer) -> CountingTracer<'a> {
+        CountingTracer {
+            tracer,
+            rays: Cell::new(0),
+        }
+    }
+}
+
+impl<'a> Tracer for CountingTracer<'a> {
+    fn trace_pixel(&self, kind: RayType, x: f64, y: f64) -> Option<TraceInfo> {
+        self.rays.set(self.rays.get() + 1);
+        self.tracer.trace_pixel(kind, x, y)
+    }
+
+    fn trace_ray(
+        &self,
+        kind: RayType,
+        ray: Ray,
+        x: f64,
+        y: f64,
+    ) -> Option<TraceInfo> {
+        self.rays.set(self.rays.get() + 1);
+        self.tracer.trace_ray(kind, ray, x, y)
+    }
+
+    fn secondary_ray(&self, info: &TraceInfo, direction: Vec3) -> Ray {
+        self.tracer.secondary_ray(info, direction)
+    }
+
+    fn shader(&self, index: usize) -> Option<&Shader> {
+        self.tracer.shader(index)
+    }
+
+    fn light(&self, index: usize) -> Option<&Light> {
+        self.tracer.light(index)
+    }
+
+    fn object(&self, index: usize) -> Option<&Object> {
+        self.tracer.object(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heat_scale() {
+        assert_eq!(heat(0.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(heat(1.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(heat(2.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(heat_log(1, 16.0), Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn parse_render_mode() {
+        assert_eq!("object-id".parse(), Ok(RenderMode::ObjectId));
+        assert!("shadows".parse::<RenderMode>().is_err());
+    }
+}
@@ -13,197 +13,931 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
+use chunks::ChunkSink;
 use math::Vec3;
-use ops::{blit, blit_region};
-use render::Renderer;
-use textures::{Texture, TileIterator};
+use ops::blit_region;
+use progress::ProgressSink;
+use render::{clear_shadow_cache, Renderer};
+use textures::{Texture, Tile};
 
-use std::io::{self, Write};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+#[cfg(feature = "rayon")]
+use rayon::ThreadPoolBuilder;
+
+use std::cmp;
+use std::io;
+use std::mem;
+use std::str::FromStr;
+#[cfg(not(feature = "rayon"))]
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
 use std::sync::mpsc::{channel, Sender};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
-struct ProgressCounter {
-    out: io::Stdout,
-    total: usize,
-    count: usize,
-    percent: usize,
-    width: usize,
-    start: Instant,
-    elapsed: Duration,
-}
-
-impl ProgressCounter {
-    pub fn new(width: usize, total: usize) -> ProgressCounter {
-        ProgressCounter {
-            width,
-            out: io::stdout(),
-            total,
-            count: 0,
-            percent: 0,
-            start: Instant::now(),
-            elapsed: Duration::new(0, 0),
-        }
-    }
-
-    fn format_duration(&self, dur: Duration) -> String {
-        let secs = dur.as_secs();
-        let ms = f64::from(dur.subsec_nanos()) / 1_000_000.0;
-
-        if secs > 3600 {
-            let mins = secs / 60;
-            let secs = secs % 60;
-            let hrs = mins / 60;
-            let mins = mins % 60;
-            format!("{}hrs {}mins {:2}secs {:5.2}ms", hrs, mins, secs, ms)
-        } else if secs > 60 {
-            let mins = secs / 60;
-            let secs = secs % 60;
-            format!("{}mins {:2}secs {:5.2}ms", mins, secs, ms)
-        } else if secs > 0 {
-            format!("{:2}secs {:5.2}ms", secs, ms)
-        } else {
-            format!("{:.2}ms", ms)
+/// Smallest and largest sizes of a tile chosen automatically
+const MIN_TILE_SIZE: usize = 8;
+const MAX_TILE_SIZE: usize = 64;
+
+/// Tiles per thread, so threads finishing early find others to render
+const TILES_PER_THREAD: usize = 16;
+
+/// Number of threads and size of tiles with which to render an image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderConfig {
+    pub threads: usize,
+    pub tile_size: usize,
+}
+
+impl RenderConfig {
+    /// Return a configuration for an image of a size, using all available
+    /// cores and tiles small enough to keep them all busy
+    pub fn detect(width: usize, height: usize) -> RenderConfig {
+        let threads = thread::available_parallelism()
+            .map(|count| count.get())
+            .unwrap_or(1);
+        RenderConfig {
+            threads,
+            tile_size: tile_size(width, height, threads),
         }
     }
 
-    fn print_bar(&mut self) {
-        let dots =
-            (self.percent as f64 / 100.0 * self.width as f64).floor() as usize;
-        print!("\r");
-        for _ in 0..dots {
-            print!("#");
+    /// Replace detected values with those given explicitly
+    pub fn with(
+        self,
+        threads: Option<usize>,
+        tile_size: Option<usize>,
+    ) -> RenderConfig {
+        RenderConfig {
+            threads: threads.unwrap_or(self.threads).max(1),
+            tile_size: tile_size.unwrap_or(self.tile_size).max(1),
         }
-        for _ in 0..self.width - dots {
-            print!(".");
+    }
+}
+
+/// Return the largest power of two tile size giving each thread a number of
+/// tiles to render
+fn tile_size(width: usize, height: usize, threads: usize) -> usize {
+    let pixels = width * height / (threads * TILES_PER_THREAD).max(1);
+    let side = (pixels as f64).sqrt() as usize;
+    let mut size = MIN_TILE_SIZE;
+    while size * 2 <= side && size < MAX_TILE_SIZE {
+        size *= 2;
+    }
+    size
+}
+
+/// Order in which the tiles of an image are rendered
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TileOrder {
+    /// Rows of tiles from top to bottom
+    Scanline,
+    /// Tiles nearest the center of the image first
+    CenterOut,
+    /// Tiles along a Hilbert curve, keeping neighbouring tiles close in time
+    Hilbert,
+    /// Tiles in a fixed pseudo random order
+    Random,
+}
+
+impl Default for TileOrder {
+    fn default() -> TileOrder {
+        TileOrder::Scanline
+    }
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(order: &str) -> Result<TileOrder, String> {
+        match order {
+            "scanline" => Ok(TileOrder::Scanline),
+            "center-out" => Ok(TileOrder::CenterOut),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            "random" => Ok(TileOrder::Random),
+            _ => Err(format!("Unknown tile order '{}'", order)),
         }
-        print!(
-            " {:3}% Elapsed {}",
-            self.percent,
-            self.format_duration(self.elapsed)
-        );
     }
+}
 
-    pub fn update(&mut self, count: usize) {
-        self.count = count;
-        self.elapsed = self.start.elapsed();
-        let percent =
-            (self.count as f64 / self.total as f64 * 100.0).floor() as usize;
-        if percent != self.percent {
-            self.percent = percent;
-            self.print_bar();
-            self.out.flush().unwrap();
+/// Return the distance along a Hilbert curve filling a square grid, of a
+/// power of two size, of a cell
+fn hilbert_index(size: usize, mut x: usize, mut y: usize) -> usize {
+    let mut index = 0;
+    let mut s = size / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as usize;
+        let ry = (y & s > 0) as usize;
+        index += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            mem::swap(&mut x, &mut y);
         }
+        s /= 2;
     }
+    index
+}
 
-    pub fn finish(&mut self) {
-        print!("\r");
-        let elapsed = self.start.elapsed();
-        print!("\r");
-        for _ in 0..80 {
-            print!(" ");
+/// Sort the tiles of an image, all of a size except at its edges
+fn order_tiles(
+    tiles: &mut Vec<Tile>,
+    order: TileOrder,
+    width: usize,
+    height: usize,
+    size: usize,
+) {
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::CenterOut => {
+            let distance = |tile: &Tile| {
+                let dx = (2 * tile.x + tile.width) as f64 - width as f64;
+                let dy = (2 * tile.y + tile.height) as f64 - height as f64;
+                dx * dx + dy * dy
+            };
+            tiles
+                .sort_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap());
+        }
+        TileOrder::Hilbert => {
+            let size = size.max(1);
+            let cells = (cmp::max(width, height) + size - 1) / size;
+            let grid = cells.next_power_of_two();
+            tiles.sort_by_key(|tile| {
+                hilbert_index(grid, tile.x / size, tile.y / size)
+            });
+        }
+        TileOrder::Random => {
+            // A fixed xorshift sequence, so renders are reproducible
+            let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+            for i in (1..tiles.len()).rev() {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                tiles.swap(i, (state % (i as u64 + 1)) as usize);
+            }
         }
-        print!("\r");
-        println!("Completed in {}", self.format_duration(elapsed));
-        self.out.flush().unwrap();
     }
 }
 
-pub fn render(image: &mut Texture<Vec3>, renderer: &Renderer) {
-    let mut progress = ProgressCounter::new(30, image.width * image.height);
+pub fn render(
+    image: &mut Texture<Vec3>,
+    renderer: &Renderer,
+    progress: &mut ProgressSink,
+) {
+    let total = image.width * image.height;
     let mut completed = 0;
 
     for y in 0..image.height {
+        clear_shadow_cache();
         for x in 0..image.width {
             let color = renderer.pixel(x, y);
             image.write1x1(x, y, color);
             completed += 1;
-            progress.update(completed);
+            progress.update(completed, total);
         }
     }
 
     progress.finish();
 }
 
-struct RenderState {
-    surface: Texture<Vec3>,
-    tiles: TileIterator,
+/// Shared state used to control a render from another thread
+#[derive(Default)]
+struct RenderControl {
+    cancelled: AtomicBool,
+    paused: AtomicBool,
+    lock: Mutex<()>,
+    resumed: Condvar,
+    /// Time after which the render is cancelled
+    deadline: Option<Instant>,
 }
 
-fn worker(
-    state: &Arc<Mutex<RenderState>>,
-    renderer: &Renderer,
-    sender: &Sender<usize>,
-    tile_size: usize,
-) {
-    let mut local = Texture::blank(tile_size, tile_size);
-    let mut work = { state.lock().unwrap().tiles.next() };
-
-    while let Some(tile) = work {
-        for y in 0..tile.height {
-            for x in 0..tile.width {
-                let pixel = renderer.pixel(tile.x + x, tile.y + y);
-                local.write1x1(x, y, pixel);
+impl RenderControl {
+    fn cancel(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.cancelled.store(true, SeqCst);
+        self.resumed.notify_all();
+    }
+
+    fn pause(&self) {
+        self.paused.store(true, SeqCst);
+    }
+
+    fn resume(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.paused.store(false, SeqCst);
+        self.resumed.notify_all();
+    }
+
+    /// Block while the render is paused, returning false once cancelled or
+    /// past the deadline
+    fn proceed(&self) -> bool {
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                self.cancel();
             }
         }
-        {
-            let mut state_ = state.lock().unwrap();
-            blit_region(
-                &local,
-                &mut state_.surface,
-                tile.x,
-                tile.y,
-                tile.width,
-                tile.height,
-            );
-            work = state_.tiles.next();
+        if self.paused.load(SeqCst) {
+            let mut guard = self.lock.lock().unwrap();
+            while self.paused.load(SeqCst) && !self.cancelled.load(SeqCst) {
+                guard = self.resumed.wait(guard).unwrap();
+            }
         }
-        sender.send(tile.width * tile.height).unwrap();
+        !self.cancelled.load(SeqCst)
     }
 }
 
-pub fn render_threaded(
-    output: &mut Texture<Vec3>,
+/// A handle to a render running in the background
+pub struct RenderHandle {
+    control: Arc<RenderControl>,
+    thread: thread::JoinHandle<Option<Texture<Vec3>>>,
+}
+
+impl RenderHandle {
+    /// Stop the render as soon as possible, discarding the output
+    pub fn cancel(&self) {
+        self.control.cancel();
+    }
+
+    /// Suspend all workers at their next scanline
+    pub fn pause(&self) {
+        self.control.pause();
+    }
+
+    pub fn resume(&self) {
+        self.control.resume();
+    }
+
+    /// Wait for the render to finish, returning `None` if it was cancelled
+    pub fn join(self) -> Option<Texture<Vec3>> {
+        self.thread.join().unwrap()
+    }
+}
+
+/// Render a single tile into its own buffer
+fn render_tile(
     renderer: &Renderer,
-    num_workers: usize,
-    tile_size: usize,
-) {
-    let width = output.width;
-    let height = output.height;
-    let total = width * height;
+    control: &RenderControl,
+    tile: Tile,
+) -> Option<Texture<Vec3>> {
+    let mut local = Texture::blank(tile.width, tile.height);
+    clear_shadow_cache();
+    for y in 0..tile.height {
+        if !control.proceed() {
+            return None;
+        }
+        for x in 0..tile.width {
+            let pixel = renderer.pixel(tile.x + x, tile.y + y);
+            local.write1x1(x, y, pixel);
+        }
+    }
+    Some(local)
+}
 
-    let state = Arc::new(Mutex::new(RenderState {
-        surface: Texture::blank(width, height),
-        tiles: output.tiles(tile_size),
-    }));
+/// Render tiles on a pool of threads, sending each tile as it completes,
+/// returning false if the render was cancelled
+#[cfg(not(feature = "rayon"))]
+fn render_tiles(
+    renderer: &Renderer,
+    control: &Arc<RenderControl>,
+    tiles: Vec<Tile>,
+    num_workers: usize,
+    sender: &Sender<(Tile, Texture<Vec3>)>,
+) -> bool {
+    let tiles = Arc::new(tiles);
+    let next = Arc::new(AtomicUsize::new(0));
 
-    let (sender, receiver) = channel();
     let mut workers = Vec::with_capacity(num_workers);
     for _ in 0..num_workers {
-        let state_ = state.clone();
+        let tiles_ = tiles.clone();
+        let next_ = next.clone();
         let renderer_ = renderer.clone();
+        let control_ = control.clone();
         let sender_ = sender.clone();
         workers.push(thread::spawn(move || {
-            worker(&state_, &renderer_, &sender_, tile_size);
+            while let Some(tile) = tiles_.get(next_.fetch_add(1, SeqCst)) {
+                match render_tile(&renderer_, &control_, *tile) {
+                    Some(local) => sender_.send((*tile, local)).unwrap(),
+                    None => break,
+                }
+            }
         }));
     }
 
-    let mut progress = ProgressCounter::new(30, total);
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    !control.cancelled.load(SeqCst)
+}
+
+/// Render tiles on a pool of threads, sending each tile as it completes,
+/// returning false if the render was cancelled
+#[cfg(feature = "rayon")]
+fn render_tiles(
+    renderer: &Renderer,
+    control: &Arc<RenderControl>,
+    tiles: Vec<Tile>,
+    num_workers: usize,
+    sender: &Sender<(Tile, Texture<Vec3>)>,
+) -> bool {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(num_workers)
+        .build()
+        .unwrap();
+
+    pool.install(|| {
+        tiles
+            .into_par_iter()
+            .map_with(sender.clone(), |sender, tile| {
+                let rendered = render_tile(renderer, control, tile)?;
+                sender.send((tile, rendered)).unwrap();
+                Some(())
+            })
+            .collect::<Option<Vec<()>>>()
+            .is_some()
+    })
+}
+
+/// Render tiles on worker threads, handing each to a function on this one as
+/// it completes and reporting progress, returning false if the render was
+/// cancelled
+fn render_each<F>(
+    renderer: &Renderer,
+    control: &Arc<RenderControl>,
+    tiles: Vec<Tile>,
+    num_workers: usize,
+    progress: &mut ProgressSink,
+    mut each: F,
+) -> bool
+where
+    F: FnMut(Tile, Texture<Vec3>),
+{
+    let total = tiles.iter().map(|tile| tile.width * tile.height).sum();
+
+    let (sender, receiver) = channel();
+    let renderer_ = renderer.clone();
+    let control_ = control.clone();
+    let workers = thread::spawn(move || {
+        render_tiles(&renderer_, &control_, tiles, num_workers, &sender)
+    });
+
+    let mut completed = 0;
+    while let Ok((tile, local)) = receiver.recv() {
+        completed += tile.width * tile.height;
+        progress.tile(tile.x, tile.y, &local);
+        progress.update(completed, total);
+        each(tile, local);
+    }
+
+    if !workers.join().unwrap() {
+        return false;
+    }
+
+    progress.finish();
+    true
+}
+
+/// Render tiles on worker threads into an image, or into the rows of one
+/// from a row at its top, reporting progress from this one, returning false
+/// if the render was cancelled
+fn render_tiled(
+    renderer: &Renderer,
+    control: &Arc<RenderControl>,
+    output: &mut Texture<Vec3>,
+    top: usize,
+    num_workers: usize,
+    tile_size: usize,
+    order: TileOrder,
+    progress: &mut ProgressSink,
+) -> bool {
+    let (width, height) = (output.width, output.height);
+    let mut tiles: Vec<Tile> = output.tiles(tile_size).collect();
+    order_tiles(&mut tiles, order, width, height, tile_size);
+    for tile in &mut tiles {
+        tile.y += top;
+    }
+
+    render_each(
+        renderer,
+        control,
+        tiles,
+        num_workers,
+        progress,
+        |tile, local| {
+            let (x, y) = (tile.x, tile.y - top);
+            blit_region(&local, output, x, y, tile.width, tile.height);
+        },
+    )
+}
+
+/// Return the number of tiles of a size covering an image
+pub fn tile_count(width: usize, height: usize, tile_size: usize) -> usize {
+    let size = tile_size.max(1);
+    ((width + size - 1) / size) * ((height + size - 1) / size)
+}
+
+/// Return tiles of an image from a start index up to an end index, numbered
+/// in rows from the top left, so the same tiles are found on any machine
+fn tile_range(
+    width: usize,
+    height: usize,
+    tile_size: usize,
+    start: usize,
+    end: usize,
+) -> Vec<Tile> {
+    let size = tile_size.max(1);
+    let columns = (width + size - 1) / size;
+    let end = cmp::min(end, tile_count(width, height, size));
+    (start..cmp::max(start, end))
+        .map(|i| {
+            let (x, y) = (i % columns * size, i / columns * size);
+            let w = cmp::min(size, width - x);
+            let h = cmp::min(size, height - y);
+            Tile::new(x, y, w, h)
+        })
+        .collect()
+}
+
+/// Render a range of the tiles of an image, returning each with its pixels,
+/// so machines each rendering a range can later merge their tiles
+pub fn render_range(
+    renderer: &Renderer,
+    width: usize,
+    height: usize,
+    tile_size: usize,
+    range: (usize, usize),
+    num_workers: usize,
+    progress: &mut ProgressSink,
+) -> Vec<(Tile, Texture<Vec3>)> {
+    let control = Arc::new(RenderControl::default());
+    let tiles = tile_range(width, height, tile_size, range.0, range.1);
+    let mut rendered = Vec::with_capacity(tiles.len());
+    render_each(
+        renderer,
+        &control,
+        tiles,
+        num_workers,
+        progress,
+        |tile, local| {
+            rendered.push((tile, local));
+        },
+    );
+    rendered.sort_by_key(|&(tile, _)| (tile.y, tile.x));
+    rendered
+}
+
+/// Start rendering in the background, reporting progress as tiles complete
+pub fn render_async<P>(
+    renderer: &Renderer,
+    width: usize,
+    height: usize,
+    num_workers: usize,
+    tile_size: usize,
+    order: TileOrder,
+    mut progress: P,
+) -> RenderHandle
+where
+    P: ProgressSink + Send + 'static,
+{
+    let control = Arc::new(RenderControl::default());
+    let control_ = control.clone();
+    let renderer_ = renderer.clone();
+
+    let thread = thread::spawn(move || {
+        let mut output = Texture::blank(width, height);
+        let completed = render_tiled(
+            &renderer_,
+            &control_,
+            &mut output,
+            0,
+            num_workers,
+            tile_size,
+            order,
+            &mut progress,
+        );
+        if completed {
+            Some(output)
+        } else {
+            None
+        }
+    });
+
+    RenderHandle { control, thread }
+}
+
+pub fn render_threaded(
+    output: &mut Texture<Vec3>,
+    renderer: &Renderer,
+    num_workers: usize,
+    tile_size: usize,
+    order: TileOrder,
+    progress: &mut ProgressSink,
+) {
+    let control = Arc::new(RenderControl::default());
+    render_tiled(
+        renderer,
+        &control,
+        output,
+        0,
+        num_workers,
+        tile_size,
+        order,
+        progress,
+    );
+}
+
+/// Return the sample counts of passes increasing fourfold up to a number of
+/// samples
+fn sample_passes(samples: usize) -> Vec<usize> {
+    let mut passes = vec![];
+    let mut pass = 1;
+    while pass < samples {
+        passes.push(pass);
+        pass *= 4;
+    }
+    passes.push(samples.max(1));
+    passes
+}
+
+/// Render passes of increasing samples, up to those of the renderer, until
+/// a time limit is reached, leaving each tile of the output with the most
+/// samples it was rendered with and returning the samples of the last
+/// completed pass, or zero if no pass completed
+pub fn render_progressive(
+    output: &mut Texture<Vec3>,
+    renderer: &Renderer,
+    num_workers: usize,
+    tile_size: usize,
+    order: TileOrder,
+    limit: Option<Duration>,
+    progress: &mut ProgressSink,
+) -> usize {
+    let control = Arc::new(RenderControl {
+        deadline: limit.map(|limit| Instant::now() + limit),
+        ..Default::default()
+    });
+
+    let pixels = output.width * output.height;
+    let passes = sample_passes(renderer.samples());
+    let total = pixels * passes.len();
     let mut completed = 0;
-    while let Ok(rendered) = receiver.recv() {
-        completed += rendered;
-        progress.update(completed);
-        if completed == total {
+    for (index, samples) in passes.into_iter().enumerate() {
+        let mut pass = renderer.clone();
+        pass.set_samples(samples);
+        let finished = render_tiled(
+            &pass,
+            &control,
+            output,
+            0,
+            num_workers,
+            tile_size,
+            order,
+            &mut StripProgress {
+                progress,
+                before: index * pixels,
+                total,
+            },
+        );
+        if !finished {
             break;
         }
+        completed = samples;
     }
+    progress.finish();
+    completed
+}
 
-    for worker in workers {
-        worker.join().unwrap();
+/// Reports the progress of a strip of an image, or a pass over it, as part
+/// of the whole render
+struct StripProgress<'a> {
+    progress: &'a mut ProgressSink,
+    before: usize,
+    total: usize,
+}
+
+impl<'a> ProgressSink for StripProgress<'a> {
+    fn update(&mut self, completed: usize, _: usize) {
+        self.progress.update(self.before + completed, self.total);
+    }
+
+    fn tile(&mut self, x: usize, y: usize, pixels: &Texture<Vec3>) {
+        self.progress.tile(x, y, pixels);
     }
+}
 
-    let state = state.lock().unwrap();
-    blit(&state.surface, output, 0, 0);
+/// Render an image in horizontal strips of a number of rows, writing each to
+/// a sink as it completes, so only one strip is held in memory at a time
+pub fn render_strips(
+    renderer: &Renderer,
+    width: usize,
+    height: usize,
+    rows: usize,
+    num_workers: usize,
+    tile_size: usize,
+    order: TileOrder,
+    sink: &mut ChunkSink<Vec3>,
+    progress: &mut ProgressSink,
+) -> io::Result<()> {
+    let control = Arc::new(RenderControl::default());
+    let mut top = 0;
+    while top < height {
+        let mut strip =
+            Texture::blank(width, cmp::min(rows.max(1), height - top));
+        render_tiled(
+            renderer,
+            &control,
+            &mut strip,
+            top,
+            num_workers,
+            tile_size,
+            order,
+            &mut StripProgress {
+                progress,
+                before: top * width,
+                total: width * height,
+            },
+        );
+        try!(sink.write(0, top, &strip));
+        top += strip.height;
+    }
     progress.finish();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cameras::OrthographicCamera;
+    use diagnostics::RenderMode;
+    use primitives::Sphere;
+    use scene::{Background, Object, Scene};
+
+    #[test]
+    fn choosing_tile_sizes() {
+        assert_eq!(tile_size(1920, 1080, 4), 64);
+        assert_eq!(tile_size(640, 480, 8), 32);
+        assert_eq!(tile_size(64, 64, 16), 8);
+
+        let config = RenderConfig::detect(640, 480).with(Some(0), Some(12));
+        assert_eq!(config.threads, 1);
+        assert_eq!(config.tile_size, 12);
+    }
+
+    /// Return the origins of the tiles of an image in an order
+    fn ordered(
+        order: TileOrder,
+        width: usize,
+        height: usize,
+    ) -> Vec<(usize, usize)> {
+        let mut image: Texture<f64> = Texture::blank(width, height);
+        let mut tiles: Vec<Tile> = image.tiles(2).collect();
+        order_tiles(&mut tiles, order, width, height, 2);
+        tiles.iter().map(|tile| (tile.x, tile.y)).collect()
+    }
+
+    #[test]
+    fn ordering_tiles() {
+        assert_eq!(
+            ordered(TileOrder::Scanline, 4, 4),
+            vec![(0, 0), (2, 0), (0, 2), (2, 2)]
+        );
+        assert_eq!(ordered(TileOrder::CenterOut, 6, 6)[0], (2, 2));
+        assert_eq!(
+            ordered(TileOrder::Hilbert, 4, 4),
+            vec![(0, 0), (0, 2), (2, 2), (2, 0)]
+        );
+
+        let mut random = ordered(TileOrder::Random, 8, 6);
+        assert_eq!(random, ordered(TileOrder::Random, 8, 6));
+        random.sort();
+        let mut scanline = ordered(TileOrder::Scanline, 8, 6);
+        scanline.sort();
+        assert_eq!(random, scanline);
+    }
+
+    #[test]
+    fn cancel_wakes_paused_workers() {
+        let control = Arc::new(RenderControl::default());
+        control.pause();
+
+        let control_ = control.clone();
+        let worker = thread::spawn(move || control_.proceed());
+        control.cancel();
+        assert_eq!(worker.join().unwrap(), false);
+
+        control.resume();
+        assert_eq!(control.proceed(), false);
+    }
+
+    /// Return a renderer of the normals of a sphere, in an image of a size
+    fn renderer(width: usize, height: usize) -> Renderer {
+        let scene = Scene {
+            background: Background::Color(Vec3::zeros()),
+            camera: Arc::new(OrthographicCamera::new(
+                width,
+                height,
+                Vec3::new(0.0, 10.0, 0.0),
+                Vec3::zeros(),
+                1.0,
+                Vec3::new(0.0, 0.0, 1.0),
+                4.0,
+            )),
+            shaders: vec![],
+            primitives: vec![Arc::new(Sphere::new(Vec3::zeros(), 1.5))],
+            objects: vec![Object::new(0, 0)],
+            lights: vec![],
+            linework: vec![],
+            labels: vec![],
+            edges: None,
+            ray_epsilon: 0.0,
+            normal_offset: 0.0,
+            face_forward: true,
+            shadow_cache: None,
+            clip_elevation: None,
+            water: None,
+        };
+        let mut renderer = Renderer::new(1, scene);
+        renderer.set_mode(RenderMode::Normals);
+        renderer
+    }
+
+    #[test]
+    fn distributing_tiles() {
+        let renderer = renderer(7, 9);
+        let mut image: Texture<f64> = Texture::blank(7, 9);
+        let tiles: Vec<Tile> = image.tiles(2).collect();
+
+        // Each tile is rendered once, however many workers share them
+        for &workers in &[1, 3, 64] {
+            let control = Arc::new(RenderControl::default());
+            let mut rendered = vec![];
+            let finished = render_each(
+                &renderer,
+                &control,
+                tiles.clone(),
+                workers,
+                &mut |_, _| {},
+                |tile, local| rendered.push((tile, local.width, local.height)),
+            );
+            assert!(finished);
+            rendered.sort_by_key(|&(tile, _, _)| (tile.y, tile.x));
+            let expected: Vec<_> = tiles
+                .iter()
+                .map(|&tile| (tile, tile.width, tile.height))
+                .collect();
+            assert_eq!(rendered, expected);
+        }
+    }
+
+    /// Records the calls made to a progress sink
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<(&'static str, usize, usize)>,
+    }
+
+    impl ProgressSink for Recorder {
+        fn update(&mut self, completed: usize, total: usize) {
+            self.events.push(("update", completed, total));
+        }
+
+        fn tile(&mut self, x: usize, y: usize, _: &Texture<Vec3>) {
+            self.events.push(("tile", x, y));
+        }
+
+        fn finish(&mut self) {
+            self.events.push(("finish", 0, 0));
+        }
+    }
+
+    #[test]
+    fn reporting_progress() {
+        let renderer = renderer(7, 9);
+        let mut image = Texture::blank(7, 9);
+        let control = Arc::new(RenderControl::default());
+        let mut recorder = Recorder::default();
+        let order = TileOrder::Scanline;
+        assert!(render_tiled(
+            &renderer,
+            &control,
+            &mut image,
+            0,
+            3,
+            2,
+            order,
+            &mut recorder
+        ));
+
+        // Each tile comes before the update counting its pixels, which grow
+        // up to the total, and the render finishes once at the end
+        let events = recorder.events;
+        let (last, updates) = events.split_last().unwrap();
+        assert_eq!(*last, ("finish", 0, 0));
+        assert_eq!(updates.len(), 2 * tile_count(7, 9, 2));
+        let mut completed = 0;
+        for pair in updates.chunks(2) {
+            assert_eq!(pair[0].0, "tile");
+            assert_eq!(pair[1].0, "update");
+            assert!(pair[1].1 > completed);
+            assert_eq!(pair[1].2, 7 * 9);
+            completed = pair[1].1;
+        }
+        assert_eq!(completed, 7 * 9);
+    }
+
+    #[test]
+    fn reporting_progress_of_passes() {
+        let mut renderer = renderer(7, 9);
+        renderer.set_samples(16);
+        let mut image = Texture::blank(7, 9);
+        let mut recorder = Recorder::default();
+        let order = TileOrder::Scanline;
+        render_progressive(
+            &mut image,
+            &renderer,
+            2,
+            4,
+            order,
+            None,
+            &mut recorder,
+        );
+
+        // Progress runs once across every pass, finishing at the end
+        let passes = sample_passes(16).len();
+        assert!(passes > 1);
+        let events = recorder.events;
+        let (last, updates) = events.split_last().unwrap();
+        assert_eq!(*last, ("finish", 0, 0));
+        let updates: Vec<_> =
+            updates.iter().filter(|event| event.0 == "update").collect();
+        assert!(updates.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        assert!(updates.iter().all(|event| event.2 == 7 * 9 * passes));
+        assert_eq!(updates[updates.len() - 1].1, 7 * 9 * passes);
+    }
+
+    #[test]
+    fn rendering_strips() {
+        let renderer = renderer(7, 9);
+
+        let mut whole = Texture::blank(7, 9);
+        let order = TileOrder::Scanline;
+        render_threaded(&mut whole, &renderer, 2, 3, order, &mut |_, _| {});
+
+        let mut strips = Texture::blank(7, 9);
+        let mut rows = vec![];
+        let mut progress = |completed, total| rows.push((completed, total));
+        render_strips(
+            &renderer,
+            7,
+            9,
+            4,
+            2,
+            3,
+            order,
+            &mut strips,
+            &mut progress,
+        )
+        .unwrap();
+        assert_eq!(strips, whole);
+        assert_eq!(rows.last(), Some(&(63, 63)));
+    }
+
+    #[test]
+    fn enumerating_tile_ranges() {
+        assert_eq!(tile_count(10, 5, 4), 6);
+        let origins = |start, end| -> Vec<(usize, usize, usize, usize)> {
+            tile_range(10, 5, 4, start, end)
+                .iter()
+                .map(|tile| (tile.x, tile.y, tile.width, tile.height))
+                .collect()
+        };
+        assert_eq!(
+            origins(1, 4),
+            vec![(4, 0, 4, 4), (8, 0, 2, 4), (0, 4, 4, 1)]
+        );
+        assert_eq!(origins(5, 100), vec![(8, 4, 2, 1)]);
+        assert_eq!(origins(7, 3), vec![]);
+    }
+
+    #[test]
+    fn stopping_at_deadlines() {
+        assert_eq!(sample_passes(1), vec![1]);
+        assert_eq!(sample_passes(16), vec![1, 4, 16]);
+        assert_eq!(sample_passes(9), vec![1, 4, 9]);
+
+        let control = RenderControl {
+            deadline: Some(Instant::now() + Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert!(control.proceed());
+        let control = RenderControl {
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        };
+        assert!(!control.proceed());
+        assert!(control.cancelled.load(SeqCst));
+    }
 }
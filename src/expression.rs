@@ -0,0 +1,329 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Arithmetic expressions of named values, evaluated for each pixel of a set
+//! of rasters, such as `weights * 2.5` or `heights - (lake > 0) * 5`.
+//!
+//! Expressions support numbers, `+ - * / ^`, comparisons giving one or zero,
+//! parentheses and the functions `abs`, `sqrt`, `exp`, `ln`, `min`, `max`,
+//! `clamp(value, low, high)` and `select(condition, then, otherwise)`.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+/// Symbols in order of length, so the longest match is taken first
+const SYMBOLS: &[&str] = &[
+    "<=", ">=", "==", "!=", "<", ">", "+", "-", "*", "/", "^", "(", ")", ",",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars: Peekable<Chars> = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                let exponent = number.ends_with('e') || number.ends_with('E');
+                if c.is_ascii_digit()
+                    || c == '.'
+                    || c == 'e'
+                    || c == 'E'
+                    || (exponent && (c == '-' || c == '+'))
+                {
+                    number.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let value = try!(number
+                .parse()
+                .map_err(|_| format!("Invalid number {}", number)));
+            tokens.push(Token::Number(value));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    name.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token::Name(name));
+        } else {
+            let rest: String = chars.clone().take(2).collect();
+            let symbol = SYMBOLS.iter().find(|s| rest.starts_with(**s));
+            match symbol {
+                Some(symbol) => {
+                    for _ in 0..symbol.len() {
+                        chars.next();
+                    }
+                    tokens.push(Token::Symbol(*symbol));
+                }
+                None => return Err(format!("Unexpected character {}", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(f64),
+    /// Index of a value in the values an expression is evaluated with
+    Variable(usize),
+    Negate(Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+    Call(String, Vec<Node>),
+}
+
+/// Arguments taken by each function
+fn arity(name: &str) -> Option<usize> {
+    match name {
+        "abs" | "sqrt" | "exp" | "ln" => Some(1),
+        "min" | "max" => Some(2),
+        "clamp" | "select" => Some(3),
+        _ => None,
+    }
+}
+
+/// Parses tokens by recursive descent, from the loosest binding operators
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    names: &'a [&'a str],
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    /// Consume the next token if it is one of a set of symbols
+    fn symbol(&mut self, symbols: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(&Token::Symbol(symbol)) if symbols.contains(&symbol) => {
+                self.position += 1;
+                Some(symbol)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), String> {
+        match self.symbol(&[symbol]) {
+            Some(_) => Ok(()),
+            None => Err(format!("Expected {}", symbol)),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        let left = try!(self.additive());
+        match self.symbol(&["<=", ">=", "==", "!=", "<", ">"]) {
+            Some(op) => {
+                let right = try!(self.additive());
+                Ok(Node::Binary(op, Box::new(left), Box::new(right)))
+            }
+            None => Ok(left),
+        }
+    }
+
+    fn additive(&mut self) -> Result<Node, String> {
+        let mut left = try!(self.term());
+        while let Some(op) = self.symbol(&["+", "-"]) {
+            let right = try!(self.term());
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn term(&mut self) -> Result<Node, String> {
+        let mut left = try!(self.unary());
+        while let Some(op) = self.symbol(&["*", "/"]) {
+            let right = try!(self.unary());
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.symbol(&["-"]).is_some() {
+            return Ok(Node::Negate(Box::new(try!(self.unary()))));
+        }
+        let base = try!(self.atom());
+        if self.symbol(&["^"]).is_some() {
+            let exponent = try!(self.unary());
+            return Ok(Node::Binary("^", Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(value)) => Ok(Node::Number(value)),
+            Some(Token::Symbol("(")) => {
+                let node = try!(self.comparison());
+                try!(self.expect(")"));
+                Ok(node)
+            }
+            Some(Token::Name(ref name)) if self.symbol(&["("]).is_some() => {
+                let count = try!(arity(name)
+                    .ok_or_else(|| format!("Unknown function {}", name)));
+                let mut args = vec![try!(self.comparison())];
+                while self.symbol(&[","]).is_some() {
+                    args.push(try!(self.comparison()));
+                }
+                try!(self.expect(")"));
+                if args.len() != count {
+                    return Err(format!(
+                        "{} takes {} arguments, not {}",
+                        name,
+                        count,
+                        args.len()
+                    ));
+                }
+                Ok(Node::Call(name.clone(), args))
+            }
+            Some(Token::Name(name)) => {
+                match self.names.iter().position(|&n| n == name) {
+                    Some(index) => Ok(Node::Variable(index)),
+                    None => Err(format!("Unknown value {}", name)),
+                }
+            }
+            Some(token) => Err(format!("Unexpected {:?}", token)),
+            None => Err(String::from("Unexpected end of expression")),
+        }
+    }
+}
+
+fn evaluate(node: &Node, values: &[f64]) -> f64 {
+    let truth = |condition: bool| if condition { 1.0 } else { 0.0 };
+    match *node {
+        Node::Number(value) => value,
+        Node::Variable(index) => values[index],
+        Node::Negate(ref node) => -evaluate(node, values),
+        Node::Binary(op, ref left, ref right) => {
+            let (a, b) = (evaluate(left, values), evaluate(right, values));
+            match op {
+                "+" => a + b,
+                "-" => a - b,
+                "*" => a * b,
+                "/" => a / b,
+                "^" => a.powf(b),
+                "<" => truth(a < b),
+                ">" => truth(a > b),
+                "<=" => truth(a <= b),
+                ">=" => truth(a >= b),
+                "==" => truth(a == b),
+                _ => truth(a != b),
+            }
+        }
+        Node::Call(ref name, ref args) => {
+            let arg = |i: usize| evaluate(&args[i], values);
+            match name.as_str() {
+                "abs" => arg(0).abs(),
+                "sqrt" => arg(0).sqrt(),
+                "exp" => arg(0).exp(),
+                "ln" => arg(0).ln(),
+                "min" => arg(0).min(arg(1)),
+                "max" => arg(0).max(arg(1)),
+                "clamp" => arg(0).max(arg(1)).min(arg(2)),
+                _ => {
+                    if arg(0) != 0.0 {
+                        arg(1)
+                    } else {
+                        arg(2)
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A parsed expression, with its names resolved to the positions of values
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    root: Node,
+}
+
+impl Expression {
+    /// Parse an expression of values with names, in the order they are given
+    /// when evaluating
+    pub fn parse(text: &str, names: &[&str]) -> Result<Expression, String> {
+        let tokens = try!(tokenize(text));
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            names,
+        };
+        let root = try!(parser.comparison());
+        match parser.peek() {
+            Some(token) => Err(format!("Unexpected {:?}", token)),
+            None => Ok(Expression { root }),
+        }
+    }
+
+    /// Return the value of the expression for values in the order of their
+    /// names
+    pub fn evaluate(&self, values: &[f64]) -> f64 {
+        evaluate(&self.root, values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluating_expressions() {
+        let eval = |text: &str| {
+            Expression::parse(text, &["a", "lake"])
+                .unwrap()
+                .evaluate(&[3.0, 2.0])
+        };
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("-a ^ 2"), -9.0);
+        assert_eq!(eval("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(eval("10 - 4 - 3"), 3.0);
+        assert_eq!(eval("a - (lake > 0) * 5"), -2.0);
+        assert_eq!(eval("a * 1.5e1 / lake"), 22.5);
+        assert_eq!(eval("max(a, lake) + min(a, lake)"), 5.0);
+        assert_eq!(eval("clamp(a, 0, 1)"), 1.0);
+        assert_eq!(eval("select(lake == 2, sqrt(4), abs(-a))"), 2.0);
+        assert_eq!(eval("select(lake != 2, 1, 0)"), 0.0);
+
+        let parse = |text: &str| Expression::parse(text, &["a"]);
+        assert!(parse("a +").is_err());
+        assert!(parse("(a").is_err());
+        assert!(parse("a b").is_err());
+        assert!(parse("b").is_err());
+        assert!(parse("min(a)").is_err());
+        assert!(parse("cos(a)").is_err());
+        assert!(parse("a $ 2").is_err());
+    }
+}
@@ -0,0 +1,567 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(feature = "gdal")]
+use super::osm::{self, TagFilter};
+use super::raster::ImageFormat;
+#[cfg(feature = "gdal")]
+use super::{gdal, ogr, other, remote};
+use super::{geojson, package, raster as images};
+use expression::Expression;
+use math::AffineTransform;
+#[cfg(feature = "gdal")]
+use ops::contours as trace_contours;
+use options::{
+    BakedLoader, ExpressionLoader, GeojsonLoader, ImageLoader, Loader,
+    TerrainRgbLoader,
+};
+#[cfg(feature = "gdal")]
+use options::{ContourLoader, OgrLoader, OsmLoader, RemoteLoader};
+use serde_json;
+use shapes::Shape;
+use textures::Texture;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+use std::sync::Arc;
+
+/// A raster band with its proj4 string and transform
+pub type Raster = (String, AffineTransform, Texture<f64>);
+
+/// A loaded value with its size in bytes and when it was last used
+struct Entry<T> {
+    value: Arc<T>,
+    memory: usize,
+    used: usize,
+}
+
+/// Rasters and vector layers loaded from files, keyed by path and band or
+/// layer name
+#[derive(Default)]
+pub struct LoaderCache {
+    rasters: HashMap<(String, usize), Entry<Raster>>,
+    layers: HashMap<String, Entry<Vec<Shape>>>,
+    /// Number of bytes above which least recently used loads are evicted
+    /// from the cache, freeing only those no scene still holds
+    budget: Option<usize>,
+    /// Counter incremented on every use of a load
+    clock: usize,
+}
+
+impl LoaderCache {
+    /// Set the number of bytes of loads to keep, evicting the least recently
+    /// used as loads are stored while building a scene, never while rendering
+    pub fn set_budget(&mut self, budget: Option<usize>) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    /// Return the number of bytes held by cached loads
+    pub fn memory(&self) -> usize {
+        let rasters = self.rasters.values().map(|entry| entry.memory);
+        let layers = self.layers.values().map(|entry| entry.memory);
+        rasters.chain(layers).sum()
+    }
+
+    /// Remove least recently used loads until within the budget
+    fn evict(&mut self) {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return,
+        };
+        let mut memory = self.memory();
+        while memory > budget {
+            let raster = self
+                .rasters
+                .iter()
+                .min_by_key(|&(_, entry)| entry.used)
+                .map(|(key, entry)| (key.clone(), entry.used));
+            let layer = self
+                .layers
+                .iter()
+                .min_by_key(|&(_, entry)| entry.used)
+                .map(|(key, entry)| (key.clone(), entry.used));
+            memory -= match (raster, layer) {
+                (Some((key, a)), Some((_, b))) if a < b => {
+                    self.rasters.remove(&key).unwrap().memory
+                }
+                (_, Some((key, _))) => self.layers.remove(&key).unwrap().memory,
+                (Some((key, _)), None) => {
+                    self.rasters.remove(&key).unwrap().memory
+                }
+                (None, None) => break,
+            };
+        }
+    }
+}
+
+thread_local! {
+    static CACHE: RefCell<Option<LoaderCache>> = RefCell::new(None);
+}
+
+/// Puts back the cache of an enclosing scope when dropped, even if a load
+/// panics
+struct Restore(Option<LoaderCache>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CACHE.with(|current| *current.borrow_mut() = self.0.take());
+    }
+}
+
+/// Run a closure with loads on this thread shared through a cache
+pub fn scope<F, R>(cache: &mut LoaderCache, callback: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _restore = Restore(CACHE.with(|current| {
+        current
+            .borrow_mut()
+            .replace(mem::replace(cache, Default::default()))
+    }));
+    let result = callback();
+    *cache = CACHE
+        .with(|current| current.borrow_mut().take())
+        .unwrap_or_default();
+    result
+}
+
+/// Return the budget of the cache of this thread's scope, if any
+#[cfg(feature = "gdal")]
+pub fn budget() -> Option<usize> {
+    CACHE.with(|cache| cache.borrow().as_ref().and_then(|cache| cache.budget))
+}
+
+/// Return a cached raster, marking it as used
+fn cached_raster(key: &(String, usize)) -> Option<Arc<Raster>> {
+    CACHE.with(|cache| {
+        cache.borrow_mut().as_mut().and_then(|cache| {
+            cache.clock += 1;
+            let clock = cache.clock;
+            cache.rasters.get_mut(key).map(|entry| {
+                entry.used = clock;
+                entry.value.clone()
+            })
+        })
+    })
+}
+
+/// Add a raster to the cache, evicting others if over budget
+fn store_raster(key: (String, usize), raster: Arc<Raster>) {
+    CACHE.with(|cache| {
+        if let Some(ref mut cache) = *cache.borrow_mut() {
+            cache.clock += 1;
+            let entry = Entry {
+                memory: raster.2.memory(),
+                value: raster,
+                used: cache.clock,
+            };
+            cache.rasters.insert(key, entry);
+            cache.evict();
+        }
+    });
+}
+
+/// Return a raster by key, loading it if not already loaded within a scope
+fn cached<F>(key: (String, usize), load: F) -> Result<Arc<Raster>>
+where
+    F: FnOnce() -> Result<Raster>,
+{
+    if let Some(raster) = cached_raster(&key) {
+        return Ok(raster);
+    }
+
+    let raster = Arc::new(try!(load()));
+    store_raster(key, raster.clone());
+    Ok(raster)
+}
+
+/// Import a raster band, optionally at a resolution, reusing it if already
+/// loaded within a scope
+#[cfg(feature = "gdal")]
+pub fn raster(
+    path: &str,
+    band: usize,
+    resolution: Option<f64>,
+) -> Result<Arc<Raster>> {
+    let key = match resolution {
+        Some(resolution) => format!("{}@{}", path, resolution),
+        None => path.to_owned(),
+    };
+    cached((key, band), || {
+        let (proj4, transform, mut rasters) =
+            try!(gdal::import_extent(path, &[band], None, resolution)
+                .map_err(other));
+        Ok((proj4, transform, rasters.remove(0)))
+    })
+}
+
+/// Fetch a remote raster band, reusing it if already fetched within a scope
+#[cfg(feature = "gdal")]
+pub fn remote(loader: &RemoteLoader) -> Result<Arc<Raster>> {
+    let key = (serde_json::to_string(loader).unwrap(), loader.band);
+    cached(key, || remote::import(loader))
+}
+
+/// Import heights from an image, reusing them if already loaded within a
+/// scope
+pub fn image(loader: &ImageLoader, format: ImageFormat) -> Result<Arc<Raster>> {
+    let options = serde_json::to_string(loader).unwrap();
+    let key = (format!("{:?}:{}", format, options), 1);
+    cached(key, || {
+        let (scale, offset) = (loader.scale, loader.offset);
+        let path = &loader.filepath;
+        images::import_image(path, format, scale, offset, loader.extent)
+    })
+}
+
+/// Import heights from a Terrain-RGB image, reusing them if already loaded
+/// within a scope
+pub fn terrain_rgb(loader: &TerrainRgbLoader) -> Result<Arc<Raster>> {
+    let key = (format!("rgb:{}", serde_json::to_string(loader).unwrap()), 1);
+    cached(key, || {
+        images::import_terrain_rgb(&loader.filepath, loader.extent)
+    })
+}
+
+/// Read a raster from a package, reusing it if already loaded within a scope
+pub fn baked_raster(loader: &BakedLoader) -> Result<Arc<Raster>> {
+    let key = (format!("baked:{}:{}", loader.package, loader.entry), 1);
+    cached(key, || {
+        let bytes = try!(package::open_entry(&loader.package, &loader.entry));
+        package::decode_raster(&bytes)
+    })
+}
+
+/// Evaluate an expression for each pixel of its input rasters, reusing the
+/// result if already computed within a scope
+pub fn expression(loader: &ExpressionLoader) -> Result<Arc<Raster>> {
+    let key = (
+        format!("expr:{}", serde_json::to_string(loader).unwrap()),
+        1,
+    );
+    cached(key, || {
+        let names: Vec<&str> = loader.inputs.keys().map(|k| &k[..]).collect();
+        let expression = try!(Expression::parse(&loader.expression, &names)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err)));
+        let mut inputs = vec![];
+        for input in loader.inputs.values() {
+            inputs.push(try!(heights(input)));
+        }
+
+        let first = match inputs.first() {
+            Some(input) => input.clone(),
+            None => {
+                let message = "An expression requires at least one input";
+                return Err(Error::new(ErrorKind::InvalidInput, message));
+            }
+        };
+        let (ref projection, transform, ref first) = *first;
+        let size = (first.width, first.height);
+        if inputs
+            .iter()
+            .any(|input| (input.2.width, input.2.height) != size)
+        {
+            let message = "The inputs of an expression differ in size";
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+
+        let mut values = vec![0.0; inputs.len()];
+        let buffer = (0..first.buffer.len())
+            .map(|i| {
+                for (value, input) in values.iter_mut().zip(&inputs) {
+                    *value = input.2.buffer[i];
+                }
+                expression.evaluate(&values)
+            })
+            .collect();
+        let texture = Texture::new(size.0, size.1, buffer);
+        Ok((projection.clone(), transform, texture))
+    })
+}
+
+/// Load the heights of any raster loader
+pub fn heights(loader: &Loader) -> Result<Arc<Raster>> {
+    match *loader {
+        #[cfg(feature = "gdal")]
+        Loader::Gdal(ref opts) => {
+            raster(&opts.filepath, opts.band, opts.resolution)
+        }
+        #[cfg(feature = "gdal")]
+        Loader::Remote(ref opts) => remote(opts),
+        Loader::Png(ref opts) => image(opts, ImageFormat::Png),
+        Loader::Bmp(ref opts) => image(opts, ImageFormat::Bmp),
+        Loader::TerrainRgb(ref opts) => terrain_rgb(opts),
+        Loader::Expression(ref opts) => expression(opts),
+        Loader::Baked(ref opts) => baked_raster(opts),
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            "Loader does not read heights",
+        )),
+    }
+}
+
+/// Return a vector layer by key, loading it if not already loaded within a
+/// scope
+fn cached_layer<F>(key: String, load: F) -> Result<Arc<Vec<Shape>>>
+where
+    F: FnOnce() -> Result<Vec<Shape>>,
+{
+    let cached = CACHE.with(|cache| {
+        cache.borrow_mut().as_mut().and_then(|cache| {
+            cache.clock += 1;
+            let clock = cache.clock;
+            cache.layers.get_mut(&key).map(|entry| {
+                entry.used = clock;
+                entry.value.clone()
+            })
+        })
+    });
+    if let Some(layer) = cached {
+        return Ok(layer);
+    }
+
+    let layer = Arc::new(try!(load()));
+    CACHE.with(|cache| {
+        if let Some(ref mut cache) = *cache.borrow_mut() {
+            cache.clock += 1;
+            let entry = Entry {
+                memory: layer.iter().map(|shape| shape.memory()).sum(),
+                value: layer.clone(),
+                used: cache.clock,
+            };
+            cache.layers.insert(key, entry);
+            cache.evict();
+        }
+    });
+    Ok(layer)
+}
+
+/// Import a vector layer, reusing it if already loaded within a scope
+#[cfg(feature = "gdal")]
+pub fn layer(loader: &OgrLoader) -> Result<Arc<Vec<Shape>>> {
+    let key = serde_json::to_string(loader).unwrap();
+    cached_layer(key, || {
+        let bounds = loader.bounds.as_ref().map(|bounds| bounds.dataset());
+        let names = [loader.layer.clone()];
+        let mut layers =
+            try!(ogr::import_within(&loader.filepath, &names, bounds)
+                .map_err(other));
+        let mut layer = layers.remove(0);
+        if let Some(tolerance) = loader.simplify {
+            layer = layer
+                .iter()
+                .map(|shape| shape.simplify(tolerance))
+                .collect();
+        }
+        if let Some(spacing) = loader.densify {
+            layer = layer.iter().map(|shape| shape.densify(spacing)).collect();
+        }
+        Ok(layer)
+    })
+}
+
+/// Import elements from an OpenStreetMap file, reusing them if already loaded
+/// within a scope
+#[cfg(feature = "gdal")]
+pub fn osm(loader: &OsmLoader) -> Result<Arc<Vec<Shape>>> {
+    let key = format!("osm:{}", serde_json::to_string(loader).unwrap());
+    cached_layer(key, || {
+        let bounds = loader.bounds.as_ref().map(|bounds| bounds.dataset());
+        let filter = TagFilter::new(&loader.filter);
+        let elements =
+            try!(
+                osm::import(&loader.filepath, &loader.layer, &filter, bounds)
+                    .map_err(other)
+            );
+        Ok(elements.into_iter().map(|(shape, _)| shape).collect())
+    })
+}
+
+/// Import the shapes of a GeoJSON file, reusing them if already loaded within
+/// a scope
+pub fn geojson(loader: &GeojsonLoader) -> Result<Arc<Vec<Shape>>> {
+    let key = format!("geojson:{}", serde_json::to_string(loader).unwrap());
+    cached_layer(key, || {
+        let bounds = loader.bounds.as_ref().map(|bounds| bounds.dataset());
+        geojson::import(&loader.filepath, bounds)
+    })
+}
+
+/// Trace contours from a raster, reusing them if already traced within a
+/// scope
+#[cfg(feature = "gdal")]
+pub fn contours(loader: &ContourLoader) -> Result<Arc<Vec<Shape>>> {
+    let key = format!("contours:{}", serde_json::to_string(loader).unwrap());
+    cached_layer(key, || {
+        let raster = try!(raster(&loader.filepath, loader.band, None));
+        let (ref proj4, transform, ref texture) = *raster;
+        let (width, height) = (texture.width, texture.height);
+        let transform = images::scaled_transform(
+            proj4,
+            &transform,
+            width,
+            height,
+            loader.scale,
+        );
+        Ok(trace_contours(
+            texture,
+            &transform,
+            loader.interval,
+            loader.base,
+        ))
+    })
+}
+
+/// Read shapes from a package, reusing them if already loaded within a scope
+pub fn baked_shapes(loader: &BakedLoader) -> Result<Arc<Vec<Shape>>> {
+    let key = format!("baked:{}:{}", loader.package, loader.entry);
+    cached_layer(key, || {
+        let bytes = try!(package::open_entry(&loader.package, &loader.entry));
+        Ok(try!(serde_json::from_slice(&bytes)))
+    })
+}
+
+/// Load the shapes of any vector loader
+pub fn shapes(loader: &Loader) -> Result<Arc<Vec<Shape>>> {
+    match *loader {
+        #[cfg(feature = "gdal")]
+        Loader::Shp(ref opts) => layer(opts),
+        #[cfg(feature = "gdal")]
+        Loader::Osm(ref opts) => osm(opts),
+        #[cfg(feature = "gdal")]
+        Loader::Contours(ref opts) => contours(opts),
+        Loader::Geojson(ref opts) => geojson(opts),
+        Loader::Baked(ref opts) => baked_shapes(opts),
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            "Loader does not read shapes",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use io::png::export;
+    use math::Color;
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::panic::{self, AssertUnwindSafe};
+
+    fn entry<T>(value: T, memory: usize, used: usize) -> Entry<T> {
+        Entry {
+            value: Arc::new(value),
+            memory,
+            used,
+        }
+    }
+
+    /// Write a grey image of a row of values, returning a loader for it
+    fn png(name: &str, values: Vec<u8>) -> ImageLoader {
+        let path = env::temp_dir().join(name);
+        let colors = values.iter().map(|&v| Color::new(v, v, v));
+        let texture = Texture::new(values.len(), 1, colors.collect());
+        export(&path, &texture).unwrap();
+        ImageLoader {
+            filepath: path.to_string_lossy().into_owned(),
+            scale: 1.0,
+            offset: 0.0,
+            extent: None,
+        }
+    }
+
+    #[test]
+    fn sharing_loads_within_a_scope() {
+        let first = png("peaks-cache-1", vec![1, 2]);
+        let second = png("peaks-cache-2", vec![3, 4]);
+        let load = |loader| image(loader, ImageFormat::Png).unwrap();
+        assert!(!Arc::ptr_eq(&load(&first), &load(&first)));
+
+        let mut cache = LoaderCache::default();
+        let loaded = scope(&mut cache, || {
+            let loaded = load(&first);
+            assert!(Arc::ptr_eq(&loaded, &load(&first)));
+            loaded
+        });
+        assert!(scope(&mut cache, || Arc::ptr_eq(&loaded, &load(&first))));
+
+        // Loads over the budget evict those least recently used
+        let budget = cache.memory();
+        cache.set_budget(Some(budget));
+        scope(&mut cache, || load(&second));
+        assert_eq!(cache.memory(), budget);
+        assert!(!scope(&mut cache, || Arc::ptr_eq(&loaded, &load(&first))));
+
+        // A load panicking within a scope leaves no cache in place after it
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+            scope(&mut cache, || panic!("Could not load"))
+        }));
+        assert!(panicked.is_err());
+        assert!(!Arc::ptr_eq(&load(&first), &load(&first)));
+    }
+
+    #[test]
+    fn evicting_least_recently_used() {
+        let mut cache = LoaderCache::default();
+        cache.layers.insert("a".to_owned(), entry(vec![], 40, 1));
+        cache.layers.insert("b".to_owned(), entry(vec![], 40, 3));
+        cache.rasters.insert(
+            ("c".to_owned(), 1),
+            entry(
+                (
+                    String::new(),
+                    AffineTransform::new(0.0, 0.0, 1.0, 1.0),
+                    Texture::blank(0, 0),
+                ),
+                40,
+                2,
+            ),
+        );
+        assert_eq!(cache.memory(), 120);
+
+        cache.set_budget(Some(100));
+        assert!(!cache.layers.contains_key("a"));
+        assert_eq!(cache.memory(), 80);
+
+        cache.set_budget(Some(50));
+        assert!(cache.rasters.is_empty());
+        assert!(cache.layers.contains_key("b"));
+    }
+
+    #[test]
+    fn evaluating_expressions_of_rasters() {
+        let image = |name, values| Loader::Png(png(name, values));
+        let mut inputs = BTreeMap::new();
+        inputs.insert(
+            String::from("heights"),
+            image("peaks-expr-1", vec![10, 20]),
+        );
+        inputs
+            .insert(String::from("lake"), image("peaks-expr-2", vec![0, 255]));
+
+        let mut loader = ExpressionLoader {
+            expression: String::from("heights - (lake > 0) * 5"),
+            inputs,
+        };
+        let (_, _, ref texture) = *expression(&loader).unwrap();
+        assert_eq!(texture.buffer, [10.0, 15.0]);
+
+        loader.expression = String::from("heights * depth");
+        assert!(expression(&loader).is_err());
+    }
+}
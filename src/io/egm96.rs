@@ -0,0 +1,102 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Loader for the EGM96 15 minute geoid grid (`WW15MGH.DAC`), a headerless
+//! file of big endian 16 bit undulations in centimetres, stored in rows from
+//! 90°N to 90°S and columns eastwards from 0°E.
+
+use math::AffineTransform;
+use textures::{Bilinear, Texture};
+
+use std::convert::AsRef;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result};
+use std::path::Path;
+
+const ROWS: usize = 721;
+const COLUMNS: usize = 1440;
+const SPACING: f64 = 0.25;
+
+/// Read a geoid grid, with undulations in metres
+pub fn read<R: Read>(mut reader: R) -> Result<Texture<f64>> {
+    let mut bytes = Vec::with_capacity(ROWS * COLUMNS * 2);
+    try!(reader.read_to_end(&mut bytes));
+    if bytes.len() != ROWS * COLUMNS * 2 {
+        return Err(Error::new(ErrorKind::InvalidData, "Not an EGM96 grid"));
+    }
+
+    // Repeat the first column at the end so lookups wrap around the globe
+    let mut grid = Texture::blank(COLUMNS + 1, ROWS);
+    for y in 0..ROWS {
+        for x in 0..COLUMNS + 1 {
+            let i = (y * COLUMNS + x % COLUMNS) * 2;
+            let value = i16::from_be_bytes([bytes[i], bytes[i + 1]]);
+            grid.write1x1(x, y, f64::from(value) / 100.0);
+        }
+    }
+
+    Ok(grid)
+}
+
+/// Import a geoid grid from a file
+pub fn import<P: AsRef<Path>>(path: P) -> Result<Texture<f64>> {
+    read(try!(File::open(path.as_ref())))
+}
+
+/// Return the undulation of the geoid at a longitude and latitude in degrees
+pub fn undulation(grid: &Texture<f64>, lon: f64, lat: f64) -> f64 {
+    let x = lon.rem_euclid(360.0) / SPACING;
+    let y = (90.0 - lat) / SPACING;
+    let max = |size: usize| size as f64 - 1.0 - 1e-9;
+    grid.bilinear(x.min(max(grid.width)), y.min(max(grid.height)).max(0.0))
+}
+
+/// Sample a geoid grid for each pixel of a raster in geographic coordinates,
+/// with a transform as returned by `io::gdal::import`
+pub fn resample(
+    grid: &Texture<f64>,
+    transform: &AffineTransform,
+    output: &mut Texture<f64>,
+) {
+    for y in 0..output.height {
+        for x in 0..output.width {
+            let (lon, z) = transform.forward(x as f64 + 0.5, y as f64 + 0.5);
+            output.write1x1(x, y, undulation(grid, lon, -z));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reading_geoid_grid() {
+        let mut bytes = vec![0; ROWS * COLUMNS * 2];
+        let north_pole = 1315_i16.to_be_bytes();
+        for x in 0..COLUMNS {
+            bytes[x * 2] = north_pole[0];
+            bytes[x * 2 + 1] = north_pole[1];
+        }
+        let equator = (360 * COLUMNS + 720) * 2;
+        bytes[equator..equator + 2].copy_from_slice(&(-250_i16).to_be_bytes());
+
+        let grid = read(&bytes[..]).unwrap();
+        assert_eq!(undulation(&grid, 0.0, 90.0), 13.15);
+        assert_eq!(undulation(&grid, -180.0, 0.0), -2.5);
+        assert_eq!(undulation(&grid, 180.125, 0.0), -1.25);
+        assert!(read(&bytes[1..]).is_err());
+    }
+}
@@ -15,12 +15,19 @@
 
 use std::convert::AsRef;
 use std::f64::EPSILON;
+use std::ffi::{CStr, CString};
+use std::io::Result as IoResult;
+use std::os::raw::c_int;
 use std::path::Path;
+use std::ptr;
 
-use gdal::errors::Result;
-use gdal::raster::{Dataset, RasterBand};
+use gdal::errors::{ErrorKind, Result};
+use gdal::raster::{Buffer, Dataset, Driver, RasterBand};
 use gdal::spatial_ref::SpatialRef;
+use gdal_sys::{CPLErr, GDALAccess, GDALDataType};
 
+use chunks::{ChunkSink, ChunkSource};
+use io::other;
 use math::AffineTransform;
 use textures::Texture;
 
@@ -38,6 +45,71 @@ where
     D: Copy + Clone + Default + PartialEq + GdalRasterType<D>,
 {
     let dataset = try!(Dataset::open(path.as_ref()));
+    read_window(&dataset, bands, x, y, width, height, (width, height))
+}
+
+/// Import a region specified in the coordinates of a dataset, as west, south,
+/// east and north bounds, optionally resampled to a resolution in dataset
+/// units per pixel
+pub fn import_extent<P, D>(
+    path: P,
+    bands: &[usize],
+    extent: Option<(f64, f64, f64, f64)>,
+    resolution: Option<f64>,
+) -> Result<(String, AffineTransform, Vec<Texture<D>>)>
+where
+    P: AsRef<Path>,
+    D: Copy + Clone + Default + PartialEq + GdalRasterType<D>,
+{
+    let dataset = try!(Dataset::open(path.as_ref()));
+    let transform = try!(dataset.geo_transform());
+    let (width, height) = dataset.size();
+
+    let (x0, y0, x1, y1) = match extent {
+        Some((w, s, e, n)) => {
+            let column = |x: f64| (x - transform[0]) / transform[1];
+            let row = |y: f64| (y - transform[3]) / transform[5];
+            let clamp =
+                |v: f64, max: usize| v.max(0.0).min(max as f64) as usize;
+            (
+                clamp(column(w).floor(), width),
+                clamp(row(n).floor(), height),
+                clamp(column(e).ceil(), width),
+                clamp(row(s).ceil(), height),
+            )
+        }
+        None => (0, 0, width, height),
+    };
+
+    let (width, height) = (x1.saturating_sub(x0), y1.saturating_sub(y0));
+    let size = match resolution {
+        Some(resolution) => {
+            let pixels = |count: usize, size: f64| {
+                (count as f64 * size.abs() / resolution).round().max(1.0)
+                    as usize
+            };
+            (pixels(width, transform[1]), pixels(height, transform[5]))
+        }
+        None => (width, height),
+    };
+
+    read_window(&dataset, bands, x0, y0, width, height, size)
+}
+
+/// Read a region in pixel coordinates from a set of raster bands, resampled to
+/// a size
+fn read_window<D>(
+    dataset: &Dataset,
+    bands: &[usize],
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    size: (usize, usize),
+) -> Result<(String, AffineTransform, Vec<Texture<D>>)>
+where
+    D: Copy + Clone + Default + PartialEq + GdalRasterType<D>,
+{
     let transform = try!(dataset.geo_transform());
     let spat_ref = try!(SpatialRef::from_wkt(&dataset.projection()));
     let proj4 = try!(spat_ref.to_proj4());
@@ -46,21 +118,149 @@ where
     let mut rasters = Vec::with_capacity(bands.len());
     for band in bands {
         let raster = try!(dataset.rasterband(*band as isize));
-        let data = try!(D::read_raster(&raster, x, y, width, height));
-        rasters.push(Texture::new(width, height, data));
+        let data = try!(D::read_raster(&raster, x, y, width, height, size));
+        rasters.push(Texture::new(size.0, size.1, data));
     }
 
     assert!((transform[2] - 0.0).abs() < EPSILON);
     assert!((transform[4] - 0.0).abs() < EPSILON);
 
-    let pw = transform[1];
-    let ph = transform[5] * -1.0;
+    let pw = transform[1] * width as f64 / size.0 as f64;
+    let ph = transform[5] * height as f64 / size.1 as f64 * -1.0;
     let xo = transform[0] + (x as f64 * transform[1]);
     let yo = (transform[3] + (y as f64 * transform[5])) * -1.0;
 
     Ok((proj4, AffineTransform::new(xo, yo, pw, ph), rasters))
 }
 
+/// Export raster bands, with a transform as returned by `import`, to a GeoTIFF
+pub fn export<P>(
+    path: P,
+    proj4: &str,
+    transform: &AffineTransform,
+    rasters: &[Texture<f64>],
+) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    assert!(!rasters.is_empty());
+    let (width, height) = (rasters[0].width, rasters[0].height);
+    let path = path.as_ref().to_string_lossy();
+
+    let driver = try!(Driver::get("GTiff"));
+    let dataset = try!(driver.create_with_band_type::<f64>(
+        &path,
+        width as isize,
+        height as isize,
+        rasters.len() as isize,
+    ));
+
+    let (xo, yo) = transform.forward(0.0, 0.0);
+    let (x1, y1) = transform.forward(1.0, 1.0);
+    try!(dataset.set_geo_transform(&[xo, x1 - xo, 0.0, -yo, 0.0, yo - y1,]));
+    let spat_ref = try!(SpatialRef::from_proj4(proj4));
+    try!(dataset.set_projection(&try!(spat_ref.to_wkt())));
+
+    for (i, raster) in rasters.iter().enumerate() {
+        let buffer = Buffer::new((width, height), raster.buffer.clone());
+        try!(dataset.write_raster(
+            i as isize + 1,
+            (0, 0),
+            (width, height),
+            &buffer
+        ));
+    }
+    Ok(())
+}
+
+/// A band of a raster dataset, read in windows
+pub struct RasterReader {
+    dataset: Dataset,
+    band: usize,
+}
+
+impl RasterReader {
+    pub fn open<P: AsRef<Path>>(path: P, band: usize) -> Result<RasterReader> {
+        let dataset = try!(Dataset::open(path.as_ref()));
+        Ok(RasterReader { dataset, band })
+    }
+
+    /// Return the proj4 string of the raster and its transform from raster
+    /// space to world space, as returned by `import`
+    pub fn georeference(&self) -> Result<(String, AffineTransform)> {
+        let transform = try!(self.dataset.geo_transform());
+        let spat_ref = try!(SpatialRef::from_wkt(&self.dataset.projection()));
+        let proj4 = try!(spat_ref.to_proj4());
+        let (pw, ph) = (transform[1], transform[5] * -1.0);
+        let (xo, yo) = (transform[0], transform[3] * -1.0);
+        Ok((proj4, AffineTransform::new(xo, yo, pw, ph)))
+    }
+}
+
+impl<D> ChunkSource<D> for RasterReader
+where
+    D: Copy + Clone + Default + PartialEq + GdalRasterType<D>,
+{
+    fn size(&self) -> (usize, usize) {
+        self.dataset.size()
+    }
+
+    fn read(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> IoResult<Texture<D>> {
+        let size = (width, height);
+        let (_, _, mut rasters) =
+            read_window(&self.dataset, &[self.band], x, y, width, height, size)
+                .map_err(other)?;
+        Ok(rasters.remove(0))
+    }
+}
+
+/// A single band GeoTIFF, written in windows
+pub struct RasterWriter {
+    dataset: Dataset,
+}
+
+impl RasterWriter {
+    /// Create a raster with the size, transform and projection of another
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        like: &RasterReader,
+    ) -> Result<RasterWriter> {
+        let (width, height) = like.dataset.size();
+        let path = path.as_ref().to_string_lossy();
+        let driver = try!(Driver::get("GTiff"));
+        let dataset = try!(driver.create_with_band_type::<f64>(
+            &path,
+            width as isize,
+            height as isize,
+            1,
+        ));
+        try!(dataset.set_geo_transform(&try!(like.dataset.geo_transform())));
+        try!(dataset.set_projection(&like.dataset.projection()));
+        Ok(RasterWriter { dataset })
+    }
+}
+
+impl ChunkSink<f64> for RasterWriter {
+    fn write(
+        &mut self,
+        x: usize,
+        y: usize,
+        chunk: &Texture<f64>,
+    ) -> IoResult<()> {
+        let size = (chunk.width, chunk.height);
+        let buffer = Buffer::new(size, chunk.buffer.clone());
+        self.dataset
+            .write_raster(1, (x as isize, y as isize), size, &buffer)
+            .map_err(other)
+    }
+}
+
 /// Import all specified raster bands
 pub fn import<P, D>(
     path: P,
@@ -75,17 +275,113 @@ where
     import_rect(path, bands, 0, 0, width, height)
 }
 
+/// Return the last error reported by GDAL
+fn last_error() -> String {
+    // Safety: GDAL always returns a valid, nul terminated and thread local
+    // message, empty if there was no error, which is copied before any other
+    // call into GDAL can replace it
+    unsafe { CStr::from_ptr(gdal_sys::CPLGetLastErrorMsg()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Return the factors of the overviews of a raster, each half the size of
+/// the last, down to the first with no side longer than a size
+fn overview_factors(width: usize, height: usize, size: usize) -> Vec<usize> {
+    let mut factors = vec![];
+    let mut factor = 2;
+    while width.max(height) > size * factor / 2 {
+        factors.push(factor);
+        factor *= 2;
+    }
+    factors
+}
+
+/// Build overviews of all the bands of a raster, down to the first with no
+/// side longer than a size, returning the factors they are reduced by. They
+/// are written inside the raster, or to an `.ovr` file beside it, and are
+/// read in place of the raster by GDAL when it is imported at a resolution
+pub fn build_overviews<P>(
+    path: P,
+    resampling: &str,
+    internal: bool,
+    size: usize,
+) -> Result<Vec<usize>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref().to_string_lossy();
+    let c_path = try!(CString::new(path.as_bytes()));
+    let c_resampling = try!(CString::new(resampling.to_uppercase()));
+    let access = if internal {
+        GDALAccess::GA_Update
+    } else {
+        GDALAccess::GA_ReadOnly
+    };
+
+    // The `gdal` crate can only open datasets read only, and has no binding
+    // for building overviews, so these call into GDAL directly
+    //
+    // Safety: the path is nul terminated and outlives the call
+    let c_dataset = unsafe { gdal_sys::GDALOpen(c_path.as_ptr(), access) };
+    if c_dataset.is_null() {
+        return Err(ErrorKind::NullPointer {
+            method_name: "GDALOpen",
+            msg: last_error(),
+        }
+        .into());
+    }
+    // Closes the dataset when dropped, flushing the overviews
+    //
+    // Safety: the handle is non null, and owned by nothing else, so it is
+    // closed exactly once
+    let dataset = unsafe { Dataset::_with_c_ptr(c_dataset) };
+    let (width, height) = dataset.size();
+    let factors = overview_factors(width, height, size.max(1));
+    if factors.is_empty() {
+        return Ok(factors);
+    }
+
+    let mut levels: Vec<c_int> = factors.iter().map(|&f| f as c_int).collect();
+    // Safety: the dataset is open, the levels and resampling outlive the
+    // call, and a band count of zero with no band list selects all bands
+    let rv = unsafe {
+        gdal_sys::GDALBuildOverviews(
+            dataset._c_ptr(),
+            c_resampling.as_ptr(),
+            levels.len() as c_int,
+            levels.as_mut_ptr(),
+            0,
+            ptr::null_mut(),
+            None,
+            ptr::null_mut(),
+        )
+    };
+    if rv != CPLErr::CE_None {
+        return Err(ErrorKind::CplError {
+            class: rv,
+            // Safety: takes no arguments and only reads thread local state
+            number: unsafe { gdal_sys::CPLGetLastErrorNo() },
+            msg: last_error(),
+        }
+        .into());
+    }
+    Ok(factors)
+}
+
 // XXX: See https://github.com/georust/gdal/issues/48
 pub trait GdalRasterType<T>
 where
     T: Copy + Default + PartialEq,
 {
+    /// Read a window of a band, resampled to a size
     fn read_raster(
         raster: &RasterBand,
         x: isize,
         y: isize,
         width: usize,
         height: usize,
+        size: (usize, usize),
     ) -> Result<Vec<T>>;
 }
 
@@ -96,12 +392,103 @@ impl GdalRasterType<u8> for u8 {
         y: isize,
         width: usize,
         height: usize,
+        size: (usize, usize),
     ) -> Result<Vec<u8>> {
         let window = (width, height);
-        Ok(try!(raster.read_as::<u8>((x, y), window, window)).data)
+        Ok(try!(raster.read_as::<u8>((x, y), window, size)).data)
     }
 }
 
+/// Return true if a value is the no data value of a band
+fn is_no_data<T: Copy + Into<f64>>(value: T, nodata: Option<f64>) -> bool {
+    match nodata {
+        Some(nodata) if nodata.is_nan() => value.into().is_nan(),
+        Some(nodata) => (value.into() - nodata).abs() < EPSILON,
+        None => false,
+    }
+}
+
+/// Read integer values in their own type, with no data values set to zero
+macro_rules! integer_raster_type {
+    ($type:ty) => {
+        impl GdalRasterType<$type> for $type {
+            fn read_raster(
+                raster: &RasterBand,
+                x: isize,
+                y: isize,
+                width: usize,
+                height: usize,
+                size: (usize, usize),
+            ) -> Result<Vec<$type>> {
+                let window = (width, height);
+                let nodata = raster.no_data_value();
+                Ok(try!(raster.read_as::<$type>((x, y), window, size))
+                    .data
+                    .into_iter()
+                    .map(|d| if is_no_data(d, nodata) { 0 } else { d })
+                    .collect())
+            }
+        }
+    };
+}
+
+/// Read float values with no data values set to zero, and the scale and
+/// offset of the band applied
+macro_rules! float_raster_type {
+    ($type:ty) => {
+        impl GdalRasterType<$type> for $type {
+            fn read_raster(
+                raster: &RasterBand,
+                x: isize,
+                y: isize,
+                width: usize,
+                height: usize,
+                size: (usize, usize),
+            ) -> Result<Vec<$type>> {
+                let window = (width, height);
+                let nodata = raster.no_data_value();
+                let scale = raster.scale().unwrap_or(1.0) as $type;
+                let offset = raster.offset().unwrap_or(0.0) as $type;
+                Ok(try!(raster.read_as::<$type>((x, y), window, size))
+                    .data
+                    .into_iter()
+                    .map(|d| {
+                        if is_no_data(d, nodata) {
+                            0.0
+                        } else {
+                            d * scale + offset
+                        }
+                    })
+                    .collect())
+            }
+        }
+    };
+}
+
+integer_raster_type!(i16);
+integer_raster_type!(u16);
+integer_raster_type!(i32);
+float_raster_type!(f32);
+
+/// Widen values read in the type of a band, with no data values set to zero,
+/// and the scale and offset of the band applied to each
+fn widen<T: Copy + Into<f64>>(raster: &RasterBand, data: Vec<T>) -> Vec<f64> {
+    let nodata = raster.no_data_value();
+    let scale = raster.scale().unwrap_or(1.0);
+    let offset = raster.offset().unwrap_or(0.0);
+    data.into_iter()
+        .map(|d| {
+            if is_no_data(d, nodata) {
+                0.0
+            } else {
+                d.into() * scale + offset
+            }
+        })
+        .collect()
+}
+
+/// Read values in the type of the band, so integer elevations are not
+/// converted by GDAL before they are widened
 impl GdalRasterType<f64> for f64 {
     fn read_raster(
         raster: &RasterBand,
@@ -109,23 +496,52 @@ impl GdalRasterType<f64> for f64 {
         y: isize,
         width: usize,
         height: usize,
+        size: (usize, usize),
     ) -> Result<Vec<f64>> {
-        let window = (width, height);
-        let nodata = match raster.no_data_value() {
-            Some(val) => val,
-            None => Default::default(),
-        };
-
-        Ok(try!(raster.read_as::<f64>((x, y), window, window))
-            .data
-            .iter()
-            .map(|d| {
-                if (*d - nodata).abs() < EPSILON {
-                    Default::default()
-                } else {
-                    *d
-                }
-            })
-            .collect())
+        let (origin, window) = ((x, y), (width, height));
+        Ok(match raster.band_type() {
+            GDALDataType::GDT_Int16 => {
+                let data = try!(raster.read_as::<i16>(origin, window, size));
+                widen(raster, data.data)
+            }
+            GDALDataType::GDT_UInt16 => {
+                let data = try!(raster.read_as::<u16>(origin, window, size));
+                widen(raster, data.data)
+            }
+            GDALDataType::GDT_Int32 => {
+                let data = try!(raster.read_as::<i32>(origin, window, size));
+                widen(raster, data.data)
+            }
+            GDALDataType::GDT_Float32 => {
+                let data = try!(raster.read_as::<f32>(origin, window, size));
+                widen(raster, data.data)
+            }
+            _ => {
+                let data = try!(raster.read_as::<f64>(origin, window, size));
+                widen(raster, data.data)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_data_values() {
+        assert!(is_no_data(-32768_i16, Some(-32768.0)));
+        assert!(!is_no_data(0_i16, Some(-32768.0)));
+        assert!(is_no_data(::std::f32::NAN, Some(::std::f64::NAN)));
+        assert!(!is_no_data(1.5_f32, Some(::std::f64::NAN)));
+        assert!(!is_no_data(0_u16, None));
+    }
+
+    #[test]
+    fn choosing_overview_factors() {
+        assert_eq!(overview_factors(1000, 300, 256), vec![2, 4]);
+        assert_eq!(overview_factors(300, 1024, 256), vec![2, 4]);
+        assert_eq!(overview_factors(1025, 10, 256), vec![2, 4, 8]);
+        assert!(overview_factors(256, 256, 256).is_empty());
     }
 }
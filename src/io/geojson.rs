@@ -0,0 +1,329 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use linework::Linework;
+use math::Vec3;
+use ops::encode_srgb;
+use serde_json::{self, Value};
+use shapes::{LineString, Point, Polygon, Rect, Ring, Shape};
+use std::convert::AsRef;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Result};
+use std::path::Path;
+
+/// Export linework as line strings in view plane coordinates, and its labels
+/// as points at the top left of their text
+pub fn export<T>(path: T, linework: &Linework) -> Result<()>
+where
+    T: AsRef<Path>,
+{
+    let mut features: Vec<_> = linework
+        .polylines
+        .iter()
+        .map(|polyline| {
+            let coordinates: Vec<_> =
+                polyline.points.iter().map(|(x, y)| [*x, *y]).collect();
+            let color = encode_srgb(polyline.color);
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": coordinates,
+                },
+                "properties": {
+                    "stroke": format!(
+                        "#{:02x}{:02x}{:02x}",
+                        color.r, color.g, color.b
+                    ),
+                    "stroke-width": polyline.width,
+                },
+            })
+        })
+        .collect();
+
+    features.extend(linework.labels.iter().map(|label| {
+        let color = encode_srgb(label.color);
+        json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "Point",
+                "coordinates": [label.x, label.y],
+            },
+            "properties": {
+                "text": label.text,
+                "font-size": label.size,
+                "fill": format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b),
+            },
+        })
+    }));
+
+    let collection = json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+
+    let file = try!(File::create(path.as_ref()));
+    let writer = BufWriter::new(file);
+    try!(serde_json::to_writer(writer, &collection));
+    Ok(())
+}
+
+/// Convert a position to a point in scene coordinates
+fn position(value: &Value) -> Option<Vec3> {
+    let coords = value.as_array()?;
+    let x = coords.get(0)?.as_f64()?;
+    let y = coords.get(1)?.as_f64()?;
+    let z = coords.get(2).and_then(Value::as_f64).unwrap_or(0.0);
+    Some(Vec3::new(x, z, -y))
+}
+
+fn positions(value: &Value) -> Vec<Vec3> {
+    value
+        .as_array()
+        .map(|items| items.iter().filter_map(position).collect())
+        .unwrap_or_default()
+}
+
+fn polygon(value: &Value) -> Option<Shape> {
+    let rings: Vec<Ring> = value
+        .as_array()?
+        .iter()
+        .map(|ring| Ring::new(positions(ring)))
+        .collect();
+    let (exterior, holes) = rings.split_first()?;
+    Some(Shape::Polygon(Polygon::new(
+        exterior.clone(),
+        holes.to_vec(),
+    )))
+}
+
+/// Append the shapes of a GeoJSON object, of any type, to a list
+fn read_object(object: &Value, shapes: &mut Vec<Shape>) {
+    let coords = &object["coordinates"];
+    let items = || coords.as_array().into_iter().flat_map(|items| items);
+    match object["type"].as_str().unwrap_or("") {
+        "FeatureCollection" => {
+            for feature in object["features"].as_array().into_iter().flatten() {
+                read_object(feature, shapes);
+            }
+        }
+        "Feature" => read_object(&object["geometry"], shapes),
+        "GeometryCollection" => {
+            let geometries = object["geometries"].as_array();
+            for geometry in geometries.into_iter().flatten() {
+                read_object(geometry, shapes);
+            }
+        }
+        "Point" => {
+            shapes.extend(position(coords).map(|p| Shape::Point(Point::new(p))))
+        }
+        "MultiPoint" => shapes.extend(
+            positions(coords)
+                .into_iter()
+                .map(|p| Shape::Point(Point::new(p))),
+        ),
+        "LineString" => {
+            shapes.push(Shape::LineString(LineString::new(positions(coords))))
+        }
+        "MultiLineString" => {
+            shapes.extend(items().map(|line| {
+                Shape::LineString(LineString::new(positions(line)))
+            }))
+        }
+        "Polygon" => shapes.extend(polygon(coords)),
+        "MultiPolygon" => shapes.extend(items().filter_map(polygon)),
+        _ => (),
+    }
+}
+
+/// Import the shapes of a GeoJSON document, keeping only the parts within
+/// west, south, east and north bounds
+pub fn read(
+    document: &Value,
+    bounds: Option<(f64, f64, f64, f64)>,
+) -> Vec<Shape> {
+    let mut shapes = vec![];
+    read_object(document, &mut shapes);
+    match bounds {
+        Some((w, s, e, n)) => {
+            let rect = Rect::new(
+                Vec3::new(w, 0.0, -n),
+                Vec3::new(e, 0.0, -n),
+                Vec3::new(e, 0.0, -s),
+                Vec3::new(w, 0.0, -s),
+            );
+            shapes.iter().flat_map(|shape| shape.clip(&rect)).collect()
+        }
+        None => shapes,
+    }
+}
+
+/// Import the shapes of a GeoJSON file
+pub fn import<T>(
+    path: T,
+    bounds: Option<(f64, f64, f64, f64)>,
+) -> Result<Vec<Shape>>
+where
+    T: AsRef<Path>,
+{
+    let file = try!(File::open(path.as_ref()));
+    let document: Value = try!(serde_json::from_reader(BufReader::new(file))
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err)));
+    Ok(read(&document, bounds))
+}
+
+/// Return the text of a property of a feature, if it is a string or number
+fn name(feature: &Value, field: &str) -> Option<String> {
+    match feature["properties"][field] {
+        Value::String(ref text) => Some(text.clone()),
+        Value::Number(ref number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+/// Read the points of the features of a GeoJSON document, each paired with
+/// the text of a property, skipping features without one or outside west,
+/// south, east and north bounds
+pub fn read_names(
+    document: &Value,
+    field: &str,
+    bounds: Option<(f64, f64, f64, f64)>,
+) -> Vec<(Vec3, String)> {
+    let features = match document["type"].as_str() {
+        Some("FeatureCollection") => {
+            document["features"].as_array().cloned().unwrap_or_default()
+        }
+        Some("Feature") => vec![document.clone()],
+        _ => vec![],
+    };
+
+    let within = |point: &Vec3| match bounds {
+        Some((w, s, e, n)) => {
+            point.x >= w && point.x <= e && -point.z >= s && -point.z <= n
+        }
+        None => true,
+    };
+
+    let mut names = vec![];
+    for feature in &features {
+        let text = match name(feature, field) {
+            Some(text) => text,
+            None => continue,
+        };
+        let mut shapes = vec![];
+        read_object(&feature["geometry"], &mut shapes);
+        for shape in shapes {
+            if let Shape::Point(point) = shape {
+                let position = point.position();
+                if within(&position) {
+                    names.push((position, text.clone()));
+                }
+            }
+        }
+    }
+    names
+}
+
+/// Import the points of a GeoJSON file, each paired with the text of a
+/// property
+pub fn import_names<T>(
+    path: T,
+    field: &str,
+    bounds: Option<(f64, f64, f64, f64)>,
+) -> Result<Vec<(Vec3, String)>>
+where
+    T: AsRef<Path>,
+{
+    let file = try!(File::open(path.as_ref()));
+    let document: Value = try!(serde_json::from_reader(BufReader::new(file))
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err)));
+    Ok(read_names(&document, field, bounds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reading_features() {
+        let document = json!({
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": {"type": "Point", "coordinates": [1, 2, 3]},
+                },
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "MultiLineString",
+                        "coordinates": [[[0, 0], [1, 1]], [[2, 2], [3, 3]]],
+                    },
+                },
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 0]]],
+                    },
+                },
+            ],
+        });
+        let shapes = read(&document, None);
+        assert_eq!(shapes.len(), 4);
+        assert_eq!(
+            shapes[0],
+            Shape::Point(Point::new(Vec3::new(1.0, 3.0, -2.0)))
+        );
+        assert_eq!(shapes[3].lines()[0].len(), 4);
+
+        let shapes = read(&document, Some((-1.0, -1.0, 1.5, 1.5)));
+        assert_eq!(shapes.len(), 2);
+    }
+
+    #[test]
+    fn reading_names() {
+        let point = |x, y| json!({"type": "Point", "coordinates": [x, y]});
+        let document = json!({
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": point(1, 2),
+                    "properties": {"name": "Peak", "ele": 1200},
+                },
+                {
+                    "type": "Feature",
+                    "geometry": point(5, 5),
+                    "properties": {"ele": 900},
+                },
+                {
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "LineString",
+                        "coordinates": [[0, 0], [1, 1]],
+                    },
+                    "properties": {"name": "Ridge"},
+                },
+            ],
+        });
+        let names = read_names(&document, "name", None);
+        assert_eq!(names, vec![(Vec3::new(1.0, 0.0, -2.0), "Peak".to_owned())]);
+
+        let names = read_names(&document, "ele", Some((0.0, 0.0, 3.0, 3.0)));
+        assert_eq!(names.len(), 1);
+        assert_eq!(names[0].1, "1200");
+    }
+}
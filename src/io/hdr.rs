@@ -0,0 +1,173 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Loader for Radiance RGBE (`.hdr`) images, with flat or run length encoded
+//! scanlines and a standard `-Y height +X width` orientation.
+
+use super::invalid;
+use math::Vec3;
+use textures::Texture;
+
+use std::convert::AsRef;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Result};
+use std::path::Path;
+
+/// Convert a shared exponent pixel to linear values
+fn decode(rgbe: [u8; 4]) -> Vec3 {
+    if rgbe[3] == 0 {
+        return Vec3::zeros();
+    }
+    let scale = 2f64.powi(i32::from(rgbe[3]) - 136);
+    Vec3::new(
+        f64::from(rgbe[0]) * scale,
+        f64::from(rgbe[1]) * scale,
+        f64::from(rgbe[2]) * scale,
+    )
+}
+
+/// Read the components of a run length encoded scanline, each stored
+/// separately as runs or literal spans of bytes
+fn read_rle<R: Read>(reader: &mut R, width: usize) -> Result<Vec<[u8; 4]>> {
+    let mut scanline = vec![[0; 4]; width];
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0; 1];
+            try!(reader.read_exact(&mut count));
+            let (run, count) = if count[0] > 128 {
+                (true, count[0] as usize - 128)
+            } else {
+                (false, count[0] as usize)
+            };
+            if count == 0 || x + count > width {
+                return Err(invalid("Bad scanline length"));
+            }
+
+            let mut bytes = vec![0; if run { 1 } else { count }];
+            try!(reader.read_exact(&mut bytes));
+            for i in 0..count {
+                scanline[x + i][component] = bytes[if run { 0 } else { i }];
+            }
+            x += count;
+        }
+    }
+    Ok(scanline)
+}
+
+/// Read an image, with rows from top to bottom
+pub fn read<R: Read>(reader: R) -> Result<Texture<Vec3>> {
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    try!(reader.read_line(&mut line));
+    if !line.starts_with("#?") {
+        return Err(invalid("Not a Radiance image"));
+    }
+
+    // Header variables end with a blank line, before the resolution
+    loop {
+        line.clear();
+        if try!(reader.read_line(&mut line)) == 0 {
+            return Err(invalid("Missing resolution"));
+        }
+        let line = line.trim();
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid("Unsupported pixel format"));
+        }
+        if line.is_empty() {
+            break;
+        }
+    }
+
+    line.clear();
+    try!(reader.read_line(&mut line));
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let (height, width) = match parts[..] {
+        ["-Y", height, "+X", width] => match (height.parse(), width.parse()) {
+            (Ok(height), Ok(width)) => (height, width),
+            _ => return Err(invalid("Bad resolution")),
+        },
+        _ => return Err(invalid("Unsupported orientation")),
+    };
+
+    let mut texture = Texture::blank(width, height);
+    for y in 0..height {
+        let mut first = [0; 4];
+        try!(reader.read_exact(&mut first));
+        let rle = first[0] == 2
+            && first[1] == 2
+            && (usize::from(first[2]) << 8 | usize::from(first[3])) == width
+            && width >= 8
+            && width < 32768;
+
+        let scanline = if rle {
+            try!(read_rle(&mut reader, width))
+        } else {
+            let mut scanline = vec![first; width];
+            for pixel in scanline.iter_mut().skip(1) {
+                try!(reader.read_exact(pixel));
+            }
+            scanline
+        };
+        for (x, &pixel) in scanline.iter().enumerate() {
+            texture.write1x1(x, y, decode(pixel));
+        }
+    }
+
+    Ok(texture)
+}
+
+/// Import an image from a file
+pub fn import<P: AsRef<Path>>(path: P) -> Result<Texture<Vec3>> {
+    read(try!(File::open(path.as_ref())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(width: usize, height: usize) -> Vec<u8> {
+        let header = format!(
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            height, width
+        );
+        header.into_bytes()
+    }
+
+    #[test]
+    fn reading_flat_scanlines() {
+        let mut bytes = header(2, 1);
+        bytes.extend(&[128, 64, 0, 129, 0, 0, 0, 0]);
+        let image = read(&bytes[..]).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.buffer[0], Vec3::new(1.0, 0.5, 0.0));
+        assert_eq!(image.buffer[1], Vec3::zeros());
+    }
+
+    #[test]
+    fn reading_rle_scanlines() {
+        let mut bytes = header(8, 1);
+        bytes.extend(&[2, 2, 0, 8]);
+        // Red as a run, green as literals, blue and exponent as runs
+        bytes.extend(&[136, 128]);
+        bytes.extend(&[8, 0, 16, 32, 64, 0, 0, 0, 0]);
+        bytes.extend(&[136, 0]);
+        bytes.extend(&[136, 129]);
+        let image = read(&bytes[..]).unwrap();
+        assert_eq!(image.buffer[0], Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(image.buffer[3], Vec3::new(1.0, 0.5, 0.0));
+        assert_eq!(image.buffer[7], Vec3::new(1.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,338 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Reader for matrix and tone curve based ICC profiles, such as Adobe RGB,
+//! used to encode rendered colors for print.
+
+use super::invalid;
+use math::{Color, Vec3};
+
+use std::convert::AsRef;
+use std::fs::File;
+use std::io::{Read, Result};
+use std::path::Path;
+
+/// Number of samples of a tone curve used to invert it
+const CURVE_SAMPLES: usize = 4096;
+
+/// Linear sRGB to the D50 connection space of profiles, with Bradford
+/// chromatic adaptation from D65
+const SRGB_TO_XYZ: [[f64; 3]; 3] = [
+    [0.436_074_7, 0.385_064_9, 0.143_080_4],
+    [0.222_504_5, 0.716_878_6, 0.060_616_9],
+    [0.013_932_2, 0.097_104_5, 0.714_173_3],
+];
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    match data.get(offset..offset + 2) {
+        Some(bytes) => Ok(u16::from(bytes[0]) << 8 | u16::from(bytes[1])),
+        None => Err(invalid("Truncated profile")),
+    }
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    let high = try!(u16_at(data, offset));
+    let low = try!(u16_at(data, offset + 2));
+    Ok(u32::from(high) << 16 | u32::from(low))
+}
+
+/// Read a signed 15.16 fixed point number
+fn fixed_at(data: &[u8], offset: usize) -> Result<f64> {
+    Ok(f64::from(try!(u32_at(data, offset)) as i32) / 65536.0)
+}
+
+/// Return the contents of a tagged element
+fn tag<'a>(data: &'a [u8], signature: &[u8]) -> Result<&'a [u8]> {
+    let count = try!(u32_at(data, 128)) as usize;
+    for i in 0..count {
+        let entry = 132 + i * 12;
+        if data.get(entry..entry + 4) != Some(signature) {
+            continue;
+        }
+        let offset = try!(u32_at(data, entry + 4)) as usize;
+        let size = try!(u32_at(data, entry + 8)) as usize;
+        return data
+            .get(offset..offset + size)
+            .ok_or_else(|| invalid("Truncated profile"));
+    }
+    Err(invalid("Profile is not matrix based"))
+}
+
+fn xyz(data: &[u8], signature: &[u8]) -> Result<Vec3> {
+    let element = try!(tag(data, signature));
+    if element.get(0..4) != Some(b"XYZ ") {
+        return Err(invalid("Bad colorant tag"));
+    }
+    Ok(Vec3::new(
+        try!(fixed_at(element, 8)),
+        try!(fixed_at(element, 12)),
+        try!(fixed_at(element, 16)),
+    ))
+}
+
+/// Return samples of a tone curve, mapping encoded values to linear
+fn curve(data: &[u8], signature: &[u8]) -> Result<Vec<f64>> {
+    let element = try!(tag(data, signature));
+    let sample = |i: usize| i as f64 / (CURVE_SAMPLES - 1) as f64;
+    match element.get(0..4) {
+        Some(b"curv") => {
+            let count = try!(u32_at(element, 8)) as usize;
+            if count < 2 {
+                let gamma = if count == 0 {
+                    1.0
+                } else {
+                    f64::from(try!(u16_at(element, 12))) / 256.0
+                };
+                return Ok((0..CURVE_SAMPLES)
+                    .map(|i| sample(i).powf(gamma))
+                    .collect());
+            }
+            let mut table = Vec::with_capacity(count);
+            for i in 0..count {
+                let value = try!(u16_at(element, 12 + i * 2));
+                table.push(f64::from(value) / 65535.0);
+            }
+            Ok((0..CURVE_SAMPLES)
+                .map(|i| {
+                    let x = sample(i) * (count - 1) as f64;
+                    let j = (x as usize).min(count - 2);
+                    let t = x - j as f64;
+                    table[j] * (1.0 - t) + table[j + 1] * t
+                })
+                .collect())
+        }
+        Some(b"para") => {
+            let function = try!(u16_at(element, 8));
+            let lengths = [1, 3, 4, 5, 7];
+            let length = match lengths.get(function as usize) {
+                Some(length) => *length,
+                None => return Err(invalid("Unknown parametric curve")),
+            };
+            let mut p = [0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+            for (i, value) in p.iter_mut().enumerate().take(length) {
+                *value = try!(fixed_at(element, 12 + i * 4));
+            }
+            let (g, a, b, c, d, e, f) =
+                (p[0], p[1], p[2], p[3], p[4], p[5], p[6]);
+            Ok((0..CURVE_SAMPLES)
+                .map(|i| {
+                    let x = sample(i);
+                    let power =
+                        |offset: f64| (a * x + b).max(0.0).powf(g) + offset;
+                    match function {
+                        0 => x.powf(g),
+                        1 if x >= -b / a => power(0.0),
+                        2 if x >= -b / a => power(c),
+                        1 => 0.0,
+                        2 => c,
+                        3 if x >= d => power(0.0),
+                        4 if x >= d => power(e),
+                        3 => c * x,
+                        _ => c * x + f,
+                    }
+                })
+                .collect())
+        }
+        _ => Err(invalid("Unknown tone curve")),
+    }
+}
+
+/// Return the inverse of a matrix
+fn invert(m: [[f64; 3]; 3]) -> Result<[[f64; 3]; 3]> {
+    let cofactor = |r: usize, c: usize| {
+        let (r0, r1) = ((r + 1) % 3, (r + 2) % 3);
+        let (c0, c1) = ((c + 1) % 3, (c + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det = (0..3).map(|c| m[0][c] * cofactor(0, c)).sum::<f64>();
+    if det.abs() < 1e-12 {
+        return Err(invalid("Singular colorant matrix"));
+    }
+    let mut inverse = [[0.0; 3]; 3];
+    for (r, row) in inverse.iter_mut().enumerate() {
+        for (c, value) in row.iter_mut().enumerate() {
+            *value = cofactor(c, r) / det;
+        }
+    }
+    Ok(inverse)
+}
+
+fn multiply(a: [[f64; 3]; 3], b: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut output = [[0.0; 3]; 3];
+    for (r, row) in output.iter_mut().enumerate() {
+        for (c, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|i| a[r][i] * b[i][c]).sum();
+        }
+    }
+    output
+}
+
+/// An RGB color space described by primaries and tone curves
+#[derive(Clone, Debug, PartialEq)]
+pub struct IccProfile {
+    /// Name embedded alongside the profile in images
+    pub name: String,
+    /// The profile as read, for embedding in images
+    pub data: Vec<u8>,
+    /// Linear sRGB to linear profile RGB
+    matrix: [[f64; 3]; 3],
+    /// Samples of the tone curve of each channel, from encoded to linear
+    curves: [Vec<f64>; 3],
+}
+
+impl IccProfile {
+    /// Parse the contents of a profile
+    pub fn parse(name: &str, data: Vec<u8>) -> Result<IccProfile> {
+        if data.get(36..40) != Some(b"acsp") {
+            return Err(invalid("Not an ICC profile"));
+        }
+        if data.get(16..20) != Some(b"RGB ") {
+            return Err(invalid("Profile is not RGB"));
+        }
+
+        let (r, g, b) = (
+            try!(xyz(&data, b"rXYZ")),
+            try!(xyz(&data, b"gXYZ")),
+            try!(xyz(&data, b"bXYZ")),
+        );
+        let to_xyz = [[r.x, g.x, b.x], [r.y, g.y, b.y], [r.z, g.z, b.z]];
+        let matrix = multiply(try!(invert(to_xyz)), SRGB_TO_XYZ);
+        let curves = [
+            try!(curve(&data, b"rTRC")),
+            try!(curve(&data, b"gTRC")),
+            try!(curve(&data, b"bTRC")),
+        ];
+
+        Ok(IccProfile {
+            name: name.to_owned(),
+            data,
+            matrix,
+            curves,
+        })
+    }
+
+    /// Encode a linear sRGB color in the color space of the profile
+    pub fn encode(&self, val: Vec3) -> Color {
+        let linear = [val.x, val.y, val.z];
+        let mut output = [0; 3];
+        for (i, value) in output.iter_mut().enumerate() {
+            let row = self.matrix[i];
+            let y = (0..3).map(|j| row[j] * linear[j]).sum::<f64>();
+            let y = y.min(1.0).max(0.0);
+
+            // Find the encoded value whose linear value brackets the color
+            let curve = &self.curves[i];
+            let j =
+                match curve.binary_search_by(|v| v.partial_cmp(&y).unwrap()) {
+                    Ok(j) => j,
+                    Err(j) => j,
+                }
+                .max(1)
+                .min(curve.len() - 1);
+            let (y0, y1) = (curve[j - 1], curve[j]);
+            let t = if y1 > y0 { (y - y0) / (y1 - y0) } else { 0.0 };
+            let x = (j as f64 - 1.0 + t) / (curve.len() - 1) as f64;
+            *value = (x * 255.0).round().min(255.0).max(0.0) as u8;
+        }
+        Color::new(output[0], output[1], output[2])
+    }
+}
+
+/// Read a profile from a file, named after the file
+pub fn read<P: AsRef<Path>>(path: P) -> Result<IccProfile> {
+    let path = path.as_ref();
+    let mut data = vec![];
+    let mut file = try!(File::open(path));
+    try!(file.read_to_end(&mut data));
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "ICC profile".to_owned());
+    IccProfile::parse(&name, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_u32(data: &mut Vec<u8>, value: u32) {
+        data.extend(&[
+            (value >> 24) as u8,
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ]);
+    }
+
+    /// Build a profile with sRGB primaries and a single gamma curve
+    fn profile(gamma: Option<u16>) -> Vec<u8> {
+        let mut elements: Vec<(&[u8], Vec<u8>)> = vec![];
+        for (i, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].iter().enumerate() {
+            let mut element = b"XYZ \0\0\0\0".to_vec();
+            for row in &SRGB_TO_XYZ {
+                push_u32(&mut element, (row[i] * 65536.0).round() as u32);
+            }
+            elements.push((&signature[..], element));
+        }
+        let mut curve = b"curv\0\0\0\0".to_vec();
+        match gamma {
+            Some(gamma) => {
+                push_u32(&mut curve, 1);
+                curve.extend(&[(gamma >> 8) as u8, gamma as u8, 0, 0]);
+            }
+            None => push_u32(&mut curve, 0),
+        }
+        for signature in &[b"rTRC", b"gTRC", b"bTRC"] {
+            elements.push((&signature[..], curve.clone()));
+        }
+
+        let mut data = vec![0; 128];
+        data[16..20].copy_from_slice(b"RGB ");
+        data[36..40].copy_from_slice(b"acsp");
+        push_u32(&mut data, elements.len() as u32);
+        let mut offset = 132 + elements.len() * 12;
+        for &(signature, ref element) in &elements {
+            data.extend(signature);
+            push_u32(&mut data, offset as u32);
+            push_u32(&mut data, element.len() as u32);
+            offset += element.len();
+        }
+        for &(_, ref element) in &elements {
+            data.extend(element);
+        }
+        data
+    }
+
+    #[test]
+    fn encoding_linear_profiles() {
+        let profile = IccProfile::parse("linear", profile(None)).unwrap();
+        let color = profile.encode(Vec3::new(0.6, 0.25, 1.0));
+        assert_eq!(color, Color::new(153, 64, 255));
+    }
+
+    #[test]
+    fn encoding_gamma_profiles() {
+        // Adobe RGB stores its gamma of 2.2 as 563 / 256
+        let profile = IccProfile::parse("gamma", profile(Some(563))).unwrap();
+        let color = profile.encode(Vec3::new(0.5, 0.0, 1.0));
+        let expected = (0.5f64.powf(256.0 / 563.0) * 255.0).round() as u8;
+        assert_eq!(color, Color::new(expected, 0, 255));
+    }
+
+    #[test]
+    fn rejecting_other_files() {
+        assert!(IccProfile::parse("empty", vec![0; 256]).is_err());
+    }
+}
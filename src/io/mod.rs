@@ -13,6 +13,35 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod cache;
+pub mod egm96;
+#[cfg(feature = "gdal")]
 pub mod gdal;
+pub mod geojson;
+pub mod hdr;
+pub mod icc;
+#[cfg(feature = "gdal")]
 pub mod ogr;
+#[cfg(feature = "gdal")]
+pub mod osm;
+pub mod package;
+pub mod partial;
+pub mod pdf;
 pub mod png;
+pub mod raster;
+#[cfg(feature = "gdal")]
+pub mod remote;
+pub mod svg;
+
+use std::io::{Error, ErrorKind};
+
+/// Return an error for data that is not in the expected format
+pub fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Convert an error from GDAL to an IO error
+#[cfg(feature = "gdal")]
+pub fn other<E: ::std::fmt::Display>(err: E) -> Error {
+    Error::new(ErrorKind::Other, err.to_string())
+}
@@ -17,29 +17,143 @@ use std::convert::AsRef;
 use std::path::Path;
 
 use gdal::errors::Result;
-use gdal::vector::{Dataset, Geometry, OGRwkbGeometryType};
+use gdal::vector::{
+    Dataset, Feature, FieldValue, Geometry, OGRwkbGeometryType,
+};
 
 use math::Vec3;
-use shapes::{LineString, Point, Polygon, Ring, Shape};
+use shapes::{LineString, Point, Polygon, Rect, Ring, Shape};
 
-/// Import geometry in multiple layers from an OGR supported file
-pub fn import<P>(path: P, layers: &[String]) -> Result<Vec<Vec<Shape>>>
+/// Import geometry in multiple layers from an OGR supported file, keeping only
+/// the parts within west, south, east and north bounds in dataset coordinates,
+/// if given. Empty layers give no shapes
+pub fn import_within<P>(
+    path: P,
+    layers: &[String],
+    bounds: Option<(f64, f64, f64, f64)>,
+) -> Result<Vec<Vec<Shape>>>
 where
     P: AsRef<Path>,
+{
+    let layers = try!(read(path, layers, bounds, |_, _| Some(())));
+    Ok(layers
+        .into_iter()
+        .map(|shapes| shapes.into_iter().map(|(shape, _)| shape).collect())
+        .collect())
+}
+
+/// Import geometry from a layer of an OGR supported file, along with the
+/// numeric value of an attribute of each feature, if it has one
+pub fn import_attribute<P>(
+    path: P,
+    layer: &str,
+    field: &str,
+    bounds: Option<(f64, f64, f64, f64)>,
+) -> Result<Vec<(Shape, Option<f64>)>>
+where
+    P: AsRef<Path>,
+{
+    let mut layers =
+        try!(read(path, &[layer.to_owned()], bounds, |_, feature| {
+            Some(match feature.field(field) {
+                Ok(FieldValue::RealValue(value)) => Some(value),
+                Ok(FieldValue::IntegerValue(value)) => Some(f64::from(value)),
+                Ok(FieldValue::StringValue(value)) => value.trim().parse().ok(),
+                Err(_) => None,
+            })
+        }));
+    Ok(layers.remove(0))
+}
+
+/// Import the points of a layer of an OGR supported file, each paired with
+/// the text of an attribute, skipping features without one
+pub fn import_names<P>(
+    path: P,
+    layer: &str,
+    field: &str,
+    bounds: Option<(f64, f64, f64, f64)>,
+) -> Result<Vec<(Vec3, String)>>
+where
+    P: AsRef<Path>,
+{
+    let mut layers =
+        try!(read(path, &[layer.to_owned()], bounds, |_, feature| {
+            match feature.field(field) {
+                Ok(FieldValue::StringValue(value)) => Some(value),
+                Ok(FieldValue::IntegerValue(value)) => Some(value.to_string()),
+                Ok(FieldValue::RealValue(value)) => Some(value.to_string()),
+                Err(_) => None,
+            }
+        }));
+    Ok(layers
+        .remove(0)
+        .into_iter()
+        .filter_map(|(shape, text)| match shape {
+            Shape::Point(point) => Some((point.position(), text)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Read the shapes of each feature in some layers, paired with a value taken
+/// from the feature and its layer's field names, skipping features without one
+pub fn read<P, T, F>(
+    path: P,
+    layers: &[String],
+    bounds: Option<(f64, f64, f64, f64)>,
+    value: F,
+) -> Result<Vec<Vec<(Shape, T)>>>
+where
+    P: AsRef<Path>,
+    T: Clone,
+    F: Fn(&[String], &Feature) -> Option<T>,
 {
     let mut dataset = try!(Dataset::open(path.as_ref()));
     let mut output = Vec::with_capacity(layers.len());
 
+    let filter = match bounds {
+        Some((w, s, e, n)) => Some(try!(Geometry::bbox(w, s, e, n))),
+        None => None,
+    };
+    let rect = bounds.map(|(w, s, e, n)| {
+        Rect::new(
+            Vec3::new(w, 0.0, -n),
+            Vec3::new(e, 0.0, -n),
+            Vec3::new(e, 0.0, -s),
+            Vec3::new(w, 0.0, -s),
+        )
+    });
+
     for name in layers {
         let input_layer = try!(dataset.layer_by_name(name));
+        if let Some(ref filter) = filter {
+            input_layer.set_spatial_filter(filter);
+        }
+        let fields: Vec<String> = input_layer
+            .defn()
+            .fields()
+            .map(|field| field.name())
+            .collect();
+
         let mut shapes = vec![];
         for feature in input_layer.features() {
+            let value = match value(&fields, &feature) {
+                Some(value) => value,
+                None => continue,
+            };
             let geometry = feature.geometry();
             for shape in from(geometry) {
-                shapes.push(shape);
+                match rect {
+                    Some(ref rect) => shapes.extend(
+                        shape
+                            .clip(rect)
+                            .into_iter()
+                            .map(|shape| (shape, value.clone())),
+                    ),
+                    None => shapes.push((shape, value.clone())),
+                }
             }
         }
-        assert!(shapes.len() > 0);
         output.push(shapes);
     }
 
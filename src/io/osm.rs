@@ -0,0 +1,211 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+use std::convert::AsRef;
+use std::path::Path;
+
+use gdal::errors::Result;
+use gdal::vector::FieldValue;
+
+use super::ogr;
+use shapes::Shape;
+
+/// Tags of an OpenStreetMap element
+pub type Tags = BTreeMap<String, String>;
+
+/// Fields added by the OGR driver that are not tags of the element
+const RESERVED: &[&str] = &[
+    "osm_id",
+    "osm_way_id",
+    "osm_version",
+    "osm_timestamp",
+    "osm_uid",
+    "osm_user",
+    "osm_changeset",
+    "other_tags",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Term {
+    Present(String),
+    Absent(String),
+    OneOf(String, Vec<String>),
+    NoneOf(String, Vec<String>),
+}
+
+/// A conjunction of conditions on tags, written as comma separated terms of
+/// `key`, `!key`, `key=a|b` or `key!=a|b`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TagFilter {
+    terms: Vec<Term>,
+}
+
+impl TagFilter {
+    pub fn new(expression: &str) -> TagFilter {
+        let values = |values: &str| {
+            values
+                .split('|')
+                .map(|value| value.trim().to_owned())
+                .collect()
+        };
+
+        let terms = expression
+            .split(',')
+            .map(str::trim)
+            .filter(|term| !term.is_empty())
+            .map(|term| {
+                if let Some(i) = term.find("!=") {
+                    let key = term[..i].trim().to_owned();
+                    Term::NoneOf(key, values(&term[i + 2..]))
+                } else if let Some(i) = term.find('=') {
+                    let key = term[..i].trim().to_owned();
+                    Term::OneOf(key, values(&term[i + 1..]))
+                } else if term.starts_with('!') {
+                    Term::Absent(term[1..].trim().to_owned())
+                } else {
+                    Term::Present(term.to_owned())
+                }
+            })
+            .collect();
+
+        TagFilter { terms }
+    }
+
+    /// Return true if tags satisfy every term of the filter
+    pub fn matches(&self, tags: &Tags) -> bool {
+        self.terms.iter().all(|term| match *term {
+            Term::Present(ref key) => tags.contains_key(key),
+            Term::Absent(ref key) => !tags.contains_key(key),
+            Term::OneOf(ref key, ref values) => match tags.get(key) {
+                Some(value) => values.contains(value),
+                None => false,
+            },
+            Term::NoneOf(ref key, ref values) => match tags.get(key) {
+                Some(value) => !values.contains(value),
+                None => true,
+            },
+        })
+    }
+}
+
+/// Parse the `"key"=>"value"` pairs the OGR driver collects in `other_tags`
+fn parse_other_tags(text: &str, tags: &mut Tags) {
+    let mut strings = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => current.extend(chars.next()),
+            '"' if quoted => {
+                strings.push(current.clone());
+                current.clear();
+                quoted = false;
+            }
+            '"' => quoted = true,
+            _ if quoted => current.push(c),
+            _ => (),
+        }
+    }
+
+    for pair in strings.chunks(2) {
+        if let [ref key, ref value] = *pair {
+            tags.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+/// Import the shapes of elements from an OpenStreetMap file, such as an
+/// `.osm.pbf` extract, with tags matching a filter. The layer is one of those
+/// made by the OGR driver: points, lines, multilinestrings, multipolygons or
+/// other_relations.
+pub fn import<P>(
+    path: P,
+    layer: &str,
+    filter: &TagFilter,
+    bounds: Option<(f64, f64, f64, f64)>,
+) -> Result<Vec<(Shape, Tags)>>
+where
+    P: AsRef<Path>,
+{
+    let names = [layer.to_owned()];
+    let mut layers =
+        try!(ogr::read(path, &names, bounds, |fields, feature| {
+            let mut tags = Tags::new();
+            for field in fields {
+                let value = match feature.field(field) {
+                    Ok(FieldValue::StringValue(value)) => value,
+                    Ok(FieldValue::IntegerValue(value)) => value.to_string(),
+                    Ok(FieldValue::RealValue(value)) => value.to_string(),
+                    Err(_) => continue,
+                };
+                if value.is_empty() {
+                    continue;
+                }
+                if field == "other_tags" {
+                    parse_other_tags(&value, &mut tags);
+                } else if !RESERVED.contains(&field.as_str()) {
+                    tags.insert(field.clone(), value);
+                }
+            }
+
+            if filter.matches(&tags) {
+                Some(tags)
+            } else {
+                None
+            }
+        }));
+    Ok(layers.remove(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> Tags {
+        pairs
+            .iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn filtering_tags() {
+        let filter = TagFilter::new("highway=primary|secondary, tunnel!=yes");
+        assert!(filter.matches(&tags(&[("highway", "primary")])));
+        assert!(!filter.matches(&tags(&[("highway", "track")])));
+        assert!(!filter
+            .matches(&tags(&[("highway", "secondary"), ("tunnel", "yes"),])));
+
+        let filter = TagFilter::new("natural, !name");
+        assert!(filter.matches(&tags(&[("natural", "water")])));
+        assert!(!filter.matches(&tags(&[("natural", "peak"), ("name", "x")])));
+        assert!(TagFilter::new("").matches(&Tags::new()));
+    }
+
+    #[test]
+    fn parsing_other_tags() {
+        let mut output = Tags::new();
+        parse_other_tags(
+            r#""surface"=>"gravel","name"=>"Say \"hi\"""#,
+            &mut output,
+        );
+        assert_eq!(
+            output,
+            tags(&[("surface", "gravel"), ("name", "Say \"hi\"")])
+        );
+    }
+}
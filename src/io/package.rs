@@ -0,0 +1,228 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! A single file archive of named entries, written once and read an entry at
+//! a time. An index of entry names, offsets and lengths follows a header, then
+//! the entries themselves.
+
+use super::cache::Raster;
+use super::invalid;
+use math::AffineTransform;
+use textures::Texture;
+
+use std::fs::File;
+use std::io::{
+    BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write,
+};
+use std::path::Path;
+
+/// Identifies a package file, and the version of its layout
+const MAGIC: &[u8; 8] = b"PEAKSPK1";
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut bytes = [0; 8];
+    try!(reader.read_exact(&mut bytes));
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Read a length prefixed run of bytes, of no more than a number of bytes
+fn read_bytes<R: Read>(reader: &mut R, limit: u64) -> Result<Vec<u8>> {
+    let length = try!(read_u64(reader));
+    if length > limit {
+        return Err(invalid("Entry runs past the end of the package"));
+    }
+    let mut bytes = vec![0; length as usize];
+    try!(reader.read_exact(&mut bytes));
+    Ok(bytes)
+}
+
+/// Entries collected in memory before being written to a package
+#[derive(Default)]
+pub struct PackageWriter {
+    entries: Vec<(String, Vec<u8>)>,
+}
+
+impl PackageWriter {
+    pub fn new() -> PackageWriter {
+        Default::default()
+    }
+
+    pub fn add(&mut self, name: &str, bytes: Vec<u8>) {
+        self.entries.push((name.to_owned(), bytes));
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        try!(writer.write_all(MAGIC));
+        try!(write_u64(writer, self.entries.len() as u64));
+
+        let header = self
+            .entries
+            .iter()
+            .fold(16, |size, &(ref name, _)| size + 24 + name.len() as u64);
+        let mut offset = header;
+        for &(ref name, ref bytes) in &self.entries {
+            try!(write_u64(writer, name.len() as u64));
+            try!(writer.write_all(name.as_bytes()));
+            try!(write_u64(writer, offset));
+            try!(write_u64(writer, bytes.len() as u64));
+            offset += bytes.len() as u64;
+        }
+        for &(_, ref bytes) in &self.entries {
+            try!(writer.write_all(bytes));
+        }
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(try!(File::create(path)));
+        try!(self.write(&mut writer));
+        writer.flush()
+    }
+}
+
+/// Read an entry of a package by name
+pub fn read_entry<R: Read + Seek>(
+    reader: &mut R,
+    name: &str,
+) -> Result<Vec<u8>> {
+    let start = try!(reader.stream_position());
+    let size = try!(reader.seek(SeekFrom::End(0))) - start;
+    try!(reader.seek(SeekFrom::Start(start)));
+
+    let mut magic = [0; 8];
+    try!(reader.read_exact(&mut magic));
+    if &magic != MAGIC {
+        return Err(invalid("Not a Peaks package"));
+    }
+
+    let count = try!(read_u64(reader));
+    for _ in 0..count {
+        let entry = try!(read_bytes(reader, size));
+        let offset = try!(read_u64(reader));
+        let length = try!(read_u64(reader));
+        if entry == name.as_bytes() {
+            match offset.checked_add(length) {
+                Some(end) if end <= size => (),
+                _ => {
+                    return Err(invalid(
+                        "Entry runs past the end of the package",
+                    ))
+                }
+            }
+            try!(reader.seek(SeekFrom::Start(start + offset)));
+            let mut bytes = vec![0; length as usize];
+            try!(reader.read_exact(&mut bytes));
+            return Ok(bytes);
+        }
+    }
+
+    let message = format!("No entry {} in package", name);
+    Err(Error::new(ErrorKind::NotFound, message))
+}
+
+/// Read an entry of a package file by name
+pub fn open_entry<P: AsRef<Path>>(path: P, name: &str) -> Result<Vec<u8>> {
+    let mut reader = BufReader::new(try!(File::open(path)));
+    read_entry(&mut reader, name)
+}
+
+/// Encode a raster band, with its projection and transform
+pub fn encode_raster(raster: &Raster) -> Vec<u8> {
+    let (ref proj4, ref transform, ref texture) = *raster;
+    let mut bytes = Vec::with_capacity(64 + texture.buffer.len() * 8);
+    bytes.extend_from_slice(&(proj4.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(proj4.as_bytes());
+    for value in &transform.coefficients() {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&(texture.width as u64).to_le_bytes());
+    bytes.extend_from_slice(&(texture.height as u64).to_le_bytes());
+    for value in &texture.buffer {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes
+}
+
+/// Decode a raster band written by `encode_raster`
+pub fn decode_raster(bytes: &[u8]) -> Result<Raster> {
+    let mut reader = bytes;
+    let limit = reader.len() as u64;
+    let proj4 = try!(String::from_utf8(try!(read_bytes(&mut reader, limit)))
+        .map_err(|_| invalid("Bad projection in package")));
+    let mut coefficients = [0.0; 4];
+    for value in coefficients.iter_mut() {
+        *value = f64::from_bits(try!(read_u64(&mut reader)));
+    }
+    let width = try!(read_u64(&mut reader)) as usize;
+    let height = try!(read_u64(&mut reader)) as usize;
+    let length = width.checked_mul(height).and_then(|n| n.checked_mul(8));
+    if length != Some(reader.len()) {
+        return Err(invalid("Truncated raster in package"));
+    }
+    let buffer = reader
+        .chunks(8)
+        .map(|chunk| {
+            let mut value = [0; 8];
+            value.copy_from_slice(chunk);
+            f64::from_le_bytes(value)
+        })
+        .collect();
+    let transform = AffineTransform::from(coefficients);
+    Ok((proj4, transform, Texture::new(width, height, buffer)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn reading_entries() {
+        let mut package = PackageWriter::new();
+        package.add("scene.json", b"{}".to_vec());
+        package.add("data/0", vec![1, 2, 3]);
+        let mut bytes = vec![];
+        package.write(&mut bytes).unwrap();
+
+        let mut reader = Cursor::new(bytes);
+        assert_eq!(read_entry(&mut reader, "data/0").unwrap(), vec![1, 2, 3]);
+        reader.set_position(0);
+        assert_eq!(read_entry(&mut reader, "scene.json").unwrap(), b"{}");
+        reader.set_position(0);
+        assert!(read_entry(&mut reader, "data/1").is_err());
+
+        // Lengths past the end of the package are rejected before allocating
+        let mut bytes = reader.into_inner();
+        let length = 16 + 8 + "scene.json".len() + 8;
+        bytes[length..length + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let err = read_entry(&mut Cursor::new(bytes), "scene.json");
+        assert_eq!(err.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn encoding_rasters() {
+        let transform = AffineTransform::new(-10.0, -50.0, 0.5, 0.25);
+        let texture = Texture::new(3, 2, vec![1.0, 2.5, -3.0, 4.0, 0.0, 6.0]);
+        let raster = (String::from("+proj=longlat"), transform, texture);
+        let decoded = decode_raster(&encode_raster(&raster)).unwrap();
+        assert_eq!(decoded.0, raster.0);
+        assert_eq!(decoded.1.coefficients(), transform.coefficients());
+        assert_eq!(decoded.2, raster.2);
+    }
+}
@@ -0,0 +1,272 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Files of the tiles of an image rendered by one of several machines, each
+//! rendering a range of its tiles, merged into the whole image once all of
+//! the ranges are rendered.
+
+use super::invalid;
+use chunks::ChunkSink;
+use math::Vec3;
+use ops::blit_region;
+use textures::{Texture, Tile};
+
+use std::cmp;
+use std::collections::{BTreeMap, HashSet};
+use std::convert::AsRef;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Result, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Identifies a file of tiles, and the version of its layout
+const MAGIC: &[u8; 8] = b"PEAKSPT1";
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut bytes = [0; 8];
+    try!(reader.read_exact(&mut bytes));
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Write the tiles of an image of a size, each followed by its linear pixels
+pub fn write<W: Write>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    tiles: &[(Tile, Texture<Vec3>)],
+) -> Result<()> {
+    try!(writer.write_all(MAGIC));
+    try!(write_u64(writer, width as u64));
+    try!(write_u64(writer, height as u64));
+    try!(write_u64(writer, tiles.len() as u64));
+    for &(ref tile, ref pixels) in tiles {
+        for &value in &[tile.x, tile.y, tile.width, tile.height] {
+            try!(write_u64(writer, value as u64));
+        }
+        for pixel in &pixels.buffer {
+            for &value in &[pixel.x, pixel.y, pixel.z] {
+                try!(writer.write_all(&(value as f32).to_le_bytes()));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn save<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+    tiles: &[(Tile, Texture<Vec3>)],
+) -> Result<()> {
+    let file = try!(File::create(path));
+    let mut writer = BufWriter::new(file);
+    try!(write(&mut writer, width, height, tiles));
+    writer.flush()
+}
+
+/// The tiles of a file and the offsets of their pixels
+fn read_index<R: Read + Seek>(
+    reader: &mut R,
+) -> Result<(usize, usize, Vec<(Tile, u64)>)> {
+    let mut magic = [0; 8];
+    try!(reader.read_exact(&mut magic));
+    if &magic != MAGIC {
+        return Err(invalid("Not a file of rendered tiles"));
+    }
+
+    let width = try!(read_u64(reader)) as usize;
+    let height = try!(read_u64(reader)) as usize;
+    let count = try!(read_u64(reader));
+    let mut tiles = vec![];
+    for _ in 0..count {
+        let mut rect = [0; 4];
+        for value in &mut rect {
+            *value = try!(read_u64(reader)) as usize;
+        }
+        let [x, y, w, h] = rect;
+        if x > width || w > width - x || y > height || h > height - y {
+            return Err(invalid("Tile outside of the image"));
+        }
+        let offset = try!(reader.seek(SeekFrom::Current(0)));
+        let length = (w * h * 3 * 4) as i64;
+        try!(reader.seek(SeekFrom::Current(length)));
+        tiles.push((Tile::new(x, y, w, h), offset));
+    }
+    Ok((width, height, tiles))
+}
+
+/// Read the pixels of a tile at an offset
+fn read_tile<R: Read + Seek>(
+    reader: &mut R,
+    tile: &Tile,
+    offset: u64,
+) -> Result<Texture<Vec3>> {
+    try!(reader.seek(SeekFrom::Start(offset)));
+    let mut bytes = vec![0; tile.width * tile.height * 3 * 4];
+    try!(reader.read_exact(&mut bytes));
+    let values: Vec<f64> = bytes
+        .chunks(4)
+        .map(|b| f64::from(f32::from_le_bytes([b[0], b[1], b[2], b[3]])))
+        .collect();
+    let pixels = values
+        .chunks(3)
+        .map(|v| Vec3::new(v[0], v[1], v[2]))
+        .collect();
+    Ok(Texture::new(tile.width, tile.height, pixels))
+}
+
+/// Files of tiles which together cover an image exactly once
+pub struct Partials {
+    pub width: usize,
+    pub height: usize,
+    files: Vec<BufReader<File>>,
+    /// Tiles by their row, each with its file and offset
+    rows: BTreeMap<usize, Vec<(Tile, usize, u64)>>,
+}
+
+impl Partials {
+    pub fn open<P: AsRef<Path>>(paths: &[P]) -> Result<Partials> {
+        let mut size = None;
+        let mut files = vec![];
+        let mut indexed = vec![];
+        for (i, path) in paths.iter().enumerate() {
+            let mut reader = BufReader::new(try!(File::open(path)));
+            let (width, height, tiles) = try!(read_index(&mut reader));
+            if *size.get_or_insert((width, height)) != (width, height) {
+                return Err(invalid("Tiles are of images of different sizes"));
+            }
+            indexed.extend(tiles.into_iter().map(|(tile, o)| (tile, i, o)));
+            files.push(reader);
+        }
+
+        // Tiles must fall on the grid of the largest, so tiles of renders
+        // with different tile sizes can not overlap
+        let (width, height) = size.unwrap_or((0, 0));
+        let grid = indexed
+            .iter()
+            .map(|&(tile, _, _)| cmp::max(tile.width, tile.height))
+            .fold(1, cmp::max);
+        let mut rows: BTreeMap<usize, Vec<_>> = BTreeMap::new();
+        let mut seen = HashSet::new();
+        let mut covered = 0;
+        for (tile, i, offset) in indexed {
+            if tile.x % grid != 0
+                || tile.y % grid != 0
+                || tile.width != cmp::min(grid, width - tile.x)
+                || tile.height != cmp::min(grid, height - tile.y)
+            {
+                return Err(invalid("Tiles were rendered at different sizes"));
+            }
+            if !seen.insert((tile.x, tile.y)) {
+                return Err(invalid("A tile was rendered more than once"));
+            }
+            covered += tile.width * tile.height;
+            rows.entry(tile.y).or_default().push((tile, i, offset));
+        }
+
+        if covered != width * height {
+            return Err(invalid("Tiles are missing from the image"));
+        }
+        Ok(Partials {
+            width,
+            height,
+            files,
+            rows,
+        })
+    }
+
+    /// Write the image to a sink, one row of tiles at a time
+    pub fn merge(&mut self, sink: &mut ChunkSink<Vec3>) -> Result<()> {
+        for (&y, tiles) in &self.rows {
+            let rows = tiles.iter().map(|&(tile, _, _)| tile.height).max();
+            let mut strip = Texture::blank(self.width, rows.unwrap_or(0));
+            for &(ref tile, file, offset) in tiles {
+                let pixels =
+                    try!(read_tile(&mut self.files[file], tile, offset));
+                blit_region(
+                    &pixels,
+                    &mut strip,
+                    tile.x,
+                    0,
+                    tile.width,
+                    tile.height,
+                );
+            }
+            try!(sink.write(0, y, &strip));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn merging_partial_renders() {
+        let (width, height) = (5, 3);
+        let image: Vec<Vec3> = (0..width * height)
+            .map(|i| Vec3::new(i as f64, 0.5, -(i as f64) * 0.25))
+            .collect();
+        let tile = |x, y, w, h| {
+            let mut pixels = Texture::blank(w, h);
+            for row in 0..h {
+                for column in 0..w {
+                    let pixel = image[(y + row) * width + x + column];
+                    pixels.write1x1(column, row, pixel);
+                }
+            }
+            (Tile::new(x, y, w, h), pixels)
+        };
+
+        let dir = env::temp_dir();
+        let (first, second) =
+            (dir.join("peaks-tiles-1"), dir.join("peaks-tiles-2"));
+        save(&first, width, height, &[tile(0, 0, 2, 2), tile(2, 0, 2, 2)])
+            .unwrap();
+        save(
+            &second,
+            width,
+            height,
+            &[tile(4, 0, 1, 2), tile(0, 2, 2, 1)],
+        )
+        .unwrap();
+        assert!(Partials::open(&[&first, &second]).is_err());
+        assert!(Partials::open(&[&first, &first]).is_err());
+
+        // Tiles of different sizes covering every pixel, some twice
+        let overlapping = dir.join("peaks-tiles-4");
+        save(
+            &overlapping,
+            width,
+            height,
+            &[tile(0, 0, 3, 3), tile(2, 0, 3, 2)],
+        )
+        .unwrap();
+        assert!(Partials::open(&[&overlapping]).is_err());
+
+        let third = dir.join("peaks-tiles-3");
+        save(&third, width, height, &[tile(2, 2, 2, 1), tile(4, 2, 1, 1)])
+            .unwrap();
+        let mut partials = Partials::open(&[&third, &first, &second]).unwrap();
+        let mut merged = Texture::blank(width, height);
+        partials.merge(&mut merged).unwrap();
+        assert_eq!(merged.buffer, image);
+    }
+}
@@ -0,0 +1,136 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use labels::BASELINE;
+use linework::Linework;
+use ops::encode_srgb;
+use std::convert::AsRef;
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::Path;
+
+/// Return text as a PDF string literal in the WinAnsi encoding, replacing
+/// characters it does not have
+fn string(text: &str) -> String {
+    let mut output = String::from("(");
+    for c in text.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                output.push('\\');
+                output.push(c);
+            }
+            ' '..='~' => output.push(c),
+            '\u{a0}'..='\u{ff}' => {
+                output.push_str(&format!("\\{:03o}", c as u32))
+            }
+            _ => output.push('?'),
+        }
+    }
+    output.push(')');
+    output
+}
+
+/// Return the drawing operators for the linework as a PDF content stream
+fn content(linework: &Linework) -> String {
+    let height = linework.height as f64;
+    let mut stream = String::from("1 J 1 j\n");
+
+    for polyline in &linework.polylines {
+        let color = encode_srgb(polyline.color);
+        stream.push_str(&format!(
+            "{:.3} {:.3} {:.3} RG {} w\n",
+            f64::from(color.r) / 255.0,
+            f64::from(color.g) / 255.0,
+            f64::from(color.b) / 255.0,
+            polyline.width
+        ));
+
+        // PDF places the origin at the bottom left of the page
+        for (i, (x, y)) in polyline.points.iter().enumerate() {
+            let op = if i == 0 { "m" } else { "l" };
+            stream.push_str(&format!("{:.2} {:.2} {}\n", x, height - y, op));
+        }
+        stream.push_str("S\n");
+    }
+
+    for label in &linework.labels {
+        let color = encode_srgb(label.color);
+        stream.push_str(&format!(
+            "BT /F1 {} Tf {:.3} {:.3} {:.3} rg {:.2} {:.2} Td {} Tj ET\n",
+            label.size,
+            f64::from(color.r) / 255.0,
+            f64::from(color.g) / 255.0,
+            f64::from(color.b) / 255.0,
+            label.x,
+            height - label.y - label.size * BASELINE,
+            string(&label.text)
+        ));
+    }
+
+    stream
+}
+
+pub fn export<T>(path: T, linework: &Linework) -> Result<()>
+where
+    T: AsRef<Path>,
+{
+    let stream = content(linework);
+    let objects = vec![
+        String::from("<< /Type /Catalog /Pages 2 0 R >>"),
+        String::from("<< /Type /Pages /Kids [3 0 R] /Count 1 >>"),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+             /Resources << /Font << /F1 5 0 R >> >> /Contents 4 0 R >>",
+            linework.width, linework.height
+        ),
+        format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            stream.len(),
+            stream
+        ),
+        String::from(
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica \
+             /Encoding /WinAnsiEncoding >>",
+        ),
+    ];
+
+    let mut bytes = Vec::new();
+    let mut offsets = Vec::with_capacity(objects.len());
+    try!(write!(bytes, "%PDF-1.4\n"));
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(bytes.len());
+        try!(write!(bytes, "{} 0 obj\n{}\nendobj\n", i + 1, object));
+    }
+
+    let xref = bytes.len();
+    try!(write!(
+        bytes,
+        "xref\n0 {}\n0000000000 65535 f \n",
+        objects.len() + 1
+    ));
+    for offset in offsets {
+        try!(write!(bytes, "{:010} 00000 n \n", offset));
+    }
+    try!(write!(
+        bytes,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    ));
+
+    let file = try!(File::create(path.as_ref()));
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&bytes)
+}
@@ -13,15 +13,117 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
+use chunks::ChunkSink;
+use deflate::write::ZlibEncoder;
+use deflate::Compression;
+use io::icc::IccProfile;
 use math::Color;
 use png::{self, HasParameters};
 use std::convert::AsRef;
 use std::fs::File;
-use std::io::{BufWriter, Result};
+use std::io::{BufWriter, Error, ErrorKind, Result, Write};
 use std::path::Path;
 use textures::Texture;
 
+/// Number of compressed bytes held before they are written as a chunk
+const IDAT_SIZE: usize = 1 << 20;
+
+/// The color space in which an image is encoded, recorded in its metadata
+pub enum ColorSpace<'a> {
+    Srgb,
+    /// Encoded by a power law with a display gamma
+    Gamma(f64),
+    Icc(&'a IccProfile),
+}
+
+/// Wrap data in a zlib stream of uncompressed blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut output = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = if data.is_empty() {
+        vec![data]
+    } else {
+        data.chunks(0xffff).collect()
+    };
+    for (i, block) in blocks.iter().enumerate() {
+        let length = block.len() as u16;
+        output.push(if i + 1 == blocks.len() { 1 } else { 0 });
+        output.extend(&[length as u8, (length >> 8) as u8]);
+        output.extend(&[!length as u8, (!length >> 8) as u8]);
+        output.extend(*block);
+    }
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    let checksum = (b << 16) | a;
+    output.extend(&[
+        (checksum >> 24) as u8,
+        (checksum >> 16) as u8,
+        (checksum >> 8) as u8,
+        checksum as u8,
+    ]);
+    output
+}
+
+fn gamma_chunk(gamma: f64) -> Vec<u8> {
+    let value = (100_000.0 / gamma).round() as u32;
+    vec![
+        (value >> 24) as u8,
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ]
+}
+
+/// Import an image, of gray or RGB pixels with or without alpha, as 8 bit
+/// colors, ignoring its alpha
+pub fn import<T>(path: T) -> Result<Texture<Color>>
+where
+    T: AsRef<Path>,
+{
+    let file = try!(File::open(path.as_ref()));
+    let mut decoder = png::Decoder::new(file);
+    decoder.set(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = try!(decoder.read_info());
+    let mut bytes = vec![0; reader.output_buffer_size()];
+    try!(reader.next_frame(&mut bytes));
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => {
+            let message = "Indexed images are not supported";
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+    };
+    let buffer = bytes
+        .chunks(channels)
+        .map(|pixel| match channels {
+            1 | 2 => Color::new(pixel[0], pixel[0], pixel[0]),
+            _ => Color::new(pixel[0], pixel[1], pixel[2]),
+        })
+        .collect();
+    let (width, height) = (info.width as usize, info.height as usize);
+    Ok(Texture::new(width, height, buffer))
+}
+
 pub fn export<T>(path: T, texture: &Texture<Color>) -> Result<()>
+where
+    T: AsRef<Path>,
+{
+    export_with(path, texture, &ColorSpace::Srgb)
+}
+
+/// Export an image, tagged with the color space of its values
+pub fn export_with<T>(
+    path: T,
+    texture: &Texture<Color>,
+    space: &ColorSpace,
+) -> Result<()>
 where
     T: AsRef<Path>,
 {
@@ -36,12 +138,211 @@ where
     }
 
     let file = try!(File::create(path.as_ref()));
-    let writer = &mut BufWriter::new(file);
+    let writer = BufWriter::new(file);
+    let mut writer =
+        try!(write_header(writer, texture.width, texture.height, space));
+    try!(writer.write_image_data(&bytes));
+    Ok(())
+}
 
-    let mut encoder = png::Encoder::new(writer, width, height);
+/// Begin an 8 bit RGB image, tagged with the color space of its values
+fn write_header<W: Write>(
+    writer: W,
+    width: usize,
+    height: usize,
+    space: &ColorSpace,
+) -> Result<png::Writer<W>> {
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
     encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
 
     let mut writer = try!(encoder.write_header());
-    try!(writer.write_image_data(&bytes));
-    Ok(())
+    match *space {
+        ColorSpace::Srgb => {
+            try!(writer.write_chunk(*b"sRGB", &[0]));
+            try!(writer.write_chunk(*b"gAMA", &gamma_chunk(2.2)));
+        }
+        ColorSpace::Gamma(gamma) => {
+            try!(writer.write_chunk(*b"gAMA", &gamma_chunk(gamma)));
+        }
+        ColorSpace::Icc(profile) => {
+            // Names are limited to 79 Latin-1 characters
+            let mut chunk: Vec<u8> = profile
+                .name
+                .chars()
+                .filter(|c| (' '..='~').contains(c))
+                .take(79)
+                .map(|c| c as u8)
+                .collect();
+            if chunk.is_empty() {
+                chunk.extend(b"ICC profile");
+            }
+            chunk.extend(&[0, 0]);
+            chunk.extend(zlib_stored(&profile.data));
+            try!(writer.write_chunk(*b"iCCP", &chunk));
+        }
+    }
+    Ok(writer)
+}
+
+/// Compressed image data, written out in chunks as it accumulates
+struct ImageData<W: Write> {
+    writer: png::Writer<W>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Write for ImageData<W> {
+    fn write(&mut self, data: &[u8]) -> Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= IDAT_SIZE {
+            try!(self.flush());
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            try!(self.writer.write_chunk(*b"IDAT", &self.buffer));
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Writes an image in horizontal strips from top to bottom, compressing each
+/// as it is given, so the whole image is never held in memory
+pub struct StripWriter {
+    data: ZlibEncoder<ImageData<BufWriter<File>>>,
+    width: usize,
+    height: usize,
+    rows: usize,
+}
+
+impl StripWriter {
+    pub fn create<T>(
+        path: T,
+        width: usize,
+        height: usize,
+        space: &ColorSpace,
+    ) -> Result<StripWriter>
+    where
+        T: AsRef<Path>,
+    {
+        let file = try!(File::create(path.as_ref()));
+        let writer =
+            try!(write_header(BufWriter::new(file), width, height, space));
+        let data = ImageData {
+            writer,
+            buffer: vec![],
+        };
+        Ok(StripWriter {
+            data: ZlibEncoder::new(data, Compression::Default),
+            width,
+            height,
+            rows: 0,
+        })
+    }
+
+    /// Write the remaining compressed data and the end of the image, once
+    /// all of its rows have been written
+    pub fn finish(self) -> Result<()> {
+        if self.rows != self.height {
+            let message =
+                format!("{} of {} rows written", self.rows, self.height);
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+        let mut data = try!(self.data.finish());
+        data.flush()
+    }
+}
+
+impl ChunkSink<Color> for StripWriter {
+    fn write(
+        &mut self,
+        x: usize,
+        y: usize,
+        strip: &Texture<Color>,
+    ) -> Result<()> {
+        if x != 0 || y != self.rows || strip.width != self.width {
+            let message = "Strips must span the image and follow each other";
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+        if y + strip.height > self.height {
+            let message = "Strip extends past the bottom of the image";
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+
+        // Each row is filtered by the difference to the pixel on its left
+        let mut line = Vec::with_capacity(strip.width * 3 + 1);
+        for row in 0..strip.height {
+            line.clear();
+            line.push(1);
+            let mut previous = [0u8; 3];
+            for column in 0..strip.width {
+                let color = strip.lookup1x1(column, row);
+                let pixel = [color.r, color.g, color.b];
+                for i in 0..3 {
+                    line.push(pixel[i].wrapping_sub(previous[i]));
+                }
+                previous = pixel;
+            }
+            try!(self.data.write_all(&line));
+        }
+        self.rows += strip.height;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn importing_images() {
+        let image = Texture::new(
+            2,
+            1,
+            vec![Color::new(255, 0, 10), Color::new(1, 2, 3)],
+        );
+        let path = env::temp_dir().join("peaks-importing-images.png");
+        export(&path, &image).unwrap();
+        assert_eq!(import(&path).unwrap(), image);
+    }
+
+    #[test]
+    fn writing_strips() {
+        let (width, height) = (5, 7);
+        let image: Vec<Color> = (0..width * height)
+            .map(|i| Color {
+                r: (i * 37) as u8,
+                g: (i * 11) as u8,
+                b: 255 - i as u8,
+            })
+            .collect();
+        let path = env::temp_dir().join("peaks-writing-strips.png");
+
+        let mut writer =
+            StripWriter::create(&path, width, height, &ColorSpace::Srgb)
+                .unwrap();
+        for (y, rows) in image.chunks(width * 3).enumerate() {
+            let strip = Texture::new(width, rows.len() / width, rows.to_vec());
+            writer.write(0, y * 3, &strip).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let (info, mut reader) = decoder.read_info().unwrap();
+        let mut bytes = vec![0; info.buffer_size()];
+        reader.next_frame(&mut bytes).unwrap();
+        let expected: Vec<u8> =
+            image.iter().flat_map(|c| vec![c.r, c.g, c.b]).collect();
+        assert_eq!(bytes, expected);
+
+        let mut writer =
+            StripWriter::create(&path, width, height, &ColorSpace::Srgb)
+                .unwrap();
+        let strip = Texture::new(width, 1, image[..width].to_vec());
+        assert!(writer.write(0, 1, &strip).is_err());
+        assert!(writer.finish().is_err());
+    }
 }
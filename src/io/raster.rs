@@ -0,0 +1,343 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Georeferencing of rasters and loaders for height maps stored in plain
+//! images, which need no system libraries.
+
+use super::cache::Raster;
+use super::invalid;
+use math::{AffineTransform, EARTH_RADIUS};
+use png::{self, HasParameters};
+use textures::Texture;
+
+use std::convert::AsRef;
+use std::fs::File;
+use std::io::{Read, Result};
+use std::path::Path;
+
+/// Return true if a proj4 string describes a geographic coordinate system
+pub fn is_geographic(proj4: &str) -> bool {
+    proj4
+        .split_whitespace()
+        .any(|param| param == "+proj=longlat" || param == "+proj=latlong")
+}
+
+/// Return the number of metres per degree of longitude and latitude at the
+/// centre of a raster in geographic coordinates
+pub fn metres_per_degree(
+    transform: &AffineTransform,
+    width: usize,
+    height: usize,
+) -> (f64, f64) {
+    let (_, y) = transform.forward(width as f64 / 2.0, height as f64 / 2.0);
+    let latitude = -y;
+    let metres = EARTH_RADIUS.to_radians();
+    (metres * latitude.to_radians().cos(), metres)
+}
+
+/// Return the transform of a raster scaled on its horizontal axes, by default
+/// converting rasters in geographic coordinates from degrees to metres
+pub fn scaled_transform(
+    proj4: &str,
+    transform: &AffineTransform,
+    width: usize,
+    height: usize,
+    scale: Option<[f64; 2]>,
+) -> AffineTransform {
+    let (x, z) = match scale {
+        Some([x, z]) => (x, z),
+        None if is_geographic(proj4) => {
+            metres_per_degree(transform, width, height)
+        }
+        None => (1.0, 1.0),
+    };
+    transform.scale(x, z)
+}
+
+/// Return the transform of an image covering west, south, east and north
+/// bounds, or with one unit per pixel from the origin if not given
+pub fn extent_transform(
+    extent: Option<[f64; 4]>,
+    width: usize,
+    height: usize,
+) -> AffineTransform {
+    match extent {
+        Some([w, s, e, n]) => AffineTransform::new(
+            w,
+            -n,
+            (e - w) / width as f64,
+            (n - s) / height as f64,
+        ),
+        None => AffineTransform::new(0.0, 0.0, 1.0, 1.0),
+    }
+}
+
+/// Decode the channels of each pixel of a PNG image, keeping 16 bit samples
+fn decode_png<R: Read>(reader: R) -> Result<(usize, usize, usize, Vec<u16>)> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set(png::Transformations::EXPAND);
+    let (info, mut reader) = try!(decoder.read_info());
+    let mut bytes = vec![0; reader.output_buffer_size()];
+    try!(reader.next_frame(&mut bytes));
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => {
+            return Err(invalid("Indexed images are not supported"));
+        }
+    };
+    let samples = match info.bit_depth {
+        png::BitDepth::Sixteen => bytes
+            .chunks(2)
+            .map(|pair| u16::from(pair[0]) << 8 | u16::from(pair[1]))
+            .collect(),
+        _ => bytes.into_iter().map(u16::from).collect(),
+    };
+    let (width, height) = (info.width as usize, info.height as usize);
+    Ok((width, height, channels, samples))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from(bytes[offset]) | u16::from(bytes[offset + 1]) << 8
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from(read_u16(bytes, offset))
+        | u32::from(read_u16(bytes, offset + 2)) << 16
+}
+
+/// Decode the channels of each pixel of an uncompressed BMP image, of 8 bit
+/// palette indices or 24 or 32 bit colors, in red, green and blue order
+fn decode_bmp<R: Read>(
+    mut reader: R,
+) -> Result<(usize, usize, usize, Vec<u16>)> {
+    let mut bytes = vec![];
+    try!(reader.read_to_end(&mut bytes));
+    if bytes.len() < 54 || &bytes[0..2] != b"BM" {
+        return Err(invalid("Not a BMP image"));
+    }
+
+    let offset = read_u32(&bytes, 10) as usize;
+    let header = read_u32(&bytes, 14) as usize;
+    let width = read_u32(&bytes, 18) as i32;
+    let height = read_u32(&bytes, 22) as i32;
+    let bits = read_u16(&bytes, 28) as usize;
+    let compression = read_u32(&bytes, 30);
+    if width <= 0 || height == 0 {
+        return Err(invalid("Bad BMP image size"));
+    }
+    // Bit fields of 32 bit images are assumed to be in the usual order
+    if compression != 0 && !(compression == 3 && bits == 32) {
+        return Err(invalid("Compressed BMP images are not supported"));
+    }
+
+    let (width, rows) = (width as usize, height.abs() as usize);
+    let stride = (width * bits + 31) / 32 * 4;
+    if bytes.len() < offset + stride * rows {
+        return Err(invalid("Truncated BMP image"));
+    }
+
+    let palette = 14 + header;
+    let colors = match read_u32(&bytes, 46) {
+        0 => 256,
+        count => count as usize,
+    };
+    let channels = if bits == 8 { 1 } else { 3 };
+    let mut samples = Vec::with_capacity(width * rows * channels);
+    for row in 0..rows {
+        // Rows are stored bottom up, unless the height is negative
+        let row = if height > 0 { rows - 1 - row } else { row };
+        let line = &bytes[offset + row * stride..];
+        for x in 0..width {
+            match bits {
+                8 => {
+                    let index = line[x] as usize;
+                    if index >= colors || palette + index * 4 + 2 >= offset {
+                        return Err(invalid("Bad BMP palette index"));
+                    }
+                    samples.push(u16::from(bytes[palette + index * 4 + 2]));
+                }
+                24 | 32 => {
+                    let pixel = &line[x * bits / 8..];
+                    samples.push(u16::from(pixel[2]));
+                    samples.push(u16::from(pixel[1]));
+                    samples.push(u16::from(pixel[0]));
+                }
+                _ => return Err(invalid("Unsupported BMP bit depth")),
+            }
+        }
+    }
+    Ok((width, rows, channels, samples))
+}
+
+/// Encodings of plain images holding heights
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Bmp,
+}
+
+/// Convert the first channel of an image to heights with a scale and offset
+fn png_heights(
+    width: usize,
+    height: usize,
+    channels: usize,
+    samples: &[u16],
+    scale: f64,
+    offset: f64,
+) -> Texture<f64> {
+    let buffer = samples
+        .chunks(channels)
+        .map(|pixel| offset + f64::from(pixel[0]) * scale)
+        .collect();
+    Texture::new(width, height, buffer)
+}
+
+/// Decode heights from the red, green and blue channels of a Mapbox
+/// Terrain-RGB image
+fn terrain_rgb_heights(
+    width: usize,
+    height: usize,
+    channels: usize,
+    samples: &[u16],
+) -> Result<Texture<f64>> {
+    if channels < 3 {
+        return Err(invalid(
+            "Terrain-RGB images must have red, green and blue",
+        ));
+    }
+    let buffer = samples
+        .chunks(channels)
+        .map(|pixel| {
+            let (r, g, b) = (pixel[0], pixel[1], pixel[2]);
+            let value = f64::from(r) * 65536.0 + f64::from(g) * 256.0;
+            -10_000.0 + (value + f64::from(b)) * 0.1
+        })
+        .collect();
+    Ok(Texture::new(width, height, buffer))
+}
+
+/// Import heights from a grayscale image, or the first channel of a color
+/// one, scaled and offset from their stored values
+pub fn import_image<P: AsRef<Path>>(
+    path: P,
+    format: ImageFormat,
+    scale: f64,
+    offset: f64,
+    extent: Option<[f64; 4]>,
+) -> Result<Raster> {
+    let file = try!(File::open(path.as_ref()));
+    let (width, height, channels, samples) = match format {
+        ImageFormat::Png => try!(decode_png(file)),
+        ImageFormat::Bmp => try!(decode_bmp(file)),
+    };
+    let texture = png_heights(width, height, channels, &samples, scale, offset);
+    let transform = extent_transform(extent, width, height);
+    Ok((String::new(), transform, texture))
+}
+
+/// Import heights from a Mapbox Terrain-RGB PNG image
+pub fn import_terrain_rgb<P: AsRef<Path>>(
+    path: P,
+    extent: Option<[f64; 4]>,
+) -> Result<Raster> {
+    let file = try!(File::open(path.as_ref()));
+    let (width, height, channels, samples) = try!(decode_png(file));
+    let texture = try!(terrain_rgb_heights(width, height, channels, &samples));
+    let transform = extent_transform(extent, width, height);
+    Ok((String::new(), transform, texture))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geographic_coordinate_systems() {
+        assert!(is_geographic("+proj=longlat +datum=WGS84 +no_defs"));
+        assert!(!is_geographic("+proj=utm +zone=30 +datum=WGS84 +units=m"));
+
+        let transform = AffineTransform::new(0.0, -60.0, 0.1, 0.1);
+        let (x, y) = metres_per_degree(&transform, 10, 0);
+        assert!((x - y * 0.5).abs() < 1e-6);
+        assert!((y - 111_195.08).abs() < 0.01);
+    }
+
+    #[test]
+    fn image_extents() {
+        let transform = extent_transform(Some([10.0, 20.0, 30.0, 60.0]), 4, 8);
+        assert_eq!(transform.forward(0.0, 0.0), (10.0, -60.0));
+        assert_eq!(transform.forward(4.0, 8.0), (30.0, -20.0));
+    }
+
+    /// Return a BMP file of a width and height, with 4 byte aligned rows
+    fn bmp(width: i32, height: i32, bits: u16, pixels: &[u8]) -> Vec<u8> {
+        let palette: Vec<u8> = if bits == 8 {
+            (0..=255).flat_map(|i| vec![i, i, i, 0]).collect()
+        } else {
+            vec![]
+        };
+        let offset = 54 + palette.len() as u32;
+        let mut bytes = b"BM".to_vec();
+        let le32 = |v: u32| {
+            vec![v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]
+        };
+        bytes.extend(le32(offset + pixels.len() as u32));
+        bytes.extend(le32(0));
+        bytes.extend(le32(offset));
+        bytes.extend(le32(40));
+        bytes.extend(le32(width as u32));
+        bytes.extend(le32(height as u32));
+        bytes.extend(&[1, 0, bits as u8, (bits >> 8) as u8]);
+        bytes.extend(le32(0));
+        bytes.extend(le32(pixels.len() as u32));
+        bytes.extend(le32(0));
+        bytes.extend(le32(0));
+        bytes.extend(le32(0));
+        bytes.extend(le32(0));
+        bytes.extend(palette);
+        bytes.extend(pixels);
+        bytes
+    }
+
+    #[test]
+    fn decoding_bmp_images() {
+        // Two rows of two gray pixels, stored bottom up and padded
+        let file = bmp(2, 2, 8, &[30, 40, 0, 0, 10, 20, 0, 0]);
+        let (width, height, channels, samples) = decode_bmp(&file[..]).unwrap();
+        assert_eq!((width, height, channels), (2, 2, 1));
+        assert_eq!(samples, vec![10, 20, 30, 40]);
+
+        // A top down row of one blue, green and red pixel
+        let file = bmp(1, -1, 24, &[3, 2, 1, 0]);
+        let (_, _, channels, samples) = decode_bmp(&file[..]).unwrap();
+        assert_eq!((channels, samples), (3, vec![1, 2, 3]));
+
+        assert!(decode_bmp(&b"BM"[..]).is_err());
+    }
+
+    #[test]
+    fn decoding_heights() {
+        let heights = png_heights(2, 1, 2, &[100, 255, 200, 255], 0.5, 10.0);
+        assert_eq!(heights.buffer, vec![60.0, 110.0]);
+
+        let heights = terrain_rgb_heights(1, 1, 3, &[1, 134, 160]).unwrap();
+        assert!((heights.buffer[0] - 0.0).abs() < 1e-9);
+        assert!(terrain_rgb_heights(1, 1, 1, &[0]).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+use super::cache::Raster;
+use super::{gdal, other};
+use options::RemoteLoader;
+use serde_json;
+
+/// Return a path for GDAL to read a URL with, using HTTP range requests for
+/// plain URLs and leaving virtual file system paths and connection strings,
+/// such as those of the WMS driver, unchanged
+pub fn gdal_path(url: &str) -> String {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        format!("/vsicurl/{}", url)
+    } else {
+        url.to_owned()
+    }
+}
+
+/// Return a file name, stable across runs, for a snapshot of a loader
+fn snapshot_name(loader: &RemoteLoader) -> String {
+    // FNV-1a
+    let key = serde_json::to_string(loader).unwrap();
+    let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}.tif", hash)
+}
+
+/// Fetch a raster band from a remote dataset, reusing a snapshot of it in the
+/// loader's cache directory if one was saved before
+pub fn import(loader: &RemoteLoader) -> Result<Raster> {
+    let snapshot: Option<PathBuf> = loader
+        .cache
+        .as_ref()
+        .map(|directory| Path::new(directory).join(snapshot_name(loader)));
+
+    if let Some(ref path) = snapshot {
+        if path.exists() {
+            let (proj4, transform, mut rasters) =
+                try!(gdal::import(path, &[1]).map_err(other));
+            return Ok((proj4, transform, rasters.remove(0)));
+        }
+    }
+
+    let extent = loader.extent.map(|[w, s, e, n]| (w, s, e, n));
+    let (proj4, transform, mut rasters) = try!(gdal::import_extent(
+        gdal_path(&loader.url),
+        &[loader.band],
+        extent,
+        loader.resolution,
+    )
+    .map_err(other));
+
+    if let Some(ref path) = snapshot {
+        if let Some(directory) = path.parent() {
+            try!(fs::create_dir_all(directory));
+        }
+        // Written to a temporary file renamed into place, so an interrupted
+        // write leaves no partial snapshot to be reused
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        try!(gdal::export(&temporary, &proj4, &transform, &rasters)
+            .map_err(other));
+        try!(fs::rename(&temporary, path));
+    }
+
+    Ok((proj4, transform, rasters.remove(0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_paths() {
+        assert_eq!(
+            gdal_path("https://example.com/dem.tif"),
+            "/vsicurl/https://example.com/dem.tif"
+        );
+        assert_eq!(
+            gdal_path("WMS:https://example.com"),
+            "WMS:https://example.com"
+        );
+
+        let mut loader = RemoteLoader {
+            url: String::from("https://example.com/dem.tif"),
+            band: 1,
+            extent: None,
+            resolution: None,
+            cache: None,
+        };
+        let name = snapshot_name(&loader);
+        assert_eq!(name, snapshot_name(&loader.clone()));
+        loader.resolution = Some(30.0);
+        assert_ne!(name, snapshot_name(&loader));
+    }
+}
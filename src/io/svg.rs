@@ -0,0 +1,263 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use labels::{extent, BASELINE};
+use legend::{Entry, Symbol};
+use linework::Linework;
+use math::Vec3;
+use ops::encode_srgb;
+use std::convert::AsRef;
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::Path;
+
+/// Return text with the characters special to XML escaped
+fn escape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+/// Height of each row of a legend, and of the text within it
+const LEGEND_ROW: f64 = 24.0;
+const LEGEND_TEXT: f64 = 12.0;
+
+/// Size of the symbols of a legend, and of the bars of its ramps
+const LEGEND_SYMBOL: (f64, f64) = (32.0, 16.0);
+const LEGEND_RAMP: (f64, f64) = (160.0, 12.0);
+
+/// Number of stops of ramps in legends
+const LEGEND_STOPS: usize = 16;
+
+/// Space around the entries of a legend, and between symbols and titles
+const LEGEND_MARGIN: f64 = 8.0;
+
+fn rgb(color: Vec3) -> String {
+    let color = encode_srgb(color);
+    format!("rgb({},{},{})", color.r, color.g, color.b)
+}
+
+/// Return the SVG elements of a line of text in a legend, from its left or
+/// right side, with the top of the row it sits in
+fn legend_text(text: &str, x: f64, top: f64, anchor: &str) -> String {
+    let y = top + (LEGEND_ROW - LEGEND_TEXT) / 2.0 + LEGEND_TEXT * BASELINE;
+    format!(
+        r#"<text x="{:.2}" y="{:.2}" font-family="sans-serif" font-size="{}" text-anchor="{}">{}</text>"#,
+        x,
+        y,
+        LEGEND_TEXT,
+        anchor,
+        escape(text)
+    )
+}
+
+/// Export the entries of a legend, each a symbol beside its title, with the
+/// values at either end of ramps below them
+pub fn export_legend<T>(path: T, entries: &[Entry]) -> Result<()>
+where
+    T: AsRef<Path>,
+{
+    let (symbol_width, symbol_height) = LEGEND_SYMBOL;
+    let (ramp_width, ramp_height) = LEGEND_RAMP;
+    let text_x = LEGEND_MARGIN * 2.0 + symbol_width;
+
+    let mut elements = vec![];
+    let mut gradients = vec![];
+    let mut width = LEGEND_MARGIN * 2.0 + ramp_width;
+    let mut top = LEGEND_MARGIN;
+    for entry in entries {
+        let middle = top + LEGEND_ROW / 2.0;
+        let symbol_top = middle - symbol_height / 2.0;
+        match entry.symbol {
+            Symbol::Fill {
+                color,
+                stroke,
+                opacity,
+            } => {
+                let (stroke, stroke_width) = match stroke {
+                    Some((color, width)) => (rgb(color), width),
+                    None => (String::from("none"), 0.0),
+                };
+                elements.push(format!(
+                    r#"<rect x="{}" y="{:.2}" width="{}" height="{}" fill="{}" fill-opacity="{}" stroke="{}" stroke-width="{}"/>"#,
+                    LEGEND_MARGIN,
+                    symbol_top,
+                    symbol_width,
+                    symbol_height,
+                    color.map_or(String::from("none"), rgb),
+                    opacity,
+                    stroke,
+                    stroke_width
+                ));
+            }
+            Symbol::Line { color, width, dash } => {
+                let dash = match dash {
+                    Some([dash, gap]) => {
+                        format!(r#" stroke-dasharray="{} {}""#, dash, gap)
+                    }
+                    None => String::new(),
+                };
+                elements.push(format!(
+                    r#"<line x1="{}" y1="{:.2}" x2="{}" y2="{:.2}" stroke="{}" stroke-width="{}"{}/>"#,
+                    LEGEND_MARGIN,
+                    middle,
+                    LEGEND_MARGIN + symbol_width,
+                    middle,
+                    rgb(color),
+                    width,
+                    dash
+                ));
+            }
+            Symbol::Ramp { ramp, min, max } => {
+                let id = format!("ramp{}", gradients.len());
+                let stops: Vec<String> = (0..LEGEND_STOPS)
+                    .map(|i| {
+                        let t = i as f64 / (LEGEND_STOPS - 1) as f64;
+                        format!(
+                            r#"<stop offset="{:.3}" stop-color="{}"/>"#,
+                            t,
+                            rgb(ramp.color(t))
+                        )
+                    })
+                    .collect();
+                gradients.push(format!(
+                    r#"<linearGradient id="{}">{}</linearGradient>"#,
+                    id,
+                    stops.join("")
+                ));
+
+                // The title sits above the bar, with the values below
+                elements.push(legend_text(
+                    &entry.title,
+                    LEGEND_MARGIN,
+                    top,
+                    "start",
+                ));
+                top += LEGEND_ROW;
+                elements.push(format!(
+                    r#"<rect x="{}" y="{:.2}" width="{}" height="{}" fill="url(#{})"/>"#,
+                    LEGEND_MARGIN,
+                    top + (LEGEND_ROW - ramp_height) / 2.0,
+                    ramp_width,
+                    ramp_height,
+                    id
+                ));
+                top += LEGEND_ROW;
+                elements.push(legend_text(
+                    &min.to_string(),
+                    LEGEND_MARGIN,
+                    top,
+                    "start",
+                ));
+                elements.push(legend_text(
+                    &max.to_string(),
+                    LEGEND_MARGIN + ramp_width,
+                    top,
+                    "end",
+                ));
+                top += LEGEND_ROW;
+                continue;
+            }
+        }
+        elements.push(legend_text(&entry.title, text_x, top, "start"));
+        let (text_width, _) = extent(&entry.title, LEGEND_TEXT);
+        width = width.max(text_x + text_width + LEGEND_MARGIN);
+        top += LEGEND_ROW;
+    }
+    let height = top + LEGEND_MARGIN;
+
+    let file = try!(File::create(path.as_ref()));
+    let mut writer = BufWriter::new(file);
+    try!(writeln!(
+        writer,
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#
+    ));
+    try!(writeln!(
+        writer,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+        width.ceil(),
+        height.ceil()
+    ));
+    if !gradients.is_empty() {
+        try!(writeln!(writer, "<defs>{}</defs>", gradients.join("")));
+    }
+    for element in elements {
+        try!(writeln!(writer, "{}", element));
+    }
+    try!(writeln!(writer, "</svg>"));
+    Ok(())
+}
+
+pub fn export<T>(path: T, linework: &Linework) -> Result<()>
+where
+    T: AsRef<Path>,
+{
+    let file = try!(File::create(path.as_ref()));
+    let mut writer = BufWriter::new(file);
+
+    try!(writeln!(
+        writer,
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#
+    ));
+    try!(writeln!(
+        writer,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+        linework.width, linework.height
+    ));
+
+    for polyline in &linework.polylines {
+        let points: Vec<String> = polyline
+            .points
+            .iter()
+            .map(|(x, y)| format!("{:.2},{:.2}", x, y))
+            .collect();
+        let color = encode_srgb(polyline.color);
+        try!(writeln!(
+            writer,
+            r#"<polyline points="{}" fill="none" stroke="rgb({},{},{})" stroke-width="{}" stroke-linecap="round" stroke-linejoin="round"/>"#,
+            points.join(" "),
+            color.r,
+            color.g,
+            color.b,
+            polyline.width
+        ));
+    }
+
+    for label in &linework.labels {
+        let color = encode_srgb(label.color);
+        try!(writeln!(
+            writer,
+            r#"<text x="{:.2}" y="{:.2}" font-family="sans-serif" font-size="{}" fill="rgb({},{},{})">{}</text>"#,
+            label.x,
+            label.y + label.size * BASELINE,
+            label.size,
+            color.r,
+            color.g,
+            color.b,
+            escape(&label.text)
+        ));
+    }
+
+    try!(writeln!(writer, "</svg>"));
+    Ok(())
+}
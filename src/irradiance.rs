@@ -0,0 +1,184 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use math::Vec3;
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// An estimate of irradiance at a point, with the distance over which it
+/// changes, i.e. the mean distance to surrounding surfaces
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Record {
+    position: Vec3,
+    normal: Vec3,
+    irradiance: Vec3,
+    radius: f64,
+}
+
+impl Record {
+    /// Return the weight of the record at a point, the inverse of the error
+    /// estimated by Ward et al. for a change in position and normal
+    fn weight(&self, position: Vec3, normal: Vec3) -> f64 {
+        let distance = Vec3::distance(position, self.position) / self.radius;
+        let turn = (1.0 - Vec3::dot(normal, self.normal)).max(0.0).sqrt();
+        1.0 / (distance + turn).max(1e-9)
+    }
+
+    /// Return true if a point is behind the plane of the record, where it
+    /// may be shadowed by surfaces the record did not see
+    fn behind(&self, position: Vec3, normal: Vec3) -> bool {
+        let offset = position - self.position;
+        Vec3::dot(offset, (normal + self.normal) * 0.5) < -1e-6 * self.radius
+    }
+}
+
+/// World space cache of irradiance estimates, interpolated between nearby
+/// points with similar normals rather than computed for every shade
+#[derive(Debug)]
+pub struct IrradianceCache {
+    /// Largest error allowed when reusing a record, smaller values compute
+    /// more records
+    error: f64,
+    /// Bounds of the distance over which a record changes
+    min_radius: f64,
+    max_radius: f64,
+    /// Records filed by grid cells no smaller than the reach of a record
+    records: RwLock<HashMap<(i64, i64, i64), Vec<Record>>>,
+}
+
+impl IrradianceCache {
+    pub fn new(
+        error: f64,
+        min_radius: f64,
+        max_radius: f64,
+    ) -> IrradianceCache {
+        IrradianceCache {
+            error,
+            min_radius,
+            max_radius,
+            records: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn cell(&self, position: Vec3) -> (i64, i64, i64) {
+        let size = self.max_radius * self.error;
+        (
+            (position.x / size).floor() as i64,
+            (position.y / size).floor() as i64,
+            (position.z / size).floor() as i64,
+        )
+    }
+
+    /// Return the weighted average of records valid at a point
+    pub fn lookup(&self, position: Vec3, normal: Vec3) -> Option<Vec3> {
+        let records = self.records.read().unwrap();
+        let (x, y, z) = self.cell(position);
+        let mut total = Vec3::zeros();
+        let mut weights = 0.0;
+        for dz in -1..2 {
+            for dy in -1..2 {
+                for dx in -1..2 {
+                    let cell = match records.get(&(x + dx, y + dy, z + dz)) {
+                        Some(cell) => cell,
+                        None => continue,
+                    };
+                    for record in cell {
+                        let weight = record.weight(position, normal);
+                        if weight > 1.0 / self.error
+                            && !record.behind(position, normal)
+                        {
+                            total += record.irradiance * weight;
+                            weights += weight;
+                        }
+                    }
+                }
+            }
+        }
+
+        if weights > 0.0 {
+            Some(total / weights)
+        } else {
+            None
+        }
+    }
+
+    /// Return the irradiance at a point from nearby records, or compute it
+    /// along with the mean distance to surrounding surfaces, and store it
+    pub fn irradiance<F>(
+        &self,
+        position: Vec3,
+        normal: Vec3,
+        compute: F,
+    ) -> Vec3
+    where
+        F: FnOnce() -> (Vec3, f64),
+    {
+        if let Some(irradiance) = self.lookup(position, normal) {
+            return irradiance;
+        }
+
+        let (irradiance, distance) = compute();
+        let record = Record {
+            position,
+            normal,
+            irradiance,
+            radius: distance.max(self.min_radius).min(self.max_radius),
+        };
+        self.records
+            .write()
+            .unwrap()
+            .entry(self.cell(position))
+            .or_insert_with(Vec::new)
+            .push(record);
+        irradiance
+    }
+}
+
+impl Clone for IrradianceCache {
+    fn clone(&self) -> IrradianceCache {
+        IrradianceCache {
+            records: RwLock::new(self.records.read().unwrap().clone()),
+            ..IrradianceCache::new(self.error, self.min_radius, self.max_radius)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reusing_nearby_records() {
+        let cache = IrradianceCache::new(0.5, 1.0, 10.0);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let bright = Vec3::new(1.0, 1.0, 1.0);
+        let origin = Vec3::zeros();
+        assert_eq!(cache.irradiance(origin, up, || (bright, 4.0)), bright);
+
+        // Within reach, with the same normal
+        let near = Vec3::new(1.0, 0.0, 0.0);
+        assert_eq!(cache.lookup(near, up), Some(bright));
+
+        // Out of reach, or facing away
+        let far = Vec3::new(3.0, 0.0, 0.0);
+        assert_eq!(cache.lookup(far, up), None);
+        let side = Vec3::new(1.0, 0.0, 0.0);
+        assert_eq!(cache.lookup(origin, side), None);
+
+        // Behind the plane of the record
+        assert_eq!(cache.lookup(Vec3::new(0.0, -1.0, 0.0), up), None);
+    }
+}
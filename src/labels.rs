@@ -0,0 +1,339 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Names of points placed beside them in linework, in two passes. Points are
+//! first projected through the camera, dropping those hidden by the scene,
+//! then placed greedily from the most important, each taking the first of a
+//! set of positions around it not overlapping the labels already placed.
+
+#[cfg(feature = "gdal")]
+use io::ogr;
+#[cfg(feature = "gdal")]
+use io::other;
+use io::{geojson, package};
+use linework::Polyline;
+use math::Vec3;
+use options::{LabelOpts, Loader};
+use serde_json;
+
+use std::f64::NEG_INFINITY;
+use std::io::{Error, ErrorKind, Result};
+
+/// Width of a character as a fraction of the size of its text, an average for
+/// sans-serif fonts
+const CHARACTER_WIDTH: f64 = 0.6;
+
+/// Distance of the baseline below the top of a label, as a fraction of the
+/// size of its text
+pub const BASELINE: f64 = 0.8;
+
+/// Rings of positions tried around a point, each further away than the last
+const RINGS: usize = 4;
+
+/// Half the width of the area around each point that labels must not cover
+const POINT_RADIUS: f64 = 2.0;
+
+/// Width of the lines leading from points to their displaced labels
+const LEADER_WIDTH: f64 = 1.0;
+
+/// Named points to be labelled in linework
+#[derive(Clone, Debug)]
+pub struct LabelLayer {
+    pub points: Vec<(Vec3, String)>,
+    /// Height of the text in pixels
+    pub size: f64,
+    pub color: Vec3,
+    /// Gap between a point and its label in pixels
+    pub offset: f64,
+    /// Place the points on the surface of the scene
+    pub drape: bool,
+    /// Leave out points hidden by the scene
+    pub occlusion: bool,
+}
+
+/// Read points with the text of an attribute of each, skipping those without
+pub fn names(data: &Loader, field: &str) -> Result<Vec<(Vec3, String)>> {
+    match *data {
+        Loader::Geojson(ref opts) => {
+            let bounds = opts.bounds.as_ref().map(|b| b.dataset());
+            geojson::import_names(&opts.filepath, field, bounds)
+        }
+        #[cfg(feature = "gdal")]
+        Loader::Shp(ref opts) => {
+            let bounds = opts.bounds.as_ref().map(|b| b.dataset());
+            ogr::import_names(&opts.filepath, &opts.layer, field, bounds)
+                .map_err(other)
+        }
+        // Packages hold the names read by `names` when baked
+        Loader::Baked(ref opts) => {
+            let bytes = try!(package::open_entry(&opts.package, &opts.entry));
+            Ok(try!(serde_json::from_slice(&bytes)))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            "Labels are read from GeoJSON files or OGR layers",
+        )),
+    }
+}
+
+impl From<LabelOpts> for LabelLayer {
+    fn from(options: LabelOpts) -> LabelLayer {
+        let points = names(&options.data, &options.attribute).unwrap();
+
+        LabelLayer {
+            points,
+            size: options.size,
+            color: From::from(options.color),
+            offset: options.offset,
+            drape: options.drape,
+            occlusion: options.occlusion,
+        }
+    }
+}
+
+/// A name to place beside a point on the view plane
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anchor {
+    pub text: String,
+    pub point: (f64, f64),
+    /// Anchors of a higher priority are placed first
+    pub priority: f64,
+    pub size: f64,
+    pub color: Vec3,
+    pub offset: f64,
+}
+
+/// A name placed on the view plane, at the top left corner of its box
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub text: String,
+    pub x: f64,
+    pub y: f64,
+    pub size: f64,
+    pub color: Vec3,
+}
+
+/// Return the width and height of the box of text of a size, estimated from
+/// its number of characters
+pub fn extent(text: &str, size: f64) -> (f64, f64) {
+    (text.chars().count() as f64 * size * CHARACTER_WIDTH, size)
+}
+
+/// A box on the view plane, by its top left and bottom right corners
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Bounds {
+    x0: f64,
+    y0: f64,
+    x1: f64,
+    y1: f64,
+}
+
+impl Bounds {
+    fn overlaps(&self, other: &Bounds) -> bool {
+        self.x0 < other.x1
+            && other.x0 < self.x1
+            && self.y0 < other.y1
+            && other.y0 < self.y1
+    }
+
+    /// Return the point of the box closest to a point
+    fn nearest(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        (x.max(self.x0).min(self.x1), y.max(self.y0).min(self.y1))
+    }
+}
+
+/// Return the boxes of a label tried around its point, nearest and in order
+/// of preference first, with upper right favoured as is usual on maps
+fn candidates(anchor: &Anchor) -> Vec<Bounds> {
+    let (width, height) = extent(&anchor.text, anchor.size);
+    let (x, y) = anchor.point;
+    let mut boxes = Vec::with_capacity(RINGS * 8);
+    for ring in 0..RINGS {
+        let gap = POINT_RADIUS + anchor.offset + ring as f64 * anchor.size;
+        let corners = [
+            (x + gap, y - gap - height),
+            (x + gap, y - height / 2.0),
+            (x - gap - width, y - gap - height),
+            (x - gap - width, y - height / 2.0),
+            (x - width / 2.0, y - gap - height),
+            (x + gap, y + gap),
+            (x - gap - width, y + gap),
+            (x - width / 2.0, y + gap),
+        ];
+        boxes.extend(corners.iter().map(|&(x0, y0)| Bounds {
+            x0,
+            y0,
+            x1: x0 + width,
+            y1: y0 + height,
+        }));
+    }
+    boxes
+}
+
+/// Place labels beside their anchors on a view plane of a size, leaving out
+/// those with no room, returning the labels and lines leading to those placed
+/// away from their points
+pub fn place(
+    anchors: &[Anchor],
+    width: f64,
+    height: f64,
+) -> (Vec<Label>, Vec<Polyline>) {
+    let view = Bounds {
+        x0: 0.0,
+        y0: 0.0,
+        x1: width,
+        y1: height,
+    };
+    let inside =
+        |(x, y): (f64, f64)| x >= 0.0 && y >= 0.0 && x < width && y < height;
+    let mut anchors: Vec<&Anchor> =
+        anchors.iter().filter(|a| inside(a.point)).collect();
+    // Anchors with priorities that are not a number are placed last
+    let priority = |a: &Anchor| {
+        if a.priority.is_nan() {
+            NEG_INFINITY
+        } else {
+            a.priority
+        }
+    };
+    anchors.sort_by(|a, b| priority(b).total_cmp(&priority(a)));
+
+    // Labels may not cover any of the points
+    let mut taken: Vec<Bounds> = anchors
+        .iter()
+        .map(|anchor| {
+            let (x, y) = anchor.point;
+            Bounds {
+                x0: x - POINT_RADIUS,
+                y0: y - POINT_RADIUS,
+                x1: x + POINT_RADIUS,
+                y1: y + POINT_RADIUS,
+            }
+        })
+        .collect();
+
+    let mut labels = vec![];
+    let mut leaders = vec![];
+    for anchor in anchors {
+        let within = |b: &Bounds| {
+            b.x0 >= view.x0
+                && b.y0 >= view.y0
+                && b.x1 <= view.x1
+                && b.y1 <= view.y1
+        };
+        let found =
+            candidates(anchor).into_iter().enumerate().find(|(_, b)| {
+                within(b) && !taken.iter().any(|other| b.overlaps(other))
+            });
+        let (index, bounds) = match found {
+            Some(found) => found,
+            None => continue,
+        };
+
+        if index >= 8 {
+            leaders.push(Polyline {
+                points: vec![anchor.point, bounds.nearest(anchor.point)],
+                color: anchor.color,
+                width: LEADER_WIDTH,
+            });
+        }
+        taken.push(bounds);
+        labels.push(Label {
+            text: anchor.text.clone(),
+            x: bounds.x0,
+            y: bounds.y0,
+            size: anchor.size,
+            color: anchor.color,
+        });
+    }
+    (labels, leaders)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::NAN;
+
+    fn anchor(text: &str, x: f64, y: f64, priority: f64) -> Anchor {
+        Anchor {
+            text: text.to_owned(),
+            point: (x, y),
+            priority,
+            size: 10.0,
+            color: Vec3::zeros(),
+            offset: 2.0,
+        }
+    }
+
+    #[test]
+    fn placing_labels() {
+        let anchors = vec![
+            anchor("Lower", 50.0, 50.0, 1.0),
+            anchor("Peak", 50.0, 50.0, 2.0),
+            anchor("Outside", 150.0, 50.0, 3.0),
+        ];
+        let (labels, leaders) = place(&anchors, 100.0, 100.0);
+        assert_eq!(labels.len(), 2);
+        assert!(leaders.is_empty());
+
+        // The most important point takes the upper right position
+        let peak = &labels[0];
+        assert_eq!(peak.text, "Peak");
+        assert!(peak.x > 50.0 && peak.y + peak.size < 50.0);
+
+        let (width, height) = extent("Lower", 10.0);
+        let lower = Bounds {
+            x0: labels[1].x,
+            y0: labels[1].y,
+            x1: labels[1].x + width,
+            y1: labels[1].y + height,
+        };
+        let (width, height) = extent("Peak", 10.0);
+        let upper = Bounds {
+            x0: peak.x,
+            y0: peak.y,
+            x1: peak.x + width,
+            y1: peak.y + height,
+        };
+        assert!(!lower.overlaps(&upper));
+    }
+
+    #[test]
+    fn displacing_crowded_labels() {
+        let anchors: Vec<Anchor> = (0..9)
+            .map(|i| anchor("Summit", 60.0, 60.0, i as f64))
+            .collect();
+        let (labels, leaders) = place(&anchors, 120.0, 120.0);
+        assert_eq!(labels.len(), 9);
+        // Only some fit beside the point, the rest are led to from it
+        assert!(!leaders.is_empty() && leaders.len() < 9);
+        assert!(leaders.iter().all(|l| l.points[0] == (60.0, 60.0)));
+
+        // Labels are left out when there is no room for them
+        let (labels, _) = place(&anchors, 40.0, 40.0);
+        assert!(labels.is_empty());
+    }
+
+    #[test]
+    fn ordering_unknown_priorities() {
+        let anchors = vec![
+            anchor("Unknown", 50.0, 50.0, NAN),
+            anchor("Peak", 50.0, 50.0, 1.0),
+        ];
+        let (labels, _) = place(&anchors, 100.0, 100.0);
+        let texts: Vec<&str> = labels.iter().map(|l| &l.text[..]).collect();
+        assert_eq!(texts, vec!["Peak", "Unknown"]);
+    }
+}
@@ -0,0 +1,239 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! A key to the styling of a scene, read from the same options its shaders
+//! and linework are built from so the two never disagree.
+
+use math::Vec3;
+use options::{Loader, SceneOpts, ShaderOpts};
+use ramps::Ramp;
+use scene::flatten_shaders;
+
+use std::path::Path;
+
+/// How an entry of a legend is drawn
+#[derive(Clone, Debug, PartialEq)]
+pub enum Symbol {
+    /// An area of a color, with an outline of a color and width
+    Fill {
+        color: Option<Vec3>,
+        stroke: Option<(Vec3, f64)>,
+        opacity: f64,
+    },
+    /// A line of a color and width, with dash and gap lengths
+    Line {
+        color: Vec3,
+        width: f64,
+        dash: Option<[f64; 2]>,
+    },
+    /// A ramp of colors spanning values from a minimum to a maximum
+    Ramp { ramp: Ramp, min: f64, max: f64 },
+}
+
+/// A symbol with the name of what it stands for
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub title: String,
+    pub symbol: Symbol,
+}
+
+/// Return a name for shapes from where they are loaded
+fn title(loader: &Loader) -> String {
+    let stem = |path: &str| {
+        Path::new(path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_owned())
+    };
+    match *loader {
+        #[cfg(feature = "gdal")]
+        Loader::Shp(ref opts) => opts.layer.clone(),
+        #[cfg(feature = "gdal")]
+        Loader::Osm(ref opts) => {
+            if opts.filter.is_empty() {
+                stem(&opts.filepath)
+            } else {
+                opts.filter.clone()
+            }
+        }
+        #[cfg(feature = "gdal")]
+        Loader::Contours(ref opts) => {
+            format!("Contours every {}", opts.interval)
+        }
+        Loader::Geojson(ref opts) => stem(&opts.filepath),
+        Loader::Baked(ref opts) => opts.entry.clone(),
+        _ => String::from("Shapes"),
+    }
+}
+
+/// Return the entries of a legend for the styling of a scene, in the order
+/// they are given, without repeats
+pub fn entries(options: &SceneOpts) -> Vec<Entry> {
+    let mut entries = vec![];
+    for shader in flatten_shaders(options.shaders.clone()) {
+        match shader {
+            ShaderOpts::VectorLayers(opts) => {
+                for layer in opts.layers {
+                    let stroke = layer
+                        .stroke
+                        .map(|color| (From::from(color), layer.stroke_width));
+                    let symbol = match layer.dash {
+                        Some(dash) if layer.fill.is_none() => Symbol::Line {
+                            color: stroke.map_or(Vec3::zeros(), |s| s.0),
+                            width: layer.stroke_width,
+                            dash: Some(dash),
+                        },
+                        _ => Symbol::Fill {
+                            color: layer.fill.map(From::from),
+                            stroke,
+                            opacity: layer.opacity,
+                        },
+                    };
+                    entries.push(Entry {
+                        title: layer
+                            .title
+                            .clone()
+                            .unwrap_or_else(|| title(&layer.data)),
+                        symbol,
+                    });
+                }
+            }
+            ShaderOpts::Sdf(opts) => {
+                let stroke = if opts.stroke_width > 0.0 {
+                    Some((From::from(opts.stroke_color), opts.stroke_width))
+                } else {
+                    None
+                };
+                entries.push(Entry {
+                    title: opts
+                        .title
+                        .clone()
+                        .unwrap_or_else(|| title(&opts.data)),
+                    symbol: Symbol::Fill {
+                        color: Some(From::from(opts.color)),
+                        stroke,
+                        opacity: opts.alpha,
+                    },
+                });
+            }
+            ShaderOpts::Glacier(opts) => entries.push(Entry {
+                title: String::from("Glacier ice"),
+                symbol: Symbol::Ramp {
+                    ramp: Ramp::Glacier,
+                    min: opts.elevation[0],
+                    max: opts.elevation[1],
+                },
+            }),
+            _ => {}
+        }
+    }
+
+    if let Some(ref water) = options.water {
+        entries.push(Entry {
+            title: format!("Water below {}", water.elevation),
+            symbol: Symbol::Fill {
+                color: Some(From::from(water.color)),
+                stroke: None,
+                opacity: 1.0,
+            },
+        });
+    }
+
+    for layer in &options.linework {
+        entries.push(Entry {
+            title: layer.title.clone().unwrap_or_else(|| title(&layer.data)),
+            symbol: Symbol::Line {
+                color: From::from(layer.color),
+                width: layer.width,
+                dash: None,
+            },
+        });
+    }
+
+    let mut unique: Vec<Entry> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if !unique.contains(&entry) {
+            unique.push(entry);
+        }
+    }
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn reading_entries() {
+        let layer = json!({
+            "data": {"type": "geojson", "filepath": "data/lakes.geojson"},
+            "tolerance": 0.0,
+            "fill": [0.0, 0.0, 1.0],
+            "stroke": null,
+            "stroke_width": 0.0,
+            "dash": null,
+            "opacity": 1.0,
+            "blend": "normal",
+            "offset": 0.0,
+        });
+        let trail = json!({
+            "data": {"type": "geojson", "filepath": "trails.geojson"},
+            "color": [1.0, 0.0, 0.0],
+            "width": 2.0,
+            "drape": true,
+            "occlusion": true,
+            "title": "Trails",
+        });
+        let options: SceneOpts = serde_json::from_value(json!({
+            "background": [0.0, 0.0, 0.0],
+            "camera": {
+                "type": "orthographic",
+                "width": 8,
+                "height": 8,
+                "position": [0.0, 10.0, 0.0],
+                "look_at": [0.0, 0.0, 0.0],
+                "up": [0.0, 0.0, -1.0],
+                "view_plane_size": 8.0,
+                "view_distance": 1.0,
+            },
+            "shaders": [
+                {
+                    "type": "vector_layers",
+                    "wraps": {"type": "constant", "color": [1.0, 1.0, 1.0]},
+                    "layers": [layer.clone(), layer],
+                },
+            ],
+            "lights": [],
+            "primitives": [],
+            "objects": [],
+            "linework": [trail],
+        }))
+        .unwrap();
+
+        let entries = entries(&options);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].title, "lakes");
+        assert_eq!(
+            entries[0].symbol,
+            Symbol::Fill {
+                color: Some(Vec3::new(0.0, 0.0, 1.0)),
+                stroke: None,
+                opacity: 1.0,
+            }
+        );
+        assert_eq!(entries[1].title, "Trails");
+    }
+}
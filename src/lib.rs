@@ -13,32 +13,110 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
+extern crate deflate;
+#[cfg(feature = "gdal")]
 extern crate gdal;
+#[cfg(feature = "gdal")]
+extern crate gdal_sys;
+#[cfg(feature = "preview")]
+extern crate minifb;
 extern crate png;
+#[cfg(feature = "rayon")]
+extern crate rayon;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 
+mod accumulation;
+mod animation;
+mod bake;
+mod batch;
 mod cameras;
+mod catalog;
+mod chunks;
+mod debug;
+mod diagnostics;
 mod exec;
+mod expression;
 mod io;
+mod irradiance;
+mod labels;
+mod legend;
+mod lighting;
 mod lights;
+mod linework;
 mod math;
 mod ops;
 mod options;
+#[cfg(feature = "preview")]
+mod preview;
 mod primitives;
+mod progress;
+mod ramps;
+mod registry;
 mod render;
 mod samplers;
 mod scene;
 mod shaders;
+mod shadow_map;
 mod shapes;
 mod textures;
+mod watch;
 
-pub use exec::{render, render_threaded};
-pub use io::png::export;
-pub use math::{Color, Ray, Vec3};
-pub use ops::{linear_to_srgb, srgb_to_linear};
+pub use accumulation::AccumulationBuffer;
+pub use animation::{frame_path, frame_scene, track_value};
+pub use bake::{bake, bake_with_lighting, open_package};
+pub use batch::{
+    apply_override, merge_patch, orbit_views, scene_options, set_path,
+    stereo_views,
+};
+pub use catalog::Catalog;
+pub use chunks::{process_chunks, ChunkOp, ChunkSink, ChunkSource, WithHalo};
+pub use debug::{HitDebug, PixelDebug, SampleDebug, ShaderDebug};
+pub use diagnostics::RenderMode;
+pub use exec::{
+    render, render_async, render_progressive, render_range, render_strips,
+    render_threaded, tile_count, RenderConfig, RenderHandle, TileOrder,
+};
+pub use expression::Expression;
+pub use io::egm96::{
+    import as import_egm96, resample as resample_egm96,
+    undulation as geoid_undulation,
+};
+#[cfg(feature = "gdal")]
+pub use io::gdal::{build_overviews, RasterReader, RasterWriter};
+pub use io::geojson::export as export_geojson;
+pub use io::icc::{read as read_icc_profile, IccProfile};
+pub use io::partial::{save as save_tiles, Partials};
+pub use io::pdf::export as export_pdf;
+pub use io::png::{
+    export, export_with, import as import_png, ColorSpace, StripWriter,
+};
+pub use io::svg::{export as export_svg, export_legend};
+pub use labels::{place as place_labels, Anchor as LabelAnchor, Label};
+pub use legend::{entries as legend_entries, Entry, Symbol};
+pub use lighting::{bake_diffuse, bake_occlusion};
+pub use lights::Light;
+pub use linework::{Linework, Polyline};
+pub use math::{AffineTransform, Color, Ray, Vec3};
+pub use ops::{
+    anaglyph, apply_geoid, apply_ramp, compare, compare_colors, contact_sheet,
+    denoise, difference, encode_srgb, exaggerate, generalise, hillshade,
+    illumination_correction, linear_to_gamma, linear_to_profile,
+    linear_to_srgb, map2, map3, operator1x1, operator3x3, operator_nxn,
+    pansharpen, smooth, srgb_to_linear, statistics, stretch, unit_colors,
+    EdgePolicy, IlluminationCorrection, Metric, Pansharpen, Stats,
+};
 pub use options::*;
+#[cfg(feature = "preview")]
+pub use preview::preview;
+pub use primitives::{Aabb, HeightMap, Intersection, Primitive};
+pub use progress::{ConsoleProgress, ProgressSink};
+pub use ramps::Ramp;
 pub use render::Renderer;
-pub use scene::Scene;
+pub use scene::{drop_onto, Background, MemoryUsage, Scene, SceneCache, Water};
+pub use shaders::{RayType, Shader, TraceInfo, Tracer};
 pub use textures::Texture;
+pub use watch::{scene_files, FileWatcher};
@@ -0,0 +1,163 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Light received by a terrain from a fixed sun and sky, computed once for
+//! each point of its height map, so renders of many views of the same scene
+//! look it up instead of tracing shadow rays.
+
+use math::{AffineTransform, Vec3};
+use ops::{gradients, operator1x1};
+use shadow_map::ShadowMap;
+use textures::Texture;
+
+use std::f64::consts::PI;
+
+/// Number of directions searched for the horizon of each point
+pub const OCCLUSION_DIRECTIONS: usize = 16;
+
+/// Return the normal of each point of heights, in world space
+pub fn normals(
+    transform: &AffineTransform,
+    heights: &Texture<f64>,
+) -> Texture<Vec3> {
+    let mut normals = Texture::blank(heights.width, heights.height);
+    operator1x1(&gradients(heights, transform), &mut normals, |(dx, dz)| {
+        Vec3::normalize(Vec3::new(-dx, 1.0, -dz))
+    });
+    normals
+}
+
+/// Return the light received by each point of heights from directional
+/// lights, each a direction towards the light and an intensity, including
+/// the shadows cast by the heights
+pub fn bake_diffuse(
+    transform: &AffineTransform,
+    heights: &Texture<f64>,
+    lights: &[(Vec3, f64)],
+) -> Texture<f64> {
+    let normals = normals(transform, heights);
+    let mut diffuse = Texture::blank(heights.width, heights.height);
+    for &(direction, intensity) in lights {
+        let direction = Vec3::normalize(direction);
+        let shadows = ShadowMap::new(*transform, heights, direction);
+        for y in 0..heights.height {
+            for x in 0..heights.width {
+                let (wx, wz) = transform.forward(x as f64, y as f64);
+                let point = Vec3::new(wx, heights.lookup1x1(x, y), wz);
+                if shadows.occluded(point) == Some(true) {
+                    continue;
+                }
+                let normal = normals.lookup1x1(x, y);
+                let cosine = Vec3::dot(normal, direction).max(0.0);
+                let value = diffuse.lookup1x1(x, y) + cosine * intensity;
+                diffuse.write1x1(x, y, value);
+            }
+        }
+    }
+    diffuse
+}
+
+/// Return the fraction of light from a uniform sky reaching each point of
+/// heights, from the horizons found in a number of directions up to a
+/// distance in world units
+pub fn bake_occlusion(
+    transform: &AffineTransform,
+    heights: &Texture<f64>,
+    directions: usize,
+    distance: f64,
+) -> Texture<f64> {
+    let [_, _, a, d] = transform.coefficients();
+    let (width, height) =
+        ((heights.width - 1) as f64, (heights.height - 1) as f64);
+    let directions = directions.max(1);
+    let mut visible = Texture::blank(heights.width, heights.height);
+
+    let horizon = |x: usize, y: usize| {
+        let base = heights.lookup1x1(x, y);
+        let mut sum = 0.0;
+        for i in 0..directions {
+            let angle = 2.0 * PI * i as f64 / directions as f64;
+            let (dx, dy) = (angle.cos(), angle.sin());
+            let length = ((a * dx).powi(2) + (d * dy).powi(2)).sqrt();
+
+            // Steps lengthen with distance, as far features block less of
+            // the sky for their size
+            let mut slope: f64 = 0.0;
+            let mut step = 1.0;
+            while step * length <= distance {
+                let (sx, sy) = (x as f64 + dx * step, y as f64 + dy * step);
+                if sx < 0.0 || sy < 0.0 || sx > width || sy > height {
+                    break;
+                }
+                let rise = heights.bilinear_clamped(sx, sy) - base;
+                slope = slope.max(rise / (step * length));
+                step += (step * 0.25).max(1.0);
+            }
+
+            // Light from a uniform sky falls with the cosine of its angle to
+            // a level surface, leaving the square of the cosine of the
+            // horizon angle unblocked
+            sum += 1.0 / (1.0 + slope * slope);
+        }
+        sum / directions as f64
+    };
+
+    for y in 0..heights.height {
+        for x in 0..heights.width {
+            visible.write1x1(x, y, horizon(x, y));
+        }
+    }
+    visible
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A flat raster with a wall of a height across its middle column
+    fn wall(height: f64) -> Texture<f64> {
+        let mut heights = Texture::blank(9, 9);
+        for y in 0..9 {
+            heights.write1x1(4, y, height);
+        }
+        heights
+    }
+
+    #[test]
+    fn baking_diffuse_light() {
+        let transform = AffineTransform::new(0.0, 0.0, 2.0, 2.0);
+        // Light from +x at 45 degrees, so an 8 unit wall shadows 8 units
+        let light = (Vec3::new(1.0, 1.0, 0.0), 2.0);
+        let diffuse = bake_diffuse(&transform, &wall(8.0), &[light]);
+
+        let level = 2.0 * 0.5f64.sqrt();
+        assert!((diffuse.lookup1x1(7, 4) - level).abs() < 1e-9);
+        assert_eq!(diffuse.lookup1x1(2, 4), 0.0);
+        assert!((diffuse.lookup1x1(0, 4) - level).abs() < 1e-9);
+    }
+
+    #[test]
+    fn baking_occlusion() {
+        let transform = AffineTransform::new(0.0, 0.0, 1.0, 1.0);
+        let flat = bake_occlusion(&transform, &wall(0.0), 8, 10.0);
+        assert!(flat.buffer.iter().all(|&v| v == 1.0));
+
+        let walled = bake_occlusion(&transform, &wall(4.0), 8, 10.0);
+        let (near, far) = (walled.lookup1x1(3, 4), walled.lookup1x1(0, 4));
+        assert!(near < far && far < 1.0);
+        // The top of the wall sees all of the sky
+        assert_eq!(walled.lookup1x1(4, 4), 1.0);
+    }
+}
@@ -13,14 +13,26 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
+#[cfg(feature = "gdal")]
+use io::gdal;
+use io::hdr;
+use irradiance::IrradianceCache;
 use math::Vec3;
-use options::DirectionalLightOpts;
+use options::{DirectionalLightOpts, EnvironmentLightOpts, LightOpts};
+use primitives::load_height_map;
+use shadow_map::ShadowMap;
+use textures::Texture;
 
-#[derive(Copy, Clone, Debug, Default, PartialEq)]
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+#[derive(Clone, Debug, Default)]
 pub struct DirectionalLight {
     pub direction: Vec3,
     pub color: Vec3,
     pub intensity: f64,
+    /// Shadows of a height map, consulted instead of tracing shadow rays
+    pub shadow_map: Option<Arc<ShadowMap>>,
 }
 
 impl DirectionalLight {
@@ -33,16 +45,281 @@ impl DirectionalLight {
             direction,
             color,
             intensity,
+            shadow_map: None,
         }
     }
 }
 
 impl From<DirectionalLightOpts> for DirectionalLight {
     fn from(options: DirectionalLightOpts) -> DirectionalLight {
-        DirectionalLight::new(
+        let mut light = DirectionalLight::new(
             From::from(options.direction),
-            Vec3::zeros(),
+            From::from(options.color),
             options.intensity,
+        );
+        light.shadow_map = options.shadow_map.map(|opts| {
+            let (transform, mut heights) = load_height_map(&opts);
+            for height in &mut heights.buffer {
+                *height *= opts.exaggeration;
+            }
+            let direction = Vec3::normalize(light.direction);
+            Arc::new(ShadowMap::new(transform, &heights, direction))
+        });
+        light
+    }
+}
+
+/// Illumination from every direction, given by an equirectangular image
+/// with north (+Z) in its center and the zenith along its top edge
+#[derive(Clone, Debug, Default)]
+pub struct EnvironmentLight {
+    pub intensity: f64,
+    /// Number of directions sampled for each shade
+    pub samples: usize,
+    /// Compass bearing of the center of the image in radians
+    rotation: f64,
+    texture: Texture<Vec3>,
+    /// Cumulative distribution of brightness over rows
+    rows: Vec<f64>,
+    /// Cumulative distributions of brightness within each row
+    columns: Vec<f64>,
+    /// Sum of the brightness of all pixels, weighted by their solid angle
+    total: f64,
+    /// Estimates of light received, shared by all shaders using the light
+    pub cache: Option<IrradianceCache>,
+    /// Seed of the offsets of the directions sampled at each pixel
+    pub seed: u64,
+}
+
+/// Return the index of the first value in a cumulative distribution above a
+/// uniform random number
+fn search(cdf: &[f64], u: f64) -> usize {
+    let index =
+        match cdf.binary_search_by(|value| value.partial_cmp(&u).unwrap()) {
+            Ok(index) => index + 1,
+            Err(index) => index,
+        };
+    index.min(cdf.len() - 1)
+}
+
+/// Return the radical inverse of an integer in base two
+fn radical_inverse(mut i: usize) -> f64 {
+    let (mut result, mut digit) = (0.0, 0.5);
+    while i > 0 {
+        if i & 1 == 1 {
+            result += digit;
+        }
+        i >>= 1;
+        digit *= 0.5;
+    }
+    result
+}
+
+/// Return a pair of numbers, from a hash of a pixel position and a seed, to
+/// offset a sequence of samples so that neighbouring pixels do not share
+/// directions
+fn scramble(x: f64, y: f64, seed: u64) -> (f64, f64) {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64 ^ seed;
+    for byte in x
+        .to_bits()
+        .to_le_bytes()
+        .iter()
+        .chain(&y.to_bits().to_le_bytes())
+    {
+        hash = (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+    }
+    let unit = |bits: u64| (bits & 0xffff_ffff) as f64 / 4_294_967_296.0;
+    (unit(hash), unit(hash >> 32))
+}
+
+impl EnvironmentLight {
+    pub fn new(
+        texture: Texture<Vec3>,
+        intensity: f64,
+        samples: usize,
+        rotation: f64,
+    ) -> EnvironmentLight {
+        let (width, height) = (texture.width, texture.height);
+        let mut rows = Vec::with_capacity(height);
+        let mut columns = Vec::with_capacity(width * height);
+        let mut total = 0.0;
+
+        for y in 0..height {
+            // Rows near the poles cover a smaller solid angle
+            let theta = (y as f64 + 0.5) / height as f64 * PI;
+            let mut sum = 0.0;
+            for x in 0..width {
+                let color = texture.buffer[y * width + x];
+                let luminance =
+                    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z;
+                sum += luminance.max(0.0) * theta.sin();
+                columns.push(sum);
+            }
+            for value in &mut columns[y * width..] {
+                *value = if sum > 0.0 { *value / sum } else { 1.0 };
+            }
+            total += sum;
+            rows.push(total);
+        }
+        for value in &mut rows {
+            *value = if total > 0.0 { *value / total } else { 1.0 };
+        }
+
+        EnvironmentLight {
+            intensity,
+            samples,
+            rotation,
+            texture,
+            rows,
+            columns,
+            total,
+            cache: None,
+            seed: 0,
+        }
+    }
+
+    /// Return the direction through the center of a pixel
+    fn direction(&self, x: usize, y: usize) -> Vec3 {
+        let u = (x as f64 + 0.5) / self.texture.width as f64;
+        let v = (y as f64 + 0.5) / self.texture.height as f64;
+        let phi = (u - 0.5) * 2.0 * PI + self.rotation;
+        let theta = v * PI;
+        Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            theta.sin() * phi.cos(),
         )
     }
+
+    /// Return the radiance arriving from a direction
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let (width, height) = (self.texture.width, self.texture.height);
+        let direction = Vec3::normalize(direction);
+        let phi = direction.x.atan2(direction.z) - self.rotation;
+        let theta = direction.y.max(-1.0).min(1.0).acos();
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let x = ((u * width as f64) as usize).min(width - 1);
+        let y = ((theta / PI * height as f64) as usize).min(height - 1);
+        self.texture.buffer[y * width + x]
+    }
+
+    /// Return a sample for a pixel, as a direction, the radiance arriving
+    /// from it and its probability density over the sphere, where brighter
+    /// directions are sampled more often
+    pub fn sample(&self, index: usize, x: f64, y: f64) -> (Vec3, Vec3, f64) {
+        let (width, height) = (self.texture.width, self.texture.height);
+        if self.total <= 0.0 {
+            return (Vec3::new(0.0, 1.0, 0.0), Vec3::zeros(), 0.0);
+        }
+
+        let (du, dv) = scramble(x, y, self.seed);
+        let u = (index as f64 / self.samples as f64 + du).fract();
+        let v = (radical_inverse(index) + dv).fract();
+        let row = search(&self.rows, u);
+        let column = search(&self.columns[row * width..(row + 1) * width], v);
+
+        let color = self.texture.buffer[row * width + column];
+        let theta = (row as f64 + 0.5) / height as f64 * PI;
+        let luminance = 0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z;
+        let probability = luminance.max(0.0) * theta.sin() / self.total;
+
+        // Convert from the density over pixels to one over solid angle
+        let pixel = 2.0 * PI * PI * theta.sin() / (width * height) as f64;
+        (self.direction(column, row), color, probability / pixel)
+    }
+}
+
+/// Import the first three bands of a raster as linear colors
+#[cfg(feature = "gdal")]
+fn import_rgb(path: &str) -> Texture<Vec3> {
+    let (_, _, bands) = gdal::import::<_, f64>(path, &[1, 2, 3]).unwrap();
+    let buffer = (0..bands[0].buffer.len())
+        .map(|i| {
+            Vec3::new(
+                bands[0].buffer[i],
+                bands[1].buffer[i],
+                bands[2].buffer[i],
+            )
+        })
+        .collect();
+    Texture::new(bands[0].width, bands[0].height, buffer)
+}
+
+#[cfg(not(feature = "gdal"))]
+fn import_rgb(path: &str) -> Texture<Vec3> {
+    panic!("Reading {} needs the gdal feature, use a .hdr image", path)
+}
+
+impl From<EnvironmentLightOpts> for EnvironmentLight {
+    fn from(options: EnvironmentLightOpts) -> EnvironmentLight {
+        let texture = if options.filepath.ends_with(".hdr") {
+            hdr::import(&options.filepath).unwrap()
+        } else {
+            import_rgb(&options.filepath)
+        };
+        let mut light = EnvironmentLight::new(
+            texture,
+            options.intensity,
+            options.samples,
+            options.rotation.to_radians(),
+        );
+        light.cache = options.irradiance_cache.map(|opts| {
+            IrradianceCache::new(opts.error, opts.min_spacing, opts.max_spacing)
+        });
+        light.seed = options.seed;
+        light
+    }
+}
+
+/// A source of light available to shaders
+#[derive(Clone, Debug)]
+pub enum Light {
+    Directional(DirectionalLight),
+    Environment(EnvironmentLight),
+}
+
+impl From<LightOpts> for Light {
+    fn from(options: LightOpts) -> Light {
+        match options {
+            LightOpts::Directional(opts) => {
+                Light::Directional(From::from(opts))
+            }
+            LightOpts::Environment(opts) => {
+                Light::Environment(From::from(opts))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampling_bright_pixels() {
+        // A dark sky with a single bright pixel, north of the horizon
+        let mut texture = Texture::blank(8, 4);
+        texture.write1x1(4, 1, Vec3::new(100.0, 100.0, 100.0));
+        let light = EnvironmentLight::new(texture, 1.0, 16, 0.0);
+
+        for i in 0..16 {
+            let (direction, radiance, pdf) = light.sample(i, 3.0, 7.0);
+            assert_eq!(radiance, Vec3::new(100.0, 100.0, 100.0));
+            assert_eq!(light.radiance(direction), radiance);
+            assert!(direction.y > 0.0 && direction.z > 0.0);
+            assert!(pdf > 0.0);
+        }
+    }
+
+    #[test]
+    fn uniform_environments() {
+        // The density of uniform light approaches that of the sphere
+        let texture =
+            Texture::new(4, 256, vec![Vec3::new(1.0, 1.0, 1.0); 1024]);
+        let light = EnvironmentLight::new(texture, 1.0, 4, 0.0);
+        for i in 0..4 {
+            let (_, _, pdf) = light.sample(i, 0.0, 0.0);
+            assert!((pdf - 1.0 / (4.0 * PI)).abs() < 1e-5);
+        }
+    }
 }
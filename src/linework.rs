@@ -0,0 +1,206 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use io::cache;
+use labels::Label;
+use math::Vec3;
+use options::{EdgeDetectionOpts, LineworkOpts};
+use shapes::{perpendicular_distance, simplify_by, Shape};
+use textures::Texture;
+
+use std::sync::Arc;
+
+/// A line in view plane coordinates
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polyline {
+    pub points: Vec<(f64, f64)>,
+    pub color: Vec3,
+    pub width: f64,
+}
+
+/// A collection of lines to be drawn over a render
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Linework {
+    pub width: usize,
+    pub height: usize,
+    pub polylines: Vec<Polyline>,
+    pub labels: Vec<Label>,
+}
+
+/// Vector data to be projected through the camera as linework
+#[derive(Clone, Debug)]
+pub struct LineLayer {
+    pub shapes: Arc<Vec<Shape>>,
+    pub color: Vec3,
+    pub width: f64,
+    /// Place the shapes on the surface of the scene
+    pub drape: bool,
+    /// Hide parts of the shapes that are hidden by the scene
+    pub occlusion: bool,
+}
+
+/// Settings for extracting silhouette and crease edges from the scene
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EdgeDetection {
+    /// Angle (in radians) between normals above which an edge is a crease
+    pub crease_threshold: f64,
+    /// Difference in depth above which an edge is a silhouette
+    pub silhouette_threshold: f64,
+    /// Simplification tolerance of the resulting lines, in pixels
+    pub tolerance: f64,
+    pub color: Vec3,
+    pub width: f64,
+}
+
+impl From<EdgeDetectionOpts> for EdgeDetection {
+    fn from(options: EdgeDetectionOpts) -> EdgeDetection {
+        EdgeDetection {
+            crease_threshold: options.crease_threshold,
+            silhouette_threshold: options.silhouette_threshold,
+            tolerance: options.tolerance,
+            color: From::from(options.color),
+            width: options.width,
+        }
+    }
+}
+
+/// Simplify a line with the Ramer-Douglas-Peucker algorithm
+pub fn simplify(points: &[(f64, f64)], tolerance: f64) -> Vec<(f64, f64)> {
+    simplify_by(points, tolerance, &perpendicular_distance)
+}
+
+/// Link 8-connected pixels of an edge mask into lines through their centers
+pub fn trace_edges(mask: &Texture<bool>) -> Vec<Vec<(f64, f64)>> {
+    let mut visited = Texture::blank(mask.width, mask.height);
+    let mut lines = vec![];
+
+    let next = |visited: &Texture<bool>, x: usize, y: usize| {
+        for dy in -1..=1_isize {
+            for dx in -1..=1_isize {
+                let nx = x as isize + dx;
+                let ny = y as isize + dy;
+                if nx < 0
+                    || ny < 0
+                    || nx >= mask.width as isize
+                    || ny >= mask.height as isize
+                {
+                    continue;
+                }
+                let (nx, ny) = (nx as usize, ny as usize);
+                if mask.lookup1x1(nx, ny) && !visited.lookup1x1(nx, ny) {
+                    return Some((nx, ny));
+                }
+            }
+        }
+        None
+    };
+
+    let center = |(x, y): (usize, usize)| (x as f64 + 0.5, y as f64 + 0.5);
+
+    for y in 0..mask.height {
+        for x in 0..mask.width {
+            if !mask.lookup1x1(x, y) || visited.lookup1x1(x, y) {
+                continue;
+            }
+
+            visited.write1x1(x, y, true);
+
+            // Walk away from the start in one direction then the other, with
+            // the second walk reversed onto the front of the line
+            let mut walks = [vec![], vec![]];
+            for walk in &mut walks {
+                let mut current = (x, y);
+                while let Some(pixel) = next(&visited, current.0, current.1) {
+                    visited.write1x1(pixel.0, pixel.1, true);
+                    walk.push(center(pixel));
+                    current = pixel;
+                }
+            }
+
+            let [forwards, mut line] = walks;
+            line.reverse();
+            line.push(center((x, y)));
+            line.extend(forwards);
+            lines.push(line);
+        }
+    }
+
+    lines
+}
+
+impl Linework {
+    pub fn new(width: usize, height: usize) -> Linework {
+        Linework {
+            width,
+            height,
+            polylines: vec![],
+            labels: vec![],
+        }
+    }
+}
+
+impl From<LineworkOpts> for LineLayer {
+    fn from(options: LineworkOpts) -> LineLayer {
+        let shapes = cache::shapes(&options.data).unwrap();
+
+        LineLayer {
+            shapes,
+            color: From::from(options.color),
+            width: options.width,
+            drape: options.drape,
+            occlusion: options.occlusion,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simplifying_lines() {
+        let line = vec![(0.0, 0.0), (1.0, 0.1), (2.0, 0.0), (3.0, 2.0)];
+        assert_eq!(
+            simplify(&line, 0.5),
+            vec![(0.0, 0.0), (2.0, 0.0), (3.0, 2.0)]
+        );
+        assert_eq!(simplify(&line, 5.0), vec![(0.0, 0.0), (3.0, 2.0)]);
+    }
+
+    #[test]
+    fn tracing_edges() {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        let mask = Texture::new(4, 3, vec![
+            false, true,  false, false,
+            false, false, true,  false,
+            false, true,  false, false,
+        ]);
+        assert_eq!(
+            trace_edges(&mask),
+            vec![vec![(1.5, 0.5), (2.5, 1.5), (1.5, 2.5)]]
+        );
+
+        // Starting midway, the line walks both ways from its first pixel
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        let mask = Texture::new(3, 2, vec![
+            false, true,  false,
+            true,  false, true,
+        ]);
+        assert_eq!(
+            trace_edges(&mask),
+            vec![vec![(2.5, 1.5), (1.5, 0.5), (0.5, 1.5)]]
+        );
+    }
+}
@@ -13,49 +13,231 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
-extern crate docopt;
-extern crate serde;
-#[macro_use]
-extern crate serde_derive;
+extern crate clap;
+extern crate clap_complete;
 extern crate peaks;
 extern crate png;
 extern crate serde_json;
 
-use docopt::Docopt;
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
 use peaks::{
-    export, linear_to_srgb, render_threaded, Renderer, Scene, Texture,
+    anaglyph, apply_override, apply_ramp, bake, bake_with_lighting, compare,
+    contact_sheet, denoise, difference, export, export_geojson, export_legend,
+    export_pdf, export_svg, export_with, frame_path, frame_scene, import_png,
+    legend_entries, linear_to_gamma, linear_to_profile, linear_to_srgb,
+    open_package, orbit_views, read_icc_profile, render_progressive,
+    render_range, render_strips, render_threaded, save_tiles, scene_files,
+    scene_options, stereo_views, tile_count, unit_colors, AnimationOpts,
+    BatchOpts, Catalog, ChunkSink, ColorSpace, ConsoleProgress, FileWatcher,
+    IccProfile, Metric, Partials, Ramp, RenderConfig, RenderMode, Renderer,
+    Scene, SceneCache, SceneOpts, StripWriter, Texture, TileOrder, Vec3,
 };
 
 use std::fs::File;
-use std::io::{stdin, Read, Result};
+use std::io::{self, stdin, Error, ErrorKind, Read, Result};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
+use serde_json::Value;
 
-const USAGE: &str = "
-Peaks.
+/// Size of the tiles split between machines when none is given, as all
+/// machines must number the same tiles
+const RANGE_TILE_SIZE: usize = 64;
 
-Usage:
-    peaks [options] <input> <output>
-    peaks [options] <output>
-    peaks (-h | --help)
-    peaks --version
+/// Time between checks for changes to watched files
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
 
-Options:
-    -h, --help              Show this screen.
-    --version               Show version.
-    --samples=<number>      Number of multi-samples [default: 4].
-    --threads=<number>      Number of render threads [default: 4].
-    --tile-size=<pixels>    Size of a render tile [default: 8].
-";
+/// Peaks, a renderer for 3D maps.
+///
+/// Renders a scene to an image, reading the scene from stdin when only an
+/// output is given.
+#[derive(Debug, Parser)]
+#[command(name = "peaks", version)]
+struct Cli {
+    #[command(flatten)]
+    args: Args,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Scene to render, followed by the image to write
+    #[arg(value_name = "FILES", num_args = 0..=2)]
+    files: Vec<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Render each job of a manifest
+    Batch { manifest: String },
+    /// Render a preview of a scene each time its files change
+    Watch { input: String, output: String },
+    /// Show a scene in an interactive window
+    Preview { input: String },
+    /// Render a scene, or a package written by bake, to an image
+    Render { input: String, output: String },
+    /// Write a scene and all the data it reads to a single package
+    Bake {
+        input: String,
+        output: String,
+        /// Also bake the light received by the terrain, for renders of many
+        /// views under the same lights, searching a distance in world units
+        /// for terrain blocking the sky
+        #[arg(long, value_name = "DISTANCE")]
+        lighting: Option<f64>,
+    },
+    /// Render each frame of the animation of a scene, to images and linework
+    /// named by patterns with {} in place of the frame number
+    Animate { input: String, output: String },
+    /// Write an SVG key to the fills, lines and color ramps of a scene
+    Legend { input: String, output: String },
+    /// Compare two images, such as renders before and after a change to a
+    /// scene, printing a measure of their difference
+    Diff {
+        a: String,
+        b: String,
+        /// Measure of the difference, one of rmse, mae, max or psnr
+        #[arg(long, default_value = "rmse")]
+        metric: String,
+        /// Write an image of the difference of each pixel, with the largest
+        /// difference brightest
+        #[arg(long, value_name = "PATH")]
+        out: Option<String>,
+    },
+    /// Assemble the tiles written by renders of each tile range into an image
+    Merge {
+        output: String,
+        #[arg(required = true)]
+        tiles: Vec<String>,
+    },
+    /// Build overviews of a dataset, halving its size down to a tile, read in
+    /// place of the dataset when it is loaded at a resolution
+    Pyramids {
+        dataset: String,
+        /// Write the overviews inside the dataset instead of to an .ovr file
+        /// beside it
+        #[arg(long)]
+        internal: bool,
+        /// Resampling of overviews, one of average, nearest, gauss, cubic,
+        /// cubicspline, lanczos or mode
+        #[arg(long, default_value = "average")]
+        resampling: String,
+        /// Size in pixels of the smallest overview
+        #[arg(long, default_value_t = 256, value_name = "PIXELS")]
+        size: usize,
+    },
+    /// Print a shell completion script
+    Completions { shell: Shell },
+}
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, clap::Args)]
 struct Args {
-    flag_samples: usize,
-    flag_threads: usize,
-    flag_tile_size: usize,
-    flag_version: bool,
-    arg_input: String,
-    arg_output: String,
+    /// Number of multi-samples
+    #[arg(long, global = true, env = "PEAKS_SAMPLES", default_value_t = 4)]
+    samples: usize,
+    /// Render passes of increasing samples up to --samples, stopping after a
+    /// number of seconds and writing the image of the most samples reached
+    #[arg(
+        long,
+        global = true,
+        env = "PEAKS_TIME_LIMIT",
+        value_name = "SECONDS"
+    )]
+    time_limit: Option<f64>,
+    /// Render and write the image in strips of a number of rows, so the
+    /// whole image is never held in memory
+    #[arg(long, global = true, value_name = "ROWS")]
+    strips: Option<usize>,
+    /// Render only the tiles from a start index up to an end index, numbered
+    /// in rows from the top left, writing them to be merged with the others
+    #[arg(
+        long,
+        global = true,
+        value_name = "START:END",
+        value_parser = parse_tile_range
+    )]
+    tile_range: Option<(usize, usize)>,
+    /// Print the memory used by the scene before rendering
+    #[arg(long, global = true)]
+    verbose: bool,
+    /// Number of render threads, defaults to the number of cores
+    #[arg(long, global = true, env = "PEAKS_THREADS")]
+    threads: Option<usize>,
+    /// Size of a render tile in pixels, defaults to a size giving each thread
+    /// several tiles
+    #[arg(long, global = true, env = "PEAKS_TILE_SIZE", value_name = "PIXELS")]
+    tile_size: Option<usize>,
+    /// Order in which tiles render, one of scanline, center-out, hilbert or
+    /// random
+    #[arg(
+        long,
+        global = true,
+        default_value = "scanline",
+        value_name = "ORDER"
+    )]
+    tile_order: String,
+    /// Export linework to an SVG, PDF or GeoJSON file
+    #[arg(long, global = true, value_name = "PATH")]
+    vector: Option<String>,
+    /// Render mode, one of shaded, normals, depth, object-id, quadtree-cost
+    /// or sample-heatmap
+    #[arg(long, global = true, default_value = "shaded")]
+    mode: String,
+    /// Catalog of datasets referred to by name, defaults to
+    /// ~/.config/peaks/catalog.json
+    #[arg(long, global = true, env = "PEAKS_CATALOG", value_name = "PATH")]
+    catalog: Option<String>,
+    /// Render views orbiting the camera's look at point into a contact sheet
+    #[arg(long, global = true, value_name = "VIEWS")]
+    orbit: Option<usize>,
+    /// Elevation of orbiting views in degrees, defaults to the camera's own
+    #[arg(long, global = true, value_name = "DEGREES")]
+    elevation: Option<f64>,
+    /// Columns of the contact sheet
+    #[arg(long, global = true, default_value_t = 4)]
+    columns: usize,
+    /// Render a stereo pair as an anaglyph or side-by-side
+    #[arg(long, global = true, value_name = "LAYOUT")]
+    stereo: Option<String>,
+    /// Distance between stereo cameras
+    #[arg(long, global = true, default_value_t = 1.0)]
+    interocular: f64,
+    /// Distance at which stereo cameras converge, defaults to the camera's
+    /// look at point
+    #[arg(long, global = true, value_name = "DIST")]
+    convergence: Option<f64>,
+    /// Smooth sampling noise in shaded renders with a number of filter passes
+    #[arg(long, global = true, value_name = "PASSES")]
+    denoise: Option<usize>,
+    /// Encode images with a display gamma instead of sRGB
+    #[arg(long, global = true)]
+    gamma: Option<f64>,
+    /// Encode images in the color space of an RGB ICC profile, such as Adobe
+    /// RGB, and embed it
+    #[arg(long, global = true, value_name = "PATH")]
+    icc_profile: Option<String>,
+    /// Set a scene option by its dotted path, such as camera.width=3840 or
+    /// shaders.3.color=[1,0,0]
+    #[arg(long = "override", global = true, value_name = "PATCH")]
+    overrides: Vec<String>,
+}
+
+/// Parse a range of tiles, where a missing end includes all later tiles
+fn parse_tile_range(
+    range: &str,
+) -> ::std::result::Result<(usize, usize), String> {
+    let message =
+        || format!("Expected a range of tiles START:END, not {}", range);
+    let mut parts = range.splitn(2, ':');
+    let start = parts.next().and_then(|start| start.parse().ok());
+    let end = match parts.next() {
+        Some("") => Some(usize::MAX),
+        Some(end) => end.parse().ok(),
+        None => None,
+    };
+    match (start, end) {
+        (Some(start), Some(end)) if start <= end => Ok((start, end)),
+        _ => Err(message()),
+    }
 }
 
 fn slurp(file_path: &str) -> Result<String> {
@@ -70,29 +252,685 @@ fn slurp(file_path: &str) -> Result<String> {
 }
 
 fn main() -> Result<()> {
-    let args: Args = Docopt::new(USAGE)
-        .and_then(|d| d.deserialize())
-        .unwrap_or_else(|e| e.exit());
+    let cli = Cli::parse();
+    let args = &cli.args;
 
-    if args.flag_version {
-        println!("v{}", VERSION);
+    if let Some(Command::Completions { shell }) = cli.command {
+        let mut command = Cli::command();
+        clap_complete::generate(
+            shell,
+            &mut command,
+            "peaks",
+            &mut io::stdout(),
+        );
         return Ok(());
     }
 
-    let deff = serde_json::from_str(&slurp(&args.arg_input)?)?;
-    let scene = Scene::new(deff);
+    let catalog = match args.catalog {
+        Some(ref path) => Catalog::open(path)?,
+        None => match Catalog::user_path() {
+            Some(path) => Catalog::open(path)?,
+            None => Catalog::default(),
+        },
+    };
+
+    match cli.command {
+        Some(Command::Batch { ref manifest }) => {
+            return batch(args, manifest, &catalog);
+        }
+        Some(Command::Watch {
+            ref input,
+            ref output,
+        }) => return watch(args, input, output, &catalog),
+        Some(Command::Preview { ref input }) => {
+            let deff = read_scene(args, input, &catalog)?;
+            return preview(args, scene_opts(deff)?);
+        }
+        Some(Command::Bake {
+            ref input,
+            ref output,
+            lighting,
+        }) => {
+            let deff = read_scene(args, input, &catalog)?;
+            let options = serde_json::from_value(deff)?;
+            return match lighting {
+                Some(distance) => bake_with_lighting(options, output, distance),
+                None => bake(options, output),
+            };
+        }
+        Some(Command::Animate {
+            ref input,
+            ref output,
+        }) => return animate(args, input, output, &catalog),
+        Some(Command::Legend {
+            ref input,
+            ref output,
+        }) => {
+            let options = scene_opts(read_scene(args, input, &catalog)?)?;
+            return export_legend(output, &legend_entries(&options));
+        }
+        Some(Command::Merge {
+            ref output,
+            ref tiles,
+        }) => return merge(args, output, tiles),
+        Some(Command::Diff {
+            ref a,
+            ref b,
+            ref metric,
+            ref out,
+        }) => return diff(args, a, b, metric, out),
+        Some(Command::Pyramids {
+            ref dataset,
+            internal,
+            ref resampling,
+            size,
+        }) => return pyramids(dataset, internal, resampling, size),
+        _ => {}
+    }
+
+    let (input, output) = match (&cli.command, cli.files.len()) {
+        (
+            &Some(Command::Render {
+                ref input,
+                ref output,
+            }),
+            _,
+        ) => (input.as_str(), output),
+        (_, 1) => ("", &cli.files[0]),
+        (_, 2) => (cli.files[0].as_str(), &cli.files[1]),
+        _ => {
+            let message = "An output image is required";
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+    };
+
+    let deff = read_scene(args, input, &catalog)?;
+    if let Some(views) = args.orbit {
+        return orbit(args, scene_opts(deff)?, views, output);
+    }
+    if let Some(ref layout) = args.stereo {
+        return stereo(args, scene_opts(deff)?, layout, output);
+    }
+    let scene = Scene::new(scene_opts(deff)?);
+    render_scene(args, scene, output, &args.vector)
+}
+
+/// Return the options of a scene, or an error if they can not be rendered
+fn scene_opts(deff: Value) -> Result<SceneOpts> {
+    let options: SceneOpts = serde_json::from_value(deff)?;
+    options
+        .validate()
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+    Ok(options)
+}
+
+/// Read scene options, from a file or a package written by bake, with
+/// datasets named in the catalog resolved and the overrides given on the
+/// command line applied
+fn read_scene(args: &Args, path: &str, catalog: &Catalog) -> Result<Value> {
+    let mut deff = if path.ends_with(".peakspkg") {
+        open_package(path)?
+    } else {
+        serde_json::from_str(&slurp(path)?)?
+    };
+    catalog
+        .resolve(&mut deff)
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+    for patch in &args.overrides {
+        apply_override(&mut deff, patch)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+    }
+    Ok(deff)
+}
+
+/// Render each job of a manifest, with paths relative to the manifest
+fn batch(args: &Args, path: &str, catalog: &Catalog) -> Result<()> {
+    let manifest: BatchOpts = serde_json::from_str(&slurp(path)?)?;
+    let root = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    let resolve = |path: &str| root.join(path).to_string_lossy().into_owned();
+
+    let mut cache = SceneCache::new();
+    for job in &manifest.jobs {
+        println!("Rendering {}", job.output);
+        let deff = read_scene(args, &resolve(&job.scene), catalog)?;
+        let options = scene_options(job, deff)?;
+        options
+            .validate()
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+        let scene = Scene::with_cache(options, &mut cache);
+        let vector = job.vector.as_ref().map(|path| resolve(path));
+        render_scene(args, scene, &resolve(&job.output), &vector)?;
+    }
+
+    Ok(())
+}
+
+/// Render each frame of the animation of a scene, reusing the data loaded
+/// for earlier frames
+fn animate(
+    args: &Args,
+    input: &str,
+    output: &str,
+    catalog: &Catalog,
+) -> Result<()> {
+    let invalid = |err: String| Error::new(ErrorKind::InvalidInput, err);
+    let deff = read_scene(args, input, catalog)?;
+    let animation: AnimationOpts = match deff.get("animation") {
+        Some(animation) => serde_json::from_value(animation.clone())?,
+        None => return Err(invalid(format!("{} is not animated", input))),
+    };
+
+    let mut cache = SceneCache::new();
+    for frame in 0..animation.frames {
+        let path =
+            frame_path(output, frame, animation.frames).map_err(invalid)?;
+        println!("Rendering {}", path);
+        let deff = frame_scene(&deff, &animation, frame).map_err(invalid)?;
+        let vector = match args.vector {
+            Some(ref pattern) => Some(
+                frame_path(pattern, frame, animation.frames)
+                    .map_err(invalid)?,
+            ),
+            None => None,
+        };
+        let scene = Scene::with_cache(scene_opts(deff)?, &mut cache);
+        render_scene(args, scene, &path, &vector)?;
+    }
+
+    Ok(())
+}
+
+/// Render a preview of a scene each time it or the files it reads change
+fn watch(
+    args: &Args,
+    input: &str,
+    output: &str,
+    catalog: &Catalog,
+) -> Result<()> {
+    let preview = Args {
+        samples: 1,
+        denoise: None,
+        ..args.clone()
+    };
+    loop {
+        let mut files = vec![input.to_owned()];
+        let rendered = read_scene(args, input, catalog).and_then(|deff| {
+            files.extend(scene_files(&deff));
+            let scene = Scene::try_new(scene_opts(deff)?)?;
+            render_scene(&preview, scene, output, &None)
+        });
+        if let Err(err) = rendered {
+            println!("Could not render {}: {}", input, err);
+        }
+
+        println!("Watching {} files for changes", files.len());
+        let mut watcher = FileWatcher::new(files);
+        while !watcher.changed() {
+            thread::sleep(WATCH_INTERVAL);
+        }
+    }
+}
+
+/// Show a scene in an interactive window
+#[cfg(feature = "preview")]
+fn preview(args: &Args, options: SceneOpts) -> Result<()> {
+    peaks::preview(options, args.threads, args.tile_size)
+}
+
+#[cfg(not(feature = "preview"))]
+fn preview(_args: &Args, _options: SceneOpts) -> Result<()> {
+    let message = "Peaks was built without the preview feature";
+    Err(Error::new(ErrorKind::Other, message))
+}
+
+/// Build overviews of a dataset
+#[cfg(feature = "gdal")]
+fn pyramids(
+    dataset: &str,
+    internal: bool,
+    resampling: &str,
+    size: usize,
+) -> Result<()> {
+    let factors =
+        peaks::build_overviews(dataset, resampling, internal, size)
+            .map_err(|err| Error::new(ErrorKind::Other, err.to_string()))?;
+    if factors.is_empty() {
+        println!("{} is no larger than {} pixels", dataset, size);
+    } else {
+        let factors: Vec<String> =
+            factors.iter().map(|f| f.to_string()).collect();
+        println!("Built overviews at 1/{}", factors.join(", 1/"));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "gdal"))]
+fn pyramids(
+    _dataset: &str,
+    _internal: bool,
+    _resampling: &str,
+    _size: usize,
+) -> Result<()> {
+    let message = "Peaks was built without the gdal feature";
+    Err(Error::new(ErrorKind::Other, message))
+}
+
+/// Render views around a scene into a contact sheet
+fn orbit(
+    args: &Args,
+    options: SceneOpts,
+    views: usize,
+    output: &str,
+) -> Result<()> {
+    let mut cache = SceneCache::new();
+    let mut images = vec![];
+    let views = orbit_views(&options, views, args.elevation);
+    for (i, (azimuth, options)) in views.into_iter().enumerate() {
+        println!("Rendering view {} at azimuth {:.1}", i + 1, azimuth);
+        let scene = Scene::with_cache(options, &mut cache);
+        let (_, surface) = render_surface(args, scene)?;
+        images.push(surface);
+    }
+    let sheet = contact_sheet(&images, args.columns);
+    write_image(args, output, &sheet)
+}
+
+/// Render a stereo pair of a scene, combined into one image
+fn stereo(
+    args: &Args,
+    options: SceneOpts,
+    layout: &str,
+    output: &str,
+) -> Result<()> {
+    if layout != "anaglyph" && layout != "side-by-side" {
+        let message = format!("Unknown stereo layout {}", layout);
+        return Err(Error::new(ErrorKind::InvalidInput, message));
+    }
+
+    let (left, right) =
+        stereo_views(&options, args.interocular, args.convergence);
+    let mut cache = SceneCache::new();
+    let (_, left) = render_surface(args, Scene::with_cache(left, &mut cache))?;
+    let (_, right) =
+        render_surface(args, Scene::with_cache(right, &mut cache))?;
+
+    let surface = if layout == "anaglyph" {
+        let mut surface = Texture::blank(left.width, left.height);
+        anaglyph(&left, &right, &mut surface);
+        surface
+    } else {
+        contact_sheet(&[left, right], 2)
+    };
+    write_image(args, output, &surface)
+}
+
+/// Return a renderer for a scene, with the configuration and order of its
+/// tiles
+fn prepare(
+    args: &Args,
+    scene: Scene,
+) -> Result<(Renderer, RenderMode, RenderConfig, TileOrder)> {
     let (width, height) = scene.camera.view_plane();
-    let renderer = Renderer::new(args.flag_samples, scene);
+    if args.verbose {
+        let memory = scene.memory();
+        println!(
+            "Scene memory {} MB (primitives {} MB, shaders {} MB, shapes {} MB)",
+            memory.total() >> 20,
+            memory.primitives >> 20,
+            memory.shaders >> 20,
+            memory.shapes >> 20,
+        );
+    }
+    let mode: RenderMode = args
+        .mode
+        .parse()
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+    let order: TileOrder = args
+        .tile_order
+        .parse()
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+    let mut renderer = Renderer::new(args.samples, scene);
+    renderer.set_mode(mode);
+
+    let config =
+        RenderConfig::detect(width, height).with(args.threads, args.tile_size);
+    Ok((renderer, mode, config, order))
+}
 
+/// Render a scene into a linear color surface
+fn render_surface(
+    args: &Args,
+    scene: Scene,
+) -> Result<(Renderer, Texture<Vec3>)> {
+    let (width, height) = scene.camera.view_plane();
+    let (renderer, mode, config, order) = prepare(args, scene)?;
     let mut surface = Texture::blank(width, height);
-    let mut output = Texture::blank(width, height);
+    let mut progress = ConsoleProgress::new(30);
+    if let Some(seconds) = args.time_limit {
+        let limit = Duration::from_millis((seconds.max(0.0) * 1000.0) as u64);
+        let samples = render_progressive(
+            &mut surface,
+            &renderer,
+            config.threads,
+            config.tile_size,
+            order,
+            Some(limit),
+            &mut progress,
+        );
+        if samples < renderer.samples() {
+            println!("Time limit reached after {} samples", samples);
+        }
+    } else {
+        render_threaded(
+            &mut surface,
+            &renderer,
+            config.threads,
+            config.tile_size,
+            order,
+            &mut progress,
+        );
+    }
+
+    if let (Some(passes), RenderMode::Shaded) = (args.denoise, mode) {
+        let (normals, depths) = renderer.guides();
+        let noisy = surface.clone();
+        denoise(&noisy, &normals, &depths, passes, &mut surface);
+    }
+    Ok((renderer, surface))
+}
+
+/// Encodes strips of linear color into an image written a strip at a time
+struct EncodedStrips {
+    writer: StripWriter,
+    profile: Option<IccProfile>,
+    gamma: Option<f64>,
+}
+
+impl EncodedStrips {
+    /// Begin an image encoded as given by the arguments
+    fn create(
+        args: &Args,
+        path: &str,
+        width: usize,
+        height: usize,
+    ) -> Result<EncodedStrips> {
+        let profile = match args.icc_profile {
+            Some(ref path) => Some(read_icc_profile(path)?),
+            None => None,
+        };
+        let writer = {
+            let space = match (&profile, args.gamma) {
+                (&Some(ref profile), _) => ColorSpace::Icc(profile),
+                (&None, Some(gamma)) => ColorSpace::Gamma(gamma),
+                (&None, None) => ColorSpace::Srgb,
+            };
+            StripWriter::create(path, width, height, &space)?
+        };
+        Ok(EncodedStrips {
+            writer,
+            profile,
+            gamma: args.gamma,
+        })
+    }
+}
+
+impl ChunkSink<Vec3> for EncodedStrips {
+    fn write(
+        &mut self,
+        x: usize,
+        y: usize,
+        strip: &Texture<Vec3>,
+    ) -> Result<()> {
+        let mut output = Texture::blank(strip.width, strip.height);
+        if let Some(ref profile) = self.profile {
+            linear_to_profile(strip, &mut output, profile);
+        } else if let Some(gamma) = self.gamma {
+            linear_to_gamma(strip, &mut output, gamma);
+        } else {
+            linear_to_srgb(strip, &mut output);
+        }
+        self.writer.write(x, y, &output)
+    }
+}
+
+/// Render a scene and write it to an image in strips of rows
+fn render_in_strips(
+    args: &Args,
+    scene: Scene,
+    path: &str,
+    rows: usize,
+) -> Result<Renderer> {
+    if args.denoise.is_some() || args.time_limit.is_some() {
+        let message = "Strips cannot be denoised or rendered progressively";
+        return Err(Error::new(ErrorKind::InvalidInput, message));
+    }
+
+    let (width, height) = scene.camera.view_plane();
+    let (renderer, _, config, order) = prepare(args, scene)?;
+    let mut sink = EncodedStrips::create(args, path, width, height)?;
+
+    render_strips(
+        &renderer,
+        width,
+        height,
+        rows,
+        config.threads,
+        config.tile_size,
+        order,
+        &mut sink,
+        &mut ConsoleProgress::new(30),
+    )?;
+    sink.writer.finish()?;
+    Ok(renderer)
+}
+
+/// Render a range of the tiles of a scene, writing them to a file to be
+/// merged with the other ranges
+fn render_tile_range(
+    args: &Args,
+    scene: Scene,
+    path: &str,
+    range: (usize, usize),
+) -> Result<Renderer> {
+    if args.denoise.is_some() || args.time_limit.is_some() {
+        let message =
+            "Tile ranges cannot be denoised or rendered progressively";
+        return Err(Error::new(ErrorKind::InvalidInput, message));
+    }
 
-    render_threaded(
-        &mut surface,
+    let (width, height) = scene.camera.view_plane();
+    let (renderer, _, config, _) = prepare(args, scene)?;
+    let tile_size = args.tile_size.unwrap_or(RANGE_TILE_SIZE);
+    let count = tile_count(width, height, tile_size);
+    let end = range.1.min(count);
+    println!("Rendering tiles {} to {} of {}", range.0, end, count);
+
+    let tiles = render_range(
         &renderer,
-        args.flag_threads,
-        args.flag_tile_size,
+        width,
+        height,
+        tile_size,
+        (range.0, end),
+        config.threads,
+        &mut ConsoleProgress::new(30),
     );
-    linear_to_srgb(&surface, &mut output);
-    export(args.arg_output, &output)
+    save_tiles(path, width, height, &tiles)?;
+    Ok(renderer)
+}
+
+/// Print a measure of the difference between two images, and write an image
+/// of the difference of each pixel
+fn diff(
+    args: &Args,
+    a: &str,
+    b: &str,
+    metric: &str,
+    out: &Option<String>,
+) -> Result<()> {
+    let metric: Metric = metric
+        .parse()
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+    let (a, b) = (unit_colors(&import_png(a)?), unit_colors(&import_png(b)?));
+    println!("{}", compare(&a, &b, metric)?);
+
+    if let Some(ref path) = *out {
+        let mut differences = Texture::blank(a.width, a.height);
+        difference(&a, &b, &mut differences);
+        let max = differences.buffer.iter().cloned().fold(0.0, f64::max);
+        let mut surface = Texture::blank(a.width, a.height);
+        apply_ramp(&differences, &mut surface, Ramp::Magma, 0.0, max);
+        write_image(args, path, &surface)?;
+    }
+    Ok(())
+}
+
+/// Assemble the files of tiles of each range into an image
+fn merge(args: &Args, output: &str, paths: &[String]) -> Result<()> {
+    let mut partials = Partials::open(paths)?;
+    let mut sink =
+        EncodedStrips::create(args, output, partials.width, partials.height)?;
+    partials.merge(&mut sink)?;
+    sink.writer.finish()
+}
+
+fn render_scene(
+    args: &Args,
+    scene: Scene,
+    path: &str,
+    vector: &Option<String>,
+) -> Result<()> {
+    let renderer = match (args.tile_range, args.strips) {
+        (Some(range), _) => render_tile_range(args, scene, path, range)?,
+        (None, Some(rows)) => render_in_strips(args, scene, path, rows)?,
+        (None, None) => {
+            let (renderer, surface) = render_surface(args, scene)?;
+            write_image(args, path, &surface)?;
+            renderer
+        }
+    };
+
+    if let Some(ref path) = *vector {
+        let linework = renderer.linework();
+        if path.ends_with(".pdf") {
+            export_pdf(path, &linework)?;
+        } else if path.ends_with(".json") || path.ends_with(".geojson") {
+            export_geojson(path, &linework)?;
+        } else {
+            export_svg(path, &linework)?;
+        }
+    }
+    Ok(())
+}
+
+/// Encode a linear color surface for output and export it as an image
+fn write_image(args: &Args, path: &str, surface: &Texture<Vec3>) -> Result<()> {
+    let mut output = Texture::blank(surface.width, surface.height);
+    if let Some(ref profile) = args.icc_profile {
+        let profile = read_icc_profile(profile)?;
+        linear_to_profile(surface, &mut output, &profile);
+        export_with(path, &output, &ColorSpace::Icc(&profile))
+    } else if let Some(gamma) = args.gamma {
+        linear_to_gamma(surface, &mut output, gamma);
+        export_with(path, &output, &ColorSpace::Gamma(gamma))
+    } else {
+        linear_to_srgb(surface, &mut output);
+        export(path, &output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(args).unwrap()
+    }
+
+    #[test]
+    fn parsing_docopt_options() {
+        let cli = parse(&[
+            "peaks",
+            "--verbose",
+            "--samples=9",
+            "--threads=2",
+            "--tile-size=16",
+            "--vector=lines.svg",
+            "--mode=normals",
+            "--catalog=catalog.json",
+            "--orbit=8",
+            "--elevation=30",
+            "--columns=2",
+            "--stereo=anaglyph",
+            "--interocular=0.5",
+            "--convergence=100",
+            "--denoise=3",
+            "--gamma=2.2",
+            "--icc-profile=adobe.icc",
+            "--override=camera.width=3840",
+            "--override=shaders.3.color=[1,0,0]",
+            "scene.json",
+            "out.png",
+        ]);
+        let args = cli.args;
+        assert!(args.verbose);
+        assert_eq!(args.samples, 9);
+        assert_eq!(args.threads, Some(2));
+        assert_eq!(args.tile_size, Some(16));
+        assert_eq!(args.vector, Some(String::from("lines.svg")));
+        assert_eq!(args.mode, "normals");
+        assert_eq!(args.catalog, Some(String::from("catalog.json")));
+        assert_eq!(args.orbit, Some(8));
+        assert_eq!(args.elevation, Some(30.0));
+        assert_eq!(args.columns, 2);
+        assert_eq!(args.stereo, Some(String::from("anaglyph")));
+        assert_eq!(args.interocular, 0.5);
+        assert_eq!(args.convergence, Some(100.0));
+        assert_eq!(args.denoise, Some(3));
+        assert_eq!(args.gamma, Some(2.2));
+        assert_eq!(args.icc_profile, Some(String::from("adobe.icc")));
+        assert_eq!(
+            args.overrides,
+            vec!["camera.width=3840", "shaders.3.color=[1,0,0]"]
+        );
+        assert!(cli.command.is_none());
+        assert_eq!(cli.files, vec!["scene.json", "out.png"]);
+    }
+
+    #[test]
+    fn parsing_docopt_commands() {
+        let cli = parse(&["peaks", "out.png"]);
+        assert_eq!(cli.files, vec!["out.png"]);
+        assert_eq!(cli.args.samples, 4);
+        assert_eq!(cli.args.mode, "shaded");
+        assert_eq!(cli.args.columns, 4);
+        assert_eq!(cli.args.interocular, 1.0);
+
+        // Options are accepted after commands as before
+        let cli = parse(&["peaks", "batch", "jobs.json", "--samples", "2"]);
+        assert_eq!(cli.args.samples, 2);
+        match cli.command {
+            Some(Command::Batch { ref manifest }) => {
+                assert_eq!(manifest, "jobs.json")
+            }
+            _ => panic!("Expected a batch command"),
+        }
+        match parse(&["peaks", "watch", "scene.json", "out.png"]).command {
+            Some(Command::Watch {
+                ref input,
+                ref output,
+            }) => {
+                assert_eq!((&input[..], &output[..]), ("scene.json", "out.png"))
+            }
+            _ => panic!("Expected a watch command"),
+        }
+        match parse(&["peaks", "preview", "scene.json"]).command {
+            Some(Command::Preview { ref input }) => {
+                assert_eq!(input, "scene.json")
+            }
+            _ => panic!("Expected a preview command"),
+        }
+
+        assert!(Cli::try_parse_from(&["peaks", "a", "b", "c"]).is_err());
+        assert!(Cli::try_parse_from(&["peaks", "--samples=many"]).is_err());
+    }
 }
@@ -16,6 +16,9 @@
 use std::fmt;
 use std::ops::{Add, Mul};
 
+/// A color quantized to eight bits per component, for writing images. Colors
+/// are accumulated as linear `Vec3` values and only quantized once final, so
+/// arithmetic on them saturates rather than overflowing
 #[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub struct Color {
     pub r: u8,
@@ -41,9 +44,9 @@ impl Add for Color {
     #[inline(always)]
     fn add(self, rhs: Color) -> Color {
         Color {
-            r: self.r + rhs.r,
-            g: self.g + rhs.g,
-            b: self.b + rhs.b,
+            r: self.r.saturating_add(rhs.r),
+            g: self.g.saturating_add(rhs.g),
+            b: self.b.saturating_add(rhs.b),
         }
     }
 }
@@ -53,10 +56,27 @@ impl Mul<f64> for Color {
 
     #[inline(always)]
     fn mul(self, rhs: f64) -> Color {
+        let scale = |value: u8| {
+            (f64::from(value) * rhs).round().max(0.0).min(255.0) as u8
+        };
         Color {
-            r: (f64::from(self.r) * rhs).round().min(255.0) as u8,
-            g: (f64::from(self.g) * rhs).round().min(255.0) as u8,
-            b: (f64::from(self.b) * rhs).round().min(255.0) as u8,
+            r: scale(self.r),
+            g: scale(self.g),
+            b: scale(self.b),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saturating_arithmetic() {
+        let color = Color::new(200, 100, 0);
+        assert_eq!(color + color, Color::new(255, 200, 0));
+        assert_eq!(color * 2.0, Color::new(255, 200, 0));
+        assert_eq!(color * -1.0, Color::new(0, 0, 0));
+        assert_eq!(color * 0.5, Color::new(100, 50, 0));
+    }
+}
@@ -14,11 +14,18 @@
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
 mod color;
+mod projection;
 mod ray;
+mod simd;
 mod transform;
 mod vec3;
 
 pub use self::color::Color;
+pub use self::projection::{reproject, Projection};
 pub use self::ray::Ray;
+pub use self::simd::F64x4;
 pub use self::transform::AffineTransform;
 pub use self::vec3::Vec3;
+
+/// Mean radius of the earth in metres
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
@@ -0,0 +1,239 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use options::ProjectionOpts;
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+
+/// Semi-major axis and flattening of the WGS84 ellipsoid
+const WGS84_A: f64 = 6_378_137.0;
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+/// Scale factor, false easting and false northing in the southern hemisphere
+/// of UTM zones
+const UTM_K0: f64 = 0.9996;
+const UTM_EASTING: f64 = 500_000.0;
+const UTM_NORTHING: f64 = 10_000_000.0;
+
+/// A coordinate reference system on the WGS84 datum
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    /// Longitude and latitude in degrees
+    Geographic,
+    /// Spherical Mercator in metres, as used by web map tiles
+    WebMercator,
+    /// Universal Transverse Mercator in metres, for a zone and hemisphere
+    Utm { zone: u8, south: bool },
+}
+
+impl Projection {
+    /// Return the projected coordinates of a longitude and latitude in
+    /// degrees
+    pub fn forward(&self, lon: f64, lat: f64) -> (f64, f64) {
+        match *self {
+            Projection::Geographic => (lon, lat),
+            Projection::WebMercator => {
+                let (lon, lat) = (lon.to_radians(), lat.to_radians());
+                let y = (FRAC_PI_4 + lat / 2.0).tan().ln();
+                (WGS84_A * lon, WGS84_A * y)
+            }
+            Projection::Utm { zone, south } => {
+                let (x, y) =
+                    transverse_mercator(central_meridian(zone), lon, lat);
+                let y = if south { y + UTM_NORTHING } else { y };
+                (x + UTM_EASTING, y)
+            }
+        }
+    }
+
+    /// Return the longitude and latitude in degrees of projected coordinates
+    pub fn inverse(&self, x: f64, y: f64) -> (f64, f64) {
+        match *self {
+            Projection::Geographic => (x, y),
+            Projection::WebMercator => {
+                let lat = 2.0 * (y / WGS84_A).exp().atan() - FRAC_PI_2;
+                ((x / WGS84_A).to_degrees(), lat.to_degrees())
+            }
+            Projection::Utm { zone, south } => {
+                let y = if south { y - UTM_NORTHING } else { y };
+                inverse_transverse_mercator(
+                    central_meridian(zone),
+                    x - UTM_EASTING,
+                    y,
+                )
+            }
+        }
+    }
+}
+
+impl From<ProjectionOpts> for Projection {
+    fn from(options: ProjectionOpts) -> Projection {
+        match options {
+            ProjectionOpts::Geographic => Projection::Geographic,
+            ProjectionOpts::WebMercator => Projection::WebMercator,
+            ProjectionOpts::Utm { zone, south } => {
+                Projection::Utm { zone, south }
+            }
+        }
+    }
+}
+
+fn central_meridian(zone: u8) -> f64 {
+    f64::from(zone) * 6.0 - 183.0
+}
+
+/// Return the squared first and second eccentricities of the ellipsoid
+fn eccentricities() -> (f64, f64) {
+    let e2 = WGS84_F * (2.0 - WGS84_F);
+    (e2, e2 / (1.0 - e2))
+}
+
+/// Return the distance along the meridian from the equator to a latitude
+fn meridian_arc(lat: f64) -> f64 {
+    let (e2, _) = eccentricities();
+    let (e4, e6) = (e2 * e2, e2 * e2 * e2);
+    WGS84_A
+        * ((1.0 - e2 / 4.0 - 3.0 * e4 / 64.0 - 5.0 * e6 / 256.0) * lat
+            - (3.0 * e2 / 8.0 + 3.0 * e4 / 32.0 + 45.0 * e6 / 1024.0)
+                * (2.0 * lat).sin()
+            + (15.0 * e4 / 256.0 + 45.0 * e6 / 1024.0) * (4.0 * lat).sin()
+            - (35.0 * e6 / 3072.0) * (6.0 * lat).sin())
+}
+
+/// Project a longitude and latitude in degrees onto a transverse Mercator
+/// with the UTM scale factor, as series expansions (Snyder, 1987)
+fn transverse_mercator(lon0: f64, lon: f64, lat: f64) -> (f64, f64) {
+    let (e2, ep2) = eccentricities();
+    let lat = lat.to_radians();
+    let (sin, cos, tan) = (lat.sin(), lat.cos(), lat.tan());
+
+    let n = WGS84_A / (1.0 - e2 * sin * sin).sqrt();
+    let t = tan * tan;
+    let c = ep2 * cos * cos;
+    let a = (lon - lon0).to_radians() * cos;
+
+    let x = UTM_K0
+        * n
+        * (a + (1.0 - t + c) * a.powi(3) / 6.0
+            + (5.0 - 18.0 * t + t * t + 72.0 * c - 58.0 * ep2) * a.powi(5)
+                / 120.0);
+    let y = UTM_K0
+        * (meridian_arc(lat)
+            + n * tan
+                * (a * a / 2.0
+                    + (5.0 - t + 9.0 * c + 4.0 * c * c) * a.powi(4) / 24.0
+                    + (61.0 - 58.0 * t + t * t + 600.0 * c - 330.0 * ep2)
+                        * a.powi(6)
+                        / 720.0));
+    (x, y)
+}
+
+/// Return the longitude and latitude in degrees of coordinates on a
+/// transverse Mercator
+fn inverse_transverse_mercator(lon0: f64, x: f64, y: f64) -> (f64, f64) {
+    let (e2, ep2) = eccentricities();
+    let (e4, e6) = (e2 * e2, e2 * e2 * e2);
+
+    // Latitude of the point on the central meridian with the same northing
+    let m = y / UTM_K0;
+    let mu =
+        m / (WGS84_A * (1.0 - e2 / 4.0 - 3.0 * e4 / 64.0 - 5.0 * e6 / 256.0));
+    let e1 = (1.0 - (1.0 - e2).sqrt()) / (1.0 + (1.0 - e2).sqrt());
+    let lat1 = mu
+        + (3.0 * e1 / 2.0 - 27.0 * e1.powi(3) / 32.0) * (2.0 * mu).sin()
+        + (21.0 * e1 * e1 / 16.0 - 55.0 * e1.powi(4) / 32.0) * (4.0 * mu).sin()
+        + (151.0 * e1.powi(3) / 96.0) * (6.0 * mu).sin()
+        + (1097.0 * e1.powi(4) / 512.0) * (8.0 * mu).sin();
+
+    let (sin, cos, tan) = (lat1.sin(), lat1.cos(), lat1.tan());
+    let c = ep2 * cos * cos;
+    let t = tan * tan;
+    let n = WGS84_A / (1.0 - e2 * sin * sin).sqrt();
+    let r = WGS84_A * (1.0 - e2) / (1.0 - e2 * sin * sin).powf(1.5);
+    let d = x / (n * UTM_K0);
+
+    let lat = lat1
+        - (n * tan / r)
+            * (d * d / 2.0
+                - (5.0 + 3.0 * t + 10.0 * c - 4.0 * c * c - 9.0 * ep2)
+                    * d.powi(4)
+                    / 24.0
+                + (61.0 + 90.0 * t + 298.0 * c + 45.0 * t * t
+                    - 252.0 * ep2
+                    - 3.0 * c * c)
+                    * d.powi(6)
+                    / 720.0);
+    let lon = (d - (1.0 + 2.0 * t + c) * d.powi(3) / 6.0
+        + (5.0 - 2.0 * c + 28.0 * t - 3.0 * c * c + 8.0 * ep2 + 24.0 * t * t)
+            * d.powi(5)
+            / 120.0)
+        / cos;
+    (lon0 + lon.to_degrees(), lat.to_degrees())
+}
+
+/// Convert coordinates from one projection to another
+pub fn reproject(
+    from: &Projection,
+    to: &Projection,
+    x: f64,
+    y: f64,
+) -> (f64, f64) {
+    if from == to {
+        return (x, y);
+    }
+    let (lon, lat) = from.inverse(x, y);
+    to.forward(lon, lat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projecting_known_points() {
+        let (x, y) = Projection::WebMercator.forward(180.0, 0.0);
+        assert!((x - 20_037_508.34).abs() < 0.01 && y.abs() < 1e-6);
+
+        // On the central meridian of zone 30
+        let utm = Projection::Utm {
+            zone: 30,
+            south: false,
+        };
+        let (x, y) = utm.forward(-3.0, 50.0);
+        assert!((x - 500_000.0).abs() < 1e-6);
+        assert!((y - 5_538_630.7).abs() < 0.1);
+    }
+
+    #[test]
+    fn round_trips() {
+        let projections = [
+            Projection::WebMercator,
+            Projection::Utm {
+                zone: 32,
+                south: false,
+            },
+            Projection::Utm {
+                zone: 19,
+                south: true,
+            },
+        ];
+        let points = [(9.5, 46.2), (7.1, 44.0), (-70.3, -33.4)];
+        for (projection, &(lon, lat)) in projections.iter().zip(&points) {
+            let (x, y) = projection.forward(lon, lat);
+            let (lon2, lat2) = projection.inverse(x, y);
+            assert!((lon - lon2).abs() < 1e-7, "{:?}", projection);
+            assert!((lat - lat2).abs() < 1e-7, "{:?}", projection);
+        }
+    }
+}
@@ -16,10 +16,20 @@
 use super::vec3::Vec3;
 use std::fmt;
 
+/// Rays offset by one pixel in `x` and `y` on the view plane
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RayDifferentials {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3,
+}
+
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    pub differentials: Option<RayDifferentials>,
 }
 
 impl fmt::Display for Ray {
@@ -34,6 +44,33 @@ impl fmt::Display for Ray {
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            differentials: None,
+        }
+    }
+
+    /// Return the largest distance from the ray to its differentials at a
+    /// distance along it, approximating the width of a pixel there
+    pub fn spread(&self, t: f64) -> Option<f64> {
+        let differentials = self.differentials?;
+        let point = self.origin + self.direction * t;
+        let rx = differentials.rx_origin + differentials.rx_direction * t;
+        let ry = differentials.ry_origin + differentials.ry_direction * t;
+        Some(Vec3::distance(rx, point).max(Vec3::distance(ry, point)))
+    }
+
+    /// Return the ray with differentials from rays for neighbouring pixels
+    pub fn with_differentials(self, rx: Ray, ry: Ray) -> Ray {
+        Ray {
+            differentials: Some(RayDifferentials {
+                rx_origin: rx.origin,
+                rx_direction: rx.direction,
+                ry_origin: ry.origin,
+                ry_direction: ry.direction,
+            }),
+            ..self
+        }
     }
 }
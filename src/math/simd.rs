@@ -0,0 +1,333 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Four lanes of doubles, held in SSE2 registers with the `simd` feature on
+//! x86_64 and in arrays otherwise. Each operation rounds as the same scalar
+//! operation would, so either gives the same results as scalar code.
+
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+pub use self::lanes::F64x4;
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod lanes {
+    use std::arch::x86_64::*;
+
+    #[derive(Copy, Clone, Debug)]
+    pub struct F64x4(__m128d, __m128d);
+
+    /// The result of comparing lanes, with all bits of a lane set if true
+    #[derive(Copy, Clone, Debug)]
+    pub struct Mask4(__m128d, __m128d);
+
+    // SSE2 is part of every x86_64 processor, so these are always safe
+    impl F64x4 {
+        #[inline(always)]
+        pub fn new(a: f64, b: f64, c: f64, d: f64) -> F64x4 {
+            unsafe { F64x4(_mm_set_pd(b, a), _mm_set_pd(d, c)) }
+        }
+
+        #[inline(always)]
+        pub fn splat(value: f64) -> F64x4 {
+            unsafe { F64x4(_mm_set1_pd(value), _mm_set1_pd(value)) }
+        }
+
+        #[inline(always)]
+        pub fn to_array(self) -> [f64; 4] {
+            let mut lanes = [0.0; 4];
+            unsafe {
+                _mm_storeu_pd(lanes[..2].as_mut_ptr(), self.0);
+                _mm_storeu_pd(lanes[2..].as_mut_ptr(), self.1);
+            }
+            lanes
+        }
+
+        /// Swap the first two lanes with the last two
+        #[inline(always)]
+        pub fn swap_halves(self) -> F64x4 {
+            F64x4(self.1, self.0)
+        }
+
+        #[inline(always)]
+        pub(super) fn zip<F>(self, other: F64x4, op: F) -> F64x4
+        where
+            F: Fn(__m128d, __m128d) -> __m128d,
+        {
+            F64x4(op(self.0, other.0), op(self.1, other.1))
+        }
+
+        /// Return the larger of each pair of lanes, ignoring NaNs as
+        /// `f64::max` does
+        #[inline(always)]
+        pub fn max(self, other: F64x4) -> F64x4 {
+            self.zip(other, |a, b| unsafe {
+                // Takes `b` where `a` is NaN, then `a` where `b` is NaN
+                let max = _mm_max_pd(a, b);
+                let nan = _mm_cmpunord_pd(b, b);
+                _mm_or_pd(_mm_and_pd(nan, a), _mm_andnot_pd(nan, max))
+            })
+        }
+
+        /// Return the smaller of each pair of lanes, ignoring NaNs as
+        /// `f64::min` does
+        #[inline(always)]
+        pub fn min(self, other: F64x4) -> F64x4 {
+            self.zip(other, |a, b| unsafe {
+                let min = _mm_min_pd(a, b);
+                let nan = _mm_cmpunord_pd(b, b);
+                _mm_or_pd(_mm_and_pd(nan, a), _mm_andnot_pd(nan, min))
+            })
+        }
+
+        #[inline(always)]
+        pub fn abs(self) -> F64x4 {
+            self.zip(F64x4::splat(-0.0), |a, sign| unsafe {
+                _mm_andnot_pd(sign, a)
+            })
+        }
+
+        #[inline(always)]
+        pub fn gt(self, other: F64x4) -> Mask4 {
+            unsafe {
+                Mask4(
+                    _mm_cmpgt_pd(self.0, other.0),
+                    _mm_cmpgt_pd(self.1, other.1),
+                )
+            }
+        }
+    }
+
+    impl Mask4 {
+        /// Return lanes of one value where the mask is set, and of another
+        /// where it is not
+        #[inline(always)]
+        pub fn select(self, a: F64x4, b: F64x4) -> F64x4 {
+            let blend = |mask, a, b| unsafe {
+                _mm_or_pd(_mm_and_pd(mask, a), _mm_andnot_pd(mask, b))
+            };
+            F64x4(blend(self.0, a.0, b.0), blend(self.1, a.1, b.1))
+        }
+    }
+
+    #[inline(always)]
+    pub(super) fn add(a: __m128d, b: __m128d) -> __m128d {
+        unsafe { _mm_add_pd(a, b) }
+    }
+
+    #[inline(always)]
+    pub(super) fn sub(a: __m128d, b: __m128d) -> __m128d {
+        unsafe { _mm_sub_pd(a, b) }
+    }
+
+    #[inline(always)]
+    pub(super) fn mul(a: __m128d, b: __m128d) -> __m128d {
+        unsafe { _mm_mul_pd(a, b) }
+    }
+
+    #[inline(always)]
+    pub(super) fn div(a: __m128d, b: __m128d) -> __m128d {
+        unsafe { _mm_div_pd(a, b) }
+    }
+
+    #[inline(always)]
+    pub(super) fn neg(a: F64x4) -> F64x4 {
+        a.zip(F64x4::splat(-0.0), |a, sign| unsafe { _mm_xor_pd(a, sign) })
+    }
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+mod lanes {
+    #[derive(Copy, Clone, Debug)]
+    pub struct F64x4([f64; 4]);
+
+    /// The result of comparing lanes
+    #[derive(Copy, Clone, Debug)]
+    pub struct Mask4([bool; 4]);
+
+    impl F64x4 {
+        #[inline(always)]
+        pub fn new(a: f64, b: f64, c: f64, d: f64) -> F64x4 {
+            F64x4([a, b, c, d])
+        }
+
+        #[inline(always)]
+        pub fn splat(value: f64) -> F64x4 {
+            F64x4([value; 4])
+        }
+
+        #[inline(always)]
+        pub fn to_array(self) -> [f64; 4] {
+            self.0
+        }
+
+        /// Swap the first two lanes with the last two
+        #[inline(always)]
+        pub fn swap_halves(self) -> F64x4 {
+            let a = self.0;
+            F64x4([a[2], a[3], a[0], a[1]])
+        }
+
+        #[inline(always)]
+        pub(super) fn zip<F>(self, other: F64x4, op: F) -> F64x4
+        where
+            F: Fn(f64, f64) -> f64,
+        {
+            let (a, b) = (self.0, other.0);
+            F64x4([
+                op(a[0], b[0]),
+                op(a[1], b[1]),
+                op(a[2], b[2]),
+                op(a[3], b[3]),
+            ])
+        }
+
+        /// Return the larger of each pair of lanes, ignoring NaNs as
+        /// `f64::max` does
+        #[inline(always)]
+        pub fn max(self, other: F64x4) -> F64x4 {
+            self.zip(other, f64::max)
+        }
+
+        /// Return the smaller of each pair of lanes, ignoring NaNs as
+        /// `f64::min` does
+        #[inline(always)]
+        pub fn min(self, other: F64x4) -> F64x4 {
+            self.zip(other, f64::min)
+        }
+
+        #[inline(always)]
+        pub fn abs(self) -> F64x4 {
+            self.zip(self, |a, _| a.abs())
+        }
+
+        #[inline(always)]
+        pub fn gt(self, other: F64x4) -> Mask4 {
+            let (a, b) = (self.0, other.0);
+            Mask4([a[0] > b[0], a[1] > b[1], a[2] > b[2], a[3] > b[3]])
+        }
+    }
+
+    impl Mask4 {
+        /// Return lanes of one value where the mask is set, and of another
+        /// where it is not
+        #[inline(always)]
+        pub fn select(self, a: F64x4, b: F64x4) -> F64x4 {
+            let lane = |i: usize| if self.0[i] { a.0[i] } else { b.0[i] };
+            F64x4([lane(0), lane(1), lane(2), lane(3)])
+        }
+    }
+
+    #[inline(always)]
+    pub(super) fn add(a: f64, b: f64) -> f64 {
+        a + b
+    }
+
+    #[inline(always)]
+    pub(super) fn sub(a: f64, b: f64) -> f64 {
+        a - b
+    }
+
+    #[inline(always)]
+    pub(super) fn mul(a: f64, b: f64) -> f64 {
+        a * b
+    }
+
+    #[inline(always)]
+    pub(super) fn div(a: f64, b: f64) -> f64 {
+        a / b
+    }
+
+    #[inline(always)]
+    pub(super) fn neg(a: F64x4) -> F64x4 {
+        a.zip(a, |a, _| -a)
+    }
+}
+
+impl Add for F64x4 {
+    type Output = F64x4;
+
+    #[inline(always)]
+    fn add(self, other: F64x4) -> F64x4 {
+        self.zip(other, lanes::add)
+    }
+}
+
+impl Sub for F64x4 {
+    type Output = F64x4;
+
+    #[inline(always)]
+    fn sub(self, other: F64x4) -> F64x4 {
+        self.zip(other, lanes::sub)
+    }
+}
+
+impl Mul for F64x4 {
+    type Output = F64x4;
+
+    #[inline(always)]
+    fn mul(self, other: F64x4) -> F64x4 {
+        self.zip(other, lanes::mul)
+    }
+}
+
+impl Div for F64x4 {
+    type Output = F64x4;
+
+    #[inline(always)]
+    fn div(self, other: F64x4) -> F64x4 {
+        self.zip(other, lanes::div)
+    }
+}
+
+impl Neg for F64x4 {
+    type Output = F64x4;
+
+    #[inline(always)]
+    fn neg(self) -> F64x4 {
+        lanes::neg(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::{INFINITY, NAN};
+
+    #[test]
+    fn matching_scalar_operations() {
+        let a = F64x4::new(1.5, -2.0, NAN, 0.0);
+        let b = F64x4::new(3.0, NAN, 4.0, -0.0);
+        let (sa, sb) = (a.to_array(), b.to_array());
+
+        let (max, min) = (a.max(b).to_array(), a.min(b).to_array());
+        for i in 0..4 {
+            assert_eq!(max[i], sa[i].max(sb[i]));
+            assert_eq!(min[i], sa[i].min(sb[i]));
+        }
+        assert_eq!((a * b + a).to_array()[0], 1.5 * 3.0 + 1.5);
+        assert_eq!((a / F64x4::splat(0.0)).to_array()[1], -INFINITY);
+        assert_eq!((-a).abs().to_array()[1], 2.0);
+        assert!((-F64x4::splat(0.0)).to_array()[0].is_sign_negative());
+
+        let mask = a.gt(F64x4::splat(0.0));
+        let selected = mask.select(a, b).to_array();
+        assert_eq!(selected[0], 1.5);
+        assert!(selected[1].is_nan() && selected[2] == 4.0);
+        assert_eq!(selected[3], -0.0);
+
+        let swapped = F64x4::new(1.0, 2.0, 3.0, 4.0).swap_halves();
+        assert_eq!(swapped.to_array(), [3.0, 4.0, 1.0, 2.0]);
+    }
+}
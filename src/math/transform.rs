@@ -28,6 +28,11 @@ impl AffineTransform {
         }
     }
 
+    /// Return the offsets and scales of the transform on each axis
+    pub fn coefficients(&self) -> [f64; 4] {
+        self.transform
+    }
+
     /// Return the result of the transform
     #[inline(always)]
     #[allow(dead_code)]
@@ -38,6 +43,12 @@ impl AffineTransform {
         (x, y)
     }
 
+    /// Return the transform with its output scaled on each axis
+    pub fn scale(&self, x: f64, y: f64) -> AffineTransform {
+        let [e, f, a, d] = self.transform;
+        AffineTransform::new(e * x, f * y, a * x, d * y)
+    }
+
     /// Return the transformation across quadtree levels
     #[inline(always)]
     pub fn quadtree(&self, level: usize, x: f64, y: f64) -> (f64, f64) {
@@ -86,4 +97,11 @@ mod tests {
         assert_eq!(t.inverse(100.0, 100.0), (100.0, 100.0));
         assert_eq!(t.inverse(-100.0, 100.0), (0.0, 100.0));
     }
+
+    #[test]
+    fn scaling_affine_transforms() {
+        let t = AffineTransform::new(10.0, -50.0, 0.5, 0.25).scale(2.0, 4.0);
+        assert_eq!(t.forward(0.0, 0.0), (20.0, -200.0));
+        assert_eq!(t.forward(2.0, 4.0), (22.0, -196.0));
+    }
 }
@@ -65,6 +65,11 @@ impl Vec3 {
         (diff.x * diff.x + diff.y * diff.y + diff.z * diff.z).sqrt()
     }
 
+    #[inline(always)]
+    pub fn length(a: Vec3) -> f64 {
+        Vec3::dot(a, a).sqrt()
+    }
+
     #[inline(always)]
     pub fn sqrt(a: Vec3) -> Vec3 {
         Vec3::new(a.x.sqrt(), a.y.sqrt(), a.z.sqrt())
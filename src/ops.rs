@@ -13,11 +13,20 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
-use math::{Color, Vec3};
+use io::icc::IccProfile;
+use math::{AffineTransform, Color, Vec3};
+use ramps::Ramp;
+use shapes::{LineString, Shape};
 use textures::Texture;
 
+use std::collections::{HashMap, HashSet};
+use std::f64::{INFINITY, NEG_INFINITY};
+use std::io::{self, Error, ErrorKind};
+use std::ops::{Add, Mul};
+use std::str::FromStr;
+
 /// Map a function over each pixel in a texture
-fn operator1x1<F, I, O>(
+pub fn operator1x1<F, I, O>(
     input: &Texture<I>,
     output: &mut Texture<O>,
     mut callback: F,
@@ -41,6 +50,132 @@ fn operator1x1<F, I, O>(
     }
 }
 
+/// Map a function over the pixels at the same position in two textures
+pub fn map2<F, A, B, O>(
+    a: &Texture<A>,
+    b: &Texture<B>,
+    output: &mut Texture<O>,
+    mut callback: F,
+) where
+    F: FnMut(A, B) -> O,
+    A: Copy + Default,
+    B: Copy + Default,
+    O: Copy + Default,
+{
+    assert_eq!((a.width, a.height), (b.width, b.height));
+    assert_eq!((a.width, a.height), (output.width, output.height));
+
+    for (i, value) in output.buffer.iter_mut().enumerate() {
+        *value = callback(a.buffer[i], b.buffer[i]);
+    }
+}
+
+/// Map a function over the pixels at the same position in three textures
+pub fn map3<F, A, B, C, O>(
+    a: &Texture<A>,
+    b: &Texture<B>,
+    c: &Texture<C>,
+    output: &mut Texture<O>,
+    mut callback: F,
+) where
+    F: FnMut(A, B, C) -> O,
+    A: Copy + Default,
+    B: Copy + Default,
+    C: Copy + Default,
+    O: Copy + Default,
+{
+    assert_eq!((a.width, a.height), (b.width, b.height));
+    assert_eq!((a.width, a.height), (c.width, c.height));
+    assert_eq!((a.width, a.height), (output.width, output.height));
+
+    for (i, value) in output.buffer.iter_mut().enumerate() {
+        *value = callback(a.buffer[i], b.buffer[i], c.buffer[i]);
+    }
+}
+
+/// How neighbourhoods are filled beyond the edges of a texture
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EdgePolicy {
+    /// Repeat the nearest edge pixel
+    Clamp,
+    /// Reflect pixels about the edge, without repeating it
+    Mirror,
+    /// Continue from the opposite edge
+    Wrap,
+}
+
+impl EdgePolicy {
+    /// Return the index of a coordinate that may lie outside of a length
+    fn index(self, i: isize, length: usize) -> usize {
+        let n = length as isize;
+        match self {
+            EdgePolicy::Clamp => i.max(0).min(n - 1) as usize,
+            EdgePolicy::Mirror if n == 1 => 0,
+            EdgePolicy::Mirror => {
+                let period = 2 * (n - 1);
+                let i = ((i % period) + period) % period;
+                (if i < n { i } else { period - i }) as usize
+            }
+            EdgePolicy::Wrap => (((i % n) + n) % n) as usize,
+        }
+    }
+}
+
+/// Map a function over the square neighbourhood of each pixel in a texture,
+/// given as rows of `2 * radius + 1` values with the pixel in the center
+pub fn operator_nxn<F, I, O>(
+    input: &Texture<I>,
+    output: &mut Texture<O>,
+    radius: usize,
+    edges: EdgePolicy,
+    mut callback: F,
+) where
+    F: FnMut(&[I]) -> O,
+    I: Copy + Default,
+    O: Copy + Default,
+{
+    assert_eq!(input.width, output.width);
+    assert_eq!(input.height, output.height);
+
+    let width = input.width;
+    let height = input.height;
+    let r = radius as isize;
+    let mut neighbourhood = Vec::with_capacity((2 * radius + 1).pow(2));
+
+    for y in 0..height {
+        for x in 0..width {
+            neighbourhood.clear();
+            for dy in -r..=r {
+                let sy = edges.index(y as isize + dy, height);
+                for dx in -r..=r {
+                    let sx = edges.index(x as isize + dx, width);
+                    neighbourhood.push(input.lookup1x1(sx, sy));
+                }
+            }
+            let result = callback(&neighbourhood);
+            output.write1x1(x, y, result);
+        }
+    }
+}
+
+/// Map a function over the 3x3 neighbourhood of each pixel in a texture
+pub fn operator3x3<F, I, O>(
+    input: &Texture<I>,
+    output: &mut Texture<O>,
+    edges: EdgePolicy,
+    mut callback: F,
+) where
+    F: FnMut([I; 9]) -> O,
+    I: Copy + Default,
+    O: Copy + Default,
+{
+    operator_nxn(input, output, 1, edges, |values| {
+        let mut neighbourhood = [I::default(); 9];
+        neighbourhood.copy_from_slice(values);
+        callback(neighbourhood)
+    })
+}
+
 /// Blit one texture onto another
 pub fn blit<T>(input: &Texture<T>, output: &mut Texture<T>, x: usize, y: usize)
 where
@@ -56,6 +191,39 @@ where
     }
 }
 
+/// Arrange textures of the same size into a grid, filling rows first
+pub fn contact_sheet<T>(views: &[Texture<T>], columns: usize) -> Texture<T>
+where
+    T: Copy + Default,
+{
+    if views.is_empty() {
+        return Texture::blank(0, 0);
+    }
+    let (width, height) = (views[0].width, views[0].height);
+    let columns = columns.max(1).min(views.len());
+    let rows = (views.len() + columns - 1) / columns;
+    let mut sheet = Texture::blank(width * columns, height * rows);
+    for (i, view) in views.iter().enumerate() {
+        blit(
+            view,
+            &mut sheet,
+            (i % columns) * width,
+            (i / columns) * height,
+        );
+    }
+    sheet
+}
+
+/// Combine a stereo pair into a red-cyan anaglyph, taking red from the left
+/// eye and green and blue from the right
+pub fn anaglyph(
+    left: &Texture<Vec3>,
+    right: &Texture<Vec3>,
+    output: &mut Texture<Vec3>,
+) {
+    map2(left, right, output, |l, r| Vec3::new(l.x, r.y, r.z));
+}
+
 /// Blit one texture onto another
 pub fn blit_region<T>(
     input: &Texture<T>,
@@ -113,8 +281,893 @@ pub fn maximum_mipmap_bilinear_patch(
     }
 }
 
-/// Convert linear colors to sRGB
-pub fn linear_to_srgb(input: &Texture<Vec3>, output: &mut Texture<Color>) {
+/// Values averaged in a type holding them without rounding, so quantized
+/// values are rounded once rather than for every value summed
+pub trait Average: Copy + Default {
+    type Sum: Add<Output = Self::Sum> + Mul<f64, Output = Self::Sum> + Copy;
+    fn widen(self) -> Self::Sum;
+    fn narrow(sum: Self::Sum) -> Self;
+}
+
+impl Average for f64 {
+    type Sum = f64;
+
+    fn widen(self) -> f64 {
+        self
+    }
+
+    fn narrow(sum: f64) -> f64 {
+        sum
+    }
+}
+
+impl Average for Vec3 {
+    type Sum = Vec3;
+
+    fn widen(self) -> Vec3 {
+        self
+    }
+
+    fn narrow(sum: Vec3) -> Vec3 {
+        sum
+    }
+}
+
+impl Average for Color {
+    type Sum = Vec3;
+
+    fn widen(self) -> Vec3 {
+        Vec3::new(f64::from(self.r), f64::from(self.g), f64::from(self.b))
+    }
+
+    fn narrow(sum: Vec3) -> Color {
+        let quantize = |value: f64| value.round().max(0.0).min(255.0) as u8;
+        Color::new(quantize(sum.x), quantize(sum.y), quantize(sum.z))
+    }
+}
+
+/// Downsample a texture to half its size by averaging 2x2 windows, summing
+/// the values without rounding and rounding their mean once
+pub fn downsample<T: Average>(input: &Texture<T>, output: &mut Texture<T>) {
+    assert_eq!(input.width / 2, output.width);
+    assert_eq!(input.height / 2, output.height);
+
+    for y in 0..output.height {
+        for x in 0..output.width {
+            let [p1, p2, p3, p4] = input.lookup2x2(x * 2, y * 2);
+            let sum = p1.widen() + p2.widen() + p3.widen() + p4.widen();
+            output.write1x1(x, y, T::narrow(sum * 0.25));
+        }
+    }
+}
+
+/// Convert ellipsoidal heights to orthometric heights by subtracting the
+/// undulation of the geoid, negate the undulations to convert back
+pub fn apply_geoid(
+    heights: &Texture<f64>,
+    geoid: &Texture<f64>,
+    output: &mut Texture<f64>,
+) {
+    assert_eq!(heights.width, geoid.width);
+    assert_eq!(heights.height, geoid.height);
+    assert_eq!(heights.width, output.width);
+    assert_eq!(heights.height, output.height);
+
+    for (i, height) in heights.buffer.iter().enumerate() {
+        output.buffer[i] = height - geoid.buffer[i];
+    }
+}
+
+/// Return the apparent drop in height of a point at a distance, due to the
+/// curvature of the earth less the effect of atmospheric refraction
+pub fn curvature_drop(distance: f64, refraction: f64, radius: f64) -> f64 {
+    (1.0 - refraction) * distance * distance / (2.0 * radius)
+}
+
+/// Lower heights by their apparent drop as seen from an origin in world space
+pub fn apply_curvature(
+    input: &Texture<f64>,
+    output: &mut Texture<f64>,
+    transform: &AffineTransform,
+    origin: (f64, f64),
+    refraction: f64,
+    radius: f64,
+) {
+    assert_eq!(input.width, output.width);
+    assert_eq!(input.height, output.height);
+
+    for y in 0..input.height {
+        for x in 0..input.width {
+            let (px, pz) = transform.forward(x as f64, y as f64);
+            let distance = (px - origin.0).hypot(pz - origin.1);
+            let drop = curvature_drop(distance, refraction, radius);
+            output.write1x1(x, y, input.lookup1x1(x, y) - drop);
+        }
+    }
+}
+
+/// An edge between two neighbouring texels, to the right of or below a texel
+#[cfg_attr(not(feature = "gdal"), allow(dead_code))]
+type ContourEdge = (usize, usize, bool);
+
+/// Return the line segments of a contour level crossing each cell of four
+/// texels (marching squares), as pairs of the edges they join
+#[cfg_attr(not(feature = "gdal"), allow(dead_code))]
+fn contour_segments(
+    input: &Texture<f64>,
+    level: f64,
+) -> Vec<(ContourEdge, ContourEdge)> {
+    let mut segments = vec![];
+    for y in 0..input.height.saturating_sub(1) {
+        for x in 0..input.width.saturating_sub(1) {
+            let [a, b, d, c] = input.lookup2x2(x, y);
+            if a.is_nan() || b.is_nan() || c.is_nan() || d.is_nan() {
+                continue;
+            }
+
+            let top = (x, y, false);
+            let right = (x + 1, y, true);
+            let bottom = (x, y + 1, false);
+            let left = (x, y, true);
+
+            let case = (a >= level) as u8
+                | ((b >= level) as u8) << 1
+                | ((c >= level) as u8) << 2
+                | ((d >= level) as u8) << 3;
+            let center = (a + b + c + d) / 4.0 >= level;
+
+            match case {
+                0 | 15 => (),
+                1 | 14 => segments.push((left, top)),
+                2 | 13 => segments.push((top, right)),
+                3 | 12 => segments.push((left, right)),
+                4 | 11 => segments.push((right, bottom)),
+                6 | 9 => segments.push((top, bottom)),
+                7 | 8 => segments.push((left, bottom)),
+                // Saddles, split by the average of the cell
+                5 | 10 if (case == 5) != center => {
+                    segments.push((left, top));
+                    segments.push((right, bottom));
+                }
+                _ => {
+                    segments.push((top, right));
+                    segments.push((left, bottom));
+                }
+            }
+        }
+    }
+    segments
+}
+
+/// Return lines following a height map at regular intervals of height from a
+/// base, with texels positioned by a transform and lines at their height
+#[cfg_attr(not(feature = "gdal"), allow(dead_code))]
+pub fn contours(
+    input: &Texture<f64>,
+    transform: &AffineTransform,
+    interval: f64,
+    base: f64,
+) -> Vec<Shape> {
+    assert!(interval > 0.0);
+
+    let (min, max) = input
+        .buffer
+        .iter()
+        .filter(|value| !value.is_nan())
+        .fold((INFINITY, NEG_INFINITY), |(min, max), value| {
+            (min.min(*value), max.max(*value))
+        });
+    if min > max {
+        return vec![];
+    }
+
+    let mut output = vec![];
+    let first = ((min - base) / interval).ceil() as i64;
+    let last = ((max - base) / interval).floor() as i64;
+    for step in first..=last {
+        let level = base + step as f64 * interval;
+        let point = |(x, y, vertical): ContourEdge| {
+            let (nx, ny) = if vertical { (x, y + 1) } else { (x + 1, y) };
+            let v0 = input.lookup1x1(x, y);
+            let v1 = input.lookup1x1(nx, ny);
+            let t = if v1 == v0 {
+                0.5
+            } else {
+                (level - v0) / (v1 - v0)
+            };
+            let (px, py) = if vertical {
+                (x as f64, y as f64 + t)
+            } else {
+                (x as f64 + t, y as f64)
+            };
+            let (px, pz) = transform.forward(px, py);
+            Vec3::new(px, level, pz)
+        };
+
+        let segments = contour_segments(input, level);
+        let mut links: HashMap<ContourEdge, Vec<ContourEdge>> = HashMap::new();
+        for &(a, b) in &segments {
+            links.entry(a).or_insert_with(Vec::new).push(b);
+            links.entry(b).or_insert_with(Vec::new).push(a);
+        }
+
+        // Walk open lines from their ends before closed lines
+        let ends = segments
+            .iter()
+            .flat_map(|&(a, b)| vec![a, b])
+            .filter(|edge| links[edge].len() == 1);
+        let starts: Vec<_> =
+            ends.chain(segments.iter().map(|&(a, _)| a)).collect();
+
+        let mut visited = HashSet::new();
+        for start in starts {
+            if visited.contains(&start) {
+                continue;
+            }
+
+            visited.insert(start);
+            let mut edges = vec![start];
+            let mut current = start;
+            while let Some(&next) =
+                links[&current].iter().find(|edge| !visited.contains(edge))
+            {
+                visited.insert(next);
+                edges.push(next);
+                current = next;
+            }
+            if edges.len() > 2 && links[&current].contains(&start) {
+                edges.push(start);
+            }
+
+            let points = edges.into_iter().map(&point).collect();
+            output.push(Shape::LineString(LineString::new(points)));
+        }
+    }
+    output
+}
+
+/// Weights of the B3 spline filter applied on each denoising pass
+const DENOISE_KERNEL: [f64; 5] =
+    [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Difference in color at which neighbours stop being averaged, halved on
+/// each pass
+const DENOISE_COLOR: f64 = 0.5;
+
+/// Exponent applied to the cosine between normals of neighbours
+const DENOISE_NORMAL: i32 = 64;
+
+/// Difference in depth, relative to the distance of a pixel, at which
+/// neighbours stop being averaged
+const DENOISE_DEPTH: f64 = 0.05;
+
+/// Smooth sampling noise with an edge avoiding à-trous wavelet filter, where
+/// neighbours are averaged only where their color, normal and depth agree
+pub fn denoise(
+    input: &Texture<Vec3>,
+    normals: &Texture<Vec3>,
+    depths: &Texture<f64>,
+    passes: usize,
+    output: &mut Texture<Vec3>,
+) {
+    assert_eq!((input.width, input.height), (output.width, output.height));
+    assert_eq!((input.width, input.height), (normals.width, normals.height));
+    assert_eq!((input.width, input.height), (depths.width, depths.height));
+
+    let (width, height) = (input.width as isize, input.height as isize);
+    let mut current = input.clone();
+    for pass in 0..passes {
+        // Stop once neighbours step beyond the image on both axes
+        let step: i32 = match 1i32.checked_shl(pass as u32) {
+            Some(step) if (step as isize) < width.max(height) => step,
+            _ => break,
+        };
+        let sigma = DENOISE_COLOR / f64::from(step);
+        let mut next = Texture::blank(input.width, input.height);
+        for y in 0..height {
+            for x in 0..width {
+                let index = (y * width + x) as usize;
+                let color = current.buffer[index];
+                let normal = normals.buffer[index];
+                let depth = depths.buffer[index];
+
+                let mut total = Vec3::zeros();
+                let mut weights = 0.0;
+                for (j, ky) in DENOISE_KERNEL.iter().enumerate() {
+                    let sy = y + (j as isize - 2) * step as isize;
+                    if sy < 0 || sy >= height {
+                        continue;
+                    }
+                    for (i, kx) in DENOISE_KERNEL.iter().enumerate() {
+                        let sx = x + (i as isize - 2) * step as isize;
+                        if sx < 0 || sx >= width {
+                            continue;
+                        }
+                        let other = (sy * width + sx) as usize;
+                        let sample = current.buffer[other];
+                        let (n, d) =
+                            (normals.buffer[other], depths.buffer[other]);
+
+                        let delta = sample - color;
+                        let wc =
+                            (-Vec3::dot(delta, delta) / (sigma * sigma)).exp();
+                        // Background pixels have no normal and an infinite
+                        // depth, and are only averaged with one another
+                        let wn = if normal == n {
+                            1.0
+                        } else {
+                            Vec3::dot(normal, n).max(0.0).powi(DENOISE_NORMAL)
+                        };
+                        let wd = if depth == d {
+                            1.0
+                        } else {
+                            let change = (depth - d).abs();
+                            (-change / (DENOISE_DEPTH * depth.min(d))).exp()
+                        };
+
+                        let weight = kx * ky * wc * wn * wd;
+                        total += sample * weight;
+                        weights += weight;
+                    }
+                }
+
+                next.buffer[index] = if weights > 0.0 {
+                    total / weights
+                } else {
+                    color
+                };
+            }
+        }
+        current = next;
+    }
+
+    output.buffer.copy_from_slice(&current.buffer);
+}
+
+/// Number of bins in the histogram of texture statistics
+const HISTOGRAM_BINS: usize = 256;
+
+/// Summary of the values of a texture, ignoring NaN values
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub stddev: f64,
+    /// Value at each whole percentile, from 0 to 100
+    pub percentiles: Vec<f64>,
+    /// Count of values in equal width bins between the minimum and maximum
+    pub histogram: Vec<usize>,
+}
+
+impl Stats {
+    /// Return the value below which a percentage of values fall
+    pub fn percentile(&self, percent: f64) -> f64 {
+        if self.percentiles.is_empty() {
+            return 0.0;
+        }
+        let position = percent.max(0.0).min(100.0);
+        let i = (position as usize).min(99);
+        let t = position - i as f64;
+        self.percentiles[i] * (1.0 - t) + self.percentiles[i + 1] * t
+    }
+}
+
+/// Return a summary of the values of a texture
+pub fn statistics(input: &Texture<f64>) -> Stats {
+    let mut values: Vec<f64> = input
+        .buffer
+        .iter()
+        .cloned()
+        .filter(|value| !value.is_nan())
+        .collect();
+    if values.is_empty() {
+        return Stats::default();
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let count = values.len() as f64;
+    let (min, max) = (values[0], values[values.len() - 1]);
+    let mean = values.iter().sum::<f64>() / count;
+    let variance = values
+        .iter()
+        .map(|value| (value - mean) * (value - mean))
+        .sum::<f64>()
+        / count;
+
+    let percentiles = (0..101)
+        .map(|percent| {
+            let position = percent as f64 / 100.0 * (count - 1.0);
+            let i = (position as usize).min(values.len() - 1);
+            let j = (i + 1).min(values.len() - 1);
+            let t = position - i as f64;
+            values[i] * (1.0 - t) + values[j] * t
+        })
+        .collect();
+
+    let mut histogram = vec![0; HISTOGRAM_BINS];
+    let width = (max - min) / HISTOGRAM_BINS as f64;
+    for value in &values {
+        let bin = if width > 0.0 {
+            ((value - min) / width) as usize
+        } else {
+            0
+        };
+        histogram[bin.min(HISTOGRAM_BINS - 1)] += 1;
+    }
+
+    Stats {
+        min,
+        max,
+        mean,
+        stddev: variance.sqrt(),
+        percentiles,
+        histogram,
+    }
+}
+
+/// Scale values so those between a low and high percentile range from 0 to
+/// 1, clamping values outside of them
+pub fn stretch(
+    input: &Texture<f64>,
+    output: &mut Texture<f64>,
+    low: f64,
+    high: f64,
+) {
+    let stats = statistics(input);
+    let (low, high) = (stats.percentile(low), stats.percentile(high));
+    let range = high - low;
+    operator1x1(input, output, |value| {
+        if range > 0.0 {
+            ((value - low) / range).max(0.0).min(1.0)
+        } else {
+            0.0
+        }
+    })
+}
+
+/// Smallest illumination of a slope by the sun used when correcting imagery,
+/// so slopes facing away from the sun are not brightened without bound
+const MIN_ILLUMINATION: f64 = 0.05;
+
+/// Method of removing the shading of terrain from imagery
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IlluminationCorrection {
+    /// Minnaert correction, with a constant from zero for no correction to
+    /// one for a Lambertian surface, estimated for each band if not given
+    Minnaert(Option<f64>),
+    /// C-correction, with its constant estimated for each band from how
+    /// brightness varies with illumination
+    C,
+}
+
+/// Return the slope and intercept of a least squares line through points,
+/// ignoring those that are not finite
+fn regression<I>(points: I) -> Option<(f64, f64)>
+where
+    I: Iterator<Item = (f64, f64)>,
+{
+    let (mut n, mut sx, mut sy, mut sxx, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for (x, y) in points.filter(|&(x, y)| x.is_finite() && y.is_finite()) {
+        n += 1.0;
+        sx += x;
+        sy += y;
+        sxx += x * x;
+        sxy += x * y;
+    }
+    let denom = n * sxx - sx * sx;
+    if n < 2.0 || denom.abs() < 1e-12 {
+        return None;
+    }
+    let slope = (n * sxy - sx * sy) / denom;
+    Some((slope, (sy - slope * sx) / n))
+}
+
+/// Return the rise of heights along the columns and rows of their raster for
+/// each pixel, per world unit, by a Sobel operator
+pub fn gradients(
+    heights: &Texture<f64>,
+    transform: &AffineTransform,
+) -> Texture<(f64, f64)> {
+    let [_, _, a, d] = transform.coefficients();
+    let mut gradients = Texture::blank(heights.width, heights.height);
+    operator3x3(heights, &mut gradients, EdgePolicy::Clamp, |p| {
+        let dx = ((p[2] + 2.0 * p[5] + p[8]) - (p[0] + 2.0 * p[3] + p[6]))
+            / (8.0 * a);
+        let dy = ((p[6] + 2.0 * p[7] + p[8]) - (p[0] + 2.0 * p[1] + p[2]))
+            / (8.0 * d);
+        (dx, dy)
+    });
+    gradients
+}
+
+/// Return the cosines of the angle of the sun, at an azimuth and elevation in
+/// degrees, to the normal of each pixel of heights and of the slope of each
+fn illumination(
+    heights: &Texture<f64>,
+    transform: &AffineTransform,
+    azimuth: f64,
+    elevation: f64,
+) -> (Texture<f64>, Texture<f64>) {
+    let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+    // East, north and up, as are the normals
+    let sun = Vec3::new(
+        azimuth.sin() * elevation.cos(),
+        azimuth.cos() * elevation.cos(),
+        elevation.sin(),
+    );
+
+    let mut normals = Texture::blank(heights.width, heights.height);
+    operator1x1(&gradients(heights, transform), &mut normals, |(dx, dy)| {
+        Vec3::normalize(Vec3::new(-dx, -dy, 1.0))
+    });
+
+    let mut incidence = Texture::blank(heights.width, heights.height);
+    let mut slopes = Texture::blank(heights.width, heights.height);
+    operator1x1(&normals, &mut incidence, |n| Vec3::dot(n, sun));
+    operator1x1(&normals, &mut slopes, |n: Vec3| n.z);
+    (incidence, slopes)
+}
+
+/// Remove the shading of terrain from imagery taken with the sun at an
+/// azimuth and elevation in degrees, given heights of the same size and their
+/// transform, so it may be lit again by the renderer without double shadows
+pub fn illumination_correction(
+    imagery: &Texture<Vec3>,
+    heights: &Texture<f64>,
+    output: &mut Texture<Vec3>,
+    transform: &AffineTransform,
+    azimuth: f64,
+    elevation: f64,
+    method: IlluminationCorrection,
+) {
+    assert_eq!(
+        (imagery.width, imagery.height),
+        (heights.width, heights.height)
+    );
+    assert_eq!(
+        (imagery.width, imagery.height),
+        (output.width, output.height)
+    );
+
+    let (incidence, slopes) =
+        illumination(heights, transform, azimuth, elevation);
+    let zenith = elevation.to_radians().sin();
+    let lit = |i: f64| i.max(MIN_ILLUMINATION);
+
+    let mut factors = [vec![], vec![], vec![]];
+    for (band, factors) in factors.iter_mut().enumerate() {
+        let values: Vec<f64> = imagery
+            .buffer
+            .iter()
+            .map(|v| [v.x, v.y, v.z][band])
+            .collect();
+        let points = values.iter().zip(&incidence.buffer).zip(&slopes.buffer);
+
+        *factors = match method {
+            IlluminationCorrection::C => {
+                let fit = regression(points.map(|((&l, &i), _)| (i, l)));
+                match fit {
+                    Some((m, b)) if m.abs() > 1e-12 => {
+                        let c = b / m;
+                        incidence
+                            .buffer
+                            .iter()
+                            .map(|&i| (zenith + c) / (lit(i) + c))
+                            .collect()
+                    }
+                    _ => vec![1.0; values.len()],
+                }
+            }
+            IlluminationCorrection::Minnaert(k) => {
+                let k = k.unwrap_or_else(|| {
+                    let points = points
+                        .filter(|&((&l, &i), _)| {
+                            l > 0.0 && i > MIN_ILLUMINATION
+                        })
+                        .map(|((&l, &i), &e)| ((i * e).ln(), (l * e).ln()));
+                    regression(points).map_or(1.0, |(k, _)| k)
+                });
+                incidence
+                    .buffer
+                    .iter()
+                    .zip(&slopes.buffer)
+                    .map(|(&i, &e)| e * (zenith / (lit(i) * e)).powf(k))
+                    .collect()
+            }
+        };
+    }
+
+    for (i, value) in output.buffer.iter_mut().enumerate() {
+        let v = imagery.buffer[i];
+        let scale = |value: f64, factor: f64| {
+            if factor.is_finite() {
+                value * factor
+            } else {
+                value
+            }
+        };
+        *value = Vec3::new(
+            scale(v.x, factors[0][i]),
+            scale(v.y, factors[1][i]),
+            scale(v.z, factors[2][i]),
+        );
+    }
+}
+
+/// Shade heights lit by the sun at an azimuth and elevation in degrees, from
+/// 0 facing away from the sun to 1 facing it
+pub fn hillshade(
+    heights: &Texture<f64>,
+    output: &mut Texture<f64>,
+    transform: &AffineTransform,
+    azimuth: f64,
+    elevation: f64,
+) {
+    assert_eq!(
+        (heights.width, heights.height),
+        (output.width, output.height)
+    );
+    let (incidence, _) = illumination(heights, transform, azimuth, elevation);
+    operator1x1(&incidence, output, |i| i.max(0.0));
+}
+
+/// Average the heights within a radius of pixels of each, ignoring those that
+/// are not finite, or leave a height with none
+pub fn smooth(input: &Texture<f64>, output: &mut Texture<f64>, radius: usize) {
+    operator_nxn(input, output, radius, EdgePolicy::Clamp, |values| {
+        if values.iter().any(|v| v.is_finite()) {
+            finite_mean(values.iter().cloned())
+        } else {
+            values[values.len() / 2]
+        }
+    });
+}
+
+/// Simplify heights to the median of those within a radius of pixels of
+/// each, removing small features while keeping the edges of larger ones.
+/// Heights that are not finite are ignored, or left with none
+pub fn generalise(
+    input: &Texture<f64>,
+    output: &mut Texture<f64>,
+    radius: usize,
+) {
+    let mut sorted = Vec::with_capacity((2 * radius + 1).pow(2));
+    operator_nxn(input, output, radius, EdgePolicy::Clamp, |values| {
+        sorted.clear();
+        sorted.extend(values.iter().cloned().filter(|v| v.is_finite()));
+        if sorted.is_empty() {
+            return values[values.len() / 2];
+        }
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        sorted[sorted.len() / 2]
+    });
+}
+
+/// Multiply heights by a factor, about a base height left unchanged
+pub fn exaggerate(
+    input: &Texture<f64>,
+    output: &mut Texture<f64>,
+    factor: f64,
+    base: f64,
+) {
+    operator1x1(input, output, |h| base + (h - base) * factor);
+}
+
+/// Resample a texture to a size, interpolating bilinearly between the
+/// centers of its pixels and clamping at its edges
+fn resample<T>(input: &Texture<T>, width: usize, height: usize) -> Texture<T>
+where
+    T: Mul<f64, Output = T> + Add<Output = T> + Copy + Default,
+{
+    let mut output = Texture::blank(width, height);
+    if input.width == 0 || input.height == 0 {
+        return output;
+    }
+    let (sx, sy) = (
+        input.width as f64 / width as f64,
+        input.height as f64 / height as f64,
+    );
+    let (max_x, max_y) = ((input.width - 1) as f64, (input.height - 1) as f64);
+    for y in 0..height {
+        let v = ((y as f64 + 0.5) * sy - 0.5).max(0.0).min(max_y);
+        for x in 0..width {
+            let u = ((x as f64 + 0.5) * sx - 0.5).max(0.0).min(max_x);
+            output.write1x1(x, y, input.bilinear_clamped(u, v));
+        }
+    }
+    output
+}
+
+/// Average the values of a texture within each pixel of a smaller size
+fn box_downsample(
+    input: &Texture<f64>,
+    width: usize,
+    height: usize,
+) -> Texture<f64> {
+    let mut sums: Texture<f64> = Texture::blank(width, height);
+    let mut counts: Texture<f64> = Texture::blank(width, height);
+    for y in 0..input.height {
+        let cy = y * height / input.height;
+        for x in 0..input.width {
+            let cx = x * width / input.width;
+            let value = input.lookup1x1(x, y);
+            sums.write1x1(cx, cy, sums.lookup1x1(cx, cy) + value);
+            counts.write1x1(cx, cy, counts.lookup1x1(cx, cy) + 1.0);
+        }
+    }
+    let mut output = Texture::blank(width, height);
+    map2(&sums, &counts, &mut output, |sum, count| sum / count);
+    output
+}
+
+/// Return the mean of values, ignoring those that are not finite
+fn finite_mean<I>(values: I) -> f64
+where
+    I: Iterator<Item = f64>,
+{
+    let (sum, count) = values
+        .filter(|v| v.is_finite())
+        .fold((0.0, 0.0), |(sum, count), v| (sum + v, count + 1.0));
+    if count > 0.0 {
+        sum / count
+    } else {
+        0.0
+    }
+}
+
+/// Method of injecting the detail of a panchromatic channel into color
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Pansharpen {
+    /// Scale each band by the ratio of the channel to the channel smoothed
+    /// to the resolution of the color
+    Brovey,
+    /// Add the detail of the channel to the value of the color, scaled to
+    /// the range of values, keeping its hue and saturation
+    Hsv,
+}
+
+/// Sharpen color imagery with the detail of a panchromatic channel, such as
+/// a hillshade, of a higher resolution and covering the same extent, writing
+/// an image the size of the channel
+pub fn pansharpen(
+    color: &Texture<Vec3>,
+    pan: &Texture<f64>,
+    output: &mut Texture<Vec3>,
+    method: Pansharpen,
+) {
+    assert_eq!((pan.width, pan.height), (output.width, output.height));
+
+    let (width, height) = (pan.width, pan.height);
+    let low = (color.width.min(width), color.height.min(height));
+    let smooth = resample(&box_downsample(pan, low.0, low.1), width, height);
+    let color = resample(color, width, height);
+
+    let value = |c: Vec3| c.x.max(c.y).max(c.z);
+    let scale = match method {
+        Pansharpen::Brovey => 1.0,
+        Pansharpen::Hsv => {
+            let pan_mean = finite_mean(pan.buffer.iter().cloned());
+            let value_mean =
+                finite_mean(color.buffer.iter().map(|&c| value(c)));
+            if pan_mean != 0.0 {
+                value_mean / pan_mean
+            } else {
+                0.0
+            }
+        }
+    };
+
+    map3(&color, pan, &smooth, output, |c, p, s| {
+        let ratio = match method {
+            Pansharpen::Brovey => p / s,
+            Pansharpen::Hsv => {
+                let v = value(c);
+                (v + (p - s) * scale).max(0.0) / v
+            }
+        };
+        if ratio.is_finite() {
+            c * ratio
+        } else {
+            c
+        }
+    });
+}
+
+/// A summary of the differences between the pixels of two images
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Metric {
+    /// Root mean square difference of each channel
+    Rmse,
+    /// Mean absolute difference of each channel
+    Mae,
+    /// Largest absolute difference of any channel
+    Max,
+    /// Peak signal to noise ratio in decibels, of values from 0 to 1,
+    /// infinite for identical images
+    Psnr,
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Metric, String> {
+        match name {
+            "rmse" => Ok(Metric::Rmse),
+            "mae" => Ok(Metric::Mae),
+            "max" => Ok(Metric::Max),
+            "psnr" => Ok(Metric::Psnr),
+            _ => Err(format!("Unknown difference metric '{}'", name)),
+        }
+    }
+}
+
+/// Write the root mean square difference of the channels of each pixel of
+/// two images of the same size
+pub fn difference(
+    a: &Texture<Vec3>,
+    b: &Texture<Vec3>,
+    output: &mut Texture<f64>,
+) {
+    map2(a, b, output, |a, b| {
+        let d = a - b;
+        (Vec3::dot(d, d) / 3.0).sqrt()
+    });
+}
+
+/// Return a summary of the differences between two images of the same size
+pub fn compare(
+    a: &Texture<Vec3>,
+    b: &Texture<Vec3>,
+    metric: Metric,
+) -> io::Result<f64> {
+    if (a.width, a.height) != (b.width, b.height) {
+        let message = format!(
+            "Images differ in size, {}x{} and {}x{}",
+            a.width, a.height, b.width, b.height
+        );
+        return Err(Error::new(ErrorKind::InvalidInput, message));
+    }
+
+    let count = (a.buffer.len() * 3).max(1) as f64;
+    let channels = a.buffer.iter().zip(&b.buffer).flat_map(|(a, b)| {
+        let d = *a - *b;
+        IntoIterator::into_iter([d.x.abs(), d.y.abs(), d.z.abs()])
+    });
+    let mse = || channels.clone().map(|d| d * d).sum::<f64>() / count;
+    Ok(match metric {
+        Metric::Rmse => mse().sqrt(),
+        Metric::Mae => channels.clone().sum::<f64>() / count,
+        Metric::Max => channels.clone().fold(0.0, f64::max),
+        Metric::Psnr => -10.0 * mse().log10(),
+    })
+}
+
+/// Return a summary of the differences between two 8 bit images of the same
+/// size, from their encoded values scaled from 0 to 1
+pub fn compare_colors(
+    a: &Texture<Color>,
+    b: &Texture<Color>,
+    metric: Metric,
+) -> io::Result<f64> {
+    compare(&unit_colors(a), &unit_colors(b), metric)
+}
+
+/// Scale the encoded values of 8 bit colors from 0 to 1, without decoding them
+pub fn unit_colors(input: &Texture<Color>) -> Texture<Vec3> {
+    let mut output = Texture::blank(input.width, input.height);
+    operator1x1(input, &mut output, |val| {
+        Vec3::new(
+            f64::from(val.r) / 255.0,
+            f64::from(val.g) / 255.0,
+            f64::from(val.b) / 255.0,
+        )
+    });
+    output
+}
+
+/// Convert a linear color to sRGB
+pub fn encode_srgb(val: Vec3) -> Color {
     let encode = |component: f64| {
         if component <= 0.003_130_8 {
             component * 12.92
@@ -123,17 +1176,47 @@ pub fn linear_to_srgb(input: &Texture<Vec3>, output: &mut Texture<Color>) {
         }
     };
 
-    operator1x1(input, output, |val| {
-        Color::new(
-            (encode(val.x) * 255.0).round().min(255.0).max(0.0) as u8,
-            (encode(val.y) * 255.0).round().min(255.0).max(0.0) as u8,
-            (encode(val.z) * 255.0).round().min(255.0).max(0.0) as u8,
-        )
-    })
+    Color::new(
+        (encode(val.x) * 255.0).round().min(255.0).max(0.0) as u8,
+        (encode(val.y) * 255.0).round().min(255.0).max(0.0) as u8,
+        (encode(val.z) * 255.0).round().min(255.0).max(0.0) as u8,
+    )
 }
 
-/// Convert sRGB colors to linear
-pub fn srgb_to_linear(input: &Texture<Color>, output: &mut Texture<Vec3>) {
+/// Convert linear colors to sRGB
+pub fn linear_to_srgb(input: &Texture<Vec3>, output: &mut Texture<Color>) {
+    operator1x1(input, output, encode_srgb)
+}
+
+/// Convert a linear color with a power law for a display gamma
+pub fn encode_gamma(val: Vec3, gamma: f64) -> Color {
+    let encode = |component: f64| {
+        let value = component.max(0.0).powf(1.0 / gamma) * 255.0;
+        value.round().min(255.0) as u8
+    };
+    Color::new(encode(val.x), encode(val.y), encode(val.z))
+}
+
+/// Convert linear colors for a display gamma
+pub fn linear_to_gamma(
+    input: &Texture<Vec3>,
+    output: &mut Texture<Color>,
+    gamma: f64,
+) {
+    operator1x1(input, output, |val| encode_gamma(val, gamma))
+}
+
+/// Convert linear colors to the color space of a profile
+pub fn linear_to_profile(
+    input: &Texture<Vec3>,
+    output: &mut Texture<Color>,
+    profile: &IccProfile,
+) {
+    operator1x1(input, output, |val| profile.encode(val))
+}
+
+/// Convert an sRGB color, with components from 0 to 1, to linear
+pub fn decode_srgb(val: Vec3) -> Vec3 {
     let decode = |component: f64| {
         if component <= 0.04045 {
             component / 12.92
@@ -141,19 +1224,132 @@ pub fn srgb_to_linear(input: &Texture<Color>, output: &mut Texture<Vec3>) {
             ((component + 0.055) / 1.055).powf(2.4)
         }
     };
+    Vec3::new(decode(val.x), decode(val.y), decode(val.z))
+}
 
+/// Convert sRGB colors to linear
+pub fn srgb_to_linear(input: &Texture<Color>, output: &mut Texture<Vec3>) {
     operator1x1(input, output, |val| {
-        Vec3::new(
-            decode(f64::from(val.r) / 255.0),
-            decode(f64::from(val.g) / 255.0),
-            decode(f64::from(val.b) / 255.0),
-        )
+        decode_srgb(Vec3::new(
+            f64::from(val.r) / 255.0,
+            f64::from(val.g) / 255.0,
+            f64::from(val.b) / 255.0,
+        ))
+    })
+}
+
+/// Color values with a ramp, where values from a minimum to a maximum span
+/// the whole ramp
+pub fn apply_ramp(
+    input: &Texture<f64>,
+    output: &mut Texture<Vec3>,
+    ramp: Ramp,
+    min: f64,
+    max: f64,
+) {
+    let range = max - min;
+    operator1x1(input, output, |value| {
+        let t = if range != 0.0 {
+            (value - min) / range
+        } else {
+            0.0
+        };
+        ramp.color(t)
     })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::f64::NAN;
+
+    #[test]
+    fn comparing_images() {
+        let a =
+            Texture::new(2, 1, vec![Vec3::zeros(), Vec3::new(1.0, 1.0, 1.0)]);
+        let b =
+            Texture::new(2, 1, vec![Vec3::zeros(), Vec3::new(1.0, 0.0, 0.5)]);
+        assert_eq!(compare(&a, &a, Metric::Rmse).unwrap(), 0.0);
+        assert_eq!(compare(&a, &a, Metric::Psnr).unwrap(), INFINITY);
+        assert_eq!(compare(&a, &b, Metric::Max).unwrap(), 1.0);
+        assert_eq!(compare(&a, &b, Metric::Mae).unwrap(), 0.25);
+        assert_eq!(
+            compare(&a, &b, Metric::Rmse).unwrap(),
+            (1.25f64 / 6.0).sqrt()
+        );
+
+        let c = Texture::new(1, 1, vec![Vec3::zeros()]);
+        assert!(compare(&a, &c, Metric::Max).is_err());
+
+        let mut output = Texture::blank(2, 1);
+        difference(&a, &b, &mut output);
+        assert_eq!(output.buffer, vec![0.0, (1.25f64 / 3.0).sqrt()]);
+
+        let colors = Texture::new(1, 1, vec![Color::new(255, 0, 0)]);
+        let black = Texture::new(1, 1, vec![Color::new(0, 0, 0)]);
+        assert_eq!(compare_colors(&colors, &black, Metric::Max).unwrap(), 1.0);
+        assert!("ssim".parse::<Metric>().is_err());
+    }
+
+    #[test]
+    fn applying_geoid() {
+        let heights = Texture::new(2, 1, vec![100.0, 250.0]);
+        let geoid = Texture::new(2, 1, vec![45.0, -30.0]);
+        let mut output = Texture::blank(2, 1);
+        apply_geoid(&heights, &geoid, &mut output);
+        assert_eq!(output.buffer, vec![55.0, 280.0]);
+    }
+
+    #[test]
+    fn tracing_contours() {
+        let transform = AffineTransform::new(100.0, 200.0, 10.0, 10.0);
+
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        let peak = Texture::new(3, 3, vec![
+            0.0, 0.0, 0.0,
+            0.0, 9.0, 0.0,
+            0.0, 0.0, 0.0,
+        ]);
+        let lines = contours(&peak, &transform, 5.0, 0.0);
+        assert_eq!(lines.len(), 1);
+        let points = lines[0].lines()[0];
+        assert_eq!(points.len(), 5);
+        assert_eq!(points.first(), points.last());
+        for point in points {
+            let (x, z) = (point.x - 110.0, point.z - 210.0);
+            assert!((x.abs() + z.abs() - 40.0 / 9.0).abs() < 1e-9);
+            assert_eq!(point.y, 5.0);
+        }
+
+        let ramp = Texture::new(3, 2, vec![0.0, 1.0, 2.0, 0.0, 1.0, 2.0]);
+        let lines = contours(&ramp, &transform, 0.5, 0.25);
+        assert_eq!(lines.len(), 4);
+        for (line, x) in lines.iter().zip(&[102.5, 107.5, 112.5, 117.5]) {
+            let points = line.lines()[0];
+            assert_eq!(points.len(), 2);
+            assert!(points.iter().all(|point| point.x == *x));
+        }
+    }
+
+    #[test]
+    fn curvature_correction() {
+        let drop = curvature_drop(100_000.0, 0.13, 6_371_008.8);
+        assert!((drop - 682.79).abs() < 0.01);
+
+        let input = Texture::new(2, 1, vec![1000.0, 1000.0]);
+        let mut output = Texture::blank(2, 1);
+        let transform = AffineTransform::new(0.0, 0.0, 100_000.0, 1.0);
+        apply_curvature(
+            &input,
+            &mut output,
+            &transform,
+            (0.0, 0.0),
+            0.13,
+            6_371_008.8,
+        );
+        assert_eq!(output.lookup1x1(0, 0), 1000.0);
+        assert!((output.lookup1x1(1, 0) - (1000.0 - drop)).abs() < 1e-9);
+    }
 
     #[test]
     fn blitting_textures() {
@@ -235,6 +1431,134 @@ mod tests {
         assert_eq!(bilinear_patches_mipmap2.buffer, [13.0]);
     }
 
+    #[test]
+    fn mapping_textures() {
+        let weights = Texture::new(2, 1, vec![0.5, 1.0]);
+        let heights = Texture::new(2, 1, vec![10.0, 20.0]);
+        let lake = Texture::new(2, 1, vec![true, false]);
+        let mut output = Texture::blank(2, 1);
+        map2(&weights, &heights, &mut output, |w, h| w * h);
+        assert_eq!(output.buffer, [5.0, 20.0]);
+
+        let mut masked = Texture::blank(2, 1);
+        map3(&weights, &heights, &lake, &mut masked, |w, h, lake| {
+            if lake {
+                0.0
+            } else {
+                w * h
+            }
+        });
+        assert_eq!(masked.buffer, [0.0, 20.0]);
+    }
+
+    #[test]
+    fn correcting_illumination() {
+        // A ridge running north to south, with slopes facing east and west
+        let heights = Texture::new(
+            6,
+            3,
+            (0..18)
+                .map(|i| (2.5 - (2.5 - (i % 6) as f64).abs()) * 10.0)
+                .collect(),
+        );
+        let transform = AffineTransform::new(0.0, 0.0, 10.0, -10.0);
+        let (incidence, slopes) =
+            illumination(&heights, &transform, 90.0, 60.0);
+        assert!(incidence.lookup1x1(4, 1) > 0.9);
+        assert!(incidence.lookup1x1(1, 1) < 0.3);
+        assert!((slopes.lookup1x1(4, 1) - 0.5_f64.sqrt()).abs() < 1e-9);
+
+        // Imagery of a Lambertian surface has the same color once corrected
+        let albedo = Vec3::new(0.5, 0.25, 0.125);
+        let mut imagery = Texture::blank(6, 3);
+        operator1x1(&incidence, &mut imagery, |i: f64| albedo * i);
+        let flat = albedo * 60_f64.to_radians().sin();
+        for &method in &[
+            IlluminationCorrection::C,
+            IlluminationCorrection::Minnaert(None),
+        ] {
+            let mut output = Texture::blank(6, 3);
+            illumination_correction(
+                &imagery,
+                &heights,
+                &mut output,
+                &transform,
+                90.0,
+                60.0,
+                method,
+            );
+            for value in &output.buffer {
+                assert!(Vec3::length(*value - flat) < 1e-9, "{:?}", method);
+            }
+        }
+    }
+
+    #[test]
+    fn resampling_empty_textures() {
+        let input: Texture<f64> = Texture::blank(0, 0);
+        assert_eq!(resample(&input, 2, 1), Texture::blank(2, 1));
+    }
+
+    #[test]
+    fn pansharpening() {
+        let red = Vec3::new(0.4, 0.2, 0.2);
+        let color = Texture::new(2, 2, vec![red; 4]);
+        let pan = Texture::new(
+            4,
+            4,
+            (0..16)
+                .map(|i| if (i % 4 + i / 4) % 2 == 0 { 0.6 } else { 0.4 })
+                .collect(),
+        );
+        for &method in &[Pansharpen::Brovey, Pansharpen::Hsv] {
+            let mut output = Texture::blank(4, 4);
+            pansharpen(&color, &pan, &mut output, method);
+            let bright = Vec3::new(0.48, 0.24, 0.24);
+            let dark = Vec3::new(0.32, 0.16, 0.16);
+            assert!(Vec3::length(output.lookup1x1(0, 0) - bright) < 1e-9);
+            assert!(Vec3::length(output.lookup1x1(1, 0) - dark) < 1e-9);
+
+            let flat = Texture::new(4, 4, vec![0.5; 16]);
+            pansharpen(&color, &flat, &mut output, method);
+            assert!(output
+                .buffer
+                .iter()
+                .all(|&c| Vec3::length(c - red) < 1e-9));
+        }
+    }
+
+    #[test]
+    fn downsampling_textures() {
+        let input =
+            Texture::new(4, 2, vec![1.0, 3.0, 0.0, 0.0, 1.0, 3.0, 4.0, 8.0]);
+        let mut output = Texture::blank(2, 1);
+        downsample(&input, &mut output);
+        assert_eq!(output.buffer, [2.0, 3.0]);
+
+        // Uniform colors keep their value, rather than each quarter rounding
+        for &value in &[1, 2, 3, 127, 254, 255] {
+            let color = Color::new(value, value / 2, 255 - value);
+            let input = Texture::new(2, 2, vec![color; 4]);
+            let mut output = Texture::blank(1, 1);
+            downsample(&input, &mut output);
+            assert_eq!(output.buffer, [color]);
+        }
+
+        let input = Texture::new(
+            2,
+            2,
+            vec![
+                Color::new(1, 0, 0),
+                Color::new(1, 0, 0),
+                Color::new(2, 0, 0),
+                Color::new(2, 0, 0),
+            ],
+        );
+        let mut output = Texture::blank(1, 1);
+        downsample(&input, &mut output);
+        assert_eq!(output.buffer, [Color::new(2, 0, 0)]);
+    }
+
     #[test]
     fn test_srgb_transforms() {
         let input = Texture::new(1, 1, vec![Vec3::new(0.5, 0.5, 0.5)]);
@@ -247,4 +1571,146 @@ mod tests {
             (input.lookup1x1(0, 0) * 100.0).round(),
         );
     }
+
+    #[test]
+    fn arranging_contact_sheets() {
+        let views: Vec<Texture<u8>> =
+            (1..4).map(|i| Texture::new(2, 1, vec![i, i])).collect();
+        let sheet = contact_sheet(&views, 2);
+        assert_eq!((sheet.width, sheet.height), (4, 2));
+        assert_eq!(sheet.buffer, vec![1, 1, 2, 2, 3, 3, 0, 0]);
+    }
+
+    #[test]
+    fn combining_anaglyphs() {
+        let left = Texture::new(1, 1, vec![Vec3::new(0.1, 0.2, 0.3)]);
+        let right = Texture::new(1, 1, vec![Vec3::new(0.4, 0.5, 0.6)]);
+        let mut output = Texture::blank(1, 1);
+        anaglyph(&left, &right, &mut output);
+        assert_eq!(output.buffer, vec![Vec3::new(0.1, 0.5, 0.6)]);
+    }
+
+    #[test]
+    fn denoising_within_edges() {
+        // Noise on an upward facing surface, beside a brighter wall
+        let (up, side) = (Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let mut input = Texture::blank(8, 4);
+        let mut normals = Texture::blank(8, 4);
+        for y in 0..4 {
+            for x in 0..8 {
+                let (color, normal) = if x < 4 {
+                    (0.4 + 0.2 * ((x + y) % 2) as f64, up)
+                } else {
+                    (1.0, side)
+                };
+                input.write1x1(x, y, Vec3::new(color, color, color));
+                normals.write1x1(x, y, normal);
+            }
+        }
+        let depths = Texture::new(8, 4, vec![10.0; 32]);
+
+        let mut output = Texture::blank(8, 4);
+        denoise(&input, &normals, &depths, 3, &mut output);
+        for y in 0..4 {
+            for x in 0..8 {
+                let value = output.lookup1x1(x, y).x;
+                if x < 4 {
+                    assert!((value - 0.5).abs() < 0.05);
+                } else {
+                    assert_eq!(value, 1.0);
+                }
+            }
+        }
+
+        // Passes stepping beyond the image leave it unchanged
+        let mut more = Texture::blank(8, 4);
+        denoise(&input, &normals, &depths, 40, &mut more);
+        assert_eq!(more, output);
+    }
+
+    #[test]
+    fn summarising_values() {
+        let values = (0..=100).map(f64::from).collect();
+        let mut input = Texture::new(101, 1, values);
+        input.buffer[50] = NAN;
+        let stats = statistics(&input);
+        assert_eq!((stats.min, stats.max, stats.mean), (0.0, 100.0, 50.0));
+        assert_eq!(stats.histogram.iter().sum::<usize>(), 100);
+        assert_eq!((stats.histogram[0], stats.histogram[255]), (1, 1));
+        assert_eq!(stats.percentile(0.0), 0.0);
+        assert_eq!(stats.percentile(100.0), 100.0);
+        assert!((stats.percentile(50.0) - 50.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn processing_heights() {
+        let heights = vec![0.0, 0.0, 9.0, 0.0, 3.0, 0.0, NAN, 0.0, 0.0];
+        let input = Texture::new(3, 3, heights);
+        let mut output = Texture::blank(3, 3);
+
+        smooth(&input, &mut output, 1);
+        assert_eq!(output.lookup1x1(1, 1), 12.0 / 8.0);
+        generalise(&input, &mut output, 1);
+        assert_eq!(output.lookup1x1(1, 1), 0.0);
+        exaggerate(&input, &mut output, 2.0, 1.0);
+        assert_eq!(output.lookup1x1(2, 0), 17.0);
+        assert!(output.lookup1x1(0, 2).is_nan());
+
+        let nodata = Texture::new(1, 1, vec![NAN]);
+        let mut output = Texture::blank(1, 1);
+        smooth(&nodata, &mut output, 1);
+        assert!(output.lookup1x1(0, 0).is_nan());
+        generalise(&nodata, &mut output, 1);
+        assert!(output.lookup1x1(0, 0).is_nan());
+    }
+
+    #[test]
+    fn shading_hills() {
+        // A slope rising to the east, lit from the east and the west
+        let heights = (0..9).map(|i| f64::from(i % 3)).collect();
+        let heights = Texture::new(3, 3, heights);
+        let transform = AffineTransform::new(0.0, 0.0, 1.0, 1.0);
+        let mut east = Texture::blank(3, 3);
+        let mut west = Texture::blank(3, 3);
+        hillshade(&heights, &mut east, &transform, 90.0, 45.0);
+        hillshade(&heights, &mut west, &transform, 270.0, 45.0);
+        assert!((west.lookup1x1(1, 1) - 1.0).abs() < 1e-9);
+        assert!(east.lookup1x1(1, 1) < 0.1);
+    }
+
+    #[test]
+    fn stretching_percentiles() {
+        let values = (0..=100).map(f64::from).collect();
+        let input = Texture::new(101, 1, values);
+        let mut output = Texture::blank(101, 1);
+        stretch(&input, &mut output, 10.0, 90.0);
+        assert_eq!(output.buffer[0], 0.0);
+        assert_eq!(output.buffer[50], 0.5);
+        assert_eq!(output.buffer[100], 1.0);
+    }
+
+    #[test]
+    fn indexing_beyond_edges() {
+        let indices = |edges: EdgePolicy| -> Vec<usize> {
+            (-3..7).map(|i| edges.index(i, 4)).collect()
+        };
+        assert_eq!(indices(EdgePolicy::Clamp), [0, 0, 0, 0, 1, 2, 3, 3, 3, 3]);
+        assert_eq!(indices(EdgePolicy::Mirror), [3, 2, 1, 0, 1, 2, 3, 2, 1, 0]);
+        assert_eq!(indices(EdgePolicy::Wrap), [1, 2, 3, 0, 1, 2, 3, 0, 1, 2]);
+    }
+
+    #[test]
+    fn summing_neighbourhoods() {
+        let input = Texture::new(3, 1, vec![1.0, 2.0, 4.0]);
+        let mut output = Texture::blank(3, 1);
+        operator3x3(&input, &mut output, EdgePolicy::Wrap, |values| {
+            values[3] + values[4] + values[5]
+        });
+        assert_eq!(output.buffer, vec![7.0, 7.0, 7.0]);
+
+        operator_nxn(&input, &mut output, 1, EdgePolicy::Clamp, |values| {
+            values[3] - values[5]
+        });
+        assert_eq!(output.buffer, vec![-1.0, -3.0, -2.0]);
+    }
 }
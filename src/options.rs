@@ -13,6 +13,13 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
+use math::EARTH_RADIUS;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use serde_json::{self, Map, Value};
+
+use std::collections::{BTreeMap, HashMap};
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct PerspectiveCameraOpts {
     pub width: usize,
@@ -22,6 +29,12 @@ pub struct PerspectiveCameraOpts {
     pub fov: f64,
     pub view_distance: f64,
     pub up: [f64; 3],
+    /// Depth along the view direction before which surfaces are hidden
+    #[serde(default)]
+    pub near: f64,
+    /// Depth along the view direction beyond which surfaces are hidden
+    #[serde(default)]
+    pub far: Option<f64>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -33,6 +46,22 @@ pub struct OrthographicCameraOpts {
     pub view_plane_size: f64,
     pub view_distance: f64,
     pub up: [f64; 3],
+    /// Depth along the view direction before which surfaces are hidden
+    #[serde(default)]
+    pub near: f64,
+    /// Depth along the view direction beyond which surfaces are hidden
+    #[serde(default)]
+    pub far: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EquirectangularCameraOpts {
+    pub width: usize,
+    pub height: usize,
+    pub position: [f64; 3],
+    /// Point placed in the center of the panorama
+    pub look_at: [f64; 3],
+    pub up: [f64; 3],
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -40,42 +69,433 @@ pub struct OrthographicCameraOpts {
 pub enum CameraOpts {
     Perspective(PerspectiveCameraOpts),
     Orthographic(OrthographicCameraOpts),
+    Equirectangular(EquirectangularCameraOpts),
+}
+
+impl CameraOpts {
+    pub fn position(&self) -> [f64; 3] {
+        match self {
+            CameraOpts::Perspective(opts) => opts.position,
+            CameraOpts::Orthographic(opts) => opts.position,
+            CameraOpts::Equirectangular(opts) => opts.position,
+        }
+    }
+
+    pub fn up(&self) -> [f64; 3] {
+        match self {
+            CameraOpts::Perspective(opts) => opts.up,
+            CameraOpts::Orthographic(opts) => opts.up,
+            CameraOpts::Equirectangular(opts) => opts.up,
+        }
+    }
+
+    /// Return mutable references to the width and height of the image
+    pub fn size_mut(&mut self) -> (&mut usize, &mut usize) {
+        match self {
+            CameraOpts::Perspective(opts) => {
+                (&mut opts.width, &mut opts.height)
+            }
+            CameraOpts::Orthographic(opts) => {
+                (&mut opts.width, &mut opts.height)
+            }
+            CameraOpts::Equirectangular(opts) => {
+                (&mut opts.width, &mut opts.height)
+            }
+        }
+    }
+
+    /// Return mutable references to the position and look at points
+    pub fn placement_mut(&mut self) -> (&mut [f64; 3], &mut [f64; 3]) {
+        match self {
+            CameraOpts::Perspective(opts) => {
+                (&mut opts.position, &mut opts.look_at)
+            }
+            CameraOpts::Orthographic(opts) => {
+                (&mut opts.position, &mut opts.look_at)
+            }
+            CameraOpts::Equirectangular(opts) => {
+                (&mut opts.position, &mut opts.look_at)
+            }
+        }
+    }
+
+    /// Return the position and look at point
+    pub fn placement(&self) -> ([f64; 3], [f64; 3]) {
+        match self {
+            CameraOpts::Perspective(opts) => (opts.position, opts.look_at),
+            CameraOpts::Orthographic(opts) => (opts.position, opts.look_at),
+            CameraOpts::Equirectangular(opts) => (opts.position, opts.look_at),
+        }
+    }
+
+    /// Return an error if the camera has no direction to look in
+    pub fn validate(&self) -> Result<(), String> {
+        let (position, look_at) = self.placement();
+        if position == look_at {
+            return Err(format!(
+                "Camera position and look at point are both {:?}",
+                position
+            ));
+        }
+        Ok(())
+    }
+
+    /// Return the compass bearing and elevation in degrees of the camera
+    /// from its look at point, where north is +Z and east is +X
+    pub fn angles(&self) -> (f64, f64) {
+        let (position, look_at) = self.placement();
+        let [x, y, z] = [
+            position[0] - look_at[0],
+            position[1] - look_at[1],
+            position[2] - look_at[2],
+        ];
+        let horizontal = (x * x + z * z).sqrt();
+        (x.atan2(z).to_degrees(), y.atan2(horizontal).to_degrees())
+    }
+
+    /// Move the camera around its look at point to a compass bearing and
+    /// elevation in degrees, keeping its distance
+    pub fn orbit(&mut self, azimuth: f64, elevation: f64) {
+        let (position, look_at) = self.placement_mut();
+        let offset = [
+            position[0] - look_at[0],
+            position[1] - look_at[1],
+            position[2] - look_at[2],
+        ];
+        let distance = offset.iter().map(|v| v * v).sum::<f64>().sqrt();
+        let (azimuth, elevation) =
+            (azimuth.to_radians(), elevation.to_radians());
+        *position = [
+            look_at[0] + azimuth.sin() * elevation.cos() * distance,
+            look_at[1] + elevation.sin() * distance,
+            look_at[2] + azimuth.cos() * elevation.cos() * distance,
+        ];
+    }
+
+    /// Scale the distance of the camera from its look at point, and the view
+    /// plane of orthographic cameras, so that values below one zoom in
+    pub fn zoom(&mut self, factor: f64) {
+        if let CameraOpts::Orthographic(opts) = self {
+            opts.view_plane_size *= factor;
+        }
+        let (position, look_at) = self.placement_mut();
+        for i in 0..3 {
+            position[i] = look_at[i] + (position[i] - look_at[i]) * factor;
+        }
+    }
+}
+
+/// Place the camera to fit the primitives visible to it in view
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FrameOpts {
+    /// Space around the primitives, as a fraction of the view plane
+    #[serde(default)]
+    pub margin: f64,
+    /// Compass bearing of the camera from the primitives in degrees, where
+    /// north is +Z and east is +X, otherwise the camera direction is kept
+    #[serde(default)]
+    pub azimuth: Option<f64>,
+    /// Angle of the camera above the horizon in degrees
+    #[serde(default)]
+    pub elevation: Option<f64>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct GdalLoader {
     pub filepath: String,
     pub band: usize,
+    /// Size of a pixel to resample to, in dataset units, read from the
+    /// overviews of the raster where it has them
+    #[serde(default)]
+    pub resolution: Option<f64>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct OgrLoader {
     pub filepath: String,
     pub layer: String,
+    /// Only load features within an area of interest
+    #[serde(default)]
+    pub bounds: Option<BoundsOpts>,
+    /// Remove vertices closer than this distance to the line through their
+    /// neighbours
+    #[serde(default)]
+    pub simplify: Option<f64>,
+    /// Insert vertices so that no segment is longer than this distance, to
+    /// let draped shapes follow the terrain
+    #[serde(default)]
+    pub densify: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RemoteLoader {
+    /// URL of a dataset, such as a Cloud-Optimized GeoTIFF, or a GDAL
+    /// connection string, such as `WMS:<url>`
+    pub url: String,
+    pub band: usize,
+    /// West, south, east and north bounds to fetch, in dataset coordinates
+    #[serde(default)]
+    pub extent: Option<[f64; 4]>,
+    /// Size of a pixel to resample to, in dataset units
+    #[serde(default)]
+    pub resolution: Option<f64>,
+    /// Directory to save fetched rasters in, and load them from on later runs
+    #[serde(default)]
+    pub cache: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ContourLoader {
+    /// A raster of heights to trace contours from
+    pub filepath: String,
+    pub band: usize,
+    /// Difference in height between contours
+    pub interval: f64,
+    /// Height from which contours are spaced
+    #[serde(default)]
+    pub base: f64,
+    /// Scale applied to the horizontal axes of the raster, matching the
+    /// `scale` of a height map from the same raster
+    #[serde(default)]
+    pub scale: Option<[f64; 2]>,
+}
+
+fn osm_lines() -> String {
+    String::from("lines")
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OsmLoader {
+    /// An OpenStreetMap file, such as an `.osm.pbf` extract
+    pub filepath: String,
+    /// One of points, lines, multilinestrings, multipolygons or
+    /// other_relations
+    #[serde(default = "osm_lines")]
+    pub layer: String,
+    /// Comma separated conditions on tags, of `key`, `!key`, `key=a|b` or
+    /// `key!=a|b`, that elements must all satisfy
+    #[serde(default)]
+    pub filter: String,
+    /// Only load elements within an area of interest
+    #[serde(default)]
+    pub bounds: Option<BoundsOpts>,
+}
+
+fn unit() -> f64 {
+    1.0
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImageLoader {
+    /// A grayscale image of heights, a PNG of 8 or 16 bits or an 8 bit BMP,
+    /// otherwise the first channel of a color image is used
+    pub filepath: String,
+    /// Height of one step of the stored values
+    #[serde(default = "unit")]
+    pub scale: f64,
+    /// Height of a stored value of zero
+    #[serde(default)]
+    pub offset: f64,
+    /// West, south, east and north bounds covered by the image, otherwise
+    /// each pixel is one unit wide from the origin
+    #[serde(default)]
+    pub extent: Option<[f64; 4]>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TerrainRgbLoader {
+    /// A PNG image with heights encoded in its color channels, as served by
+    /// Mapbox Terrain-RGB tiles
+    pub filepath: String,
+    /// West, south, east and north bounds covered by the image
+    #[serde(default)]
+    pub extent: Option<[f64; 4]>,
+}
+
+/// A raster computed for each pixel from an expression of other rasters, all
+/// of the same size, such as `weights * 2.5` or `heights - (lake > 0) * 5`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExpressionLoader {
+    pub expression: String,
+    /// Rasters by the names they have in the expression, the first giving
+    /// the transform and projection of the result
+    pub inputs: BTreeMap<String, Loader>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GeojsonLoader {
+    /// A GeoJSON file of features or geometries
+    pub filepath: String,
+    /// Only load shapes within an area of interest
+    #[serde(default)]
+    pub bounds: Option<BoundsOpts>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoordinateSpace {
+    /// Coordinates of the dataset, with `y` increasing northwards
+    Dataset,
+    /// Coordinates of the scene, where `z` is the negated dataset `y`
+    Scene,
+}
+
+impl Default for CoordinateSpace {
+    fn default() -> CoordinateSpace {
+        CoordinateSpace::Dataset
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BoundsOpts {
+    pub min: [f64; 2],
+    pub max: [f64; 2],
+    #[serde(default)]
+    pub space: CoordinateSpace,
+}
+
+impl BoundsOpts {
+    /// Return the west, south, east and north bounds in dataset coordinates
+    pub fn dataset(&self) -> (f64, f64, f64, f64) {
+        match self.space {
+            CoordinateSpace::Dataset => {
+                (self.min[0], self.min[1], self.max[0], self.max[1])
+            }
+            CoordinateSpace::Scene => {
+                (self.min[0], -self.max[1], self.max[0], -self.min[1])
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BakedLoader {
+    /// A package written by `bake`, set when the package is opened
+    #[serde(default)]
+    pub package: String,
+    /// Name of the entry of the package holding the raster or shapes
+    pub entry: String,
+    /// Name of an entry holding a height map built from the raster
+    #[serde(default)]
+    pub height_map: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Loader {
+    #[cfg(feature = "gdal")]
     Gdal(GdalLoader),
+    #[cfg(feature = "gdal")]
     Shp(OgrLoader),
+    #[cfg(feature = "gdal")]
+    Osm(OsmLoader),
+    #[cfg(feature = "gdal")]
+    Contours(ContourLoader),
+    #[cfg(feature = "gdal")]
+    Remote(RemoteLoader),
+    Png(ImageLoader),
+    Bmp(ImageLoader),
+    TerrainRgb(TerrainRgbLoader),
+    Expression(ExpressionLoader),
+    Geojson(GeojsonLoader),
+    Baked(BakedLoader),
+}
+
+impl Loader {
+    /// Return true if the loader reads a raster, rather than shapes
+    pub fn is_raster(&self) -> bool {
+        match *self {
+            #[cfg(feature = "gdal")]
+            Loader::Gdal(_) | Loader::Remote(_) => true,
+            Loader::Png(_) | Loader::Bmp(_) | Loader::TerrainRgb(_) => true,
+            Loader::Expression(_) => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct HeightMapOpts {
     pub data: Loader,
+    #[serde(default)]
+    pub curvature: Option<CurvatureOpts>,
+    /// Scale applied to the horizontal axes of the raster, by default rasters
+    /// in geographic coordinates are converted from degrees to metres
+    #[serde(default)]
+    pub scale: Option<[f64; 2]>,
+    /// Store the quadtree of maximum heights in 16 bits, using less memory
+    /// at the cost of visiting slightly more nodes
+    #[serde(default)]
+    pub quantize_mipmaps: bool,
+    /// A `.peaks-hm` file caching the quadtree built from the heights, read
+    /// when it was built from the same heights and written otherwise
+    #[serde(default)]
+    pub cache: Option<String>,
+    /// Factor the heights are multiplied by when rendered
+    #[serde(default = "exaggeration")]
+    pub exaggeration: f64,
+    /// Size in pixels below which parts of the surface seen by camera rays
+    /// are intersected as a single coarser patch, shadow rays always see the
+    /// full detail so a normal offset may be needed to avoid self shadowing
+    #[serde(default)]
+    pub lod: Option<f64>,
+}
+
+fn exaggeration() -> f64 {
+    1.0
+}
+
+fn refraction() -> f64 {
+    0.13
+}
+
+fn earth_radius() -> f64 {
+    EARTH_RADIUS
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CurvatureOpts {
+    /// Coefficient of atmospheric refraction
+    #[serde(default = "refraction")]
+    pub refraction: f64,
+    #[serde(default = "earth_radius")]
+    pub radius: f64,
+    /// Position on the `x` and `z` axes to measure distances from, defaults
+    /// to the position of the scene camera
+    #[serde(default)]
+    pub origin: Option<[f64; 2]>,
+}
+
+/// What the heights of a primitive are measured from
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Anchor {
+    Absolute,
+    /// The surface of the first height map of the scene, beneath the
+    /// primitive
+    Terrain,
+}
+
+impl Default for Anchor {
+    fn default() -> Anchor {
+        Anchor::Absolute
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct AabbOpts {
     pub min: [f64; 3],
     pub max: [f64; 3],
+    #[serde(default)]
+    pub anchor: Anchor,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SphereOpts {
     pub position: [f64; 3],
     pub radius: f64,
+    #[serde(default)]
+    pub anchor: Anchor,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -93,21 +513,244 @@ pub struct BilinearPatchOpts {
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExtrusionOpts {
+    pub data: Loader,
+    /// Height of the walls above their base
+    #[serde(default)]
+    pub height: f64,
+    /// Numeric attribute of each feature to use as its height, falling back
+    /// to `height` for features without it
+    #[serde(default)]
+    pub attribute: Option<String>,
+    /// Surface to place the base of the shapes on, otherwise the height of
+    /// their vertices is used
+    #[serde(default)]
+    pub terrain: Option<HeightMapOpts>,
+    /// Place the shapes on the first height map of the scene, when no
+    /// terrain is given
+    #[serde(default)]
+    pub anchor: Anchor,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkerSymbol {
+    /// A disc turned to face the camera
+    Disc,
+    /// A square based pyramid
+    Pyramid,
+    /// A pole with a pennant at its top
+    Flag,
+}
+
+impl Default for MarkerSymbol {
+    fn default() -> MarkerSymbol {
+        MarkerSymbol::Disc
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MarkerOpts {
+    /// Points to place markers at
+    pub data: Loader,
+    #[serde(default)]
+    pub symbol: MarkerSymbol,
+    /// Height of the markers
+    pub size: f64,
+    /// Surface to place the markers on, otherwise the height of the points
+    /// is used
+    #[serde(default)]
+    pub terrain: Option<HeightMapOpts>,
+    /// Place the markers on the first height map of the scene, when no
+    /// terrain is given
+    #[serde(default)]
+    pub anchor: Anchor,
+}
+
+/// Shape of the instances scattered over a terrain, resting on their base
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
+pub enum InstanceOpts {
+    /// A cone pointing upwards, such as a conifer
+    Cone { radius: f64, height: f64 },
+    /// A sphere, such as a boulder or the crown of a tree
+    Sphere { radius: f64 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScatterOpts {
+    /// Raster of the density of instances, as a fraction of `density`, or of
+    /// land cover classes when `classes` is given
+    pub data: Loader,
+    /// Values of the raster to scatter instances over at the full density
+    #[serde(default)]
+    pub classes: Vec<f64>,
+    /// Number of instances per square unit
+    pub density: f64,
+    /// Surface the instances are placed on
+    pub terrain: HeightMapOpts,
+    pub instance: InstanceOpts,
+    #[serde(default)]
+    pub seed: u64,
+    /// Fraction by which the size of each instance varies at random
+    #[serde(default)]
+    pub variation: f64,
+    /// Range of slopes, in degrees, that instances are placed on
+    #[serde(default)]
+    pub slope: Option<[f64; 2]>,
+    /// Range of elevations that instances are placed on
+    #[serde(default)]
+    pub elevation: Option<[f64; 2]>,
+}
+
+/// Prefix of the type of shaders and primitives registered by other crates
+pub const CUSTOM_PREFIX: &str = "custom:";
+
+fn custom_type<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    let kind = try!(String::deserialize(deserializer));
+    if !kind.starts_with(CUSTOM_PREFIX) {
+        let message = format!("unknown type `{}`", kind);
+        return Err(de::Error::custom(message));
+    }
+    Ok(kind)
+}
+
+/// Options of a shader or primitive registered by another crate, with a type
+/// of `custom:<name>`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CustomOpts {
+    #[serde(rename = "type", deserialize_with = "custom_type")]
+    pub kind: String,
+    /// All other fields, as given in the scene
+    #[serde(flatten)]
+    pub options: Map<String, Value>,
+}
+
+impl CustomOpts {
+    /// Return the name the type was registered with
+    pub fn name(&self) -> &str {
+        &self.kind[CUSTOM_PREFIX.len()..]
+    }
+}
+
+/// Deserialize options of a type registered by another crate, or else those
+/// of a built-in type, keeping the errors of each rather than trying one
+/// after the other
+fn builtin_or_custom<'de, D, T, F>(
+    deserializer: D,
+    builtin: F,
+    custom: fn(CustomOpts) -> T,
+) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    F: FnOnce(Value) -> Result<T, serde_json::Error>,
+{
+    let value = try!(Value::deserialize(deserializer));
+    let is_custom = value
+        .get("type")
+        .and_then(Value::as_str)
+        .map_or(false, |kind| kind.starts_with(CUSTOM_PREFIX));
+    let options = if is_custom {
+        CustomOpts::deserialize(value).map(custom)
+    } else {
+        builtin(value)
+    };
+    options.map_err(de::Error::custom)
+}
+
+/// Serialized as a built-in type, or its fields for a custom type
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(remote = "Self", tag = "type", rename_all = "snake_case")]
 pub enum PrimitiveOpts {
     HeightMap(HeightMapOpts),
     Aabb(AabbOpts),
     Plane(PlaneOpts),
     Sphere(SphereOpts),
     BilinearPatch(BilinearPatchOpts),
+    Extrusion(ExtrusionOpts),
+    Marker(MarkerOpts),
+    Scatter(ScatterOpts),
+    #[serde(skip)]
+    Custom(CustomOpts),
+}
+
+impl Serialize for PrimitiveOpts {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match *self {
+            PrimitiveOpts::Custom(ref opts) => opts.serialize(serializer),
+            _ => PrimitiveOpts::serialize(self, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PrimitiveOpts {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        builtin_or_custom(
+            deserializer,
+            PrimitiveOpts::deserialize,
+            PrimitiveOpts::Custom,
+        )
+    }
+}
+
+/// A reference to a shader, either by its index or declared inline
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ShaderRef {
+    Index(usize),
+    Inline(Box<ShaderOpts>),
+}
+
+impl ShaderRef {
+    /// Return the index of the referenced shader
+    pub fn index(&self) -> usize {
+        match *self {
+            ShaderRef::Index(index) => index,
+            ShaderRef::Inline(_) => panic!("Inline shader was not flattened"),
+        }
+    }
+}
+
+impl From<usize> for ShaderRef {
+    fn from(index: usize) -> ShaderRef {
+        ShaderRef::Index(index)
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct NormalShaderOpts;
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HatchPatternOpts {
+    pub angle: f64,
+    pub spacing: f64,
+    pub width: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DotPatternOpts {
+    pub spacing: f64,
+    pub radius: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FillPatternOpts {
+    Hatch(HatchPatternOpts),
+    Dots(DotPatternOpts),
+    Texture(TextureShaderOpts),
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SdfShaderOpts {
-    pub wraps: usize,
+    pub wraps: ShaderRef,
     pub data: Loader,
     pub tolerance: f64,
     pub color: [f64; 3],
@@ -116,11 +759,52 @@ pub struct SdfShaderOpts {
     pub stroke_color: [f64; 3],
     pub stroke_alpha: f64,
     pub offset: f64,
+    #[serde(default)]
+    pub pattern: Option<FillPatternOpts>,
+    /// Ignore shapes more than this height above or below the surface
+    #[serde(default)]
+    pub vertical_tolerance: Option<f64>,
+    /// Name of the shapes in legends, defaults to one taken from their data
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VectorLayerOpts {
+    pub data: Loader,
+    pub tolerance: f64,
+    pub fill: Option<[f64; 3]>,
+    #[serde(default)]
+    pub pattern: Option<FillPatternOpts>,
+    pub stroke: Option<[f64; 3]>,
+    pub stroke_width: f64,
+    pub dash: Option<[f64; 2]>,
+    pub opacity: f64,
+    pub blend: BlendMode,
+    pub offset: f64,
+    /// Name of the layer in legends, defaults to one taken from its data
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VectorLayerShaderOpts {
+    pub wraps: ShaderRef,
+    pub layers: Vec<VectorLayerOpts>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct PhongShaderOpts {
-    pub wraps: usize,
+    pub wraps: ShaderRef,
     pub lights: Vec<usize>,
     pub bias: f64,
     pub ambient: [f64; 3],
@@ -128,6 +812,28 @@ pub struct PhongShaderOpts {
     pub specular_exponent: f64,
     pub ks: f64,
     pub cel_shading: Option<(usize, f64)>,
+    /// Divide the light received by the total intensity of all lights, so
+    /// that adding lights does not brighten the surface
+    #[serde(default)]
+    pub normalize: bool,
+    /// Light received by the terrain, looked up instead of tracing shadows
+    #[serde(default)]
+    pub baked: Option<BakedLightingOpts>,
+}
+
+/// Light received by each point of a terrain, in world space, written by
+/// `bake` with lighting
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BakedLightingOpts {
+    /// Light received from the directional lights of the shader, including
+    /// the shadows of the terrain
+    pub diffuse: Loader,
+    /// Fraction of the light of a uniform sky reaching each point, which
+    /// scales environment lights
+    pub occlusion: Loader,
+    /// Index of the height map primitive the light was baked for, which
+    /// other primitives sharing the shader are lit without
+    pub primitive: usize,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -135,60 +841,594 @@ pub struct ConstantShaderOpts {
     pub color: [f64; 3],
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LineAttenuationOpts {
+    pub near: f64,
+    pub far: f64,
+    pub max_intensity: f64,
+    pub min_intensity: f64,
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct FeatureLineShaderOpts {
-    pub wraps: usize,
+    pub wraps: ShaderRef,
     pub color: [f64; 3],
     pub quality: usize,
     pub radius: f64,
     pub crease_threshold: f64,
     pub self_silhoutte_threshold: f64,
+    #[serde(default)]
+    pub attenuation: Option<LineAttenuationOpts>,
+}
+
+/// How the amount of fog grows with the distance to a surface
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FalloffOpts {
+    /// No fog before `near`, rising linearly to full fog at `far`
+    Linear { near: f64, far: f64 },
+    /// Fog rising from `near`, with `density` the fraction of light lost per
+    /// unit of distance
+    Exponential {
+        #[serde(default)]
+        near: f64,
+        density: f64,
+    },
+}
+
+fn opaque() -> f64 {
+    1.0
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DepthCueShaderOpts {
+    pub wraps: ShaderRef,
+    /// Color of the fog
+    pub color: [f64; 3],
+    pub falloff: FalloffOpts,
+    /// Largest amount of fog, covering the wrapped shader at one
+    #[serde(default = "opaque")]
+    pub opacity: f64,
+}
+
+/// Lines around square cells of the `x` and `z` plane, such as for a
+/// reference ground under a terrain
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GridShaderOpts {
+    pub color: [f64; 3],
+    /// Color of alternate cells, for a checkerboard
+    #[serde(default)]
+    pub checker: Option<[f64; 3]>,
+    /// Size of a cell in map units
+    pub cell_size: f64,
+    pub line_color: [f64; 3],
+    /// Width of the lines in map units
+    #[serde(default)]
+    pub line_width: f64,
+    /// Fading of the lines and checks with distance
+    #[serde(default)]
+    pub fade: Option<FalloffOpts>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextureFilter {
+    Bilinear,
+    Trilinear,
+    Anisotropic,
+}
+
+impl Default for TextureFilter {
+    fn default() -> TextureFilter {
+        TextureFilter::Bilinear
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProjectionOpts {
+    /// Longitude and latitude in degrees
+    Geographic,
+    WebMercator,
+    Utm {
+        zone: u8,
+        #[serde(default)]
+        south: bool,
+    },
+}
+
+/// Coordinate reference systems of a texture and the scene it is draped over
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReprojectOpts {
+    pub scene: ProjectionOpts,
+    pub texture: ProjectionOpts,
+    /// Spacing in scene units of a grid of precomputed texture coordinates,
+    /// reprojecting every sample if unset
+    #[serde(default)]
+    pub grid: Option<f64>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct TextureShaderOpts {
+    #[serde(default)]
+    pub filter: TextureFilter,
     pub transform: [f64; 4],
     pub width: usize,
     pub height: usize,
     pub components: usize,
     pub data: Vec<f64>,
+    #[serde(default)]
+    pub reproject: Option<ReprojectOpts>,
 }
 
+/// Only the shadows falling on a surface, over white, for multiplying over
+/// other imagery
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MatteShaderOpts {
+    pub lights: Vec<usize>,
+    #[serde(default)]
+    pub bias: f64,
+    /// Color of fully shadowed areas
+    #[serde(default)]
+    pub color: [f64; 3],
+    /// Darkness of shadows, from clear at zero to the shadow color at one
+    #[serde(default = "opaque")]
+    pub opacity: f64,
+    /// Also darken surfaces facing away from the lights
+    #[serde(default)]
+    pub shading: bool,
+}
+
+/// Ice drawn inside glacier polygons
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GlacierShaderOpts {
+    pub wraps: ShaderRef,
+    pub data: Loader,
+    pub lights: Vec<usize>,
+    /// Elevations at which the ice is bluest and whitest, lower ice standing
+    /// in for thicker ice
+    pub elevation: [f64; 2],
+    /// Distance in map units between crevasses, drawn across the direction
+    /// the ice flows down the slope
+    #[serde(default = "crevasse_spacing")]
+    pub crevasse_spacing: f64,
+    #[serde(default = "crevasse_width")]
+    pub crevasse_width: f64,
+    #[serde(default = "ice_ks")]
+    pub ks: f64,
+    #[serde(default = "ice_specular_exponent")]
+    pub specular_exponent: f64,
+    #[serde(default)]
+    pub bias: f64,
+}
+
+fn crevasse_spacing() -> f64 {
+    40.0
+}
+
+fn crevasse_width() -> f64 {
+    4.0
+}
+
+fn ice_ks() -> f64 {
+    0.6
+}
+
+fn ice_specular_exponent() -> f64 {
+    40.0
+}
+
+/// Small scale relief added to the normals of a surface
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
+pub enum DetailOpts {
+    /// Fractal value noise with features of a size in map units
+    Noise {
+        scale: f64,
+        #[serde(default = "octaves")]
+        octaves: usize,
+        #[serde(default)]
+        seed: u64,
+    },
+    /// A tangent space normal map, tiled across the surface with the
+    /// transform of the texture, its bands pointing east, north and up
+    NormalMap(TextureShaderOpts),
+}
+
+fn octaves() -> usize {
+    4
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DetailNormalShaderOpts {
+    pub wraps: ShaderRef,
+    pub detail: DetailOpts,
+    /// Amount the normal is tilted by the detail
+    #[serde(default = "opaque")]
+    pub strength: f64,
+}
+
+/// Serialized as a built-in type, or its fields for a custom type
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(remote = "Self", tag = "type", rename_all = "snake_case")]
 pub enum ShaderOpts {
     Normal(NormalShaderOpts),
     Sdf(SdfShaderOpts),
+    VectorLayers(VectorLayerShaderOpts),
     Phong(PhongShaderOpts),
     Constant(ConstantShaderOpts),
+    DepthCue(DepthCueShaderOpts),
     FeatureLines(FeatureLineShaderOpts),
     Texture(TextureShaderOpts),
+    DetailNormal(DetailNormalShaderOpts),
+    Glacier(GlacierShaderOpts),
+    Matte(MatteShaderOpts),
+    Grid(GridShaderOpts),
+    #[serde(skip)]
+    Custom(CustomOpts),
+}
+
+impl Serialize for ShaderOpts {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match *self {
+            ShaderOpts::Custom(ref opts) => opts.serialize(serializer),
+            _ => ShaderOpts::serialize(self, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ShaderOpts {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        builtin_or_custom(
+            deserializer,
+            ShaderOpts::deserialize,
+            ShaderOpts::Custom,
+        )
+    }
+}
+
+impl ShaderOpts {
+    /// Return references to the shaders wrapped by this shader
+    pub fn children_mut(&mut self) -> Vec<&mut ShaderRef> {
+        match *self {
+            ShaderOpts::Sdf(ref mut opts) => vec![&mut opts.wraps],
+            ShaderOpts::VectorLayers(ref mut opts) => vec![&mut opts.wraps],
+            ShaderOpts::Phong(ref mut opts) => vec![&mut opts.wraps],
+            ShaderOpts::FeatureLines(ref mut opts) => vec![&mut opts.wraps],
+            ShaderOpts::DepthCue(ref mut opts) => vec![&mut opts.wraps],
+            ShaderOpts::DetailNormal(ref mut opts) => vec![&mut opts.wraps],
+            ShaderOpts::Glacier(ref mut opts) => vec![&mut opts.wraps],
+            ShaderOpts::Normal(_)
+            | ShaderOpts::Constant(_)
+            | ShaderOpts::Texture(_)
+            | ShaderOpts::Matte(_)
+            | ShaderOpts::Grid(_)
+            | ShaderOpts::Custom(_) => vec![],
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct DirectionalLightOpts {
     pub intensity: f64,
     pub direction: [f64; 3],
+    #[serde(default = "white")]
+    pub color: [f64; 3],
+    /// Height map whose shadows are precomputed for the light, replacing
+    /// shadow rays for points above it, other objects cast no shadows there
+    #[serde(default)]
+    pub shadow_map: Option<HeightMapOpts>,
+}
+
+fn white() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EnvironmentLightOpts {
+    /// Path to an equirectangular image, either a Radiance `.hdr` file or a
+    /// three band raster readable by GDAL
+    pub filepath: String,
+    pub intensity: f64,
+    /// Number of directions sampled for each shade
+    pub samples: usize,
+    /// Compass bearing of the center of the image in degrees
+    #[serde(default)]
+    pub rotation: f64,
+    /// Interpolate light received between nearby shades
+    #[serde(default)]
+    pub irradiance_cache: Option<IrradianceCacheOpts>,
+    /// Seed of the offsets of the directions sampled at each pixel
+    #[serde(default)]
+    pub seed: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IrradianceCacheOpts {
+    /// Largest error allowed when reusing an estimate, smaller values are
+    /// slower and more accurate
+    pub error: f64,
+    /// Bounds of the distance, in world units, over which an estimate is
+    /// expected to change
+    pub min_spacing: f64,
+    pub max_spacing: f64,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum LightOpts {
     Directional(DirectionalLightOpts),
+    Environment(EnvironmentLightOpts),
+}
+
+fn visible() -> bool {
+    true
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ObjectOpts {
     pub primitive: usize,
     pub shader: usize,
+    #[serde(default = "visible")]
+    pub camera: bool,
+    #[serde(default = "visible")]
+    pub shadow: bool,
+    #[serde(default = "visible")]
+    pub stencil: bool,
+    /// Names of the groups the object belongs to, whose overrides are
+    /// applied in order
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+/// Overrides applied to every object of a group
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GroupOpts {
+    /// Include the objects of the group in the scene
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Shade the objects with this shader instead of their own
+    #[serde(default)]
+    pub shader: Option<usize>,
+    #[serde(default)]
+    pub camera: Option<bool>,
+    #[serde(default)]
+    pub shadow: Option<bool>,
+    #[serde(default)]
+    pub stencil: Option<bool>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LineworkOpts {
+    pub data: Loader,
+    pub color: [f64; 3],
+    pub width: f64,
+    pub drape: bool,
+    pub occlusion: bool,
+    /// Name of the lines in legends, defaults to one taken from their data
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LabelOpts {
+    /// Points to label, from a GeoJSON file or OGR layer
+    pub data: Loader,
+    /// Field of each feature holding its name
+    pub attribute: String,
+    /// Height of the text in pixels
+    pub size: f64,
+    pub color: [f64; 3],
+    #[serde(default)]
+    pub drape: bool,
+    /// Leave out points hidden by the scene
+    #[serde(default = "default_true")]
+    pub occlusion: bool,
+    /// Gap between a point and its label in pixels
+    #[serde(default)]
+    pub offset: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EdgeDetectionOpts {
+    pub crease_threshold: f64,
+    pub silhouette_threshold: f64,
+    pub tolerance: f64,
+    pub color: [f64; 3],
+    pub width: f64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A sky of colors blending from the horizon, up to the zenith and down to
+/// the ground, by the angle of a ray above or below the horizon
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GradientOpts {
+    pub horizon: [f64; 3],
+    pub zenith: [f64; 3],
+    /// Color straight down, defaults to the horizon color
+    #[serde(default)]
+    pub ground: Option<[f64; 3]>,
+    /// Exponent of the blend away from the horizon, above 1 widening the
+    /// band of horizon color and below 1 narrowing it
+    #[serde(default = "unit", deserialize_with = "softness")]
+    pub softness: f64,
+}
+
+fn softness<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<f64, D::Error> {
+    let softness = try!(f64::deserialize(deserializer));
+    if softness.is_nan() || softness <= 0.0 {
+        let message = format!("softness `{}` is not above zero", softness);
+        return Err(de::Error::custom(message));
+    }
+    Ok(softness)
+}
+
+/// Water filling everything below an elevation
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WaterOpts {
+    pub elevation: f64,
+    pub color: [f64; 3],
+    /// Distance through the water after which a little over a third of the
+    /// light from the surfaces beneath remains, opaque when zero
+    #[serde(default)]
+    pub clarity: f64,
+}
+
+/// The color seen by rays that hit nothing
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum BackgroundOpts {
+    Color([f64; 3]),
+    Gradient(GradientOpts),
+}
+
+impl<'de> Deserialize<'de> for BackgroundOpts {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        // Chosen by the shape of the value, so the errors of a gradient are
+        // kept rather than those of trying each variant
+        let value = try!(Value::deserialize(deserializer));
+        let options = if value.is_array() {
+            Deserialize::deserialize(value).map(BackgroundOpts::Color)
+        } else {
+            GradientOpts::deserialize(value).map(BackgroundOpts::Gradient)
+        };
+        options.map_err(de::Error::custom)
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SceneOpts {
-    pub background: [f64; 3],
+    pub background: BackgroundOpts,
     pub camera: CameraOpts,
     pub shaders: Vec<ShaderOpts>,
     pub lights: Vec<LightOpts>,
     pub primitives: Vec<PrimitiveOpts>,
     pub objects: Vec<ObjectOpts>,
+    /// Overrides for named groups of objects
+    #[serde(default)]
+    pub groups: HashMap<String, GroupOpts>,
+    #[serde(default)]
+    pub linework: Vec<LineworkOpts>,
+    /// Names of points placed in linework
+    #[serde(default)]
+    pub labels: Vec<LabelOpts>,
+    #[serde(default)]
+    pub edges: Option<EdgeDetectionOpts>,
+    /// Intersections closer than this distance along a ray are ignored
+    #[serde(default)]
+    pub ray_epsilon: f64,
+    /// Distance secondary rays are moved away from a surface along its normal
+    #[serde(default)]
+    pub normal_offset: f64,
+    /// Flip normals to face against the rays that hit them
+    #[serde(default = "default_true")]
+    pub face_forward: bool,
+    /// Distance within which shadow rays of a tile, in the same direction,
+    /// first test the object that last occluded one of them
+    #[serde(default)]
+    pub shadow_cache: Option<f64>,
+    /// Lowest and highest elevations of surfaces that are rendered, those
+    /// outside being cut away
+    #[serde(default)]
+    pub clip_elevation: Option<[f64; 2]>,
+    /// Flood surfaces below an elevation
+    #[serde(default)]
+    pub water: Option<WaterOpts>,
+    /// Megabytes of loaded files and cached resources kept between scenes,
+    /// evicted while scenes are built but not during a render. When set,
+    /// height maps of local GDAL rasters at their full resolution, without
+    /// curvature or a cache, are built from the rasters read in strips
+    #[serde(default)]
+    pub memory_budget: Option<usize>,
+    /// Move the camera to frame the scene, curvature corrections are still
+    /// measured from its configured position
+    #[serde(default)]
+    pub frame: Option<FrameOpts>,
+    /// Varies every random choice of the scene, such as scattered instances,
+    /// noise and light samples, leaving their own seeds unchanged when zero
+    #[serde(default)]
+    pub seed: u64,
+    /// Options changing over the frames of a sequence, read by `animate`
+    #[serde(default)]
+    pub animation: Option<AnimationOpts>,
+}
+
+/// How values between keyframes are found
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    Linear,
+    /// Easing in and out of each keyframe
+    Smooth,
+    /// Holding the value of each keyframe until the next
+    Step,
+    /// Turning at an even rate between directions, such as of lights
+    Spherical,
+}
+
+impl Default for Interpolation {
+    fn default() -> Interpolation {
+        Interpolation::Linear
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KeyframeOpts {
+    pub frame: f64,
+    /// A number or array of numbers, or any value for step interpolation
+    pub value: Value,
+}
+
+/// Keyframes of an option, by its dotted path such as `lights.0.direction`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrackOpts {
+    pub path: String,
+    pub keyframes: Vec<KeyframeOpts>,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AnimationOpts {
+    /// Number of frames in the sequence, numbered from zero
+    pub frames: usize,
+    pub tracks: Vec<TrackOpts>,
+}
+
+impl SceneOpts {
+    /// Return an error for options that can not be rendered
+    pub fn validate(&self) -> Result<(), String> {
+        self.camera.validate()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatchJobOpts {
+    /// Path to a scene file
+    pub scene: String,
+    /// A JSON merge patch applied to the scene before it is loaded
+    #[serde(default)]
+    pub overrides: Option<Value>,
+    /// Path to the rendered image
+    pub output: String,
+    /// Path to export linework to
+    #[serde(default)]
+    pub vector: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatchOpts {
+    pub jobs: Vec<BatchJobOpts>,
 }
@@ -0,0 +1,156 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use exec::{render_async, RenderConfig, TileOrder};
+use math::Vec3;
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+use ops::encode_srgb;
+use options::SceneOpts;
+use progress::ProgressSink;
+use render::Renderer;
+use scene::{Scene, SceneCache};
+use textures::Texture;
+
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Factor by which the resolution of the camera is reduced for previews
+const PREVIEW_SCALE: usize = 2;
+
+/// Degrees the camera orbits for each key press
+const ORBIT_STEP: f64 = 5.0;
+
+/// Factor the camera distance is scaled by for each step of zooming in
+const ZOOM_STEP: f64 = 0.9;
+
+/// Time between updates of the window
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Copies completed tiles into a frame buffer shown by the window
+struct FrameSink {
+    frame: Arc<Mutex<Vec<u32>>>,
+    width: usize,
+}
+
+impl ProgressSink for FrameSink {
+    fn update(&mut self, _completed: usize, _total: usize) {}
+
+    fn tile(&mut self, x: usize, y: usize, pixels: &Texture<Vec3>) {
+        let mut frame = self.frame.lock().unwrap();
+        for j in 0..pixels.height {
+            for i in 0..pixels.width {
+                let color = encode_srgb(pixels.lookup1x1(i, j));
+                let (r, g, b) =
+                    (color.r as u32, color.g as u32, color.b as u32);
+                frame[(y + j) * self.width + x + i] = (r << 16) | (g << 8) | b;
+            }
+        }
+    }
+}
+
+/// Move the camera of a scene from the keys pressed and scrolling in a
+/// window, returning true if it moved
+fn navigate(window: &Window, options: &mut SceneOpts) -> bool {
+    let (azimuth, elevation) = options.camera.angles();
+    let mut moved = false;
+    for key in window.get_keys_pressed(KeyRepeat::Yes) {
+        match key {
+            Key::Left => options.camera.orbit(azimuth - ORBIT_STEP, elevation),
+            Key::Right => options.camera.orbit(azimuth + ORBIT_STEP, elevation),
+            Key::Up => {
+                let elevation = (elevation + ORBIT_STEP).min(89.0);
+                options.camera.orbit(azimuth, elevation)
+            }
+            Key::Down => {
+                let elevation = (elevation - ORBIT_STEP).max(-89.0);
+                options.camera.orbit(azimuth, elevation)
+            }
+            Key::Equal | Key::NumPadPlus => options.camera.zoom(ZOOM_STEP),
+            Key::Minus | Key::NumPadMinus => {
+                options.camera.zoom(1.0 / ZOOM_STEP)
+            }
+            _ => continue,
+        }
+        moved = true;
+    }
+    if let Some((_, scroll)) = window.get_scroll_wheel() {
+        options.camera.zoom(ZOOM_STEP.powf(scroll.signum() as f64));
+        moved = true;
+    }
+    moved
+}
+
+/// Show a scene in a window as its tiles complete, re-rendering it at a
+/// reduced resolution as the camera is orbited with the arrow keys and zoomed
+/// with the plus and minus keys or the scroll wheel
+pub fn preview(
+    mut options: SceneOpts,
+    threads: Option<usize>,
+    tile_size: Option<usize>,
+) -> Result<()> {
+    let (width, height) = {
+        let (width, height) = options.camera.size_mut();
+        (*width, *height)
+    };
+    let mut window =
+        Window::new("Peaks", width, height, WindowOptions::default())
+            .map_err(|err| Error::new(ErrorKind::Other, err.to_string()))?;
+    window.limit_update_rate(Some(FRAME_INTERVAL));
+
+    let mut cache = SceneCache::new();
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        let mut reduced = options.clone();
+        {
+            let (width, height) = reduced.camera.size_mut();
+            *width = (*width / PREVIEW_SCALE).max(1);
+            *height = (*height / PREVIEW_SCALE).max(1);
+        }
+        let scene = Scene::with_cache(reduced, &mut cache);
+        let (width, height) = scene.camera.view_plane();
+        let frame = Arc::new(Mutex::new(vec![0; width * height]));
+        let sink = FrameSink {
+            frame: frame.clone(),
+            width,
+        };
+        let renderer = Renderer::new(1, scene);
+        let config =
+            RenderConfig::detect(width, height).with(threads, tile_size);
+        let handle = render_async(
+            &renderer,
+            width,
+            height,
+            config.threads,
+            config.tile_size,
+            TileOrder::CenterOut,
+            sink,
+        );
+
+        while window.is_open() && !window.is_key_down(Key::Escape) {
+            if navigate(&window, &mut options) {
+                break;
+            }
+            let buffer = frame.lock().unwrap().clone();
+            window
+                .update_with_buffer(&buffer, width, height)
+                .map_err(|err| Error::new(ErrorKind::Other, err.to_string()))?;
+        }
+
+        handle.cancel();
+        handle.join();
+    }
+
+    Ok(())
+}
@@ -14,7 +14,7 @@
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
 use super::primitive::{Intersection, Primitive};
-use math::{Ray, Vec3};
+use math::{F64x4, Ray, Vec3};
 use options::AabbOpts;
 
 use std::f64::INFINITY;
@@ -30,7 +30,45 @@ impl Aabb {
         Aabb { min, max }
     }
 
-    fn center(&self) -> Vec3 {
+    /// Return the smallest box enclosing a set of points
+    pub fn enclosing(points: &[Vec3]) -> Aabb {
+        let mut min = Vec3::new(INFINITY, INFINITY, INFINITY);
+        let mut max = Vec3::new(-INFINITY, -INFINITY, -INFINITY);
+        for point in points {
+            min = Vec3::new(
+                min.x.min(point.x),
+                min.y.min(point.y),
+                min.z.min(point.z),
+            );
+            max = Vec3::new(
+                max.x.max(point.x),
+                max.y.max(point.y),
+                max.z.max(point.z),
+            );
+        }
+        Aabb { min, max }
+    }
+
+    /// Return the smallest box enclosing both boxes
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::enclosing(&[self.min, self.max, other.min, other.max])
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Vec3::new(a.x, a.y, a.z),
+            Vec3::new(b.x, a.y, a.z),
+            Vec3::new(a.x, b.y, a.z),
+            Vec3::new(b.x, b.y, a.z),
+            Vec3::new(a.x, a.y, b.z),
+            Vec3::new(b.x, a.y, b.z),
+            Vec3::new(a.x, b.y, b.z),
+            Vec3::new(b.x, b.y, b.z),
+        ]
+    }
+
+    pub fn center(&self) -> Vec3 {
         Vec3::new(
             self.min.x + (self.max.x - self.min.x) / 2.0,
             self.min.y + (self.max.y - self.min.y) / 2.0,
@@ -45,8 +83,10 @@ impl From<AabbOpts> for Aabb {
     }
 }
 
-impl Primitive for Aabb {
-    fn intersects(&self, ray: Ray) -> Option<Intersection> {
+impl Aabb {
+    /// Return the distance along a ray at which it enters the box, or leaves
+    /// it if the ray starts inside
+    pub fn entry(&self, ray: Ray) -> Option<f64> {
         let bounds = [self.min, self.max];
 
         let inverse_dir = Vec3::new(
@@ -74,7 +114,69 @@ impl Primitive for Aabb {
             return None;
         }
 
-        let t = if tmin < 0.0 { tmax } else { tmin };
+        Some(if tmin < 0.0 { tmax } else { tmin })
+    }
+
+    /// Return the distances along a ray at which it enters four boxes, as
+    /// `entry` would, testing all of them at once
+    pub fn entries(boxes: &[Aabb; 4], ray: Ray) -> [Option<f64>; 4] {
+        let lanes = |axis: fn(&Aabb) -> f64| {
+            F64x4::new(
+                axis(&boxes[0]),
+                axis(&boxes[1]),
+                axis(&boxes[2]),
+                axis(&boxes[3]),
+            )
+        };
+        let slab = |min: F64x4, max: F64x4, origin: f64, direction: f64| {
+            let inverse = 1.0 / direction;
+            let (near, far) = if inverse < 0.0 {
+                (max, min)
+            } else {
+                (min, max)
+            };
+            let (origin, inverse) =
+                (F64x4::splat(origin), F64x4::splat(inverse));
+            ((near - origin) * inverse, (far - origin) * inverse)
+        };
+
+        let (txmin, txmax) = slab(
+            lanes(|b| b.min.x),
+            lanes(|b| b.max.x),
+            ray.origin.x,
+            ray.direction.x,
+        );
+        let (tymin, tymax) = slab(
+            lanes(|b| b.min.y),
+            lanes(|b| b.max.y),
+            ray.origin.y,
+            ray.direction.y,
+        );
+        let (tzmin, tzmax) = slab(
+            lanes(|b| b.min.z),
+            lanes(|b| b.max.z),
+            ray.origin.z,
+            ray.direction.z,
+        );
+
+        let tmin = txmin.max(F64x4::splat(-INFINITY)).max(tymin).max(tzmin);
+        let tmax = txmax.min(F64x4::splat(INFINITY)).min(tymax).min(tzmax);
+        let (tmin, tmax) = (tmin.to_array(), tmax.to_array());
+
+        let mut entries = [None; 4];
+        for i in 0..4 {
+            if tmin[i] <= tmax[i] {
+                entries[i] =
+                    Some(if tmin[i] < 0.0 { tmax[i] } else { tmin[i] });
+            }
+        }
+        entries
+    }
+}
+
+impl Primitive for Aabb {
+    fn intersects(&self, ray: Ray) -> Option<Intersection> {
+        let t = self.entry(ray)?;
         let bias = 1.000_001;
 
         let p = (ray.origin + ray.direction * t) - self.center();
@@ -83,6 +185,10 @@ impl Primitive for Aabb {
 
         Some(Intersection::new(t, n))
     }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(*self)
+    }
 }
 
 #[cfg(test)]
@@ -102,10 +208,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn entering_four_boxes() {
+        let boxes = [
+            Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 2.0, 1.0)),
+            Aabb::new(Vec3::new(1.0, 0.0, 0.0), Vec3::new(2.0, 1.0, 1.0)),
+            Aabb::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.5, 2.0)),
+            Aabb::new(Vec3::new(1.0, 0.0, 1.0), Vec3::new(2.0, 3.0, 2.0)),
+        ];
+        // Rays along axes and edges, from inside and outside the boxes
+        let rays = [
+            Ray::new(Vec3::new(0.5, 5.0, 0.5), Vec3::new(0.0, -1.0, 0.0)),
+            Ray::new(Vec3::new(-1.0, 0.5, 1.0), Vec3::new(1.0, 0.0, 0.0)),
+            Ray::new(Vec3::new(1.5, 0.5, 1.5), Vec3::new(0.0, 0.0, -1.0)),
+            Ray::new(
+                Vec3::new(3.0, 4.0, -1.0),
+                Vec3::normalize(Vec3::new(-1.0, -1.5, 1.0)),
+            ),
+            Ray::new(Vec3::new(1.0, 1.0, 1.0), Vec3::new(1.0, 0.0, 0.0)),
+        ];
+        for ray in &rays {
+            let entries = Aabb::entries(&boxes, *ray);
+            for (aabb, entry) in boxes.iter().zip(&entries) {
+                assert_eq!(*entry, aabb.entry(*ray));
+            }
+        }
+        let entries = Aabb::entries(&boxes, rays[0]);
+        assert_eq!(entries, [Some(3.0), None, None, None]);
+    }
+
     #[test]
     fn aabb_center() {
         let aabb =
             Aabb::new(Vec3::new(2.5, 2.5, 2.5), Vec3::new(7.5, 7.5, 7.5));
         assert_eq!(aabb.center(), Vec3::new(5.0, 5.0, 5.0));
     }
+
+    #[test]
+    fn aabb_union() {
+        let a = Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        let b = Aabb::enclosing(&[
+            Vec3::new(2.0, -1.0, 0.5),
+            Vec3::new(3.0, 0.5, 0.0),
+        ]);
+        assert_eq!(
+            a.union(&b),
+            Aabb::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(3.0, 1.0, 1.0))
+        );
+    }
 }
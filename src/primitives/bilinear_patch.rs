@@ -13,10 +13,45 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
+use super::aabb::Aabb;
 use super::primitive::{Intersection, Primitive};
-use math::{Ray, Vec3};
+use math::{F64x4, Ray, Vec3};
 use options::BilinearPatchOpts;
 
+/// Distance in the parameters of a patch by which an intersection may fall
+/// outside of it, so rays through the seam of two patches hit one of them
+const EDGE_EPSILON: f64 = 1e-9;
+
+/// Return the index of the largest component of a vector
+fn dominant_axis(v: Vec3) -> usize {
+    let (x, y, z) = (v.x.abs(), v.y.abs(), v.z.abs());
+    if x > y && x > z {
+        0
+    } else if y > z {
+        1
+    } else {
+        2
+    }
+}
+
+/// Return the component of a vector along an axis
+fn component(v: Vec3, axis: usize) -> f64 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+/// Rotate the axes of a vector so its component along an axis comes last
+fn rotate(v: Vec3, axis: usize) -> Vec3 {
+    match axis {
+        0 => Vec3::new(v.y, v.z, v.x),
+        1 => Vec3::new(v.z, v.x, v.y),
+        _ => v,
+    }
+}
+
 pub struct BilinearPatch {
     p00: Vec3,
     p01: Vec3,
@@ -42,20 +77,31 @@ impl BilinearPatch {
         BilinearPatch { p00, p01, p10, p11 }
     }
 
-    /// Return a corresponding `u` value for `v` picking the best denominator
-    fn compute_u(&self, v: f64, vars: Variables) -> f64 {
-        let denom1 = v * (vars.a2 - vars.a1) + vars.b2 - vars.b1;
-        let denom2 = v * vars.a2 + vars.b2;
-        if denom1.abs() > denom2.abs() {
-            return (v * (vars.c1 - vars.c2) + vars.d1 - vars.d2) / denom1;
-        }
-        -(v * vars.c2 + vars.d2) / denom2
+    /// Return corresponding `u` values for two values of `v`, picking the
+    /// best denominator for each, solving both at once
+    fn compute_u(&self, v: [f64; 2], vars: Variables) -> [f64; 2] {
+        // The first two lanes solve with the first denominator, and the last
+        // two with the second. Adding -0 and subtracting 0 leave a value
+        // unchanged, so each lane rounds as its scalar expression would
+        let v = F64x4::new(v[0], v[1], v[0], v[1]);
+        let (a, b, c) = (vars.a2 - vars.a1, vars.b2, vars.c1 - vars.c2);
+        let denom = v * F64x4::new(a, a, vars.a2, vars.a2) + F64x4::splat(b)
+            - F64x4::new(vars.b1, vars.b1, 0.0, 0.0);
+        let numer = v * F64x4::new(c, c, -vars.c2, -vars.c2)
+            + F64x4::new(vars.d1, vars.d1, -0.0, -0.0)
+            - F64x4::splat(vars.d2);
+        let u = numer / denom;
+        let denom = denom.abs();
+        let u = denom.gt(denom.swap_halves()).select(u, u.swap_halves());
+        let u = u.to_array();
+        [u[0], u[1]]
     }
 
-    /// Return a value for `t` along the ray for a position on the surface
-    fn compute_t(&self, ray: Ray, position: Vec3) -> f64 {
-        let ts = (position - ray.origin) / ray.direction;
-        ts.x.min(ts.y).min(ts.z)
+    /// Return a value for `t` along the ray for a position on the surface,
+    /// measured along the dominant axis of the ray
+    fn compute_t(&self, ray: Ray, position: Vec3, axis: usize) -> f64 {
+        let offset = component(position - ray.origin, axis);
+        offset / component(ray.direction, axis)
     }
 
     /// Return the 3d position for a 2d point on the surface
@@ -104,35 +150,42 @@ impl BilinearPatch {
         Vec3::normalize(Vec3::cross(tan_u, tan_v))
     }
 
-    /// Return possible solutions for `v`
-    fn solutions(&self, a: f64, b: f64, c: f64) -> Vec<f64> {
+    /// Return possible solutions for `v`, and how many of them there are
+    fn solutions(&self, a: f64, b: f64, c: f64) -> ([f64; 2], usize) {
         if a == 0.0 && b != 0.0 {
-            return vec![-c / b];
+            return ([-c / b, 0.0], 1);
         } else if a == 0.0 {
-            return vec![];
+            return ([0.0; 2], 0);
         }
 
         let d = b * b - 4.0 * a * c;
         if d == 0.0 {
-            return vec![-b / a];
+            return ([-b / a, 0.0], 1);
         } else if d < 0.0 {
-            return vec![];
+            return ([0.0; 2], 0);
         }
 
         let b_sign = if b < 0.0 { -1.0 } else { 1.0 };
         let q = -0.5 * (b + d.sqrt() * b_sign);
-        return vec![c / q, q / a];
+        ([c / q, q / a], 2)
     }
 
-    /// Solve the intersection with `v`
-    fn solve(&self, ray: Ray, v: f64, vars: Variables) -> Option<Intersection> {
-        let u = self.compute_u(v, vars);
-        let p = self.position(u, v);
-        let t = self.compute_t(ray, p);
+    /// Solve the intersection at `u` and `v`, returning `t` and `u`
+    fn solve(
+        &self,
+        ray: Ray,
+        u: f64,
+        v: f64,
+        axis: usize,
+    ) -> Option<(f64, f64)> {
+        if u < -EDGE_EPSILON || u > 1.0 + EDGE_EPSILON {
+            return None;
+        }
 
-        if t > 0.0 && u <= 1.0 && u >= 0.0 {
-            let normal = self.normal(u, v);
-            return Some(Intersection::new(t, normal));
+        let u = u.max(0.0).min(1.0);
+        let t = self.compute_t(ray, self.position(u, v), axis);
+        if t > 0.0 {
+            return Some((t, u));
         }
 
         None
@@ -152,21 +205,37 @@ impl From<BilinearPatchOpts> for BilinearPatch {
 
 impl Primitive for BilinearPatch {
     fn intersects(&self, ray: Ray) -> Option<Intersection> {
+        // Solve in the planes containing the dominant axis of the ray, as
+        // rays parallel to both planes, such as along the `y` axis, would
+        // otherwise leave no equations to solve
+        let axis = dominant_axis(ray.direction);
         let vars = {
-            let a = self.p11 - self.p10 - self.p01 + self.p00;
-            let b = self.p10 - self.p00;
-            let c = self.p01 - self.p00;
-            let d = self.p00 - ray.origin;
+            let a = rotate(self.p11 - self.p10 - self.p01 + self.p00, axis);
+            let b = rotate(self.p10 - self.p00, axis);
+            let c = rotate(self.p01 - self.p00, axis);
+            let d = rotate(self.p00 - ray.origin, axis);
+            let dir = rotate(ray.direction, axis);
+
+            // Cross the corners with the ray in both planes at once
+            let xs = F64x4::new(a.x, b.x, c.x, d.x);
+            let ys = F64x4::new(a.y, b.y, c.y, d.y);
+            let zs = F64x4::new(a.z, b.z, c.z, d.z);
+            let [a1, b1, c1, d1] = (xs * F64x4::splat(dir.z)
+                - zs * F64x4::splat(dir.x))
+            .to_array();
+            let [a2, b2, c2, d2] = (ys * F64x4::splat(dir.z)
+                - zs * F64x4::splat(dir.y))
+            .to_array();
 
             Variables {
-                a1: a.x * ray.direction.z - a.z * ray.direction.x,
-                a2: a.y * ray.direction.z - a.z * ray.direction.y,
-                b1: b.x * ray.direction.z - b.z * ray.direction.x,
-                b2: b.y * ray.direction.z - b.z * ray.direction.y,
-                c1: c.x * ray.direction.z - c.z * ray.direction.x,
-                c2: c.y * ray.direction.z - c.z * ray.direction.y,
-                d1: d.x * ray.direction.z - d.z * ray.direction.x,
-                d2: d.y * ray.direction.z - d.z * ray.direction.y,
+                a1,
+                a2,
+                b1,
+                b2,
+                c1,
+                c2,
+                d1,
+                d2,
             }
         };
 
@@ -176,19 +245,33 @@ impl Primitive for BilinearPatch {
                 vars.b2 * vars.c1 - vars.b1 * vars.c2;
         let c = vars.b2 * vars.d1 - vars.b1 * vars.d2;
 
-        // Find the closest intersection for the possible solutions of `v`
-        self.solutions(a, b, c)
-            .into_iter()
-            .filter(|v| *v >= 0.0 && *v <= 1.0)
-            .filter_map(|v| self.solve(ray, v, vars))
-            .fold(Intersection::none(), |closest, current| {
-                if current.t < closest.t {
-                    current
-                } else {
-                    closest
+        // Find the closest intersection for the possible solutions of `v`,
+        // only computing the normal of the closest
+        let (solutions, count) = self.solutions(a, b, c);
+        let v = [
+            solutions[0].max(0.0).min(1.0),
+            solutions[1].max(0.0).min(1.0),
+        ];
+        let u = self.compute_u(v, vars);
+        let mut closest: Option<(f64, f64, f64)> = None;
+        for i in 0..count {
+            if solutions[i] < -EDGE_EPSILON || solutions[i] > 1.0 + EDGE_EPSILON
+            {
+                continue;
+            }
+            if let Some((t, u)) = self.solve(ray, u[i], v[i], axis) {
+                match closest {
+                    Some((closest_t, _, _)) if closest_t <= t => (),
+                    _ => closest = Some((t, u, v[i])),
                 }
-            })
-            .to_option()
+            }
+        }
+
+        closest.map(|(t, u, v)| Intersection::new(t, self.normal(u, v)))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::enclosing(&[self.p00, self.p01, self.p10, self.p11]))
     }
 }
 
@@ -209,7 +292,7 @@ mod tests {
         let direction = Vec3::normalize(Vec3::new(0.100499, 0.0, -0.994937));
         let ray = Ray::new(Vec3::new(1.0, 0.3, 10.0), direction);
         let hit = patch.intersects(ray).unwrap();
-        assert_eq!(hit.t, 7.583153100172977);
+        assert!((hit.t - 7.583153100172977).abs() < 1e-12);
         assert_eq!(
             hit.normal,
             Vec3::new(
@@ -0,0 +1,398 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use super::aabb::Aabb;
+use super::height_map::HeightMap;
+use super::primitive::{Intersection, Primitive};
+
+#[cfg(feature = "gdal")]
+use io::ogr;
+#[cfg(feature = "gdal")]
+use io::osm::{self, TagFilter};
+#[cfg(feature = "gdal")]
+use io::other;
+use io::{cache, package};
+use math::{Ray, Vec3};
+use options::{ExtrusionOpts, Loader};
+use serde_json;
+use shapes::{Polygon, Shape};
+
+use std::f64::{INFINITY, NEG_INFINITY};
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+
+/// A vertical quad standing on the line between two base points
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Wall {
+    a: Vec3,
+    b: Vec3,
+    /// Heights of the top of the wall above `a` and `b`
+    top: (f64, f64),
+}
+
+impl Wall {
+    fn intersects(&self, ray: Ray) -> Option<Intersection> {
+        let edge = self.b - self.a;
+        let normal = Vec3::normalize(Vec3::new(edge.z, 0.0, -edge.x));
+        let denominator = Vec3::dot(ray.direction, normal);
+        if denominator == 0.0 {
+            return None;
+        }
+
+        let t = Vec3::dot(self.a - ray.origin, normal) / denominator;
+        if t <= 0.0 {
+            return None;
+        }
+
+        let p = ray.origin + ray.direction * t;
+        let u = ((p.x - self.a.x) * edge.x + (p.z - self.a.z) * edge.z)
+            / (edge.x * edge.x + edge.z * edge.z);
+        if u < 0.0 || u > 1.0 {
+            return None;
+        }
+
+        let bottom = self.a.y + (self.b.y - self.a.y) * u;
+        let top = self.top.0 + (self.top.1 - self.top.0) * u;
+        if p.y < bottom || p.y > top {
+            return None;
+        }
+
+        Some(Intersection::new(t, normal))
+    }
+}
+
+/// The walls, and roof for polygons, extruded from a single shape
+struct Solid {
+    bounds: Aabb,
+    walls: Vec<Wall>,
+    roof: Option<(Polygon, f64)>,
+}
+
+impl Solid {
+    fn new(shape: &Shape, height: f64) -> Option<Solid> {
+        let lines = shape.lines();
+        let roof = match *shape {
+            Shape::Polygon(ref polygon) => {
+                let base = lines
+                    .iter()
+                    .flat_map(|line| line.iter())
+                    .fold(NEG_INFINITY, |base, point| base.max(point.y));
+                Some((polygon.clone(), base + height))
+            }
+            _ => None,
+        };
+
+        let mut walls = vec![];
+        let mut min = Vec3::new(INFINITY, INFINITY, INFINITY);
+        let mut max = Vec3::new(NEG_INFINITY, NEG_INFINITY, NEG_INFINITY);
+        for line in lines {
+            for pair in line.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                if a.x == b.x && a.z == b.z {
+                    continue;
+                }
+                let top = match roof {
+                    Some((_, roof)) => (roof, roof),
+                    None => (a.y + height, b.y + height),
+                };
+                for &(point, top) in &[(a, top.0), (b, top.1)] {
+                    min = Vec3::new(
+                        min.x.min(point.x),
+                        min.y.min(point.y),
+                        min.z.min(point.z),
+                    );
+                    max = Vec3::new(
+                        max.x.max(point.x),
+                        max.y.max(top),
+                        max.z.max(point.z),
+                    );
+                }
+                walls.push(Wall { a, b, top });
+            }
+        }
+
+        if walls.is_empty() || height <= 0.0 {
+            return None;
+        }
+
+        Some(Solid {
+            bounds: Aabb::new(min, max),
+            walls,
+            roof,
+        })
+    }
+
+    fn intersects(&self, ray: Ray, visited: &mut usize) -> Intersection {
+        let mut nearest = Intersection::none();
+        for wall in &self.walls {
+            *visited += 1;
+            if let Some(hit) = wall.intersects(ray) {
+                if hit.t < nearest.t {
+                    nearest = hit;
+                }
+            }
+        }
+
+        if let Some((ref polygon, roof)) = self.roof {
+            let t = (roof - ray.origin.y) / ray.direction.y;
+            if t > 0.0 && t < nearest.t {
+                let p = ray.origin + ray.direction * t;
+                if polygon.contains(p) {
+                    nearest = Intersection::new(t, Vec3::new(0.0, 1.0, 0.0));
+                }
+            }
+        }
+
+        nearest
+    }
+}
+
+/// Vector shapes extruded vertically into walls, with flat roofs on polygons
+pub struct Extrusion {
+    solids: Vec<Solid>,
+}
+
+impl Extrusion {
+    /// Create an extrusion from shapes, with vertices at the height of their
+    /// base, paired with the height of their walls
+    pub fn new(shapes: &[(Shape, f64)]) -> Extrusion {
+        Extrusion {
+            solids: shapes
+                .iter()
+                .filter_map(|&(ref shape, height)| Solid::new(shape, height))
+                .collect(),
+        }
+    }
+
+    fn traverse(&self, ray: Ray, visited: &mut usize) -> Intersection {
+        let mut nearest = Intersection::none();
+        for solid in &self.solids {
+            *visited += 1;
+            if solid.bounds.intersects(ray).is_none() {
+                continue;
+            }
+            let hit = solid.intersects(ray, visited);
+            if hit.t < nearest.t {
+                nearest = hit;
+            }
+        }
+        nearest
+    }
+}
+
+/// Read shapes with the numeric value of an attribute of each, if it has one
+#[cfg_attr(not(feature = "gdal"), allow(unused_variables))]
+pub fn attribute_values(
+    data: &Loader,
+    field: &str,
+) -> Result<Vec<(Shape, Option<f64>)>> {
+    match *data {
+        #[cfg(feature = "gdal")]
+        Loader::Shp(ref opts) => {
+            let bounds = opts.bounds.as_ref().map(|b| b.dataset());
+            let shapes = try!(ogr::import_attribute(
+                &opts.filepath,
+                &opts.layer,
+                field,
+                bounds,
+            )
+            .map_err(other));
+            Ok(shapes
+                .into_iter()
+                .map(|(shape, value)| {
+                    let shape = match opts.simplify {
+                        Some(tolerance) => shape.simplify(tolerance),
+                        None => shape,
+                    };
+                    let shape = match opts.densify {
+                        Some(spacing) => shape.densify(spacing),
+                        None => shape,
+                    };
+                    (shape, value)
+                })
+                .collect())
+        }
+        #[cfg(feature = "gdal")]
+        Loader::Osm(ref opts) => {
+            let bounds = opts.bounds.as_ref().map(|b| b.dataset());
+            let filter = TagFilter::new(&opts.filter);
+            let shapes =
+                try!(osm::import(&opts.filepath, &opts.layer, &filter, bounds)
+                    .map_err(other));
+            Ok(shapes
+                .into_iter()
+                .map(|(shape, tags)| {
+                    // Heights are tagged in metres, sometimes with units
+                    let value = tags
+                        .get(field)
+                        .and_then(|value| value.split_whitespace().next())
+                        .and_then(|value| value.parse().ok());
+                    (shape, value)
+                })
+                .collect())
+        }
+        // Packages hold the values read by `attribute_values` when baked
+        Loader::Baked(ref opts) => {
+            let bytes = try!(package::open_entry(&opts.package, &opts.entry));
+            Ok(try!(serde_json::from_slice(&bytes)))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            "Heights can only be read from attributes of shp or osm data",
+        )),
+    }
+}
+
+/// Read the shapes of an extrusion with their heights, from an attribute of
+/// each if one is given
+fn load(options: &ExtrusionOpts) -> Result<Vec<(Shape, f64)>> {
+    let height = options.height;
+    Ok(match options.attribute {
+        Some(ref field) => try!(attribute_values(&options.data, field))
+            .into_iter()
+            .map(|(shape, value)| (shape, value.unwrap_or(height)))
+            .collect(),
+        None => try!(cache::shapes(&options.data))
+            .iter()
+            .map(|shape| (shape.clone(), height))
+            .collect(),
+    })
+}
+
+impl From<ExtrusionOpts> for Extrusion {
+    fn from(options: ExtrusionOpts) -> Extrusion {
+        let shapes = load(&options).unwrap();
+        let shapes = match options.terrain {
+            Some(terrain) => {
+                let terrain = HeightMap::from(terrain);
+                shapes
+                    .iter()
+                    .map(|&(ref shape, height)| {
+                        (shape.map(|p| terrain.drape(p)), height)
+                    })
+                    .collect()
+            }
+            None => shapes,
+        };
+
+        Extrusion::new(&shapes)
+    }
+}
+
+impl Primitive for Extrusion {
+    fn intersects(&self, ray: Ray) -> Option<Intersection> {
+        self.traverse(ray, &mut 0).to_option()
+    }
+
+    fn cost(&self, ray: Ray) -> usize {
+        let mut visited = 0;
+        self.traverse(ray, &mut visited);
+        visited
+    }
+
+    fn memory(&self) -> usize {
+        self.solids
+            .iter()
+            .map(|solid| {
+                let roof = match solid.roof {
+                    Some((ref polygon, _)) => polygon.memory(),
+                    None => 0,
+                };
+                mem::size_of::<Solid>()
+                    + solid.walls.len() * mem::size_of::<Wall>()
+                    + roof
+            })
+            .sum()
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.solids.iter().map(|solid| solid.bounds).fold(
+            None,
+            |bounds, next| match bounds {
+                Some(bounds) => Some(next.union(&bounds)),
+                None => Some(next),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shapes::{LineString, Ring};
+
+    fn square() -> Shape {
+        Shape::Polygon(Polygon::new(
+            Ring::new(vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(2.0, 1.0, 2.0),
+                Vec3::new(0.0, 1.0, 2.0),
+                Vec3::new(0.0, 0.0, 0.0),
+            ]),
+            vec![],
+        ))
+    }
+
+    #[test]
+    fn unsupported_loaders() {
+        let options: ExtrusionOpts = serde_json::from_str(
+            r#"{
+                "data": {"type": "png", "filepath": "heights.png"},
+                "attribute": "height"
+            }"#,
+        )
+        .unwrap();
+        let err = load(&options).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let options = ExtrusionOpts {
+            attribute: None,
+            ..options
+        };
+        assert_eq!(load(&options).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn extruded_walls() {
+        let fence = Shape::LineString(LineString::new(vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(4.0, 4.0, 0.0),
+        ]));
+        let extrusion = Extrusion::new(&[(fence, 1.0)]);
+
+        let ray = Ray::new(Vec3::new(1.0, 1.5, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let hit = extrusion.intersects(ray).unwrap();
+        assert_eq!(hit.t, 5.0);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, -1.0));
+
+        let ray = Ray::new(Vec3::new(1.0, 2.5, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(extrusion.intersects(ray), None);
+    }
+
+    #[test]
+    fn extruded_roofs() {
+        let extrusion = Extrusion::new(&[(square(), 2.0)]);
+
+        let ray =
+            Ray::new(Vec3::new(1.0, 10.0, 1.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit = extrusion.intersects(ray).unwrap();
+        assert_eq!(hit.t, 7.0);
+        assert_eq!(hit.normal, Vec3::new(0.0, 1.0, 0.0));
+
+        let ray = Ray::new(Vec3::new(1.0, 2.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(extrusion.intersects(ray).unwrap().t, 5.0);
+    }
+}
@@ -17,14 +17,47 @@ use super::aabb::Aabb;
 use super::bilinear_patch::BilinearPatch;
 use super::primitive::{Intersection, Primitive};
 
-use io::gdal;
+use chunks::ChunkSource;
+#[cfg(feature = "gdal")]
+use io::gdal::RasterReader;
+#[cfg(feature = "gdal")]
+use io::other;
+use io::{cache, package, raster};
 use math::{AffineTransform, Ray, Vec3};
-use ops::{blit, height_map_to_bilinear_patch, maximum_mipmap_bilinear_patch};
-use options::{HeightMapOpts, Loader};
-use textures::Texture;
+use ops::{
+    apply_curvature, blit, height_map_to_bilinear_patch,
+    maximum_mipmap_bilinear_patch,
+};
+#[cfg(feature = "gdal")]
+use options::GdalLoader;
+use options::{BakedLoader, HeightMapOpts, Loader};
 use shapes::Rect;
+use textures::{QuantizedTexture, Texture};
 
 use std::cmp;
+use std::f64::INFINITY;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+use std::slice;
+
+/// Height from which points are dropped onto the surface when draping
+const DRAPE_HEIGHT: f64 = 1.0e7;
+
+/// Distance in pixels within which a ray is considered to lie on a seam
+const SEAM: f64 = 1.0e-6;
+
+/// Number of rays traversing the quadtree together, one for each bit of a
+/// mask
+const PACKET_SIZE: usize = 64;
+
+/// Number of rows of a raster read at once when streaming a height map
+#[cfg(feature = "gdal")]
+const STREAM_ROWS: usize = 256;
+
+/// Identifies a file of cached acceleration data, and the version of its
+/// layout
+const CACHE_MAGIC: &[u8; 8] = b"PEAKSHM1";
 
 fn ceil_pow2(num: usize) -> usize {
     let num = num as f64;
@@ -32,6 +65,61 @@ fn ceil_pow2(num: usize) -> usize {
     2.0_f64.powf(exp) as usize
 }
 
+/// Levels of maximum heights over the quadtree, either as computed or
+/// quantized to save memory
+pub enum MaximumMipmaps {
+    Full(Vec<Texture<f64>>),
+    Quantized(Vec<QuantizedTexture>),
+}
+
+impl MaximumMipmaps {
+    pub fn len(&self) -> usize {
+        match *self {
+            MaximumMipmaps::Full(ref levels) => levels.len(),
+            MaximumMipmaps::Quantized(ref levels) => levels.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read the maximum height of a node in a level
+    pub fn lookup1x1(&self, level: usize, x: usize, y: usize) -> f64 {
+        match *self {
+            MaximumMipmaps::Full(ref levels) => levels[level].lookup1x1(x, y),
+            MaximumMipmaps::Quantized(ref levels) => {
+                levels[level].lookup1x1(x, y)
+            }
+        }
+    }
+
+    /// Return the number of bytes held by all levels
+    pub fn memory(&self) -> usize {
+        match *self {
+            MaximumMipmaps::Full(ref levels) => {
+                levels.iter().map(|level| level.memory()).sum()
+            }
+            MaximumMipmaps::Quantized(ref levels) => {
+                levels.iter().map(|level| level.memory()).sum()
+            }
+        }
+    }
+}
+
+/// Create the levels of maximum mipmaps above the first
+fn mipmaps(level0: Texture<f64>) -> MaximumMipmaps {
+    let mut size = level0.width;
+    let mut levels = vec![level0];
+    while size > 1 {
+        size /= 2;
+        let mut next = Texture::blank(size, size);
+        maximum_mipmap_bilinear_patch(&levels[levels.len() - 1], &mut next);
+        levels.push(next);
+    }
+    MaximumMipmaps::Full(levels)
+}
+
 pub struct HeightMap {
     pub rect: Rect,
     /// A transform from world space coordinates to raster space
@@ -39,7 +127,13 @@ pub struct HeightMap {
     /// Map containing bilinear patches
     pub bilinear_patches: Texture<[f64; 4]>,
     /// Maximum mipmaps for the bilinear patches
-    pub maximum_mipmaps: Vec<Texture<f64>>,
+    pub maximum_mipmaps: MaximumMipmaps,
+    /// Factor the stored heights are multiplied by when intersected, leaving
+    /// the quadtree and any cache of it unchanged
+    pub exaggeration: f64,
+    /// Projected size in pixels below which a node of the quadtree is
+    /// intersected as one patch fitted to its corners
+    pub lod: Option<f64>,
 }
 
 impl HeightMap {
@@ -49,7 +143,7 @@ impl HeightMap {
     ) -> HeightMap {
         // Round the height map size to the nearest power of two
         let height_map_size = height_map.width.max(height_map.height);
-        let mut size = ceil_pow2(height_map_size);
+        let size = ceil_pow2(height_map_size);
 
         // Create a new height map with the size of n^2+1 and blit the original
         let mut height_map2 = Texture::blank(size + 1, size + 1);
@@ -64,66 +158,546 @@ impl HeightMap {
             &mut bilinear_patches_mipmap0,
         );
 
-        // Create the subsequent mipmap levels from level0
-        let mut maximum_mipmaps = vec![bilinear_patches_mipmap0];
-        while size > 1 {
-            size /= 2;
-            let mut next = Texture::blank(size, size);
-            {
-                let previous = &maximum_mipmaps[maximum_mipmaps.len() - 1];
-                maximum_mipmap_bilinear_patch(previous, &mut next);
-            }
-            maximum_mipmaps.push(next);
+        HeightMap {
+            rect: surface_rect(&transform, height_map.width, height_map.height),
+            transform,
+            bilinear_patches,
+            maximum_mipmaps: mipmaps(bilinear_patches_mipmap0),
+            exaggeration: 1.0,
+            lod: None,
         }
+    }
+
+    /// Build a height map from a raster read in strips of a number of rows,
+    /// so that the whole raster is never held in memory
+    pub fn stream<S>(
+        transform: AffineTransform,
+        source: &mut S,
+        rows: usize,
+    ) -> Result<HeightMap>
+    where
+        S: ChunkSource<f64>,
+    {
+        let (width, height) = source.size();
+        let size = ceil_pow2(width.max(height));
+        let rows = rows.max(1);
 
-        let rect = {
-            let width = height_map.width;
-            let depth = height_map.height;
+        let mut bilinear_patches = Texture::blank(size, size);
+        let mut bilinear_patches_mipmap0 = Texture::blank(size, size);
+        let mut y = 0;
+        while y < height {
+            // Each strip reads the first row of the next for its last patches
+            let strip = cmp::min(rows, height - y);
+            let chunk =
+                try!(source.read(0, y, width, cmp::min(strip + 1, height - y)));
+            let mut padded = Texture::blank(size + 1, strip + 1);
+            blit(&chunk, &mut padded, 0, 0);
 
-            let (x0, z0) = transform.forward(0.0, 0.0);
-            let (x1, _) = transform.forward(width as f64, 0.0);
-            let (_, z1) = transform.forward(width as f64, depth as f64);
-            let (_, _) = transform.forward(0.0, depth as f64);
+            let mut patches = Texture::blank(size, strip);
+            let mut mipmap0 = Texture::blank(size, strip);
+            height_map_to_bilinear_patch(&padded, &mut patches, &mut mipmap0);
+            blit(&patches, &mut bilinear_patches, 0, y);
+            blit(&mipmap0, &mut bilinear_patches_mipmap0, 0, y);
+            y += strip;
+        }
 
-            Rect::new(
-                Vec3::new(x0, 0.0, z0),
-                Vec3::new(x1, 0.0, z0),
-                Vec3::new(x1, 0.0, z1),
-                Vec3::new(x0, 0.0, z1),
-            )
+        Ok(HeightMap {
+            rect: surface_rect(&transform, width, height),
+            transform,
+            bilinear_patches,
+            maximum_mipmaps: mipmaps(bilinear_patches_mipmap0),
+            exaggeration: 1.0,
+            lod: None,
+        })
+    }
+
+    /// Store the maximum mipmaps as 16 bit steps between the lowest and
+    /// highest of their heights, rounded up so the quadtree still bounds the
+    /// surface
+    pub fn quantize(&mut self) {
+        let levels = match self.maximum_mipmaps {
+            MaximumMipmaps::Full(ref levels) if !levels.is_empty() => levels,
+            _ => return,
         };
+        let min = levels[0].buffer.iter().cloned().fold(INFINITY, f64::min);
+        let max = levels[levels.len() - 1].buffer[0];
+        let quantized = levels
+            .iter()
+            .map(|level| QuantizedTexture::new(level, min, max))
+            .collect();
+        self.maximum_mipmaps = MaximumMipmaps::Quantized(quantized);
+    }
 
-        HeightMap {
-            rect,
+    /// Return a point moved vertically onto the surface, or unchanged if it
+    /// lies outside of it
+    pub fn drape(&self, point: Vec3) -> Vec3 {
+        let origin = Vec3::new(point.x, DRAPE_HEIGHT, point.z);
+        let ray = Ray::new(origin, Vec3::new(0.0, -1.0, 0.0));
+        match self.intersects(ray) {
+            Some(hit) => ray.origin + ray.direction * hit.t,
+            None => point,
+        }
+    }
+
+    /// Return the patch under a world position, with the position within it
+    /// from zero to one on each axis
+    fn locate(&self, x: f64, z: f64) -> Option<([f64; 4], f64, f64)> {
+        if !self.rect.contains(Vec3::new(x, 0.0, z)) {
+            return None;
+        }
+        let (u, v) = self.transform.inverse(x, z);
+        let last = |size: usize| (size - 1) as f64;
+        let px = u.floor().max(0.0).min(last(self.bilinear_patches.width));
+        let py = v.floor().max(0.0).min(last(self.bilinear_patches.height));
+        let [nw, ne, se, sw] =
+            self.bilinear_patches.lookup1x1(px as usize, py as usize);
+        let e = self.exaggeration;
+        Some(([nw * e, ne * e, se * e, sw * e], u - px, v - py))
+    }
+
+    /// Return the height of the surface at a world position, or nothing
+    /// outside of it
+    pub fn elevation(&self, x: f64, z: f64) -> Option<f64> {
+        let ([nw, ne, se, sw], s, t) = self.locate(x, z)?;
+        let north = nw + (ne - nw) * s;
+        let south = sw + (se - sw) * s;
+        Some(north + (south - north) * t)
+    }
+
+    /// Return the rate of change in height of the surface along the `x` and
+    /// `z` axes at a world position, or nothing outside of it
+    pub fn gradient(&self, x: f64, z: f64) -> Option<(f64, f64)> {
+        let ([nw, ne, se, sw], s, t) = self.locate(x, z)?;
+        let du = (ne - nw) * (1.0 - t) + (se - sw) * t;
+        let dv = (sw - nw) * (1.0 - s) + (se - ne) * s;
+        let [_, _, a, d] = self.transform.coefficients();
+        Some((du / a, dv / d))
+    }
+}
+
+/// Return the rectangle covered by a height map of a size in world space
+fn surface_rect(
+    transform: &AffineTransform,
+    width: usize,
+    depth: usize,
+) -> Rect {
+    let (x0, z0) = transform.forward(0.0, 0.0);
+    let (x1, z1) = transform.forward(width as f64, depth as f64);
+    Rect::new(
+        Vec3::new(x0, 0.0, z0),
+        Vec3::new(x1, 0.0, z0),
+        Vec3::new(x1, 0.0, z1),
+        Vec3::new(x0, 0.0, z1),
+    )
+}
+
+/// Return a hash of heights and their transform, identifying the source of
+/// cached acceleration data
+pub fn content_hash(
+    transform: &AffineTransform,
+    heights: &Texture<f64>,
+) -> u64 {
+    // 64 bit FNV-1a
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut feed = |value: u64| {
+        for byte in &value.to_le_bytes() {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+    feed(heights.width as u64);
+    feed(heights.height as u64);
+    for value in transform.coefficients().iter().chain(&heights.buffer) {
+        feed(value.to_bits());
+    }
+    hash
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut bytes = [0; 8];
+    try!(reader.read_exact(&mut bytes));
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64<R: Read>(reader: &mut R) -> Result<f64> {
+    Ok(f64::from_bits(try!(read_u64(reader))))
+}
+
+/// Write the size of a texture followed by the components of its values
+fn write_texture<W, T, F>(
+    writer: &mut W,
+    texture: &Texture<T>,
+    components: F,
+) -> Result<()>
+where
+    W: Write,
+    T: Copy + Default,
+    F: Fn(&T) -> &[f64],
+{
+    try!(write_u64(writer, texture.width as u64));
+    try!(write_u64(writer, texture.height as u64));
+    for value in &texture.buffer {
+        for component in components(value) {
+            try!(write_u64(writer, component.to_bits()));
+        }
+    }
+    Ok(())
+}
+
+/// Read a texture written by `write_texture`, reading each value of a number
+/// of components with a function, from no more than a length of bytes
+fn read_texture<R, T, F>(
+    reader: &mut R,
+    length: u64,
+    components: usize,
+    mut read: F,
+) -> Result<Texture<T>>
+where
+    R: Read,
+    T: Copy + Default,
+    F: FnMut(&mut R) -> Result<T>,
+{
+    let width = try!(read_u64(reader)) as usize;
+    let height = try!(read_u64(reader)) as usize;
+    let bytes = width
+        .checked_mul(height)
+        .and_then(|count| count.checked_mul(components * 8));
+    match bytes {
+        Some(bytes) if bytes as u64 <= length => (),
+        _ => {
+            let message = "Texture is larger than the data it is read from";
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+    }
+    let mut buffer = Vec::with_capacity(width * height);
+    for _ in 0..width * height {
+        buffer.push(try!(read(reader)));
+    }
+    Ok(Texture::new(width, height, buffer))
+}
+
+impl HeightMap {
+    /// Write the acceleration structures, with the size of the heights they
+    /// were built from and a hash identifying them
+    pub fn save<W: Write>(
+        &self,
+        writer: &mut W,
+        size: (usize, usize),
+        hash: u64,
+    ) -> Result<()> {
+        let levels = match self.maximum_mipmaps {
+            MaximumMipmaps::Full(ref levels) => levels,
+            MaximumMipmaps::Quantized(_) => {
+                let message = "Quantized height maps cannot be saved";
+                return Err(Error::new(ErrorKind::InvalidInput, message));
+            }
+        };
+        try!(writer.write_all(CACHE_MAGIC));
+        try!(write_u64(writer, hash));
+        try!(write_u64(writer, size.0 as u64));
+        try!(write_u64(writer, size.1 as u64));
+        for value in &self.transform.coefficients() {
+            try!(write_u64(writer, value.to_bits()));
+        }
+        try!(write_texture(writer, &self.bilinear_patches, |patch| {
+            &patch[..]
+        }));
+        try!(write_u64(writer, levels.len() as u64));
+        for level in levels {
+            try!(write_texture(writer, level, slice::from_ref));
+        }
+        Ok(())
+    }
+
+    /// Read acceleration structures written by `save`, of a length in bytes,
+    /// or `None` if they were built from heights of another hash, when one is
+    /// given
+    pub fn restore<R: Read>(
+        reader: &mut R,
+        length: u64,
+        hash: Option<u64>,
+    ) -> Result<Option<HeightMap>> {
+        let mut magic = [0; 8];
+        try!(reader.read_exact(&mut magic));
+        if &magic != CACHE_MAGIC {
+            return Ok(None);
+        }
+        let saved = try!(read_u64(reader));
+        if hash.map_or(false, |hash| hash != saved) {
+            return Ok(None);
+        }
+        let width = try!(read_u64(reader)) as usize;
+        let depth = try!(read_u64(reader)) as usize;
+        let mut coefficients = [0.0; 4];
+        for value in coefficients.iter_mut() {
+            *value = try!(read_f64(reader));
+        }
+        let transform = AffineTransform::from(coefficients);
+
+        let bilinear_patches =
+            try!(read_texture(reader, length, 4, |reader| {
+                let mut patch = [0.0; 4];
+                for value in patch.iter_mut() {
+                    *value = try!(read_f64(reader));
+                }
+                Ok(patch)
+            }));
+        let count = try!(read_u64(reader));
+        let mut levels = vec![];
+        for _ in 0..count {
+            levels.push(try!(read_texture(reader, length, 1, read_f64)));
+        }
+
+        Ok(Some(HeightMap {
+            rect: surface_rect(&transform, width, depth),
             transform,
             bilinear_patches,
-            maximum_mipmaps,
+            maximum_mipmaps: MaximumMipmaps::Full(levels),
+            exaggeration: 1.0,
+            lod: None,
+        }))
+    }
+}
+
+/// Return a height map read from a cache built from the same heights, or
+/// build one and write it to the cache. The cache is written to a temporary
+/// file renamed into place, so an interrupted write leaves no partial cache
+fn cached<P: AsRef<Path>>(
+    path: P,
+    transform: AffineTransform,
+    heights: &Texture<f64>,
+) -> Result<HeightMap> {
+    let path = path.as_ref();
+    let hash = content_hash(&transform, heights);
+    if let Ok(file) = File::open(path) {
+        let length = try!(file.metadata()).len();
+        let mut reader = BufReader::new(file);
+        if let Ok(Some(height_map)) =
+            HeightMap::restore(&mut reader, length, Some(hash))
+        {
+            return Ok(height_map);
         }
     }
+
+    let height_map = HeightMap::new(transform, heights);
+    let size = (heights.width, heights.height);
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    {
+        let mut writer = BufWriter::new(try!(File::create(&temporary)));
+        try!(height_map.save(&mut writer, size, hash));
+        try!(writer.flush());
+    }
+    try!(fs::rename(&temporary, path));
+    Ok(height_map)
+}
+
+/// Read the raster of a height map, returning the transform from its raster
+/// space to world space and its heights after any curvature correction
+pub fn load(options: &HeightMapOpts) -> (AffineTransform, Texture<f64>) {
+    let raster = cache::heights(&options.data).unwrap();
+    let (ref proj4, transform, ref texture) = *raster;
+    let (w, h) = (texture.width, texture.height);
+    let transform =
+        raster::scaled_transform(proj4, &transform, w, h, options.scale);
+
+    let texture = match options.curvature {
+        Some(ref curvature) => {
+            let [x, z] = curvature.origin.unwrap_or([0.0, 0.0]);
+            let mut corrected = Texture::blank(w, h);
+            apply_curvature(
+                texture,
+                &mut corrected,
+                &transform,
+                (x, z),
+                curvature.refraction,
+                curvature.radius,
+            );
+            corrected
+        }
+        None => texture.clone(),
+    };
+    (transform, texture)
+}
+
+/// Build a height map from a GDAL raster in strips, rather than loading the
+/// raster whole into the loader cache
+#[cfg(feature = "gdal")]
+fn streamed(loader: &GdalLoader, scale: Option<[f64; 2]>) -> Result<HeightMap> {
+    let mut reader =
+        try!(RasterReader::open(&loader.filepath, loader.band).map_err(other));
+    let (proj4, transform) = try!(reader.georeference().map_err(other));
+    let (w, h) = ChunkSource::<f64>::size(&reader);
+    let transform = raster::scaled_transform(&proj4, &transform, w, h, scale);
+    HeightMap::stream(transform, &mut reader, STREAM_ROWS)
 }
 
 impl From<HeightMapOpts> for HeightMap {
     fn from(options: HeightMapOpts) -> HeightMap {
-        let (transform, texture) = match options.data {
-            Loader::Gdal(opts) => {
-                let (_, transform, rasters) =
-                    gdal::import(opts.filepath, &[opts.band]).unwrap();
-                (transform, rasters[0].clone())
+        let mut height_map = match options.data {
+            Loader::Baked(BakedLoader {
+                ref package,
+                height_map: Some(ref entry),
+                ..
+            }) => {
+                let bytes = package::open_entry(package, entry).unwrap();
+                let length = bytes.len() as u64;
+                HeightMap::restore(&mut &bytes[..], length, None)
+                    .unwrap()
+                    .unwrap()
+            }
+            #[cfg(feature = "gdal")]
+            Loader::Gdal(ref loader)
+                if cache::budget().is_some()
+                    && loader.resolution.is_none()
+                    && options.curvature.is_none()
+                    && options.cache.is_none() =>
+            {
+                streamed(loader, options.scale).unwrap()
+            }
+            _ => {
+                let (transform, texture) = load(&options);
+                match options.cache {
+                    Some(ref path) => {
+                        cached(path, transform, &texture).unwrap()
+                    }
+                    None => HeightMap::new(transform, &texture),
+                }
             }
-            _ => panic!("Unsupported format"),
         };
-
-        HeightMap::new(transform, &texture)
+        if options.quantize_mipmaps {
+            height_map.quantize();
+        }
+        height_map.exaggeration = options.exaggeration;
+        height_map.lod = options.lod;
+        height_map
     }
 }
 
-impl Primitive for HeightMap {
-    fn intersects(&self, ray: Ray) -> Option<Intersection> {
+impl HeightMap {
+    /// Return the deepest node of the quadtree enclosing the part of a ray
+    /// between the lowest and highest heights of the surface, as a level and
+    /// position within it
+    fn start(&self, ray: Ray) -> (usize, usize, usize) {
+        let top = self.maximum_mipmaps.len() - 1;
+        let root = (top, 0, 0);
+        if ray.direction.y == 0.0 {
+            return root;
+        }
+
+        let height =
+            self.maximum_mipmaps.lookup1x1(top, 0, 0) * self.exaggeration;
+        let to = |y: f64| (y - ray.origin.y) / ray.direction.y;
+        let (ta, tb) = (to(0.0), to(height));
+        let (t0, t1) = (ta.min(tb).max(0.0), ta.max(tb));
+        if t1 < t0 || !t1.is_finite() {
+            return root;
+        }
+
+        let size = 1 << top;
+        let (ax, ay) = {
+            let p = ray.origin + ray.direction * t0;
+            self.transform.inverse(p.x, p.z)
+        };
+        let (bx, by) = {
+            let p = ray.origin + ray.direction * t1;
+            self.transform.inverse(p.x, p.z)
+        };
+        // Widen the span slightly so that rays along the boundary of two
+        // nodes start from a node enclosing both
+        let index = |v: f64| v.floor().max(0.0).min(size as f64 - 1.0) as usize;
+        let (x0, x1) = (index(ax.min(bx) - SEAM), index(ax.max(bx) + SEAM));
+        let (y0, y1) = (index(ay.min(by) - SEAM), index(ay.max(by) + SEAM));
+
+        let mut level = 0;
+        while level < top
+            && (x0 >> level != x1 >> level || y0 >> level != y1 >> level)
+        {
+            level += 1;
+        }
+        (level, x0 >> level, y0 >> level)
+    }
+
+    /// Return whether a node is small enough, as seen along a ray entering
+    /// it, to be intersected as a single patch
+    fn coarse_enough(&self, ray: Ray, entry: f64, bounds: &Aabb) -> bool {
+        let error = match self.lod {
+            Some(error) => error,
+            None => return false,
+        };
+        match ray.spread(entry) {
+            Some(pixel) if pixel > 0.0 => {
+                let [a, _, _, _, _, _, _, b] = bounds.corners();
+                let size = (b.x - a.x).abs().max((b.z - a.z).abs());
+                size / pixel < error
+            }
+            _ => false,
+        }
+    }
+
+    /// Return the bilinear patch through the corners of a node
+    fn patch(
+        &self,
+        level: usize,
+        x: usize,
+        y: usize,
+        bounds: &Aabb,
+    ) -> BilinearPatch {
+        let (x0, y0) = (x << level, y << level);
+        let (x1, y1) = (x0 + (1 << level) - 1, y0 + (1 << level) - 1);
+        let patches = &self.bilinear_patches;
+        let e = self.exaggeration;
+        let nw = patches.lookup1x1(x0, y0)[0] * e;
+        let ne = patches.lookup1x1(x1, y0)[1] * e;
+        let se = patches.lookup1x1(x1, y1)[2] * e;
+        let sw = patches.lookup1x1(x0, y1)[3] * e;
+
+        let [a, _, _, _, _, _, _, b] = bounds.corners();
+        BilinearPatch::new(
+            Vec3::new(a.x, nw, a.z),
+            Vec3::new(b.x, ne, a.z),
+            Vec3::new(b.x, se, b.z),
+            Vec3::new(a.x, sw, b.z),
+        )
+    }
+
+    /// Traverse the quadtree for an intersection, counting the nodes visited
+    fn traverse(&self, ray: Ray, visited: &mut usize) -> Option<Intersection> {
         if self.maximum_mipmaps.is_empty() {
             return None;
         }
+        self.traverse_from(ray, self.start(ray), visited)
+    }
 
-        let origin = Vec3::new(ray.origin.x, 0.0, ray.origin.z);
+    /// Return the bounds of a node of the quadtree
+    fn node_bounds(&self, level: usize, x: usize, y: usize) -> Aabb {
+        let (fx, fx1) = (x as f64, x as f64 + 1.0);
+        let (fy, fy1) = (y as f64, y as f64 + 1.0);
+
+        let (min_x, min_z) = self.transform.quadtree(level, fx, fy);
+        let (max_x, max_z) = self.transform.quadtree(level, fx1, fy1);
+        let (min_y, max_y) = (
+            0.0,
+            self.maximum_mipmaps.lookup1x1(level, x, y) * self.exaggeration,
+        );
+
+        Aabb::new(
+            Vec3::new(min_x, min_y, min_z),
+            Vec3::new(max_x, max_y, max_z),
+        )
+    }
+
+    /// Return the children of a node, ordered from the furthest to the
+    /// nearest to an origin on the ground plane, to be pushed onto a stack
+    fn children(
+        &self,
+        (level, x, y): (usize, usize, usize),
+        origin: Vec3,
+    ) -> [(usize, usize, usize); 4] {
         let flat_dist_comp =
             |(al, ax, ay): &(usize, usize, usize),
              (bl, bx, by): &(usize, usize, usize)| {
@@ -146,60 +720,605 @@ impl Primitive for HeightMap {
                 }
             };
 
-        let mut stack = vec![(self.maximum_mipmaps.len() - 1, 0, 0)];
-        while let Some((level, x, y)) = stack.pop() {
-            let mipmap = &self.maximum_mipmaps[level];
+        let (cx, cy) = (x * 2, y * 2);
+        let mut children = [
+            (level - 1, cx, cy),
+            (level - 1, cx + 1, cy),
+            (level - 1, cx, cy + 1),
+            (level - 1, cx + 1, cy + 1),
+        ];
+        children.sort_by(flat_dist_comp);
+        children
+    }
 
-            let (fx, fx1) = (x as f64, x as f64 + 1.0);
-            let (fy, fy1) = (y as f64, y as f64 + 1.0);
+    /// Return the bounds of four nodes
+    fn bounds4(&self, nodes: &[(usize, usize, usize); 4]) -> [Aabb; 4] {
+        let bounds = |(level, x, y): (usize, usize, usize)| {
+            self.node_bounds(level, x, y)
+        };
+        [
+            bounds(nodes[0]),
+            bounds(nodes[1]),
+            bounds(nodes[2]),
+            bounds(nodes[3]),
+        ]
+    }
 
-            let (min_x, min_z) = self.transform.quadtree(level, fx, fy);
-            let (max_x, max_z) = self.transform.quadtree(level, fx1, fy1);
-            let (min_y, max_y) = (0.0, mipmap.lookup1x1(x, y));
+    /// Traverse the quadtree from a node for an intersection, testing the
+    /// children of each node entered at once
+    fn traverse_from(
+        &self,
+        ray: Ray,
+        start: (usize, usize, usize),
+        visited: &mut usize,
+    ) -> Option<Intersection> {
+        let origin = Vec3::new(ray.origin.x, 0.0, ray.origin.z);
 
-            let aabb = Aabb::new(
-                Vec3::new(min_x, min_y, min_z),
-                Vec3::new(max_x, max_y, max_z),
-            );
+        *visited += 1;
+        let bounds = self.node_bounds(start.0, start.1, start.2);
+        let entry = match bounds.entry(ray) {
+            Some(t) if t >= 0.0 => t,
+            _ => return None,
+        };
 
-            let intersection = match aabb.intersects(ray) {
-                None => continue,
-                Some(intersection) => intersection,
-            };
+        let mut stack = vec![(start, bounds, entry)];
+        while let Some(((level, x, y), aabb, entry)) = stack.pop() {
+            if level == 0 || self.coarse_enough(ray, entry, &aabb) {
+                let patch = self.patch(level, x, y, &aabb);
+                if let Some(intersection) = patch.intersects(ray) {
+                    let p = ray.origin + ray.direction * intersection.t;
+                    if self.rect.contains(Vec3::new(p.x, 0.0, p.z)) {
+                        return Some(intersection);
+                    }
+                }
+            } else {
+                let children = self.children((level, x, y), origin);
+                let bounds = self.bounds4(&children);
+                *visited += children.len();
+                let entries = Aabb::entries(&bounds, ray);
+                for i in 0..4 {
+                    match entries[i] {
+                        Some(t) if t >= 0.0 => {
+                            stack.push((children[i], bounds[i], t))
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
 
-            if intersection.t < 0.0 {
+        None
+    }
+
+    /// Traverse the quadtree once for a packet of rays from the same origin,
+    /// visiting each node entered by any of them in the order a single ray
+    /// would, and reading its bounds and patch once for all of them
+    fn traverse_packet(&self, rays: &[Ray]) -> Vec<Option<Intersection>> {
+        let mut hits = vec![None; rays.len()];
+        if self.maximum_mipmaps.is_empty() || rays.is_empty() {
+            return hits;
+        }
+
+        // Start from the deepest node enclosing the start of every ray
+        let lift = |(level, x, y): (usize, usize, usize), to: usize| {
+            (to, x >> (to - level), y >> (to - level))
+        };
+        let mut start = self.start(rays[0]);
+        for ray in &rays[1..] {
+            let other = self.start(*ray);
+            let level = start.0.max(other.0);
+            let mut other = lift(other, level);
+            start = lift(start, level);
+            while start != other {
+                start = lift(start, start.0 + 1);
+                other = lift(other, other.0 + 1);
+            }
+        }
+
+        let bounds = self.node_bounds(start.0, start.1, start.2);
+        let mut entered: u64 = 0;
+        for (i, ray) in rays.iter().enumerate() {
+            if bounds.entry(*ray).map_or(false, |t| t >= 0.0) {
+                entered |= 1 << i;
+            }
+        }
+
+        let origin = Vec3::new(rays[0].origin.x, 0.0, rays[0].origin.z);
+        let mut done: u64 = 0;
+        let mut stack = vec![(start, bounds, entered)];
+        while let Some(((level, x, y), aabb, mask)) = stack.pop() {
+            let mask = mask & !done;
+            if mask == 0 {
                 continue;
             }
 
-            if level == 0 {
-                let [nw, ne, se, sw] = self.bilinear_patches.lookup1x1(x, y);
-                let nw = Vec3::new(min_x, nw, min_z);
-                let ne = Vec3::new(max_x, ne, min_z);
-                let se = Vec3::new(max_x, se, max_z);
-                let sw = Vec3::new(min_x, sw, max_z);
-                let patch = BilinearPatch::new(nw, ne, se, sw);
-                match patch.intersects(ray) {
-                    Some(intersection) => {
+            let mut patch = None;
+            let mut descend: u64 = 0;
+            for (i, ray) in rays.iter().enumerate() {
+                if mask & (1 << i) == 0 {
+                    continue;
+                }
+                // The distance a ray enters a node is only needed for its
+                // level of detail
+                let coarse = self.lod.is_some()
+                    && aabb.entry(*ray).map_or(false, |entry| {
+                        self.coarse_enough(*ray, entry, &aabb)
+                    });
+                if level == 0 || coarse {
+                    let patch = patch
+                        .get_or_insert_with(|| self.patch(level, x, y, &aabb));
+                    if let Some(intersection) = patch.intersects(*ray) {
                         let p = ray.origin + ray.direction * intersection.t;
                         if self.rect.contains(Vec3::new(p.x, 0.0, p.z)) {
-                            return Some(intersection)
+                            hits[i] = Some(intersection);
+                            done |= 1 << i;
+                        }
+                    }
+                } else {
+                    descend |= 1 << i;
+                }
+            }
+
+            if descend != 0 {
+                let children = self.children((level, x, y), origin);
+                let bounds = self.bounds4(&children);
+                let mut masks = [0u64; 4];
+                for (i, ray) in rays.iter().enumerate() {
+                    if descend & (1 << i) == 0 {
+                        continue;
+                    }
+                    let entries = Aabb::entries(&bounds, *ray);
+                    for (mask, entry) in masks.iter_mut().zip(&entries) {
+                        if entry.map_or(false, |t| t >= 0.0) {
+                            *mask |= 1 << i;
                         }
-                    },
-                    _ => continue,
+                    }
+                }
+                for i in 0..4 {
+                    stack.push((children[i], bounds[i], masks[i]));
+                }
+            }
+        }
+
+        hits
+    }
+}
+
+impl Primitive for HeightMap {
+    fn intersects(&self, ray: Ray) -> Option<Intersection> {
+        self.traverse(ray, &mut 0)
+    }
+
+    fn intersects_batch(&self, rays: &[Ray]) -> Vec<Option<Intersection>> {
+        // Rays of a packet share the order nodes are visited in, which only
+        // depends on their origin
+        let mut hits = Vec::with_capacity(rays.len());
+        let mut rest = rays;
+        while !rest.is_empty() {
+            let origin = rest[0].origin;
+            let count = rest
+                .iter()
+                .take(PACKET_SIZE)
+                .take_while(|ray| ray.origin == origin)
+                .count();
+            hits.append(&mut self.traverse_packet(&rest[..count]));
+            rest = &rest[count..];
+        }
+        hits
+    }
+
+    fn cost(&self, ray: Ray) -> usize {
+        let mut visited = 0;
+        self.traverse(ray, &mut visited);
+        visited
+    }
+
+    fn memory(&self) -> usize {
+        self.bilinear_patches.memory() + self.maximum_mipmaps.memory()
+    }
+
+    fn elevation(&self, x: f64, z: f64) -> Option<f64> {
+        HeightMap::elevation(self, x, z)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let corners = self.rect.corners();
+        let (width, depth) = self.transform.inverse(corners[2].x, corners[2].z);
+        let patches = &self.bilinear_patches;
+
+        // Ignore the patches padding the height map to a power of two
+        let mut min = INFINITY;
+        for y in 0..(depth.round() as usize).saturating_sub(1) {
+            for x in 0..(width.round() as usize).saturating_sub(1) {
+                let patch = patches.buffer[y * patches.width + x];
+                min = patch.iter().fold(min, |min, &height| min.min(height));
+            }
+        }
+        let top = self.maximum_mipmaps.len() - 1;
+        let max = self.maximum_mipmaps.lookup1x1(top, 0, 0) * self.exaggeration;
+        let min = min * self.exaggeration;
+
+        let (a, b) = (corners[0], corners[2]);
+        Some(Aabb::enclosing(&[
+            Vec3::new(a.x, min.min(max), a.z),
+            Vec3::new(b.x, max, b.z),
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn height_map() -> HeightMap {
+        let heights = (0..64 * 64).map(|i| f64::from(i % 7)).collect();
+        let texture = Texture::new(64, 64, heights);
+        HeightMap::new(AffineTransform::default(), &texture)
+    }
+
+    #[test]
+    fn restoring_saved_height_maps() {
+        let heights = (0..5 * 3).map(|i| f64::from(i % 4)).collect();
+        let heights = Texture::new(5, 3, heights);
+        let transform = AffineTransform::new(-2.0, 3.0, 0.5, 0.25);
+        let hash = content_hash(&transform, &heights);
+        let height_map = HeightMap::new(transform, &heights);
+
+        let mut bytes = vec![];
+        height_map.save(&mut bytes, (5, 3), hash).unwrap();
+        let length = bytes.len() as u64;
+        let restored =
+            HeightMap::restore(&mut &bytes[..], length, Some(hash)).unwrap();
+        let restored = restored.unwrap();
+        assert_eq!(restored.rect.corners(), height_map.rect.corners());
+        assert_eq!(restored.bilinear_patches, height_map.bilinear_patches);
+        assert_eq!(restored.memory(), height_map.memory());
+
+        let ray =
+            Ray::new(Vec3::new(-1.0, 5.0, 3.4), Vec3::new(0.3, -1.0, 0.1));
+        assert_eq!(restored.intersects(ray), height_map.intersects(ray));
+
+        let mut other = heights.clone();
+        other.buffer[0] += 1.0;
+        let stale = content_hash(&transform, &other);
+        assert!(HeightMap::restore(&mut &bytes[..], length, Some(stale))
+            .unwrap()
+            .is_none());
+
+        // Sizes larger than the data are rejected before allocating them
+        let mut corrupt = bytes.clone();
+        corrupt[64..72].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        let err = HeightMap::restore(&mut &corrupt[..], length, Some(hash));
+        assert_eq!(err.err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn caching_height_maps() {
+        let heights = Texture::new(2, 2, vec![1.0, 2.0, 3.0, 4.0]);
+        let transform = AffineTransform::default();
+        let path = env::temp_dir().join("peaks-caching-height-maps.bin");
+        let _ = fs::remove_file(&path);
+
+        let built = cached(&path, transform, &heights).unwrap();
+        let restored = cached(&path, transform, &heights).unwrap();
+        assert_eq!(restored.bilinear_patches, built.bilinear_patches);
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        assert!(!Path::new(&temporary).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn streaming_strips_of_rows() {
+        let heights = (0..7 * 5).map(|i| f64::from(i % 6)).collect();
+        let mut heights = Texture::new(7, 5, heights);
+        let transform = AffineTransform::new(-2.0, 3.0, 0.5, 0.25);
+        let whole = HeightMap::new(transform, &heights);
+        for rows in 1..7 {
+            let streamed =
+                HeightMap::stream(transform, &mut heights, rows).unwrap();
+            assert_eq!(streamed.rect.corners(), whole.rect.corners());
+            assert_eq!(streamed.bilinear_patches, whole.bilinear_patches);
+            assert_eq!(streamed.memory(), whole.memory());
+            for level in 0..whole.maximum_mipmaps.len() {
+                let size = whole.bilinear_patches.width >> level;
+                for i in 0..size * size {
+                    assert_eq!(
+                        streamed.maximum_mipmaps.lookup1x1(
+                            level,
+                            i % size,
+                            i / size
+                        ),
+                        whole.maximum_mipmaps.lookup1x1(
+                            level,
+                            i % size,
+                            i / size
+                        )
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn starting_below_the_root() {
+        let height_map = height_map();
+        let ray =
+            Ray::new(Vec3::new(10.5, 20.0, 30.5), Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(height_map.start(ray), (0, 10, 30));
+        assert!(height_map.cost(ray) < 4);
+
+        // A ray crossing the whole surface starts at its root
+        let direction = Vec3::normalize(Vec3::new(1.0, -0.05, 1.0));
+        let ray = Ray::new(Vec3::new(-1.0, 6.5, -1.0), direction);
+        assert_eq!(height_map.start(ray), (6, 0, 0));
+    }
+
+    #[test]
+    fn matching_hits_from_the_root() {
+        let height_map = height_map();
+        let top = height_map.maximum_mipmaps.len() - 1;
+        let origin = Vec3::new(20.0, 10.0, 20.0);
+        for i in 0..32 {
+            let angle = f64::from(i) * 0.2;
+            let dip = -0.2 - f64::from(i % 4) * 0.3;
+            let direction =
+                Vec3::normalize(Vec3::new(angle.cos(), dip, angle.sin()));
+            let ray = Ray::new(origin, direction);
+            let expected = height_map.traverse_from(ray, (top, 0, 0), &mut 0);
+            assert_eq!(height_map.intersects(ray), expected);
+        }
+    }
+
+    #[test]
+    fn tracing_packets() {
+        let mut height_map = height_map();
+        let origins = [Vec3::new(20.0, 10.0, 20.0), Vec3::new(-5.0, 8.0, 70.0)];
+        // A fan of rays from one origin, more than fit in a packet, then
+        // rays alternating between two
+        let rays: Vec<Ray> = (0..160)
+            .map(|i| {
+                let origin = if i < 100 { origins[0] } else { origins[i % 2] };
+                let angle = i as f64 * 0.04;
+                let dip = -0.1 - (i % 5) as f64 * 0.2;
+                let direction =
+                    Vec3::normalize(Vec3::new(angle.cos(), dip, angle.sin()));
+                Ray::new(origin, direction)
+            })
+            .collect();
+        let expected: Vec<_> =
+            rays.iter().map(|ray| height_map.intersects(*ray)).collect();
+        assert!(expected.iter().any(Option::is_some));
+        assert!(expected.iter().any(Option::is_none));
+        assert_eq!(height_map.intersects_batch(&rays), expected);
+
+        height_map.lod = Some(4.0);
+        let spread: Vec<Ray> = rays
+            .iter()
+            .map(|ray| {
+                let offset = |x: f64| {
+                    let direction = ray.direction + Vec3::new(x, 0.0, -x);
+                    Ray::new(ray.origin, Vec3::normalize(direction))
                 };
-            } else {
-                let (cx, cy) = (x * 2, y * 2);
-                let mut children = vec![
-                    (level - 1, cx, cy),
-                    (level - 1, cx + 1, cy),
-                    (level - 1, cx, cy + 1),
-                    (level - 1, cx + 1, cy + 1),
-                ];
-                children.sort_by(flat_dist_comp);
-                stack.append(&mut children);
+                ray.with_differentials(offset(0.05), offset(-0.05))
+            })
+            .collect();
+        let expected: Vec<_> = spread
+            .iter()
+            .map(|ray| height_map.intersects(*ray))
+            .collect();
+        assert_eq!(height_map.intersects_batch(&spread), expected);
+        assert!(height_map.intersects_batch(&[]).is_empty());
+    }
+
+    #[test]
+    fn exaggerating_heights() {
+        let mut height_map = height_map();
+        let direction = Vec3::normalize(Vec3::new(0.1, -1.0, 0.2));
+        let ray = Ray::new(Vec3::new(10.3, 50.0, 30.6), direction);
+        let hit = height_map.intersects(ray).unwrap();
+        let height = (ray.origin + direction * hit.t).y;
+
+        height_map.exaggeration = 2.5;
+        let hit = height_map.intersects(ray).unwrap();
+        let exaggerated = (ray.origin + direction * hit.t).y;
+        assert!(exaggerated > height);
+        assert_eq!(height_map.bounds().unwrap().corners()[7].y, 6.0 * 2.5);
+    }
+
+    #[test]
+    fn coarser_levels_of_detail() {
+        let mut height_map = height_map();
+        let direction = Vec3::normalize(Vec3::new(1.0, -0.3, 1.0));
+        let origin = Vec3::new(-1.0, 10.0, -1.0);
+        // Rays spreading by 0.1 units for each unit travelled
+        let offset = |x: f64, z: f64| {
+            Ray::new(origin, Vec3::normalize(direction + Vec3::new(x, 0.0, z)))
+        };
+        let ray = Ray::new(origin, direction)
+            .with_differentials(offset(0.1, -0.1), offset(-0.1, 0.1));
+
+        let full = height_map.cost(ray);
+        assert!(height_map.intersects(ray).is_some());
+
+        height_map.lod = Some(1.0);
+        assert!(height_map.cost(ray) < full);
+        assert!(height_map.intersects(ray).is_some());
+
+        // Rays without differentials are always intersected in full detail
+        let plain = Ray::new(origin, direction);
+        assert_eq!(height_map.cost(plain), full);
+    }
+
+    #[test]
+    fn querying_elevations() {
+        // A plane rising two units per unit east and a quarter per unit south
+        let heights = (0..8 * 8)
+            .map(|i| f64::from(i % 8) + f64::from(i / 8) * 0.5)
+            .collect();
+        let texture = Texture::new(8, 8, heights);
+        let transform = AffineTransform::new(10.0, 20.0, 0.5, 2.0);
+        let mut height_map = HeightMap::new(transform, &texture);
+
+        assert_eq!(height_map.elevation(10.0, 20.0), Some(0.0));
+        assert_eq!(height_map.elevation(11.25, 23.0), Some(2.5 + 0.75));
+        assert_eq!(height_map.gradient(11.25, 23.0), Some((2.0, 0.25)));
+        assert_eq!(height_map.elevation(9.0, 23.0), None);
+
+        height_map.exaggeration = 2.0;
+        assert_eq!(height_map.elevation(11.25, 23.0), Some(6.5));
+        assert_eq!(height_map.gradient(11.25, 23.0), Some((4.0, 0.5)));
+    }
+
+    /// A reproducible sequence of numbers in the range `[0, 1)`
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> f64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        fn range(&mut self, min: f64, max: f64) -> f64 {
+            min + (max - min) * self.next()
+        }
+    }
+
+    /// Return the nearest intersection with every patch of a height map
+    fn brute_force(height_map: &HeightMap, ray: Ray) -> Option<Intersection> {
+        let corners = height_map.rect.corners();
+        let (width, depth) =
+            height_map.transform.inverse(corners[2].x, corners[2].z);
+        let mut nearest = Intersection::none();
+        for y in 0..depth.round() as usize {
+            for x in 0..width.round() as usize {
+                let t = &height_map.transform;
+                let (min_x, min_z) = t.quadtree(0, x as f64, y as f64);
+                let (max_x, max_z) =
+                    t.quadtree(0, x as f64 + 1.0, y as f64 + 1.0);
+                let bounds = Aabb::new(
+                    Vec3::new(min_x, 0.0, min_z),
+                    Vec3::new(max_x, 0.0, max_z),
+                );
+                let patch = height_map.patch(0, x, y, &bounds);
+                if let Some(hit) = patch.intersects(ray) {
+                    if hit.t < nearest.t {
+                        nearest = hit;
+                    }
+                }
             }
         }
+        nearest.to_option()
+    }
 
-        None
+    /// Compare intersections of random rays with random height maps against
+    /// those found by intersecting every patch
+    fn fuzz(seed: u64, cases: usize, rays: usize) {
+        let mut random = Random(seed);
+        for case in 0..cases {
+            let (width, depth) = (
+                2 + (random.next() * 11.0) as usize,
+                2 + (random.next() * 11.0) as usize,
+            );
+            let heights = (0..width * depth)
+                .map(|_| (random.range(0.0, 4.0) * 4.0).round() / 4.0)
+                .collect();
+            let texture = Texture::new(width, depth, heights);
+            let transform = AffineTransform::new(
+                random.range(-5.0, 5.0),
+                random.range(-5.0, 5.0),
+                random.range(0.5, 2.0),
+                random.range(0.5, 2.0),
+            );
+            let height_map = HeightMap::new(transform, &texture);
+            let corners = height_map.rect.corners();
+            let (a, b) = (corners[0], corners[2]);
+
+            for i in 0..rays {
+                // Rays from above the surface to points on or around it, at
+                // grazing angles, along its grid lines or straight down
+                let target = Vec3::new(
+                    random.range(a.x - 1.0, b.x + 1.0),
+                    random.range(0.0, 4.0),
+                    random.range(a.z - 1.0, b.z + 1.0),
+                );
+                let origin = match i % 4 {
+                    0 => Vec3::new(target.x, 10.0, target.z),
+                    1 => Vec3::new(
+                        a.x + (b.x - a.x) * (random.next() * 4.0).floor() / 4.0,
+                        random.range(0.0, 6.0),
+                        a.z - 3.0,
+                    ),
+                    2 => Vec3::new(
+                        target.x + random.range(-30.0, 30.0),
+                        target.y + random.range(0.0, 0.5),
+                        target.z + random.range(-30.0, 30.0),
+                    ),
+                    _ => Vec3::new(
+                        random.range(a.x - 5.0, b.x + 5.0),
+                        random.range(0.0, 12.0),
+                        random.range(a.z - 5.0, b.z + 5.0),
+                    ),
+                };
+                let direction = if i % 4 == 1 {
+                    Vec3::new(0.0, random.range(-0.3, 0.0), 1.0)
+                } else {
+                    target - origin
+                };
+                if Vec3::length(direction) == 0.0 {
+                    continue;
+                }
+                let ray = Ray::new(origin, Vec3::normalize(direction));
+
+                let expected = brute_force(&height_map, ray);
+                let found = height_map.intersects(ray);
+                if i % 4 == 0 {
+                    if let Some(elevation) =
+                        height_map.elevation(origin.x, origin.z)
+                    {
+                        let t = found.map_or(INFINITY, |hit| hit.t);
+                        assert!(
+                            (origin.y - t - elevation).abs() < 1e-9,
+                            "case {} missed the surface below {:?}",
+                            case,
+                            origin
+                        );
+                    }
+                }
+                match (expected, found) {
+                    (None, None) => (),
+                    (Some(expected), Some(found)) => assert!(
+                        (expected.t - found.t).abs()
+                            < 1e-6 * expected.t.max(1.0),
+                        "case {} ray {:?}: {} != {}",
+                        case,
+                        ray,
+                        expected.t,
+                        found.t
+                    ),
+                    _ => panic!(
+                        "case {} ray {:?}: {:?} != {:?}",
+                        case, ray, expected, found
+                    ),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matching_brute_force_intersections() {
+        fuzz(0x2545_f491_4f6c_dd1d, 100, 100);
+    }
+
+    /// A longer search for differences, run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn fuzzing_intersections() {
+        for seed in 1..=20 {
+            fuzz(seed * 0x9e37_79b9_7f4a_7c15, 500, 400);
+        }
     }
 }
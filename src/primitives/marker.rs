@@ -0,0 +1,256 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use super::aabb::Aabb;
+use super::height_map::HeightMap;
+use super::primitive::{Intersection, Primitive};
+
+use io::cache;
+use math::{Ray, Vec3};
+use options::{MarkerOpts, MarkerSymbol};
+use shapes::Shape;
+
+use std::mem;
+
+/// Return the intersection of a ray with a triangle (Möller–Trumbore)
+fn triangle(ray: Ray, a: Vec3, b: Vec3, c: Vec3) -> Option<Intersection> {
+    let (ab, ac) = (b - a, c - a);
+    let p = Vec3::cross(ray.direction, ac);
+    let determinant = Vec3::dot(ab, p);
+    if determinant == 0.0 {
+        return None;
+    }
+
+    let s = ray.origin - a;
+    let u = Vec3::dot(s, p) / determinant;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+
+    let q = Vec3::cross(s, ab);
+    let v = Vec3::dot(ray.direction, q) / determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = Vec3::dot(ac, q) / determinant;
+    if t <= 0.0 {
+        return None;
+    }
+
+    Some(Intersection::new(t, Vec3::normalize(Vec3::cross(ab, ac))))
+}
+
+/// A symbol of some size anchored by its base to a point
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Symbol {
+    anchor: Vec3,
+    size: f64,
+}
+
+impl Symbol {
+    /// Return a disc, facing the ray, resting on the anchor
+    fn disc(&self, ray: Ray) -> Option<Intersection> {
+        let radius = self.size * 0.5;
+        let center = self.anchor + Vec3::new(0.0, radius, 0.0);
+        let t = Vec3::dot(center - ray.origin, ray.direction)
+            / Vec3::dot(ray.direction, ray.direction);
+        if t <= 0.0 {
+            return None;
+        }
+
+        let p = ray.origin + ray.direction * t;
+        if Vec3::distance(p, center) > radius {
+            return None;
+        }
+        Some(Intersection::new(t, -Vec3::normalize(ray.direction)))
+    }
+
+    /// Return a square based pyramid as wide as it is tall
+    fn pyramid(&self, ray: Ray) -> Option<Intersection> {
+        let h = self.size * 0.5;
+        let apex = self.anchor + Vec3::new(0.0, self.size, 0.0);
+        let corners = [
+            self.anchor + Vec3::new(-h, 0.0, -h),
+            self.anchor + Vec3::new(h, 0.0, -h),
+            self.anchor + Vec3::new(h, 0.0, h),
+            self.anchor + Vec3::new(-h, 0.0, h),
+        ];
+
+        let mut nearest = Intersection::none();
+        for i in 0..corners.len() {
+            let (a, b) = (corners[i], corners[(i + 1) % corners.len()]);
+            if let Some(hit) = triangle(ray, b, a, apex) {
+                if hit.t < nearest.t {
+                    nearest = hit;
+                }
+            }
+        }
+        nearest.to_option()
+    }
+
+    /// Return a pole with a pennant at its top, turned about the vertical
+    /// axis to face the ray
+    fn flag(&self, ray: Ray) -> Option<Intersection> {
+        let facing = Vec3::new(-ray.direction.x, 0.0, -ray.direction.z);
+        if facing.x == 0.0 && facing.z == 0.0 {
+            return None;
+        }
+
+        let normal = Vec3::normalize(facing);
+        let denominator = Vec3::dot(ray.direction, normal);
+        let t = Vec3::dot(self.anchor - ray.origin, normal) / denominator;
+        if t <= 0.0 {
+            return None;
+        }
+
+        // Position on the plane, across from and up from the anchor
+        let right = Vec3::new(-normal.z, 0.0, normal.x);
+        let p = ray.origin + ray.direction * t - self.anchor;
+        let (x, y) = (Vec3::dot(p, right), p.y);
+
+        let pole = x.abs() <= self.size * 0.025 && y >= 0.0 && y <= self.size;
+        let pennant = x >= 0.0
+            && x <= self.size * 0.5
+            && y >= self.size * (2.0 / 3.0)
+            && y <= self.size;
+        if pole || pennant {
+            Some(Intersection::new(t, normal))
+        } else {
+            None
+        }
+    }
+}
+
+/// Symbols marking points, such as summits and huts, in the scene
+pub struct Marker {
+    symbol: MarkerSymbol,
+    symbols: Vec<(Aabb, Symbol)>,
+}
+
+impl Marker {
+    pub fn new(symbol: MarkerSymbol, size: f64, anchors: &[Vec3]) -> Marker {
+        let symbols = anchors
+            .iter()
+            .map(|&anchor| {
+                let bounds = Aabb::new(
+                    anchor + Vec3::new(-size, 0.0, -size),
+                    anchor + Vec3::new(size, size, size),
+                );
+                (bounds, Symbol { anchor, size })
+            })
+            .collect();
+        Marker { symbol, symbols }
+    }
+
+    fn traverse(&self, ray: Ray, visited: &mut usize) -> Option<Intersection> {
+        let mut nearest = Intersection::none();
+        for &(ref bounds, ref symbol) in &self.symbols {
+            *visited += 1;
+            if bounds.intersects(ray).is_none() {
+                continue;
+            }
+
+            let hit = match self.symbol {
+                MarkerSymbol::Disc => symbol.disc(ray),
+                MarkerSymbol::Pyramid => symbol.pyramid(ray),
+                MarkerSymbol::Flag => symbol.flag(ray),
+            };
+            if let Some(hit) = hit {
+                if hit.t < nearest.t {
+                    nearest = hit;
+                }
+            }
+        }
+        nearest.to_option()
+    }
+}
+
+impl From<MarkerOpts> for Marker {
+    fn from(options: MarkerOpts) -> Marker {
+        let shapes = cache::shapes(&options.data).unwrap();
+
+        let terrain = options.terrain.map(HeightMap::from);
+        let anchors: Vec<Vec3> = shapes
+            .iter()
+            .filter_map(|shape| match *shape {
+                Shape::Point(point) => Some(point.position()),
+                _ => None,
+            })
+            .map(|point| match terrain {
+                Some(ref terrain) => terrain.drape(point),
+                None => point,
+            })
+            .collect();
+
+        Marker::new(options.symbol, options.size, &anchors)
+    }
+}
+
+impl Primitive for Marker {
+    fn intersects(&self, ray: Ray) -> Option<Intersection> {
+        self.traverse(ray, &mut 0)
+    }
+
+    fn cost(&self, ray: Ray) -> usize {
+        let mut visited = 0;
+        self.traverse(ray, &mut visited);
+        visited
+    }
+
+    fn memory(&self) -> usize {
+        self.symbols.len() * mem::size_of::<(Aabb, Symbol)>()
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.symbols.iter().map(|&(bounds, _)| bounds).fold(
+            None,
+            |bounds, next| match bounds {
+                Some(bounds) => Some(next.union(&bounds)),
+                None => Some(next),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marker(symbol: MarkerSymbol) -> Marker {
+        Marker::new(symbol, 2.0, &[Vec3::new(0.0, 10.0, 0.0)])
+    }
+
+    #[test]
+    fn marker_symbols() {
+        let ray =
+            Ray::new(Vec3::new(0.0, 11.0, -10.0), Vec3::new(0.0, 0.0, 1.0));
+        let hit = marker(MarkerSymbol::Disc).intersects(ray).unwrap();
+        assert_eq!(hit.t, 10.0);
+        assert_eq!(hit.normal, Vec3::new(0.0, 0.0, -1.0));
+
+        let hit = marker(MarkerSymbol::Pyramid).intersects(ray).unwrap();
+        assert_eq!(hit.t, 9.5);
+        assert!(hit.normal.z < 0.0);
+
+        let ray =
+            Ray::new(Vec3::new(0.5, 11.5, -10.0), Vec3::new(0.0, 0.0, 1.0));
+        let hit = marker(MarkerSymbol::Flag).intersects(ray).unwrap();
+        assert_eq!(hit.t, 10.0);
+        let ray =
+            Ray::new(Vec3::new(0.5, 10.5, -10.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(marker(MarkerSymbol::Flag).intersects(ray), None);
+    }
+}
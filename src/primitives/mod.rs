@@ -15,14 +15,20 @@
 
 mod aabb;
 mod bilinear_patch;
+mod extrusion;
 mod height_map;
+mod marker;
 mod plane;
 mod primitive;
+mod scatter;
 mod sphere;
 
 pub use self::aabb::Aabb;
 pub use self::bilinear_patch::BilinearPatch;
-pub use self::height_map::HeightMap;
+pub use self::extrusion::{attribute_values, Extrusion};
+pub use self::height_map::{content_hash, load as load_height_map, HeightMap};
+pub use self::marker::Marker;
 pub use self::plane::Plane;
 pub use self::primitive::{Intersection, Primitive};
+pub use self::scatter::Scatter;
 pub use self::sphere::Sphere;
@@ -13,12 +13,40 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
+use super::aabb::Aabb;
 use math::{Ray, Vec3};
 use std::f64::INFINITY;
 
 pub trait Primitive {
     /// Object ray intersection test
     fn intersects(&self, ray: Ray) -> Option<Intersection>;
+
+    /// Intersection tests of a batch of rays, which primitives may share work
+    /// between when the rays are coherent
+    fn intersects_batch(&self, rays: &[Ray]) -> Vec<Option<Intersection>> {
+        rays.iter().map(|ray| self.intersects(*ray)).collect()
+    }
+
+    /// Return the number of steps taken by an intersection test
+    fn cost(&self, _ray: Ray) -> usize {
+        1
+    }
+
+    /// Return an estimate of the number of bytes held by the primitive
+    fn memory(&self) -> usize {
+        0
+    }
+
+    /// Return a box enclosing the primitive, if it is finite
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+
+    /// Return the height of the surface at a position on the `x` and `z`
+    /// axes, for primitives that other objects may be placed on
+    fn elevation(&self, _x: f64, _z: f64) -> Option<f64> {
+        None
+    }
 }
 
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
@@ -43,6 +71,15 @@ impl Intersection {
         self.t == INFINITY
     }
 
+    /// Return the intersection with its normal facing against a direction
+    pub fn face_forward(&self, direction: Vec3) -> Intersection {
+        if Vec3::dot(self.normal, direction) > 0.0 {
+            Intersection::new(self.t, -self.normal)
+        } else {
+            *self
+        }
+    }
+
     pub fn to_option(&self) -> Option<Intersection> {
         if self.is_none() {
             None
@@ -51,3 +88,17 @@ impl Intersection {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn face_forward_normal() {
+        let hit = Intersection::new(1.0, Vec3::new(0.0, 1.0, 0.0));
+        let below = Vec3::new(0.0, 1.0, 0.0);
+        let above = Vec3::new(0.0, -1.0, 0.0);
+        assert_eq!(hit.face_forward(below).normal, Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(hit.face_forward(above), hit);
+    }
+}
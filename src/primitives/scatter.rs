@@ -0,0 +1,484 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use super::aabb::Aabb;
+use super::height_map::HeightMap;
+use super::primitive::{Intersection, Primitive};
+use super::sphere::Sphere;
+
+use io::{cache, raster};
+use math::{AffineTransform, Ray, Vec3};
+use options::{InstanceOpts, ScatterOpts};
+use textures::Texture;
+
+use std::f64::INFINITY;
+use std::mem;
+
+/// Average number of instances in each cell of the grid
+const INSTANCES_PER_CELL: f64 = 2.0;
+
+/// Maximum number of cells along each axis of the grid
+const MAX_CELLS: f64 = 4096.0;
+
+/// A reproducible sequence of random numbers (xorshift64*)
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        match seed ^ 0x9e37_79b9_7f4a_7c15 {
+            0 => Random(1),
+            state => Random(state),
+        }
+    }
+
+    /// Return the next number in the range `[0, 1)`
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        let bits = self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11;
+        bits as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// An instance resting on a point of the terrain
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Instance {
+    pub base: Vec3,
+    pub scale: f64,
+}
+
+/// Return the intersection of a ray with the surface of an upright cone,
+/// open at its base
+fn cone(
+    ray: Ray,
+    apex: Vec3,
+    radius: f64,
+    height: f64,
+) -> Option<Intersection> {
+    let k = radius / height;
+    let (o, d) = (ray.origin - apex, ray.direction);
+    let a = d.x * d.x + d.z * d.z - k * k * d.y * d.y;
+    let b = 2.0 * (o.x * d.x + o.z * d.z - k * k * o.y * d.y);
+    let c = o.x * o.x + o.z * o.z - k * k * o.y * o.y;
+    let delta = b * b - 4.0 * a * c;
+    if a == 0.0 || delta < 0.0 {
+        return None;
+    }
+
+    let t1 = (-b - delta.sqrt()) / (2.0 * a);
+    let t2 = (-b + delta.sqrt()) / (2.0 * a);
+    for &t in &[t1.min(t2), t1.max(t2)] {
+        // Only the lower half of the double cone, down to its base
+        let q = o + d * t;
+        if t <= 0.0 || q.y > 0.0 || q.y < -height {
+            continue;
+        }
+        let r = (q.x * q.x + q.z * q.z).sqrt();
+        let normal = if r > 0.0 {
+            Vec3::normalize(Vec3::new(q.x, k * r, q.z))
+        } else {
+            Vec3::new(0.0, 1.0, 0.0)
+        };
+        return Some(Intersection::new(t, normal));
+    }
+    None
+}
+
+/// Many instances of a shape scattered over a terrain, found through a grid
+/// over the `x` and `z` axes
+pub struct Scatter {
+    shape: InstanceOpts,
+    instances: Vec<Instance>,
+    lower: Vec3,
+    upper: Vec3,
+    size: f64,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl Scatter {
+    pub fn new(shape: InstanceOpts, instances: Vec<Instance>) -> Scatter {
+        let (radius, height) = extent(shape);
+        let mut lower = Vec3::new(INFINITY, INFINITY, INFINITY);
+        let mut upper = -lower;
+        for instance in &instances {
+            let r = radius * instance.scale;
+            let h = height * instance.scale;
+            let base = instance.base;
+            lower = Vec3::new(
+                lower.x.min(base.x - r),
+                lower.y.min(base.y),
+                lower.z.min(base.z - r),
+            );
+            upper = Vec3::new(
+                upper.x.max(base.x + r),
+                upper.y.max(base.y + h),
+                upper.z.max(base.z + r),
+            );
+        }
+
+        let mut scatter = Scatter {
+            shape,
+            instances,
+            lower,
+            upper,
+            size: 1.0,
+            columns: 0,
+            rows: 0,
+            cells: vec![],
+        };
+        if !scatter.instances.is_empty() {
+            scatter.build_grid();
+        }
+        scatter
+    }
+
+    /// Sort the instances into the cells of the grid they overlap
+    fn build_grid(&mut self) {
+        let (width, depth) =
+            (self.upper.x - self.lower.x, self.upper.z - self.lower.z);
+        let count = self.instances.len() as f64;
+        self.size = (width * depth * INSTANCES_PER_CELL / count)
+            .sqrt()
+            .max(width.max(depth) / MAX_CELLS)
+            .max(1e-9);
+        self.columns = (width / self.size).floor() as usize + 1;
+        self.rows = (depth / self.size).floor() as usize + 1;
+        self.cells = vec![vec![]; self.columns * self.rows];
+
+        let (radius, _) = extent(self.shape);
+        for (i, instance) in self.instances.iter().enumerate() {
+            let r = radius * instance.scale;
+            let (x, z) = (instance.base.x, instance.base.z);
+            let (x0, z0) = self.cell(x - r, z - r);
+            let (x1, z1) = self.cell(x + r, z + r);
+            for row in z0..=z1 {
+                for column in x0..=x1 {
+                    self.cells[row * self.columns + column].push(i);
+                }
+            }
+        }
+    }
+
+    /// Return the cell containing a position on the `x` and `z` axes
+    fn cell(&self, x: f64, z: f64) -> (usize, usize) {
+        let index = |value: f64, origin: f64, count: usize| {
+            let i = ((value - origin) / self.size).floor().max(0.0) as usize;
+            i.min(count - 1)
+        };
+        (
+            index(x, self.lower.x, self.columns),
+            index(z, self.lower.z, self.rows),
+        )
+    }
+
+    fn intersect_instance(
+        &self,
+        instance: &Instance,
+        ray: Ray,
+    ) -> Option<Intersection> {
+        let s = instance.scale;
+        match self.shape {
+            InstanceOpts::Cone { radius, height } => {
+                let apex = instance.base + Vec3::new(0.0, height * s, 0.0);
+                cone(ray, apex, radius * s, height * s)
+            }
+            InstanceOpts::Sphere { radius } => {
+                let center = instance.base + Vec3::new(0.0, radius * s, 0.0);
+                Sphere::new(center, radius * s)
+                    .intersects(ray)
+                    .filter(|hit| hit.t > 0.0)
+            }
+        }
+    }
+
+    /// Return the range of distances along a ray within the bounds
+    fn clip(&self, ray: Ray) -> Option<(f64, f64)> {
+        let axes = [
+            (ray.origin.x, ray.direction.x, self.lower.x, self.upper.x),
+            (ray.origin.y, ray.direction.y, self.lower.y, self.upper.y),
+            (ray.origin.z, ray.direction.z, self.lower.z, self.upper.z),
+        ];
+        let (mut near, mut far) = (0.0f64, INFINITY);
+        for &(origin, direction, min, max) in &axes {
+            if direction == 0.0 {
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (min - origin) / direction;
+            let t1 = (max - origin) / direction;
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        if near > far {
+            None
+        } else {
+            Some((near, far))
+        }
+    }
+
+    fn traverse(&self, ray: Ray, visited: &mut usize) -> Option<Intersection> {
+        if self.instances.is_empty() {
+            return None;
+        }
+        let (near, far) = self.clip(ray)?;
+
+        // Step through the cells crossed by the ray on the `x` and `z` axes
+        let entry = ray.origin + ray.direction * near;
+        let (column, row) = self.cell(entry.x, entry.z);
+        let (mut column, mut row) = (column as isize, row as isize);
+        let step = |origin: f64, direction: f64, lower: f64, i: isize| {
+            let edge = |i: isize| lower + i as f64 * self.size;
+            if direction > 0.0 {
+                (1, (edge(i + 1) - origin) / direction, self.size / direction)
+            } else if direction < 0.0 {
+                (-1, (edge(i) - origin) / direction, -self.size / direction)
+            } else {
+                (0, INFINITY, INFINITY)
+            }
+        };
+        let (step_x, mut next_x, delta_x) =
+            step(ray.origin.x, ray.direction.x, self.lower.x, column);
+        let (step_z, mut next_z, delta_z) =
+            step(ray.origin.z, ray.direction.z, self.lower.z, row);
+
+        let mut nearest = Intersection::none();
+        loop {
+            let cell =
+                &self.cells[row as usize * self.columns + column as usize];
+            for &i in cell {
+                *visited += 1;
+                if let Some(hit) =
+                    self.intersect_instance(&self.instances[i], ray)
+                {
+                    if hit.t < nearest.t {
+                        nearest = hit;
+                    }
+                }
+            }
+
+            // Instances span several cells, so a hit beyond this cell may
+            // still be behind one in the next
+            let exit = next_x.min(next_z);
+            if nearest.t <= exit || exit > far {
+                break;
+            }
+            if next_x < next_z {
+                column += step_x;
+                next_x += delta_x;
+            } else {
+                row += step_z;
+                next_z += delta_z;
+            }
+            let outside = column < 0
+                || row < 0
+                || column as usize >= self.columns
+                || row as usize >= self.rows;
+            if outside {
+                break;
+            }
+        }
+        nearest.to_option()
+    }
+}
+
+/// Return the radius and height of a shape at its unit scale
+fn extent(shape: InstanceOpts) -> (f64, f64) {
+    match shape {
+        InstanceOpts::Cone { radius, height } => (radius, height),
+        InstanceOpts::Sphere { radius } => (radius, radius * 2.0),
+    }
+}
+
+/// Return instances scattered at random over a raster of densities, in world
+/// space through a transform, and placed on a terrain
+pub fn place(
+    options: &ScatterOpts,
+    transform: &AffineTransform,
+    densities: &Texture<f64>,
+    terrain: &HeightMap,
+) -> Vec<Instance> {
+    let [_, _, a, d] = transform.coefficients();
+    let area = (a * d).abs();
+    let mut random = Random::new(options.seed);
+    let mut instances = vec![];
+
+    for row in 0..densities.height {
+        for column in 0..densities.width {
+            let value = densities.lookup1x1(column, row);
+            let weight = if options.classes.is_empty() {
+                value.max(0.0)
+            } else if options.classes.iter().any(|c| (c - value).abs() < 0.5) {
+                1.0
+            } else {
+                0.0
+            };
+
+            // Round the expected number of instances up or down at random,
+            // so sparse cells still receive their share
+            let expected = weight * area * options.density;
+            let count = expected.floor() as usize
+                + (random.next() < expected.fract()) as usize;
+
+            for _ in 0..count {
+                let u = column as f64 + random.next();
+                let v = row as f64 + random.next();
+                let scale =
+                    1.0 + options.variation * (random.next() * 2.0 - 1.0);
+                let (x, z) = transform.forward(u, v);
+
+                let y = match terrain.elevation(x, z) {
+                    Some(y) => y,
+                    None => continue,
+                };
+                if let Some([min, max]) = options.elevation {
+                    if y < min || y > max {
+                        continue;
+                    }
+                }
+                if let Some([min, max]) = options.slope {
+                    let (dx, dz) = terrain.gradient(x, z).unwrap();
+                    let slope = (dx * dx + dz * dz).sqrt().atan().to_degrees();
+                    if slope < min || slope > max {
+                        continue;
+                    }
+                }
+                instances.push(Instance {
+                    base: Vec3::new(x, y, z),
+                    scale,
+                });
+            }
+        }
+    }
+    instances
+}
+
+impl From<ScatterOpts> for Scatter {
+    fn from(options: ScatterOpts) -> Scatter {
+        let data = cache::heights(&options.data).unwrap();
+        let (ref proj4, transform, ref densities) = *data;
+        // The densities are scaled to world space in the same way as the
+        // terrain they are placed on
+        let transform = raster::scaled_transform(
+            proj4,
+            &transform,
+            densities.width,
+            densities.height,
+            options.terrain.scale,
+        );
+
+        let terrain = HeightMap::from(options.terrain.clone());
+        let instances = place(&options, &transform, densities, &terrain);
+        Scatter::new(options.instance, instances)
+    }
+}
+
+impl Primitive for Scatter {
+    fn intersects(&self, ray: Ray) -> Option<Intersection> {
+        self.traverse(ray, &mut 0)
+    }
+
+    fn cost(&self, ray: Ray) -> usize {
+        let mut visited = 0;
+        self.traverse(ray, &mut visited);
+        visited
+    }
+
+    fn memory(&self) -> usize {
+        let indices: usize = self.cells.iter().map(Vec::len).sum();
+        self.instances.len() * mem::size_of::<Instance>()
+            + self.cells.len() * mem::size_of::<Vec<usize>>()
+            + indices * mem::size_of::<usize>()
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        if self.instances.is_empty() {
+            None
+        } else {
+            Some(Aabb::new(self.lower, self.upper))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scattering_instances() {
+        // A slope rising one unit per unit east, with no trees in the west
+        // half and trees only on the first land cover class in the east
+        let heights = (0..9 * 9).map(|i| f64::from(i % 9)).collect();
+        let terrain =
+            HeightMap::new(AffineTransform::new(0.0, 0.0, 1.0, 1.0), &{
+                Texture::new(9, 9, heights)
+            });
+        let classes = (0..8 * 8).map(|i| f64::from(i % 8 / 4)).collect();
+        let densities = Texture::new(8, 8, classes);
+        let mut options: ScatterOpts = ::serde_json::from_value(json!({
+            "data": {"type": "png", "filepath": "classes.png"},
+            "classes": [1],
+            "density": 4.0,
+            "terrain": {"data": {"type": "png", "filepath": "heights.png"}},
+            "instance": {"type": "cone", "radius": 0.25, "height": 1.0},
+            "seed": 7,
+        }))
+        .unwrap();
+
+        let transform = AffineTransform::new(0.0, 0.0, 1.0, 1.0);
+        let instances = place(&options, &transform, &densities, &terrain);
+        assert_eq!(instances.len(), 4 * 8 * 4);
+        assert!(instances.iter().all(|instance| instance.base.x >= 4.0));
+        assert_eq!(
+            instances,
+            place(&options, &transform, &densities, &terrain)
+        );
+        let instance = instances[0];
+        assert!((instance.base.y - instance.base.x).abs() < 1e-9);
+
+        options.seed = 8;
+        assert_ne!(
+            instances,
+            place(&options, &transform, &densities, &terrain)
+        );
+        options.elevation = Some([0.0, 6.0]);
+        let lower = place(&options, &transform, &densities, &terrain);
+        assert!(lower.iter().all(|instance| instance.base.y <= 6.0));
+        options.slope = Some([50.0, 90.0]);
+        assert!(place(&options, &transform, &densities, &terrain).is_empty());
+
+        // Rays through the tip of a cone, beside it, and down onto it
+        let scatter = Scatter::new(
+            options.instance,
+            vec![Instance {
+                base: Vec3::new(0.0, 0.0, 0.0),
+                scale: 2.0,
+            }],
+        );
+        let ray = Ray::new(Vec3::new(0.0, 1.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let hit = scatter.intersects(ray).unwrap();
+        assert!((hit.t - 4.75).abs() < 1e-9);
+        assert!(hit.normal.z < 0.0 && hit.normal.y > 0.0);
+        let ray = Ray::new(Vec3::new(1.0, 1.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(scatter.intersects(ray), None);
+        let ray = Ray::new(Vec3::new(0.1, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let hit = scatter.intersects(ray).unwrap();
+        assert!((hit.t - 3.4).abs() < 1e-9);
+    }
+}
@@ -13,6 +13,7 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
+use super::aabb::Aabb;
 use super::primitive::{Intersection, Primitive};
 use math::{Ray, Vec3};
 use options::SphereOpts;
@@ -56,4 +57,9 @@ impl Primitive for Sphere {
         let normal = Vec3::normalize(p - self.position);
         Some(Intersection::new(t, normal))
     }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let radius = Vec3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.position - radius, self.position + radius))
+    }
 }
@@ -0,0 +1,126 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use math::Vec3;
+use ops::decode_srgb;
+
+use std::str::FromStr;
+
+/// A scale of colors for visualising data, with evenly spaced sRGB stops
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ramp {
+    Viridis,
+    Magma,
+    Cividis,
+    /// Greens through browns to white, for elevation
+    Hypsometric,
+    /// Deep blue through pale cyan to white, for glacier ice
+    Glacier,
+}
+
+const VIRIDIS: [u32; 9] = [
+    0x44_01_54, 0x47_2d_7b, 0x3b_52_8b, 0x2c_72_8e, 0x21_90_8c, 0x27_ad_81,
+    0x5d_c8_63, 0xaa_dc_32, 0xfd_e7_25,
+];
+
+const MAGMA: [u32; 9] = [
+    0x00_00_04, 0x1d_11_47, 0x51_12_7c, 0x82_26_81, 0xb6_36_79, 0xe6_51_64,
+    0xfb_88_61, 0xfe_c2_87, 0xfc_fd_bf,
+];
+
+const CIVIDIS: [u32; 10] = [
+    0x00_20_4d, 0x00_33_6f, 0x39_48_6b, 0x57_5c_6d, 0x70_71_73, 0x8a_87_79,
+    0xa6_9d_75, 0xc4_b5_6c, 0xe4_cf_5b, 0xff_ea_46,
+];
+
+const HYPSOMETRIC: [u32; 7] = [
+    0x46_7a_3c, 0x8c_b3_69, 0xe7_d9_8b, 0xd1_a3_5b, 0xa0_69_3a, 0xa8_a0_98,
+    0xff_ff_ff,
+];
+
+const GLACIER: [u32; 5] =
+    [0x3f_7f_b5, 0x6f_a8_cf, 0xa8_d4_e6, 0xd8_ee_f4, 0xfa_fd_ff];
+
+impl Ramp {
+    fn stops(&self) -> &'static [u32] {
+        match *self {
+            Ramp::Viridis => &VIRIDIS,
+            Ramp::Magma => &MAGMA,
+            Ramp::Cividis => &CIVIDIS,
+            Ramp::Hypsometric => &HYPSOMETRIC,
+            Ramp::Glacier => &GLACIER,
+        }
+    }
+
+    /// Return the linear color at a position from 0 to 1 along the ramp,
+    /// interpolating between stops in sRGB
+    pub fn color(&self, t: f64) -> Vec3 {
+        let stops = self.stops();
+        let srgb = |stop: u32| {
+            Vec3::new(
+                f64::from((stop >> 16) & 0xff),
+                f64::from((stop >> 8) & 0xff),
+                f64::from(stop & 0xff),
+            ) / 255.0
+        };
+
+        let t = if t.is_nan() { 0.0 } else { t.max(0.0).min(1.0) };
+        let position = t * (stops.len() - 1) as f64;
+        let i = (position as usize).min(stops.len() - 2);
+        let f = position - i as f64;
+        decode_srgb(srgb(stops[i]) * (1.0 - f) + srgb(stops[i + 1]) * f)
+    }
+}
+
+impl FromStr for Ramp {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Ramp, String> {
+        match name {
+            "viridis" => Ok(Ramp::Viridis),
+            "magma" => Ok(Ramp::Magma),
+            "cividis" => Ok(Ramp::Cividis),
+            "hypsometric" => Ok(Ramp::Hypsometric),
+            "glacier" => Ok(Ramp::Glacier),
+            _ => Err(format!("Unknown color ramp '{}'", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ops::encode_srgb;
+
+    use math::Color;
+
+    #[test]
+    fn interpolating_stops() {
+        let ramp = Ramp::Viridis;
+        assert_eq!(encode_srgb(ramp.color(0.0)), Color::new(0x44, 0x01, 0x54));
+        assert_eq!(encode_srgb(ramp.color(1.0)), Color::new(0xfd, 0xe7, 0x25));
+        assert_eq!(encode_srgb(ramp.color(2.0)), encode_srgb(ramp.color(1.0)));
+        // Halfway between the first two stops
+        let color = encode_srgb(ramp.color(0.0625));
+        assert_eq!(color, Color::new(0x46, 0x17, 0x68));
+    }
+
+    #[test]
+    fn parsing_names() {
+        assert_eq!("magma".parse(), Ok(Ramp::Magma));
+        assert!("rainbow".parse::<Ramp>().is_err());
+    }
+}
@@ -0,0 +1,90 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+//! Shaders and primitives registered by other crates, built from the options
+//! of scene objects with a type of `custom:<name>`.
+
+use options::CustomOpts;
+use primitives::Primitive;
+use shaders::Shader;
+
+use serde_json::{Map, Value};
+
+use std::sync::{Arc, RwLock};
+
+type Constructor<T> =
+    Arc<Fn(&Map<String, Value>) -> Result<T, String> + Send + Sync>;
+
+type Registry<T> = RwLock<Vec<(String, Constructor<T>)>>;
+
+static SHADERS: Registry<Arc<Shader>> = RwLock::new(Vec::new());
+static PRIMITIVES: Registry<Arc<Primitive>> = RwLock::new(Vec::new());
+
+/// Add a constructor, replacing any registered with the same name
+fn register<T>(
+    registry: &Registry<T>,
+    name: &str,
+    constructor: Constructor<T>,
+) {
+    let mut registry = registry.write().unwrap();
+    registry.retain(|&(ref existing, _)| existing != name);
+    registry.push((name.to_owned(), constructor));
+}
+
+/// Build a value with the constructor registered for its type
+fn build<T>(registry: &Registry<T>, kind: &str, options: &CustomOpts) -> T {
+    let constructor = registry
+        .read()
+        .unwrap()
+        .iter()
+        .find(|&&(ref name, _)| name == options.name())
+        .map(|&(_, ref constructor)| constructor.clone());
+    let constructor = match constructor {
+        Some(constructor) => constructor,
+        None => panic!("No {} registered as {}", kind, options.kind),
+    };
+    match constructor(&options.options) {
+        Ok(value) => value,
+        Err(err) => panic!("Could not create {}: {}", options.kind, err),
+    }
+}
+
+pub fn register_shader<F>(name: &str, constructor: F)
+where
+    F: Fn(&Map<String, Value>) -> Result<Arc<Shader>, String>
+        + Send
+        + Sync
+        + 'static,
+{
+    register(&SHADERS, name, Arc::new(constructor));
+}
+
+pub fn register_primitive<F>(name: &str, constructor: F)
+where
+    F: Fn(&Map<String, Value>) -> Result<Arc<Primitive>, String>
+        + Send
+        + Sync
+        + 'static,
+{
+    register(&PRIMITIVES, name, Arc::new(constructor));
+}
+
+pub fn shader(options: &CustomOpts) -> Arc<Shader> {
+    build(&SHADERS, "shader", options)
+}
+
+pub fn primitive(options: &CustomOpts) -> Arc<Primitive> {
+    build(&PRIMITIVES, "primitive", options)
+}
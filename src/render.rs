@@ -13,17 +13,60 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
-use lights::DirectionalLight;
+use debug::{DebugTracer, HitDebug, PixelDebug, SampleDebug};
+use diagnostics::{
+    heat_log, index_color, CountingTracer, RenderMode, MAX_DEPTH,
+    MAX_RAYS_PER_SAMPLE, MAX_TRAVERSAL_COST,
+};
+use labels::{place, Anchor};
+use lights::Light;
+use linework::{simplify, trace_edges, EdgeDetection, Linework, Polyline};
 use math::{Ray, Vec3};
 use primitives::Intersection;
 use samplers::{RegularGridSampler, Sampler};
-use scene::Scene;
-use shaders::{Shader, TraceInfo, Tracer};
+use scene::{Object, Scene};
+use shaders::{RayType, Shader, TraceInfo, Tracer};
+use textures::Texture;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::f64::INFINITY;
+
+/// Height from which points are dropped onto the scene when draping
+const DRAPE_HEIGHT: f64 = 1.0e7;
+
+/// Fraction of the distance to a point by which it may be occluded
+const OCCLUSION_TOLERANCE: f64 = 1.0e-3;
+
+/// Distance past a clipped intersection from which tracing resumes
+const CLIP_OFFSET: f64 = 1.0e-3;
+
+/// Largest number of clipped intersections passed through along a ray
+const MAX_CLIPPED: usize = 64;
+
+/// Fraction of light reflected by water seen from straight above
+const WATER_REFLECTANCE: f64 = 0.02;
+
+/// A shadow ray origin, rounded to the spacing of the cache, and direction
+type ShadowKey = (i64, i64, i64, u64, u64, u64);
+
+thread_local! {
+    /// Objects that last occluded shadow rays traced on this thread
+    static SHADOW_CACHE: RefCell<HashMap<ShadowKey, usize>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Forget the occluders of shadow rays traced on this thread, called as each
+/// tile is started
+pub fn clear_shadow_cache() {
+    SHADOW_CACHE.with(|cache| cache.borrow_mut().clear());
+}
 
 #[derive(Clone)]
 pub struct Renderer {
     scene: Scene,
     sampler: RegularGridSampler,
+    mode: RenderMode,
 }
 
 unsafe impl Send for Renderer {}
@@ -34,42 +77,488 @@ impl Renderer {
         Renderer {
             sampler: RegularGridSampler::new(multi_samples),
             scene,
+            mode: RenderMode::default(),
         }
     }
 
+    /// Return the number of samples taken for each pixel
+    pub fn samples(&self) -> usize {
+        self.sampler.amount()
+    }
+
+    /// Change the number of samples taken for each pixel
+    pub fn set_samples(&mut self, multi_samples: usize) {
+        self.sampler = RegularGridSampler::new(multi_samples);
+    }
+
+    /// Replace the scene shaders with a diagnostic visualisation
+    pub fn set_mode(&mut self, mode: RenderMode) {
+        self.mode = mode;
+    }
+
     /// Return a color for a pixel
     pub fn pixel(&self, x: usize, y: usize) -> Vec3 {
         let mut color = Vec3::zeros();
         let weight = 1.0 / self.sampler.amount() as f64;
 
         for (sub_x, sub_y) in self.sampler.samples() {
-            let px = x as f64 + sub_x;
-            let py = y as f64 + sub_y;
+            color += self.sample(x, y, *sub_x, *sub_y) * weight;
+        }
+
+        color
+    }
+
+    /// Return a color for a single sample at an offset (`u`, `v`) within a
+    /// pixel, where offsets range from 0 to 1
+    pub fn sample(&self, x: usize, y: usize, u: f64, v: f64) -> Vec3 {
+        let px = x as f64 + u;
+        let py = y as f64 + v;
+
+        if self.mode != RenderMode::Shaded {
+            return self.diagnostic(px, py);
+        }
 
-            let sub_color = if let Some(info) = self.trace_pixel(px, py) {
+        self.shade_sample(self, px, py).2
+    }
+
+    /// Return the ray cast through a position on the view plane, the surface
+    /// it hits and its color, shading through a tracer
+    fn shade_sample(
+        &self,
+        tracer: &Tracer,
+        x: f64,
+        y: f64,
+    ) -> (Ray, Option<TraceInfo>, Vec3) {
+        let ray = self.scene.camera.cast_ray(x, y);
+        match self.trace_camera(RayType::Camera, ray, x, y) {
+            Some(info) => {
+                let object = &self.scene.objects[info.primitive];
+                let shader = tracer.shader(object.shader).unwrap();
+                let color = shader.shade(tracer, &info);
+                let color = self.flood(ray, info.intersection.t, color);
+                (ray, Some(info), color)
+            }
+            None => (ray, None, self.scene.background.color(ray.direction)),
+        }
+    }
+
+    /// Return the color of a surface at a distance along a ray, as seen
+    /// through the water of the scene if it is below it
+    fn flood(&self, ray: Ray, t: f64, color: Vec3) -> Vec3 {
+        let water = match self.scene.water {
+            Some(ref water) => water,
+            None => return color,
+        };
+        let depth = water.elevation - (ray.origin.y + ray.direction.y * t);
+        if depth <= 0.0 {
+            return color;
+        }
+
+        // Distance travelled through the water, from its surface or from the
+        // origin if it is under water
+        let above = ray.origin.y - water.elevation;
+        let entry = if above > 0.0 {
+            above / -ray.direction.y
+        } else {
+            0.0
+        };
+        let transmittance = if water.clarity > 0.0 {
+            (-(t - entry).max(0.0) / water.clarity).exp()
+        } else {
+            0.0
+        };
+        let seen = color * transmittance + water.color * (1.0 - transmittance);
+        if above <= 0.0 {
+            return seen;
+        }
+
+        // Schlick's approximation of the light reflected off the surface
+        let cosine = (-ray.direction.y / Vec3::length(ray.direction)).min(1.0);
+        let fresnel = WATER_REFLECTANCE
+            + (1.0 - WATER_REFLECTANCE) * (1.0 - cosine).powi(5);
+        let reflected =
+            Vec3::new(ray.direction.x, -ray.direction.y, ray.direction.z);
+        seen * (1.0 - fresnel)
+            + self.scene.background.color(reflected) * fresnel
+    }
+
+    /// Return a false color for a position on the view plane
+    fn diagnostic(&self, x: f64, y: f64) -> Vec3 {
+        if self.mode == RenderMode::QuadtreeCost {
+            let ray = self.scene.camera.cast_ray(x, y);
+            let cost = self
+                .scene
+                .objects
+                .iter()
+                .filter(|object| object.visible(RayType::Camera))
+                .map(|object| self.scene.primitives[object.primitive].cost(ray))
+                .sum();
+            return heat_log(cost, MAX_TRAVERSAL_COST);
+        }
+
+        let tracer = CountingTracer::new(self);
+        let info = match tracer.trace_pixel(RayType::Camera, x, y) {
+            Some(info) => info,
+            None => return Vec3::zeros(),
+        };
+
+        match self.mode {
+            RenderMode::Normals => info.intersection.normal * 0.5 + 0.5,
+            RenderMode::Depth => {
+                let t = info.intersection.t.max(0.0);
+                let depth = (1.0 + t).ln() / (1.0 + MAX_DEPTH).ln();
+                Vec3::new(1.0, 1.0, 1.0) * (1.0 - depth).max(0.0)
+            }
+            RenderMode::ObjectId => index_color(info.primitive),
+            RenderMode::SampleHeatmap => {
                 let object = &self.scene.objects[info.primitive];
                 let shader = &self.scene.shaders[object.shader];
-                shader.shade(self, &info)
-            } else {
-                self.scene.background
+                shader.shade(&tracer, &info);
+                heat_log(tracer.rays.get(), MAX_RAYS_PER_SAMPLE)
+            }
+            RenderMode::Shaded | RenderMode::QuadtreeCost => unreachable!(),
+        }
+    }
+
+    /// Trace a pixel, recording the rays, hits and shader colors of each
+    /// sample
+    pub fn debug_pixel(&self, x: usize, y: usize) -> PixelDebug {
+        let shaders: Vec<&Shader> =
+            self.scene.shaders.iter().map(|shader| &**shader).collect();
+        let tracer = DebugTracer::new(self, &shaders);
+        let weight = 1.0 / self.sampler.amount() as f64;
+
+        let mut samples = vec![];
+        let mut color = Vec3::zeros();
+        for &(u, v) in self.sampler.samples() {
+            let px = x as f64 + u;
+            let py = y as f64 + v;
+            let (ray, info, sample) = self.shade_sample(&tracer, px, py);
+            let hit = info.map(|info| HitDebug {
+                object: info.primitive,
+                primitive: self.scene.objects[info.primitive].primitive,
+                t: info.intersection.t,
+                normal: info.intersection.normal,
+                position: info.position(),
+            });
+
+            color += sample * weight;
+            samples.push(SampleDebug {
+                u,
+                v,
+                ray,
+                hit,
+                shaders: tracer.take(),
+                color: sample,
+            });
+        }
+
+        PixelDebug {
+            x,
+            y,
+            samples,
+            color,
+        }
+    }
+
+    /// Return a point moved vertically onto the surface of the scene
+    fn drape(&self, point: Vec3) -> Vec3 {
+        let origin = Vec3::new(point.x, DRAPE_HEIGHT, point.z);
+        let ray = Ray::new(origin, Vec3::new(0.0, -1.0, 0.0));
+        match self.trace_ray(RayType::Camera, ray, 0.0, 0.0) {
+            Some(info) => info.position(),
+            None => point,
+        }
+    }
+
+    /// Return the distance from a point towards another at which the line
+    /// between them is first blocked by an object that casts shadows, or
+    /// `None` if the points see each other
+    pub fn first_blocker(&self, from: Vec3, to: Vec3) -> Option<f64> {
+        let distance = Vec3::length(to - from);
+        if distance == 0.0 {
+            return None;
+        }
+        // Points on surfaces are not blocked by the surfaces they are on
+        let ray = Ray::new(from, (to - from) / distance);
+        let near = distance * OCCLUSION_TOLERANCE;
+        let far = distance * (1.0 - OCCLUSION_TOLERANCE);
+        self.trace_range(RayType::Shadow, ray, near, far)
+            .map(|(intersection, _)| intersection.t)
+    }
+
+    /// Return true if nothing blocks the line between two points, such as
+    /// summits or an antenna and a point of the terrain
+    pub fn line_of_sight(&self, from: Vec3, to: Vec3) -> bool {
+        self.first_blocker(from, to).is_none()
+    }
+
+    /// Trace a batch of rays for the objects seen by the camera, sharing the
+    /// traversal of primitives between rays from the same origin, so coherent
+    /// rays are traced faster than one at a time
+    pub fn trace_rays(&self, rays: &[Ray]) -> Vec<Option<TraceInfo>> {
+        let mut nearest = vec![(Intersection::none(), 0); rays.len()];
+        for (i, obj) in self.scene.objects.iter().enumerate() {
+            if !obj.visible(RayType::Camera) {
+                continue;
+            }
+            let primitive = &self.scene.primitives[obj.primitive];
+            let hits = primitive.intersects_batch(rays);
+            for (nearest, hit) in nearest.iter_mut().zip(hits) {
+                if let Some(other) = hit {
+                    if other.t < nearest.0.t && other.t > self.scene.ray_epsilon
+                    {
+                        *nearest = (other, i);
+                    }
+                }
+            }
+        }
+
+        rays.iter()
+            .zip(nearest)
+            .map(|(&ray, (intersection, index))| {
+                if intersection.is_none() {
+                    None
+                } else if self.unclipped(ray, intersection.t) {
+                    Some(self.hit(ray, intersection, index, 0.0, 0.0))
+                } else {
+                    // Pass through the surfaces cut away one ray at a time
+                    self.trace_range(RayType::Camera, ray, 0.0, INFINITY).map(
+                        |(intersection, index)| {
+                            self.hit(ray, intersection, index, 0.0, 0.0)
+                        },
+                    )
+                }
+            })
+            .collect()
+    }
+
+    /// Return true if a point, projected to a view plane position, is not
+    /// hidden by anything in the scene
+    fn unoccluded(&self, point: Vec3, x: f64, y: f64) -> bool {
+        let ray = self.scene.camera.cast_ray(x, y);
+        let t = Vec3::dot(point - ray.origin, ray.direction);
+        match self.trace_camera(RayType::Camera, ray, x, y) {
+            Some(info) => {
+                info.intersection.t >= t * (1.0 - OCCLUSION_TOLERANCE)
+            }
+            None => true,
+        }
+    }
+
+    /// Return the surface normals and distances seen through the center of
+    /// each pixel, with background pixels having no normal and an infinite
+    /// distance
+    pub fn guides(&self) -> (Texture<Vec3>, Texture<f64>) {
+        let (width, height) = self.scene.camera.view_plane();
+        let mut normals = Texture::blank(width, height);
+        let mut depths = Texture::blank(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+                let (normal, depth) =
+                    match self.trace_pixel(RayType::Camera, px, py) {
+                        Some(info) => {
+                            (info.intersection.normal, info.intersection.t)
+                        }
+                        None => (Vec3::zeros(), INFINITY),
+                    };
+                normals.write1x1(x, y, normal);
+                depths.write1x1(x, y, depth);
+            }
+        }
+        (normals, depths)
+    }
+
+    /// Return a mask of pixels lying on silhouette or crease edges
+    pub fn edge_mask(&self, options: &EdgeDetection) -> Texture<bool> {
+        let (width, height) = self.scene.camera.view_plane();
+        let mut traces = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+                traces.push(self.trace_pixel(RayType::Stencil, px, py));
+            }
+        }
+
+        let is_edge =
+            |a: &Option<TraceInfo>, b: &Option<TraceInfo>| match (a, b) {
+                (Some(a), Some(b)) => {
+                    a.primitive != b.primitive
+                        || (a.intersection.t - b.intersection.t).abs()
+                            > options.silhouette_threshold
+                        || Vec3::angle(
+                            a.intersection.normal,
+                            b.intersection.normal,
+                        ) > options.crease_threshold
+                }
+                (None, None) => false,
+                _ => true,
             };
 
-            color += sub_color * weight;
+        let mut mask = Texture::blank(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let trace = &traces[y * width + x];
+                let right =
+                    x + 1 < width && is_edge(trace, &traces[y * width + x + 1]);
+                let below = y + 1 < height
+                    && is_edge(trace, &traces[(y + 1) * width + x]);
+                mask.write1x1(x, y, right || below);
+            }
         }
 
-        color
+        mask
+    }
+
+    /// Project the linework layers of the scene onto the view plane, along
+    /// with the labels of its named points
+    pub fn linework(&self) -> Linework {
+        let (width, height) = self.scene.camera.view_plane();
+        let mut output = Linework::new(width, height);
+
+        for layer in &self.scene.linework {
+            let mut flush = |points: &mut Vec<(f64, f64)>| {
+                if points.len() > 1 {
+                    output.polylines.push(Polyline {
+                        points: points.clone(),
+                        color: layer.color,
+                        width: layer.width,
+                    });
+                }
+                points.clear();
+            };
+
+            for shape in layer.shapes.iter() {
+                for line in shape.lines() {
+                    let mut points = vec![];
+                    for point in line {
+                        let point = if layer.drape {
+                            self.drape(*point)
+                        } else {
+                            *point
+                        };
+
+                        // Break the line at points behind the camera, which
+                        // have no projection, and at hidden points
+                        match self.scene.camera.project(point) {
+                            Some((x, y))
+                                if !layer.occlusion
+                                    || self.unoccluded(point, x, y) =>
+                            {
+                                points.push((x, y))
+                            }
+                            _ => flush(&mut points),
+                        }
+                    }
+                    flush(&mut points);
+                }
+            }
+        }
+
+        let mut anchors = vec![];
+        for layer in &self.scene.labels {
+            for &(point, ref text) in &layer.points {
+                let point = if layer.drape {
+                    self.drape(point)
+                } else {
+                    point
+                };
+                let (x, y) = match self.scene.camera.project(point) {
+                    Some(position) => position,
+                    None => continue,
+                };
+                let inside = x >= 0.0
+                    && y >= 0.0
+                    && x < width as f64
+                    && y < height as f64;
+                if !inside || (layer.occlusion && !self.unoccluded(point, x, y))
+                {
+                    continue;
+                }
+                // Higher points are usually the more important to name
+                anchors.push(Anchor {
+                    text: text.clone(),
+                    point: (x, y),
+                    priority: point.y,
+                    size: layer.size,
+                    color: layer.color,
+                    offset: layer.offset,
+                });
+            }
+        }
+        let (labels, leaders) = place(&anchors, width as f64, height as f64);
+        output.labels = labels;
+        output.polylines.extend(leaders);
+
+        if let Some(ref edges) = self.scene.edges {
+            for line in trace_edges(&self.edge_mask(edges)) {
+                output.polylines.push(Polyline {
+                    points: simplify(&line, edges.tolerance),
+                    color: edges.color,
+                    width: edges.width,
+                });
+            }
+        }
+
+        output
     }
 }
 
-impl Tracer for Renderer {
-    fn trace_ray(&self, ray: Ray, x: f64, y: f64) -> Option<TraceInfo> {
+impl Renderer {
+    /// Return the result of a trace for an intersection with an object
+    fn hit(
+        &self,
+        ray: Ray,
+        mut intersection: Intersection,
+        index: usize,
+        x: f64,
+        y: f64,
+    ) -> TraceInfo {
+        if self.scene.face_forward {
+            intersection = intersection.face_forward(ray.direction);
+        }
+
+        TraceInfo {
+            ray,
+            intersection,
+            primitive: index,
+            x,
+            y,
+        }
+    }
+
+    /// Trace a ray cast by the camera, hiding surfaces outside of its
+    /// clipping range
+    fn trace_camera(
+        &self,
+        kind: RayType,
+        ray: Ray,
+        x: f64,
+        y: f64,
+    ) -> Option<TraceInfo> {
+        let (near, far) = self.scene.camera.clip_range(&ray);
+        let (intersection, index) = self.trace_range(kind, ray, near, far)?;
+        Some(self.hit(ray, intersection, index, x, y))
+    }
+
+    /// Return the nearest intersection with an object along a ray
+    fn nearest(
+        &self,
+        kind: RayType,
+        ray: Ray,
+    ) -> Option<(Intersection, usize)> {
         let mut index = 0;
         let mut intersection = Intersection::none();
 
         for (i, obj) in self.scene.objects.iter().enumerate() {
+            if !obj.visible(kind) {
+                continue;
+            }
             let primitive = &self.scene.primitives[obj.primitive];
             if let Some(other) = primitive.intersects(ray) {
-                if other.t < intersection.t && other.t > 0.0 {
+                if other.t < intersection.t && other.t > self.scene.ray_epsilon
+                {
                     intersection = other;
                     index = i;
                 }
@@ -79,26 +568,377 @@ impl Tracer for Renderer {
         if intersection.is_none() {
             None
         } else {
-            Some(TraceInfo {
-                ray,
-                intersection,
-                primitive: index,
-                x,
-                y,
+            Some((intersection, index))
+        }
+    }
+
+    /// Return whether an intersection at a distance along a ray is within the
+    /// elevations of the scene that are rendered
+    fn unclipped(&self, ray: Ray, t: f64) -> bool {
+        match self.scene.clip_elevation {
+            Some((low, high)) => {
+                let y = ray.origin.y + ray.direction.y * t;
+                y >= low && y <= high
+            }
+            None => true,
+        }
+    }
+
+    /// Return the nearest intersection between two distances along a ray,
+    /// passing through those cut away by the elevation clipping of the scene
+    fn trace_range(
+        &self,
+        kind: RayType,
+        ray: Ray,
+        near: f64,
+        far: f64,
+    ) -> Option<(Intersection, usize)> {
+        let mut start = near.max(0.0);
+        for _ in 0..MAX_CLIPPED {
+            let moved = Ray {
+                origin: ray.origin + ray.direction * start,
+                ..ray
+            };
+            let (mut intersection, index) = self.nearest(kind, moved)?;
+            intersection.t += start;
+            if intersection.t > far {
+                return None;
+            }
+            if self.unclipped(ray, intersection.t) {
+                return Some((intersection, index));
+            }
+            start = intersection.t + CLIP_OFFSET;
+        }
+        None
+    }
+
+    /// Test a shadow ray against the object that last occluded a ray near
+    /// it, as any occluder is enough to put its origin in shadow
+    fn cached_shadow(
+        &self,
+        key: ShadowKey,
+        ray: Ray,
+        x: f64,
+        y: f64,
+    ) -> Option<TraceInfo> {
+        let index =
+            SHADOW_CACHE.with(|cache| cache.borrow().get(&key).cloned())?;
+        let intersection = self
+            .scene
+            .objects
+            .get(index)
+            .filter(|object| object.visible(RayType::Shadow))
+            .and_then(|object| {
+                self.scene.primitives[object.primitive].intersects(ray)
             })
+            .filter(|intersection| intersection.t > self.scene.ray_epsilon)
+            .filter(|intersection| self.unclipped(ray, intersection.t));
+
+        match intersection {
+            Some(intersection) => {
+                Some(self.hit(ray, intersection, index, x, y))
+            }
+            None => {
+                SHADOW_CACHE.with(|cache| cache.borrow_mut().remove(&key));
+                None
+            }
+        }
+    }
+}
+
+impl Tracer for Renderer {
+    fn trace_ray(
+        &self,
+        kind: RayType,
+        ray: Ray,
+        x: f64,
+        y: f64,
+    ) -> Option<TraceInfo> {
+        let key = match self.scene.shadow_cache {
+            Some(spacing) if kind == RayType::Shadow => {
+                let cell = |v: f64| (v / spacing).floor() as i64;
+                let (origin, direction) = (ray.origin, ray.direction);
+                let key = (
+                    cell(origin.x),
+                    cell(origin.y),
+                    cell(origin.z),
+                    direction.x.to_bits(),
+                    direction.y.to_bits(),
+                    direction.z.to_bits(),
+                );
+                if let Some(info) = self.cached_shadow(key, ray, x, y) {
+                    return Some(info);
+                }
+                Some(key)
+            }
+            _ => None,
+        };
+
+        let (intersection, index) =
+            self.trace_range(kind, ray, 0.0, INFINITY)?;
+        if let Some(key) = key {
+            SHADOW_CACHE.with(|cache| cache.borrow_mut().insert(key, index));
         }
+        Some(self.hit(ray, intersection, index, x, y))
     }
 
-    fn trace_pixel(&self, x: f64, y: f64) -> Option<TraceInfo> {
+    fn trace_pixel(&self, kind: RayType, x: f64, y: f64) -> Option<TraceInfo> {
         let ray = self.scene.camera.cast_ray(x, y);
-        self.trace_ray(ray, x, y)
+        self.trace_camera(kind, ray, x, y)
+    }
+
+    fn secondary_ray(&self, info: &TraceInfo, direction: Vec3) -> Ray {
+        // Offset to the side of the surface the ray is leaving from
+        let normal = info.intersection.normal;
+        let side = if Vec3::dot(direction, normal) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        let origin = info.position() + normal * self.scene.normal_offset * side;
+        Ray::new(origin, direction)
     }
 
     fn shader(&self, index: usize) -> Option<&Shader> {
         self.scene.shaders.get(index).map(|shader| &**shader)
     }
 
-    fn light(&self, index: usize) -> Option<&DirectionalLight> {
+    fn light(&self, index: usize) -> Option<&Light> {
         self.scene.lights.get(index).map(|light| &**light)
     }
+
+    fn object(&self, index: usize) -> Option<&Object> {
+        self.scene.objects.get(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cameras::OrthographicCamera;
+    use primitives::{Plane, Sphere};
+    use scene::{Background, Water};
+
+    use std::sync::Arc;
+
+    /// A unit sphere above the origin, with the shadow cache enabled
+    fn renderer() -> Renderer {
+        let scene = Scene {
+            background: Background::Color(Vec3::zeros()),
+            camera: Arc::new(OrthographicCamera::new(
+                4,
+                4,
+                Vec3::new(0.0, 10.0, 0.0),
+                Vec3::zeros(),
+                1.0,
+                Vec3::new(0.0, 0.0, 1.0),
+                4.0,
+            )),
+            shaders: vec![],
+            primitives: vec![Arc::new(Sphere::new(
+                Vec3::new(0.0, 5.0, 0.0),
+                1.0,
+            ))],
+            objects: vec![Object::new(0, 0)],
+            lights: vec![],
+            linework: vec![],
+            labels: vec![],
+            edges: None,
+            ray_epsilon: 0.0,
+            normal_offset: 0.0,
+            face_forward: true,
+            shadow_cache: Some(10.0),
+            clip_elevation: None,
+            water: None,
+        };
+        Renderer::new(1, scene)
+    }
+
+    #[test]
+    fn hiding_objects_from_rays() {
+        let mut renderer = renderer();
+        renderer.scene.shadow_cache = None;
+        renderer
+            .scene
+            .primitives
+            .push(Arc::new(Plane::new(Vec3::new(0.0, 1.0, 0.0), 0.0)));
+        renderer.scene.objects.push(Object::new(1, 0));
+
+        let seen = |renderer: &Renderer| {
+            renderer
+                .trace_pixel(RayType::Camera, 2.0, 2.0)
+                .map(|info| info.primitive)
+        };
+        let up = Ray::new(Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let shadowed = |renderer: &Renderer| {
+            renderer.trace_ray(RayType::Shadow, up, 2.0, 2.0).is_some()
+        };
+        assert_eq!(seen(&renderer), Some(0));
+        assert!(shadowed(&renderer));
+
+        // Hidden from the camera, the sphere still casts a shadow
+        renderer.scene.objects[0].camera = false;
+        assert_eq!(seen(&renderer), Some(1));
+        assert!(shadowed(&renderer));
+
+        // Hidden from shadow rays, the sphere is still seen
+        renderer.scene.objects[0].camera = true;
+        renderer.scene.objects[0].shadow = false;
+        assert_eq!(seen(&renderer), Some(0));
+        assert!(!shadowed(&renderer));
+    }
+
+    #[test]
+    fn offsetting_secondary_rays() {
+        let mut renderer = renderer();
+        renderer.scene.shadow_cache = None;
+        let normal = Vec3::normalize(Vec3::new(1.0, 1.0, 0.0));
+        renderer.scene.primitives = vec![Arc::new(Plane::new(normal, 0.0))];
+
+        // A hit on a tilted plane, found just beyond it by rounding errors
+        let ray = Ray::new(Vec3::new(2.0, 3.0, 0.5), Vec3::new(0.0, -1.0, 0.0));
+        let mut info =
+            renderer.trace_ray(RayType::Camera, ray, 0.0, 0.0).unwrap();
+        info.intersection.t += 1e-9;
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let shadowed = |renderer: &Renderer| {
+            let ray = renderer.secondary_ray(&info, up);
+            renderer.trace_ray(RayType::Shadow, ray, 0.0, 0.0).is_some()
+        };
+
+        // Without an offset, rays start at the hit and see the plane itself
+        let secondary = renderer.secondary_ray(&info, up);
+        assert_eq!(secondary.origin, info.position());
+        assert!(shadowed(&renderer));
+
+        renderer.scene.normal_offset = 1e-6;
+        assert!(!shadowed(&renderer));
+    }
+
+    #[test]
+    fn retesting_cached_occluders() {
+        let renderer = renderer();
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        clear_shadow_cache();
+
+        let shadowed = Ray::new(Vec3::new(0.0, 0.0, 0.0), up);
+        let hit = renderer.trace_ray(RayType::Shadow, shadowed, 0.0, 0.0);
+        assert_eq!(hit.map(|info| info.intersection.t), Some(4.0));
+
+        // Shares the cached occluder, but passes beside it
+        let lit = Ray::new(Vec3::new(2.0, 0.0, 0.0), up);
+        assert!(renderer.trace_ray(RayType::Shadow, lit, 0.0, 0.0).is_none());
+
+        let nearby = Ray::new(Vec3::new(0.5, 0.0, 0.0), up);
+        let hit = renderer.trace_ray(RayType::Shadow, nearby, 0.0, 0.0);
+        assert!(hit.is_some());
+    }
+
+    #[test]
+    fn seeing_between_points() {
+        let renderer = renderer();
+        let (below, above) =
+            (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 10.0, 0.0));
+        assert!(!renderer.line_of_sight(below, above));
+        assert_eq!(renderer.first_blocker(below, above), Some(4.0));
+        assert_eq!(renderer.first_blocker(above, below), Some(4.0));
+
+        let beside = Vec3::new(2.0, 10.0, 0.0);
+        assert!(renderer.line_of_sight(Vec3::new(2.0, 0.0, 0.0), beside));
+        // Points on the surface of the sphere see out from it
+        assert!(renderer.line_of_sight(Vec3::new(0.0, 6.0, 0.0), above));
+        assert!(renderer.line_of_sight(below, below));
+    }
+
+    #[test]
+    fn tracing_batches_of_rays() {
+        let mut renderer = renderer();
+        renderer
+            .scene
+            .primitives
+            .push(Arc::new(Plane::new(Vec3::new(0.0, 1.0, 0.0), 0.0)));
+        renderer.scene.objects.push(Object::new(1, 0));
+        renderer.scene.clip_elevation = Some((-1.0, 5.5));
+
+        let rays: Vec<Ray> = (0..8)
+            .map(|i| {
+                let x = f64::from(i) * 0.5 - 2.0;
+                let direction = Vec3::normalize(Vec3::new(x, -1.0, 0.0));
+                Ray::new(Vec3::new(0.0, 10.0, 0.0), direction)
+            })
+            .chain(vec![Ray::new(Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0))])
+            .collect();
+        let traced = renderer.trace_rays(&rays);
+        assert_eq!(traced.len(), rays.len());
+        for (ray, info) in rays.iter().zip(traced) {
+            let expected =
+                renderer.trace_range(RayType::Camera, *ray, 0.0, INFINITY);
+            let expected = expected.map(|(hit, index)| (hit.t, index));
+            let found = info.map(|info| (info.intersection.t, info.primitive));
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn flooding_surfaces() {
+        let mut renderer = renderer();
+        let blue = Vec3::new(0.0, 0.0, 1.0);
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        renderer.scene.water = Some(Water {
+            elevation: 4.5,
+            color: blue,
+            clarity: 0.0,
+        });
+        let down =
+            Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(renderer.flood(down, 4.0, white), white);
+        // Reflecting the black background straight back up
+        assert_eq!(renderer.flood(down, 6.0, white), blue * 0.98);
+
+        renderer.scene.water = Some(Water {
+            elevation: 4.5,
+            color: blue,
+            clarity: 1.0,
+        });
+        let transmittance = (-0.5f64).exp();
+        let expected =
+            (white * transmittance + blue * (1.0 - transmittance)) * 0.98;
+        let color = renderer.flood(down, 6.0, white);
+        assert!(Vec3::length(color - expected) < 1e-9);
+    }
+
+    #[test]
+    fn clipping_surfaces() {
+        let mut renderer = renderer();
+        let depth = |renderer: &Renderer| {
+            renderer
+                .trace_pixel(RayType::Camera, 2.0, 2.0)
+                .map(|info| info.intersection.t)
+        };
+        assert_eq!(depth(&renderer), Some(4.0));
+
+        // Cut away the sphere, seeing the ground beneath it
+        renderer
+            .scene
+            .primitives
+            .push(Arc::new(Plane::new(Vec3::new(0.0, 1.0, 0.0), 0.0)));
+        renderer.scene.objects.push(Object::new(1, 0));
+        let camera = OrthographicCamera::new(
+            4,
+            4,
+            Vec3::new(0.0, 10.0, 0.0),
+            Vec3::zeros(),
+            1.0,
+            Vec3::new(0.0, 0.0, 1.0),
+            4.0,
+        );
+        renderer.scene.camera = Arc::new(camera.with_clipping(4.5, None));
+        assert_eq!(depth(&renderer), Some(10.0));
+        renderer.scene.camera = Arc::new(camera.with_clipping(0.0, Some(3.0)));
+        assert_eq!(depth(&renderer), None);
+
+        renderer.scene.camera = Arc::new(camera);
+        renderer.scene.clip_elevation = Some((-1.0, 3.0));
+        assert_eq!(depth(&renderer), Some(10.0));
+    }
 }
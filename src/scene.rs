@@ -13,46 +13,192 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
-use cameras::{Camera, OrthographicCamera, PinholeCamera};
-use lights::DirectionalLight;
+use cameras::{
+    Camera, EquirectangularCamera, OrthographicCamera, PinholeCamera,
+};
+use io::cache::{scope, LoaderCache};
+use labels::LabelLayer;
+use lights::Light;
+use linework::{EdgeDetection, LineLayer};
 use math::Vec3;
 use options::{
-    CameraOpts, LightOpts, ObjectOpts, PrimitiveOpts, SceneOpts, ShaderOpts,
+    AabbOpts, Anchor, BackgroundOpts, CameraOpts, DetailNormalShaderOpts,
+    DetailOpts, DirectionalLightOpts, ExtrusionOpts, FrameOpts, GroupOpts,
+    HeightMapOpts, LightOpts, MarkerOpts, ObjectOpts, PrimitiveOpts,
+    ScatterOpts, SceneOpts, ShaderOpts, ShaderRef, SphereOpts, WaterOpts,
+};
+use primitives::{
+    Aabb, BilinearPatch, Extrusion, HeightMap, Marker, Plane, Primitive,
+    Scatter, Sphere,
 };
-use primitives::{Aabb, BilinearPatch, HeightMap, Plane, Primitive, Sphere};
+use registry;
 use shaders::{
-    ConstantShader, FeatureLineShader, NormalShader, PhongShader, SdfShader,
-    Shader, TextureShader,
+    ConstantShader, DepthCueShader, DetailNormalShader, FeatureLineShader,
+    GlacierShader, GridShader, MatteShader, NormalShader, PhongShader, RayType,
+    SdfShader, Shader, TextureShader, VectorLayerShader,
 };
+use shapes::Shape;
+
+use serde_json::{self, Map, Value};
 
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind, Result as IoResult};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Object {
     pub primitive: usize,
     pub shader: usize,
+    /// Visible to rays cast from the camera
+    pub camera: bool,
+    /// Visible to shadow rays, i.e. the object occludes lights
+    pub shadow: bool,
+    /// Visible to stencil rays used for detecting feature lines
+    pub stencil: bool,
 }
 
 impl Object {
     pub fn new(primitive: usize, shader: usize) -> Object {
-        Object { primitive, shader }
+        Object {
+            primitive,
+            shader,
+            camera: true,
+            shadow: true,
+            stencil: true,
+        }
+    }
+
+    /// Return true if the object should be tested against a type of ray
+    pub fn visible(&self, kind: RayType) -> bool {
+        match kind {
+            RayType::Camera => self.camera,
+            RayType::Shadow => self.shadow,
+            RayType::Stencil => self.stencil,
+        }
     }
 }
 
 impl From<ObjectOpts> for Object {
     fn from(options: ObjectOpts) -> Object {
-        Object::new(options.primitive, options.shader)
+        Object {
+            camera: options.camera,
+            shadow: options.shadow,
+            stencil: options.stencil,
+            ..Object::new(options.primitive, options.shader)
+        }
+    }
+}
+
+/// Estimated bytes held by the resources of a scene
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MemoryUsage {
+    pub primitives: usize,
+    pub shaders: usize,
+    /// Vector shapes, counted once however many shaders and line layers
+    /// share them
+    pub shapes: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.primitives + self.shaders + self.shapes
+    }
+}
+
+/// The color seen by rays that hit nothing
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Background {
+    Color(Vec3),
+    Gradient {
+        horizon: Vec3,
+        zenith: Vec3,
+        ground: Vec3,
+        softness: f64,
+    },
+}
+
+impl Background {
+    /// Return the color seen in a direction
+    pub fn color(&self, direction: Vec3) -> Vec3 {
+        match *self {
+            Background::Color(color) => color,
+            Background::Gradient {
+                horizon,
+                zenith,
+                ground,
+                softness,
+            } => {
+                // Sine of the angle above the horizon
+                let sine = Vec3::normalize(direction).y;
+                let (t, other) = if sine >= 0.0 {
+                    (sine, zenith)
+                } else {
+                    (-sine, ground)
+                };
+                let t = t.min(1.0).powf(softness.max(0.0));
+                horizon * (1.0 - t) + other * t
+            }
+        }
+    }
+}
+
+impl From<BackgroundOpts> for Background {
+    fn from(options: BackgroundOpts) -> Background {
+        match options {
+            BackgroundOpts::Color(color) => {
+                Background::Color(From::from(color))
+            }
+            BackgroundOpts::Gradient(opts) => Background::Gradient {
+                horizon: From::from(opts.horizon),
+                zenith: From::from(opts.zenith),
+                ground: From::from(opts.ground.unwrap_or(opts.horizon)),
+                softness: opts.softness,
+            },
+        }
+    }
+}
+
+/// Water filling everything below an elevation, tinting the surfaces beneath
+/// it with depth and reflecting the background
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Water {
+    pub elevation: f64,
+    pub color: Vec3,
+    /// Distance through the water over which light falls to 1/e, opaque when
+    /// zero
+    pub clarity: f64,
+}
+
+impl From<WaterOpts> for Water {
+    fn from(options: WaterOpts) -> Water {
+        Water {
+            elevation: options.elevation,
+            color: From::from(options.color),
+            clarity: options.clarity,
+        }
     }
 }
 
 #[derive(Clone)]
 pub struct Scene {
-    pub background: Vec3,
+    pub background: Background,
     pub camera: Arc<Camera>,
     pub shaders: Vec<Arc<Shader>>,
     pub primitives: Vec<Arc<Primitive>>,
     pub objects: Vec<Object>,
-    pub lights: Vec<Arc<DirectionalLight>>,
+    pub lights: Vec<Arc<Light>>,
+    pub linework: Vec<Arc<LineLayer>>,
+    pub labels: Vec<Arc<LabelLayer>>,
+    pub edges: Option<EdgeDetection>,
+    pub ray_epsilon: f64,
+    pub normal_offset: f64,
+    pub face_forward: bool,
+    pub shadow_cache: Option<f64>,
+    /// Lowest and highest elevations of visible surfaces
+    pub clip_elevation: Option<(f64, f64)>,
+    pub water: Option<Water>,
 }
 
 macro_rules! resource {
@@ -69,6 +215,9 @@ impl From<CameraOpts> for Arc<Camera> {
             CameraOpts::Orthographic(opts) => {
                 resource!(OrthographicCamera, opts)
             }
+            CameraOpts::Equirectangular(opts) => {
+                resource!(EquirectangularCamera, opts)
+            }
         }
     }
 }
@@ -77,13 +226,24 @@ impl From<ShaderOpts> for Arc<Shader> {
     fn from(opts: ShaderOpts) -> Arc<Shader> {
         match opts {
             ShaderOpts::Constant(opts) => resource!(ConstantShader, opts),
+            ShaderOpts::DepthCue(opts) => resource!(DepthCueShader, opts),
+            ShaderOpts::DetailNormal(opts) => {
+                resource!(DetailNormalShader, opts)
+            }
             ShaderOpts::FeatureLines(opts) => {
                 resource!(FeatureLineShader, opts)
             }
+            ShaderOpts::Glacier(opts) => resource!(GlacierShader, opts),
+            ShaderOpts::Grid(opts) => resource!(GridShader, opts),
+            ShaderOpts::Matte(opts) => resource!(MatteShader, opts),
             ShaderOpts::Normal(opts) => resource!(NormalShader, opts),
             ShaderOpts::Phong(opts) => resource!(PhongShader, opts),
             ShaderOpts::Sdf(opts) => resource!(SdfShader, opts),
             ShaderOpts::Texture(opts) => resource!(TextureShader, opts),
+            ShaderOpts::VectorLayers(opts) => {
+                resource!(VectorLayerShader, opts)
+            }
+            ShaderOpts::Custom(opts) => registry::shader(&opts),
         }
     }
 }
@@ -95,17 +255,343 @@ impl From<PrimitiveOpts> for Arc<Primitive> {
             PrimitiveOpts::BilinearPatch(opts) => {
                 resource!(BilinearPatch, opts)
             }
+            PrimitiveOpts::Extrusion(opts) => resource!(Extrusion, opts),
             PrimitiveOpts::HeightMap(opts) => resource!(HeightMap, opts),
+            PrimitiveOpts::Marker(opts) => resource!(Marker, opts),
             PrimitiveOpts::Plane(opts) => resource!(Plane, opts),
+            PrimitiveOpts::Scatter(opts) => resource!(Scatter, opts),
             PrimitiveOpts::Sphere(opts) => resource!(Sphere, opts),
+            PrimitiveOpts::Custom(opts) => registry::primitive(&opts),
         }
     }
 }
 
-impl From<LightOpts> for Arc<DirectionalLight> {
-    fn from(opts: LightOpts) -> Arc<DirectionalLight> {
-        match opts {
-            LightOpts::Directional(opts) => resource!(DirectionalLight, opts),
+impl From<LightOpts> for Arc<Light> {
+    fn from(opts: LightOpts) -> Arc<Light> {
+        resource!(Light, opts)
+    }
+}
+
+/// Move inline shaders to the end of the shader list, replacing them with
+/// references by index
+pub fn flatten_shaders(mut shaders: Vec<ShaderOpts>) -> Vec<ShaderOpts> {
+    let mut i = 0;
+    while i < shaders.len() {
+        let mut next = shaders.len();
+        let mut inline = vec![];
+        for child in shaders[i].children_mut() {
+            match mem::replace(child, ShaderRef::Index(next)) {
+                ShaderRef::Inline(opts) => {
+                    inline.push(*opts);
+                    next += 1;
+                }
+                reference => *child = reference,
+            }
+        }
+        shaders.append(&mut inline);
+        i += 1;
+    }
+    shaders
+}
+
+/// Return the options of every height map of primitives and lights,
+/// including the terrain that shapes are draped onto
+pub fn height_maps_mut<'a>(
+    primitives: &'a mut [PrimitiveOpts],
+    lights: &'a mut [LightOpts],
+) -> Vec<&'a mut HeightMapOpts> {
+    let mut height_maps = vec![];
+    for primitive in primitives {
+        let opts = match *primitive {
+            PrimitiveOpts::HeightMap(ref mut opts)
+            | PrimitiveOpts::Scatter(ScatterOpts {
+                terrain: ref mut opts,
+                ..
+            }) => opts,
+            PrimitiveOpts::Extrusion(ExtrusionOpts {
+                terrain: Some(ref mut opts),
+                ..
+            })
+            | PrimitiveOpts::Marker(MarkerOpts {
+                terrain: Some(ref mut opts),
+                ..
+            }) => opts,
+            _ => continue,
+        };
+        height_maps.push(opts);
+    }
+    for light in lights {
+        if let LightOpts::Directional(DirectionalLightOpts {
+            shadow_map: Some(ref mut opts),
+            ..
+        }) = *light
+        {
+            height_maps.push(opts);
+        }
+    }
+    height_maps
+}
+
+/// Give anchored shapes and markers without a terrain the first height map
+/// of the scene
+fn anchor_shapes(primitives: &mut [PrimitiveOpts]) {
+    let terrain = primitives.iter().find_map(|primitive| match *primitive {
+        PrimitiveOpts::HeightMap(ref opts) => Some(opts.clone()),
+        _ => None,
+    });
+    let terrain = match terrain {
+        Some(terrain) => terrain,
+        None => return,
+    };
+    for primitive in primitives {
+        let (surface, anchor) = match *primitive {
+            PrimitiveOpts::Extrusion(ref mut opts) => {
+                (&mut opts.terrain, opts.anchor)
+            }
+            PrimitiveOpts::Marker(ref mut opts) => {
+                (&mut opts.terrain, opts.anchor)
+            }
+            _ => continue,
+        };
+        if surface.is_none() && anchor == Anchor::Terrain {
+            *surface = Some(terrain.clone());
+        }
+    }
+}
+
+/// Return whether a primitive is placed on the terrain when the scene is
+/// built
+fn anchored(options: &PrimitiveOpts) -> bool {
+    match *options {
+        PrimitiveOpts::Aabb(AabbOpts {
+            anchor: Anchor::Terrain,
+            ..
+        })
+        | PrimitiveOpts::Sphere(SphereOpts {
+            anchor: Anchor::Terrain,
+            ..
+        }) => true,
+        _ => false,
+    }
+}
+
+/// Move a sphere or box vertically by the elevation of a surface beneath its
+/// center, so its heights are measured from the surface, returning false if
+/// the primitive cannot be placed or lies outside of the surface
+pub fn drop_onto(options: &mut PrimitiveOpts, terrain: &Primitive) -> bool {
+    match *options {
+        PrimitiveOpts::Sphere(ref mut opts) => {
+            let [x, _, z] = opts.position;
+            match terrain.elevation(x, z) {
+                Some(elevation) => {
+                    opts.position[1] += elevation;
+                    opts.anchor = Anchor::Absolute;
+                    true
+                }
+                None => false,
+            }
+        }
+        PrimitiveOpts::Aabb(ref mut opts) => {
+            let x = (opts.min[0] + opts.max[0]) / 2.0;
+            let z = (opts.min[2] + opts.max[2]) / 2.0;
+            match terrain.elevation(x, z) {
+                Some(elevation) => {
+                    opts.min[1] += elevation;
+                    opts.max[1] += elevation;
+                    opts.anchor = Anchor::Absolute;
+                    true
+                }
+                None => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Build the primitives of a scene, placing anchored primitives on the
+/// first of the others with a surface beneath them
+fn build_primitives(
+    options: Vec<PrimitiveOpts>,
+    cache: &mut SceneCache,
+) -> Vec<Arc<Primitive>> {
+    let mut primitives: Vec<Option<Arc<Primitive>>> = options
+        .iter()
+        .map(|opts| {
+            if anchored(opts) {
+                None
+            } else {
+                Some(cache.primitive(opts.clone()))
+            }
+        })
+        .collect();
+
+    for (i, mut opts) in options.into_iter().enumerate() {
+        if primitives[i].is_some() {
+            continue;
+        }
+        for terrain in primitives.iter().filter_map(|p| p.as_ref()) {
+            if drop_onto(&mut opts, &**terrain) {
+                break;
+            }
+        }
+        primitives[i] = Some(cache.primitive(opts));
+    }
+    primitives.into_iter().map(Option::unwrap).collect()
+}
+
+/// Measure curvature corrections from the camera, unless told otherwise
+pub fn place_curvature(
+    primitives: &mut [PrimitiveOpts],
+    lights: &mut [LightOpts],
+    camera: &CameraOpts,
+) {
+    let [x, _, z] = camera.position();
+    for opts in height_maps_mut(primitives, lights) {
+        if let Some(ref mut curvature) = opts.curvature {
+            curvature.origin = curvature.origin.or(Some([x, z]));
+        }
+    }
+}
+
+/// Apply the overrides of the groups of each object, dropping objects of
+/// disabled groups
+fn apply_groups(
+    objects: Vec<ObjectOpts>,
+    groups: &HashMap<String, GroupOpts>,
+) -> Vec<ObjectOpts> {
+    let mut applied = vec![];
+    'objects: for mut object in objects {
+        for name in &object.groups {
+            let group = match groups.get(name) {
+                Some(group) => group,
+                None => continue,
+            };
+            if !group.enabled {
+                continue 'objects;
+            }
+            object.shader = group.shader.unwrap_or(object.shader);
+            object.camera = group.camera.unwrap_or(object.camera);
+            object.shadow = group.shadow.unwrap_or(object.shadow);
+            object.stencil = group.stencil.unwrap_or(object.stencil);
+        }
+        applied.push(object);
+    }
+    applied
+}
+
+/// Return the seed of a resource varied by the seed of its scene
+fn mix_seed(seed: u64, scene: u64) -> u64 {
+    seed ^ scene.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+}
+
+fn seed_shader(shader: &mut ShaderOpts, scene: u64) {
+    if let ShaderOpts::DetailNormal(DetailNormalShaderOpts {
+        detail: DetailOpts::Noise { ref mut seed, .. },
+        ..
+    }) = *shader
+    {
+        *seed = mix_seed(*seed, scene);
+    }
+    for child in shader.children_mut() {
+        if let ShaderRef::Inline(ref mut opts) = *child {
+            seed_shader(opts, scene);
+        }
+    }
+}
+
+/// Vary the seeds of scattered instances, noise and light samples by the
+/// seed of the scene
+fn seed_options(options: &mut SceneOpts) {
+    let scene = options.seed;
+    if scene == 0 {
+        return;
+    }
+    for shader in &mut options.shaders {
+        seed_shader(shader, scene);
+    }
+    for primitive in &mut options.primitives {
+        if let PrimitiveOpts::Scatter(ref mut opts) = *primitive {
+            opts.seed = mix_seed(opts.seed, scene);
+        }
+    }
+    for light in &mut options.lights {
+        if let LightOpts::Environment(ref mut opts) = *light {
+            opts.seed = mix_seed(opts.seed, scene);
+        }
+    }
+}
+
+/// Point the camera from an azimuth and elevation, keeping its distance and
+/// any angle not given
+fn orient(camera: &mut CameraOpts, frame: &FrameOpts) {
+    let (azimuth, elevation) = camera.angles();
+    camera.orbit(
+        frame.azimuth.unwrap_or(azimuth),
+        frame.elevation.unwrap_or(elevation),
+    );
+}
+
+/// Return a box enclosing the primitives visible to the camera
+fn camera_bounds(
+    primitives: &[Arc<Primitive>],
+    objects: &[Object],
+) -> Option<Aabb> {
+    objects
+        .iter()
+        .filter(|object| object.camera)
+        .filter_map(|object| primitives.get(object.primitive))
+        .filter_map(|primitive| primitive.bounds())
+        .fold(None, |bounds, next| match bounds {
+            Some(bounds) => Some(next.union(&bounds)),
+            None => Some(next),
+        })
+}
+
+/// Shaders, primitives and files loaded for previous scenes, so that scenes
+/// rendered by one process share loaded data
+#[derive(Default)]
+pub struct SceneCache {
+    loaders: LoaderCache,
+    shaders: HashMap<String, Arc<Shader>>,
+    primitives: HashMap<String, Arc<Primitive>>,
+}
+
+impl SceneCache {
+    pub fn new() -> SceneCache {
+        Default::default()
+    }
+
+    fn shader(&mut self, options: ShaderOpts) -> Arc<Shader> {
+        let key = serde_json::to_string(&options).unwrap();
+        self.shaders
+            .entry(key)
+            .or_insert_with(|| From::from(options))
+            .clone()
+    }
+
+    fn primitive(&mut self, options: PrimitiveOpts) -> Arc<Primitive> {
+        let key = serde_json::to_string(&options).unwrap();
+        self.primitives
+            .entry(key)
+            .or_insert_with(|| From::from(options))
+            .clone()
+    }
+
+    /// Return the number of bytes held by the cache
+    pub fn memory(&self) -> usize {
+        let shaders = self.shaders.values().map(|shader| shader.memory());
+        let primitives = self.primitives.values().map(|prim| prim.memory());
+        self.loaders.memory() + shaders.chain(primitives).sum::<usize>()
+    }
+
+    /// Drop shaders and primitives not used by a live scene, if the cache
+    /// holds more than a number of bytes
+    fn trim(&mut self, budget: usize) {
+        if self.memory() > budget {
+            self.shaders
+                .retain(|_, shader| Arc::strong_count(shader) > 1);
+            self.primitives
+                .retain(|_, prim| Arc::strong_count(prim) > 1);
         }
     }
 }
@@ -114,21 +600,537 @@ impl Scene {
     pub fn new(options: SceneOpts) -> Scene {
         From::from(options)
     }
+
+    /// Create a scene, returning an error rather than panicking if one of
+    /// its resources cannot be built, such as from a missing file
+    pub fn try_new(options: SceneOpts) -> IoResult<Scene> {
+        panic::catch_unwind(AssertUnwindSafe(|| Scene::new(options))).map_err(
+            |payload| {
+                let message = match payload.downcast_ref::<String>() {
+                    Some(message) => message.clone(),
+                    None => match payload.downcast_ref::<&str>() {
+                        Some(message) => message.to_string(),
+                        None => String::from("Could not build the scene"),
+                    },
+                };
+                Error::new(ErrorKind::InvalidData, message)
+            },
+        )
+    }
+
+    /// Register a shader built from the options of scene shaders with a type
+    /// of `custom:<name>`
+    pub fn register_shader_type<S, F>(name: &str, constructor: F)
+    where
+        S: Shader + Send + Sync + 'static,
+        F: Fn(&Map<String, Value>) -> Result<S, String> + Send + Sync + 'static,
+    {
+        registry::register_shader(name, move |options| {
+            constructor(options).map(|shader| Arc::new(shader) as Arc<Shader>)
+        });
+    }
+
+    /// Register a primitive built from the options of scene primitives with a
+    /// type of `custom:<name>`
+    pub fn register_primitive_type<P, F>(name: &str, constructor: F)
+    where
+        P: Primitive + Send + Sync + 'static,
+        F: Fn(&Map<String, Value>) -> Result<P, String> + Send + Sync + 'static,
+    {
+        registry::register_primitive(name, move |options| {
+            constructor(options)
+                .map(|primitive| Arc::new(primitive) as Arc<Primitive>)
+        });
+    }
+
+    /// Create a scene, reusing shaders and primitives from a cache
+    pub fn with_cache(mut options: SceneOpts, cache: &mut SceneCache) -> Scene {
+        seed_options(&mut options);
+        anchor_shapes(&mut options.primitives);
+        place_curvature(
+            &mut options.primitives,
+            &mut options.lights,
+            &options.camera,
+        );
+        let budget = options.memory_budget.map(|megabytes| megabytes << 20);
+        let mut loaders = mem::replace(&mut cache.loaders, Default::default());
+        loaders.set_budget(budget);
+        if let Some(ref frame) = options.frame {
+            orient(&mut options.camera, frame);
+        }
+        let scene = scope(&mut loaders, || {
+            let primitives = build_primitives(options.primitives, cache);
+            let objects: Vec<Object> =
+                apply_groups(options.objects, &options.groups)
+                    .into_iter()
+                    .map(From::from)
+                    .collect();
+            let mut camera: Arc<Camera> = From::from(options.camera);
+            if let Some(ref frame) = options.frame {
+                if let Some(bounds) = camera_bounds(&primitives, &objects) {
+                    camera = camera.frame_bounds(&bounds, frame.margin);
+                }
+            }
+            Scene {
+                background: From::from(options.background),
+                camera,
+                shaders: flatten_shaders(options.shaders)
+                    .into_iter()
+                    .map(|opts| cache.shader(opts))
+                    .collect(),
+                primitives,
+                objects,
+                lights: options.lights.into_iter().map(From::from).collect(),
+                linework: options
+                    .linework
+                    .into_iter()
+                    .map(|opts| resource!(LineLayer, opts))
+                    .collect(),
+                labels: options
+                    .labels
+                    .into_iter()
+                    .map(|opts| resource!(LabelLayer, opts))
+                    .collect(),
+                edges: options.edges.map(From::from),
+                ray_epsilon: options.ray_epsilon,
+                normal_offset: options.normal_offset,
+                face_forward: options.face_forward,
+                shadow_cache: options.shadow_cache,
+                clip_elevation: options
+                    .clip_elevation
+                    .map(|range| (range[0], range[1])),
+                water: options.water.map(From::from),
+            }
+        });
+        cache.loaders = loaders;
+        if let Some(budget) = budget {
+            cache.trim(budget);
+        }
+        scene
+    }
+
+    /// Return an estimate of the memory held by the scene
+    pub fn memory(&self) -> MemoryUsage {
+        MemoryUsage {
+            primitives: self.primitives.iter().map(|p| p.memory()).sum(),
+            shaders: self.shaders.iter().map(|s| s.memory()).sum(),
+            shapes: shapes_memory(
+                self.shaders
+                    .iter()
+                    .flat_map(|shader| shader.shapes())
+                    .chain(self.linework.iter().map(|l| l.shapes.clone())),
+            ),
+        }
+    }
+}
+
+/// Return the bytes held by sets of shapes, counting each shared set once
+fn shapes_memory<I>(sets: I) -> usize
+where
+    I: Iterator<Item = Arc<Vec<Shape>>>,
+{
+    let mut seen = HashSet::new();
+    sets.filter(|shapes| seen.insert(Arc::as_ptr(shapes)))
+        .map(|shapes| shapes.iter().map(|shape| shape.memory()).sum::<usize>())
+        .sum()
 }
 
 impl From<SceneOpts> for Scene {
     fn from(options: SceneOpts) -> Scene {
-        Scene {
-            background: From::from(options.background),
-            camera: From::from(options.camera),
-            shaders: options.shaders.into_iter().map(From::from).collect(),
-            primitives: options
-                .primitives
-                .into_iter()
-                .map(From::from)
-                .collect(),
-            objects: options.objects.into_iter().map(From::from).collect(),
-            lights: options.lights.into_iter().map(From::from).collect(),
+        Scene::with_cache(options, &mut SceneCache::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::Ray;
+    use options::{
+        ConstantShaderOpts, NormalShaderOpts, OrthographicCameraOpts,
+        PhongShaderOpts,
+    };
+    use primitives::Intersection;
+    use shaders::{TraceInfo, Tracer};
+    use shapes::LineString;
+
+    fn phong(wraps: ShaderRef) -> ShaderOpts {
+        ShaderOpts::Phong(PhongShaderOpts {
+            wraps,
+            lights: vec![],
+            bias: 0.0,
+            ambient: [0.0, 0.0, 0.0],
+            specular_color: [0.0, 0.0, 0.0],
+            specular_exponent: 0.0,
+            ks: 0.0,
+            cel_shading: None,
+            normalize: false,
+            baked: None,
+        })
+    }
+
+    #[test]
+    fn flattening_inline_shaders() {
+        let constant = ShaderOpts::Constant(ConstantShaderOpts {
+            color: [1.0, 0.0, 0.0],
+        });
+        let shaders = flatten_shaders(vec![
+            phong(ShaderRef::Inline(Box::new(phong(ShaderRef::Inline(
+                Box::new(constant.clone()),
+            ))))),
+            ShaderOpts::Normal(NormalShaderOpts),
+            phong(ShaderRef::Index(1)),
+        ]);
+
+        assert_eq!(
+            shaders,
+            vec![
+                phong(ShaderRef::Index(3)),
+                ShaderOpts::Normal(NormalShaderOpts),
+                phong(ShaderRef::Index(1)),
+                phong(ShaderRef::Index(4)),
+                constant,
+            ]
+        );
+    }
+
+    #[test]
+    fn applying_groups() {
+        let object = |groups: &[&str]| ObjectOpts {
+            primitive: 0,
+            shader: 0,
+            camera: true,
+            shadow: true,
+            stencil: true,
+            groups: groups.iter().map(|name| name.to_string()).collect(),
+        };
+        let group = GroupOpts {
+            enabled: true,
+            shader: None,
+            camera: None,
+            shadow: None,
+            stencil: None,
+        };
+        let mut groups = HashMap::new();
+        groups.insert(
+            String::from("markers"),
+            GroupOpts {
+                enabled: false,
+                ..group.clone()
+            },
+        );
+        groups.insert(
+            String::from("terrain"),
+            GroupOpts {
+                shader: Some(2),
+                shadow: Some(false),
+                ..group.clone()
+            },
+        );
+        groups.insert(
+            String::from("flat"),
+            GroupOpts {
+                shader: Some(3),
+                ..group
+            },
+        );
+
+        let objects = apply_groups(
+            vec![
+                object(&["terrain"]),
+                object(&["terrain", "markers"]),
+                object(&["terrain", "flat"]),
+                object(&["unknown"]),
+            ],
+            &groups,
+        );
+        let shaders: Vec<usize> = objects.iter().map(|o| o.shader).collect();
+        assert_eq!(shaders, vec![2, 3, 0]);
+        let shadows: Vec<bool> = objects.iter().map(|o| o.shadow).collect();
+        assert_eq!(shadows, vec![false, false, true]);
+        assert!(objects.iter().all(|o| o.camera && o.stencil));
+    }
+
+    #[test]
+    fn seeding_scenes() {
+        let mut options: SceneOpts = serde_json::from_value(json!({
+            "background": [0, 0, 0],
+            "camera": {
+                "type": "orthographic",
+                "width": 1,
+                "height": 1,
+                "position": [0, 10, 0],
+                "look_at": [0, 0, 0],
+                "view_plane_size": 1,
+                "view_distance": 1,
+                "up": [0, 0, -1]
+            },
+            "shaders": [],
+            "lights": [{
+                "type": "environment",
+                "filepath": "sky.hdr",
+                "intensity": 1,
+                "samples": 4
+            }],
+            "primitives": [],
+            "objects": []
+        }))
+        .unwrap();
+        let detail = serde_json::from_value(json!({
+            "type": "detail_normal",
+            "detail": {"type": "noise", "scale": 1, "seed": 3},
+            "wraps": 1
+        }))
+        .unwrap();
+        options
+            .shaders
+            .push(phong(ShaderRef::Inline(Box::new(detail))));
+        let unseeded = options.clone();
+        seed_options(&mut options);
+        assert_eq!(options, unseeded);
+
+        options.seed = 5;
+        seed_options(&mut options);
+        let noise = match options.shaders[0] {
+            ShaderOpts::Phong(ref opts) => match opts.wraps {
+                ShaderRef::Inline(ref opts) => match **opts {
+                    ShaderOpts::DetailNormal(ref opts) => opts.detail.clone(),
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            noise,
+            DetailOpts::Noise {
+                scale: 1.0,
+                octaves: 4,
+                seed: mix_seed(3, 5),
+            }
+        );
+        match options.lights[0] {
+            LightOpts::Environment(ref opts) => {
+                assert_eq!(opts.seed, mix_seed(0, 5));
+                assert!(opts.seed != 0);
+            }
+            _ => unreachable!(),
         }
     }
+
+    #[test]
+    fn orienting_cameras() {
+        let mut camera = CameraOpts::Orthographic(OrthographicCameraOpts {
+            width: 100,
+            height: 100,
+            position: [0.0, 0.0, 10.0],
+            look_at: [0.0, 0.0, 0.0],
+            view_plane_size: 1.0,
+            view_distance: 1.0,
+            up: [0.0, 1.0, 0.0],
+            near: 0.0,
+            far: None,
+        });
+        let frame = FrameOpts {
+            azimuth: Some(90.0),
+            ..Default::default()
+        };
+        orient(&mut camera, &frame);
+        let [x, y, z] = camera.position();
+        assert!((x - 10.0).abs() < 1e-9 && y.abs() < 1e-9 && z.abs() < 1e-9);
+
+        let frame = FrameOpts {
+            elevation: Some(90.0),
+            ..Default::default()
+        };
+        orient(&mut camera, &frame);
+        let [x, y, z] = camera.position();
+        assert!(x.abs() < 1e-9 && (y - 10.0).abs() < 1e-9 && z.abs() < 1e-9);
+
+        // Looking straight down the up axis keeps north up
+        assert!(camera.validate().is_ok());
+        let view: Arc<Camera> = From::from(camera.clone());
+        let ray = view.cast_ray(50.0, 0.0);
+        assert!(ray.origin.z < 0.0 && ray.direction.y < 0.0);
+
+        let (position, look_at) = camera.placement_mut();
+        *position = *look_at;
+        assert!(camera.validate().is_err());
+    }
+
+    #[test]
+    fn building_custom_types() {
+        struct Flat(f64);
+
+        impl Shader for Flat {
+            fn shade(&self, _: &Tracer, _: &TraceInfo) -> Vec3 {
+                Vec3::new(self.0, self.0, self.0)
+            }
+        }
+
+        Scene::register_shader_type("flat", |options| {
+            match options.get("value").and_then(Value::as_f64) {
+                Some(value) => Ok(Flat(value)),
+                None => Err(String::from("Missing value")),
+            }
+        });
+
+        let json = json!({"type": "custom:flat", "value": 0.5});
+        let options: ShaderOpts = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&options).unwrap(), json);
+        let shader: Arc<Shader> = From::from(options);
+        assert_eq!(shader.memory(), 0);
+
+        let unknown = json!({"type": "flat", "value": 0.5});
+        assert!(serde_json::from_value::<ShaderOpts>(unknown).is_err());
+    }
+
+    #[test]
+    fn errors_in_built_in_types() {
+        // Mistakes in built-in types are reported for the field, rather than
+        // as a mismatch of every type including custom ones
+        let sphere = json!({"type": "sphere", "position": [0.0, 0.0, 0.0]});
+        let err = serde_json::from_value::<PrimitiveOpts>(sphere).unwrap_err();
+        assert!(
+            err.to_string().contains("missing field `radius`"),
+            "{}",
+            err
+        );
+
+        let constant = json!({"type": "constant", "color": "red"});
+        let err = serde_json::from_value::<ShaderOpts>(constant).unwrap_err();
+        assert!(err.to_string().contains("invalid type"), "{}", err);
+
+        let custom = json!({"type": "custom:flat", "value": 0.5});
+        let options: PrimitiveOpts =
+            serde_json::from_value(custom.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&options).unwrap(), custom);
+    }
+
+    #[test]
+    fn anchoring_to_terrain() {
+        /// A surface rising one unit per unit along the `x` axis
+        struct Slope;
+
+        impl Primitive for Slope {
+            fn intersects(&self, _: Ray) -> Option<Intersection> {
+                None
+            }
+
+            fn elevation(&self, x: f64, _: f64) -> Option<f64> {
+                Some(x)
+            }
+        }
+
+        Scene::register_primitive_type("slope", |_| Ok(Slope));
+        let primitives = vec![
+            serde_json::from_value(json!({
+                "type": "sphere",
+                "position": [3.0, 1.0, 0.0],
+                "radius": 1.0,
+                "anchor": "terrain",
+            }))
+            .unwrap(),
+            serde_json::from_value(json!({"type": "custom:slope"})).unwrap(),
+            serde_json::from_value(json!({
+                "type": "sphere",
+                "position": [3.0, 1.0, 0.0],
+                "radius": 1.0,
+            }))
+            .unwrap(),
+        ];
+        let primitives = build_primitives(primitives, &mut SceneCache::new());
+        let center = |i: usize| primitives[i].bounds().unwrap().center();
+        assert_eq!(center(0), Vec3::new(3.0, 4.0, 0.0));
+        assert_eq!(center(2), Vec3::new(3.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn background_gradients() {
+        let color: BackgroundOpts =
+            serde_json::from_value(json!([0.5, 0.5, 0.5])).unwrap();
+        let color = Background::from(color);
+        assert_eq!(
+            color.color(Vec3::new(0.0, 1.0, 0.0)),
+            Vec3::new(0.5, 0.5, 0.5)
+        );
+
+        let gradient: BackgroundOpts = serde_json::from_value(json!({
+            "horizon": [1.0, 1.0, 1.0],
+            "zenith": [0.0, 0.0, 1.0],
+        }))
+        .unwrap();
+        let gradient = Background::from(gradient);
+        let horizon = Vec3::new(1.0, 1.0, 1.0);
+        assert_eq!(gradient.color(Vec3::new(1.0, 0.0, 0.0)), horizon);
+        assert_eq!(
+            gradient.color(Vec3::new(0.0, 2.0, 0.0)),
+            Vec3::new(0.0, 0.0, 1.0)
+        );
+        // The ground defaults to the horizon color
+        assert_eq!(gradient.color(Vec3::new(0.0, -1.0, 0.0)), horizon);
+
+        let halfway = gradient.color(Vec3::new(3.0f64.sqrt(), 1.0, 0.0));
+        assert!((halfway.x - 0.5).abs() < 1e-9);
+
+        let error = |value| {
+            serde_json::from_value::<BackgroundOpts>(value)
+                .unwrap_err()
+                .to_string()
+        };
+        let missing = error(json!({"horizon": [1.0, 1.0, 1.0]}));
+        assert!(missing.contains("zenith"), "{}", missing);
+        let flat = error(json!({
+            "horizon": [1.0, 1.0, 1.0],
+            "zenith": [0.0, 0.0, 1.0],
+            "softness": 0.0,
+        }));
+        assert!(flat.contains("softness"), "{}", flat);
+    }
+
+    #[test]
+    fn reporting_scenes_that_cannot_be_built() {
+        let options = |linework| {
+            serde_json::from_value::<SceneOpts>(json!({
+                "background": [0, 0, 0],
+                "camera": {
+                    "type": "orthographic",
+                    "width": 1,
+                    "height": 1,
+                    "position": [0, 10, 0],
+                    "look_at": [0, 0, 0],
+                    "view_plane_size": 1,
+                    "view_distance": 1,
+                    "up": [0, 0, -1]
+                },
+                "shaders": [],
+                "lights": [],
+                "primitives": [],
+                "objects": [],
+                "linework": linework
+            }))
+            .unwrap()
+        };
+        let missing = json!([{
+            "data": {"type": "geojson", "filepath": "peaks-missing.geojson"},
+            "color": [0, 0, 0],
+            "width": 1,
+            "drape": false,
+            "occlusion": false
+        }]);
+        assert!(Scene::try_new(options(missing)).is_err());
+        assert!(Scene::try_new(options(json!([]))).is_ok());
+    }
+
+    #[test]
+    fn counting_shared_shapes_once() {
+        let line = LineString::new(vec![Vec3::zeros(), Vec3::zeros()]);
+        let shapes = Arc::new(vec![Shape::LineString(line)]);
+        let memory = shapes_memory(vec![shapes.clone()].into_iter());
+        assert!(memory > 0);
+
+        let shared = vec![shapes.clone(), shapes.clone()];
+        assert_eq!(shapes_memory(shared.into_iter()), memory);
+        let copied = vec![shapes.clone(), Arc::new((*shapes).clone())];
+        assert_eq!(shapes_memory(copied.into_iter()), memory * 2);
+    }
 }
@@ -0,0 +1,123 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use super::shader::{Shader, TraceInfo, Tracer};
+use math::Vec3;
+use options::{DepthCueShaderOpts, FalloffOpts};
+
+/// How the amount of fog grows with distance
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Falloff {
+    /// No fog before a near distance, rising to full fog at a far distance
+    Linear(f64, f64),
+    /// Fog rising from a near distance with a density per unit of distance
+    Exponential(f64, f64),
+}
+
+impl Falloff {
+    /// Return the amount of fog, between zero and one, at a distance
+    pub fn amount(&self, distance: f64) -> f64 {
+        match *self {
+            Falloff::Linear(near, far) => {
+                if far > near {
+                    ((distance - near) / (far - near)).min(1.0).max(0.0)
+                } else if distance < near {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            Falloff::Exponential(near, density) => {
+                1.0 - (-density * (distance - near).max(0.0)).exp()
+            }
+        }
+    }
+}
+
+impl From<FalloffOpts> for Falloff {
+    fn from(options: FalloffOpts) -> Falloff {
+        match options {
+            FalloffOpts::Linear { near, far } => Falloff::Linear(near, far),
+            FalloffOpts::Exponential { near, density } => {
+                Falloff::Exponential(near, density)
+            }
+        }
+    }
+}
+
+/// Blends the color of a shader towards a fog color with distance
+#[derive(Copy, Clone, Debug)]
+pub struct DepthCueShader {
+    wraps: usize,
+    color: Vec3,
+    falloff: Falloff,
+    opacity: f64,
+}
+
+impl DepthCueShader {
+    pub fn new(
+        wraps: usize,
+        color: Vec3,
+        falloff: Falloff,
+        opacity: f64,
+    ) -> DepthCueShader {
+        DepthCueShader {
+            wraps,
+            color,
+            falloff,
+            opacity,
+        }
+    }
+}
+
+impl From<DepthCueShaderOpts> for DepthCueShader {
+    fn from(options: DepthCueShaderOpts) -> DepthCueShader {
+        DepthCueShader::new(
+            options.wraps.index(),
+            From::from(options.color),
+            From::from(options.falloff),
+            options.opacity,
+        )
+    }
+}
+
+impl Shader for DepthCueShader {
+    fn shade(&self, tracer: &Tracer, info: &TraceInfo) -> Vec3 {
+        let color = match tracer.shader(self.wraps) {
+            Some(shader) => shader.shade(tracer, info),
+            None => Vec3::zeros(),
+        };
+        let amount = self.falloff.amount(info.intersection.t) * self.opacity;
+        (color * (1.0 - amount)) + (self.color * amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fog_falloff() {
+        let linear = Falloff::Linear(100.0, 200.0);
+        assert_eq!(linear.amount(50.0), 0.0);
+        assert_eq!(linear.amount(150.0), 0.5);
+        assert_eq!(linear.amount(500.0), 1.0);
+
+        let exponential = Falloff::Exponential(100.0, 0.01);
+        assert_eq!(exponential.amount(50.0), 0.0);
+        assert!((exponential.amount(200.0) - 0.632_120_56).abs() < 1e-8);
+        assert!(exponential.amount(1.0e6) <= 1.0);
+    }
+}
@@ -0,0 +1,229 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use super::shader::{Shader, TraceInfo, Tracer};
+use super::texture::TextureShader;
+use math::Vec3;
+use options::{DetailNormalShaderOpts, DetailOpts};
+use primitives::Intersection;
+
+/// Fraction of the feature size used as the step of finite differences
+const DELTA: f64 = 0.01;
+
+/// Relief too small to be resolved by the height map
+#[derive(Clone, Debug)]
+pub enum Detail {
+    /// Fractal value noise with features of a size in map units
+    Noise {
+        scale: f64,
+        octaves: usize,
+        seed: u64,
+    },
+    /// A tangent space normal map
+    NormalMap(TextureShader),
+}
+
+impl From<DetailOpts> for Detail {
+    fn from(options: DetailOpts) -> Detail {
+        match options {
+            DetailOpts::Noise {
+                scale,
+                octaves,
+                seed,
+            } => Detail::Noise {
+                scale,
+                octaves: octaves.max(1),
+                seed,
+            },
+            DetailOpts::NormalMap(opts) => Detail::NormalMap(From::from(opts)),
+        }
+    }
+}
+
+/// Return a pseudo random value between minus one and one for a lattice point
+fn lattice(x: i64, z: i64, octave: usize, seed: u64) -> f64 {
+    let mut h = (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (z as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+        ^ (octave as u64).wrapping_mul(0x1656_67b1_9e37_79f9)
+        ^ seed.wrapping_mul(0xd6e8_feb8_6659_fd93);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    (h >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+}
+
+/// Return smoothly interpolated lattice values at a point
+fn value_noise(x: f64, z: f64, octave: usize, seed: u64) -> f64 {
+    let (xf, zf) = (x.floor(), z.floor());
+    let (xi, zi) = (xf as i64, zf as i64);
+    let smooth = |t: f64| t * t * (3.0 - 2.0 * t);
+    let (tx, tz) = (smooth(x - xf), smooth(z - zf));
+
+    let a = lattice(xi, zi, octave, seed);
+    let b = lattice(xi + 1, zi, octave, seed);
+    let c = lattice(xi, zi + 1, octave, seed);
+    let d = lattice(xi + 1, zi + 1, octave, seed);
+    let top = a + (b - a) * tx;
+    let bottom = c + (d - c) * tx;
+    top + (bottom - top) * tz
+}
+
+/// Return noise summed over octaves of halving size and amplitude, in units
+/// of the size of the largest features
+fn fractal_noise(x: f64, z: f64, octaves: usize, seed: u64) -> f64 {
+    let mut total = 0.0;
+    let mut frequency = 1.0;
+    for octave in 0..octaves {
+        total +=
+            value_noise(x * frequency, z * frequency, octave, seed) / frequency;
+        frequency *= 2.0;
+    }
+    total
+}
+
+impl Detail {
+    /// Return a normal perturbed by the detail at a point
+    pub fn perturb(&self, point: Vec3, normal: Vec3, strength: f64) -> Vec3 {
+        match *self {
+            Detail::Noise {
+                scale,
+                octaves,
+                seed,
+            } => {
+                let (x, z) = (point.x / scale, point.z / scale);
+                let height = |x, z| fractal_noise(x, z, octaves, seed);
+                let h = height(x, z);
+                let dx = (height(x + DELTA, z) - h) / DELTA;
+                let dz = (height(x, z + DELTA) - h) / DELTA;
+
+                // Tilt the normal against the slope, within its tangent plane
+                let gradient = Vec3::new(dx, 0.0, dz);
+                let gradient = gradient - normal * Vec3::dot(gradient, normal);
+                Vec3::normalize(normal - gradient * strength)
+            }
+            Detail::NormalMap(ref texture) => {
+                let value = texture.tiled(point.x, point.z) * 2.0
+                    - Vec3::new(1.0, 1.0, 1.0);
+
+                // Tangent frame pointing east and north along the surface
+                let east = Vec3::new(1.0, 0.0, 0.0);
+                let tangent =
+                    Vec3::normalize(east - normal * Vec3::dot(east, normal));
+                let bitangent = Vec3::cross(normal, tangent);
+                let mapped = Vec3::normalize(
+                    tangent * value.x + bitangent * value.y + normal * value.z,
+                );
+                Vec3::normalize(normal + (mapped - normal) * strength)
+            }
+        }
+    }
+}
+
+/// Perturbs the normal of a surface with detail before shading it with
+/// another shader
+#[derive(Clone, Debug)]
+pub struct DetailNormalShader {
+    wraps: usize,
+    detail: Detail,
+    strength: f64,
+}
+
+impl DetailNormalShader {
+    pub fn new(
+        wraps: usize,
+        detail: Detail,
+        strength: f64,
+    ) -> DetailNormalShader {
+        DetailNormalShader {
+            wraps,
+            detail,
+            strength,
+        }
+    }
+}
+
+impl From<DetailNormalShaderOpts> for DetailNormalShader {
+    fn from(options: DetailNormalShaderOpts) -> DetailNormalShader {
+        DetailNormalShader::new(
+            options.wraps.index(),
+            From::from(options.detail),
+            options.strength,
+        )
+    }
+}
+
+impl Shader for DetailNormalShader {
+    fn shade(&self, tracer: &Tracer, info: &TraceInfo) -> Vec3 {
+        let shader = match tracer.shader(self.wraps) {
+            Some(shader) => shader,
+            None => return Vec3::zeros(),
+        };
+        let normal = self.detail.perturb(
+            info.position(),
+            info.intersection.normal,
+            self.strength,
+        );
+        let info = TraceInfo {
+            ray: info.ray,
+            intersection: Intersection::new(info.intersection.t, normal),
+            primitive: info.primitive,
+            x: info.x,
+            y: info.y,
+        };
+        shader.shade(tracer, &info)
+    }
+
+    fn memory(&self) -> usize {
+        match self.detail {
+            Detail::NormalMap(ref texture) => texture.memory(),
+            Detail::Noise { .. } => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perturbing_normals() {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let noise = Detail::Noise {
+            scale: 10.0,
+            octaves: 3,
+            seed: 0,
+        };
+        let point = Vec3::new(13.7, 0.0, -4.2);
+        assert_eq!(noise.perturb(point, up, 0.0), up);
+
+        let normal = noise.perturb(point, up, 1.0);
+        assert!((Vec3::length(normal) - 1.0).abs() < 1e-9);
+        assert!(normal != up && normal.y > 0.0);
+
+        // Other seeds give other noise
+        let reseeded = Detail::Noise {
+            scale: 10.0,
+            octaves: 3,
+            seed: 1,
+        };
+        assert!(reseeded.perturb(point, up, 1.0) != normal);
+
+        // Lattice values stay within range
+        for i in 0..100 {
+            let value = lattice(i * 7 - 300, i * 13, 2, i as u64);
+            assert!(value >= -1.0 && value < 1.0);
+        }
+    }
+}
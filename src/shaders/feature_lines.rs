@@ -13,11 +13,61 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
-use super::shader::{Shader, TraceInfo, Tracer};
+use super::shader::{RayType, Shader, TraceInfo, Tracer};
 use math::Vec3;
-use options::FeatureLineShaderOpts;
+use options::{FeatureLineShaderOpts, LineAttenuationOpts};
 use samplers::{RayStencilSampler, Sampler};
 
+/// Fades the intensity of lines between a near and far distance
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LineAttenuation {
+    near: f64,
+    far: f64,
+    max_intensity: f64,
+    min_intensity: f64,
+}
+
+impl LineAttenuation {
+    pub fn new(
+        near: f64,
+        far: f64,
+        max_intensity: f64,
+        min_intensity: f64,
+    ) -> LineAttenuation {
+        LineAttenuation {
+            near,
+            far,
+            max_intensity,
+            min_intensity,
+        }
+    }
+
+    /// Return the intensity of a line at a distance
+    pub fn intensity(&self, distance: f64) -> f64 {
+        let t = if self.far > self.near {
+            ((distance - self.near) / (self.far - self.near))
+                .min(1.0)
+                .max(0.0)
+        } else if distance < self.near {
+            0.0
+        } else {
+            1.0
+        };
+        self.max_intensity * (1.0 - t) + self.min_intensity * t
+    }
+}
+
+impl From<LineAttenuationOpts> for LineAttenuation {
+    fn from(options: LineAttenuationOpts) -> LineAttenuation {
+        LineAttenuation::new(
+            options.near,
+            options.far,
+            options.max_intensity,
+            options.min_intensity,
+        )
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct FeatureLineShader {
     wraps: usize,
@@ -25,6 +75,7 @@ pub struct FeatureLineShader {
     stencil: RayStencilSampler,
     crease_threshold: f64,
     self_silhoutte_threshold: f64,
+    attenuation: Option<LineAttenuation>,
 }
 
 impl FeatureLineShader {
@@ -35,6 +86,7 @@ impl FeatureLineShader {
         radius: f64,
         crease_threshold: f64,
         self_silhoutte_threshold: f64,
+        attenuation: Option<LineAttenuation>,
     ) -> FeatureLineShader {
         FeatureLineShader {
             wraps,
@@ -42,6 +94,7 @@ impl FeatureLineShader {
             stencil: RayStencilSampler::new(quality, radius),
             crease_threshold,
             self_silhoutte_threshold,
+            attenuation,
         }
     }
 }
@@ -49,21 +102,25 @@ impl FeatureLineShader {
 impl From<FeatureLineShaderOpts> for FeatureLineShader {
     fn from(options: FeatureLineShaderOpts) -> FeatureLineShader {
         FeatureLineShader::new(
-            options.wraps,
+            options.wraps.index(),
             From::from(options.color),
             options.quality,
             options.radius,
             options.crease_threshold,
             options.self_silhoutte_threshold,
+            options.attenuation.map(From::from),
         )
     }
 }
 
 impl Shader for FeatureLineShader {
     fn shade(&self, tracer: &Tracer, info: &TraceInfo) -> Vec3 {
-        let i = self.stencil
+        let i = self
+            .stencil
             .samples()
-            .map(|(x, y)| tracer.trace_pixel(info.x + x, info.y + y))
+            .map(|(x, y)| {
+                tracer.trace_pixel(RayType::Stencil, info.x + x, info.y + y)
+            })
             .filter_map(|stencil| stencil)
             .filter(|stencil| stencil.primitive == info.primitive)
             .filter(|stencil| {
@@ -81,6 +138,12 @@ impl Shader for FeatureLineShader {
         // Normalise, accounting for the region on the otherside of the edge
         let num_stencils = self.stencil.amount() as f64;
         let edge_metric = 1.0 - (i - num_stencils * 0.5) / (num_stencils * 0.5);
+        let edge_metric = match self.attenuation {
+            Some(attenuation) => {
+                edge_metric * attenuation.intensity(info.intersection.t)
+            }
+            None => edge_metric,
+        };
 
         let color = match tracer.shader(self.wraps) {
             Some(shader) => shader.shade(tracer, info),
@@ -89,3 +152,16 @@ impl Shader for FeatureLineShader {
         (color * (1.0 - edge_metric)) + (self.color * edge_metric)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_attenuation() {
+        let attenuation = LineAttenuation::new(100.0, 200.0, 1.0, 0.2);
+        assert_eq!(attenuation.intensity(50.0), 1.0);
+        assert_eq!(attenuation.intensity(150.0), 0.6);
+        assert_eq!(attenuation.intensity(500.0), 0.2);
+    }
+}
@@ -0,0 +1,208 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use super::sdf::coverage;
+use super::shader::{shadowed, Shader, TraceInfo, Tracer};
+use io::cache;
+use lights::Light;
+use math::Vec3;
+use options::GlacierShaderOpts;
+use ramps::Ramp;
+use shapes::Shape;
+
+use std::sync::Arc;
+
+/// Slopes below which the ice has no direction of flow, as the length of
+/// the horizontal part of the normal
+const FLAT: f64 = 0.02;
+
+/// Brightness of the ice inside crevasses
+const CREVASSE_SHADE: f64 = 0.55;
+
+/// Shades glacier polygons as ice, and everything else with another shader
+#[derive(Clone)]
+pub struct GlacierShader {
+    wraps: usize,
+    shapes: Arc<Vec<Shape>>,
+    lights: Vec<usize>,
+    elevation: (f64, f64),
+    crevasse_spacing: f64,
+    crevasse_width: f64,
+    ks: f64,
+    specular_exponent: f64,
+    bias: f64,
+}
+
+impl GlacierShader {
+    pub fn new(
+        wraps: usize,
+        shapes: Arc<Vec<Shape>>,
+        lights: Vec<usize>,
+        elevation: (f64, f64),
+        crevasse_spacing: f64,
+        crevasse_width: f64,
+        ks: f64,
+        specular_exponent: f64,
+        bias: f64,
+    ) -> GlacierShader {
+        GlacierShader {
+            wraps,
+            shapes,
+            lights,
+            elevation,
+            crevasse_spacing,
+            crevasse_width,
+            ks,
+            specular_exponent,
+            bias,
+        }
+    }
+
+    /// Return the color of the ice at a point with a normal, before lighting
+    pub fn albedo(&self, point: Vec3, normal: Vec3) -> Vec3 {
+        let (low, high) = self.elevation;
+        let t = if high > low {
+            (point.y - low) / (high - low)
+        } else {
+            1.0
+        };
+        let color = Ramp::Glacier.color(t);
+
+        // Crevasses open across the direction the ice flows downhill
+        let flow = Vec3::new(normal.x, 0.0, normal.z);
+        let steepness = Vec3::length(flow);
+        if steepness < FLAT || self.crevasse_spacing <= 0.0 {
+            return color;
+        }
+        let flow = flow / steepness;
+        let along = point.x * flow.x + point.z * flow.z;
+        if along.rem_euclid(self.crevasse_spacing) < self.crevasse_width {
+            color * CREVASSE_SHADE
+        } else {
+            color
+        }
+    }
+
+    /// Return the lit color of ice
+    fn ice(&self, tracer: &Tracer, info: &TraceInfo, albedo: Vec3) -> Vec3 {
+        let normal = info.intersection.normal;
+        let eye = info.ray.direction;
+        let mut diffuse = Vec3::zeros();
+        let mut specular = Vec3::zeros();
+
+        for index in &self.lights {
+            let light = match tracer.light(*index) {
+                Some(Light::Directional(ref light)) => light,
+                // Ice takes only direct light, as it is usually the brightest
+                // part of the scene
+                _ => continue,
+            };
+            if shadowed(tracer, info, light, self.bias) {
+                continue;
+            }
+
+            let radiance = light.color * light.intensity;
+            let reflection = Vec3::reflect(light.direction, normal);
+            let highlight = Vec3::dot(reflection, eye).max(0.0);
+            specular +=
+                radiance * (highlight.powf(self.specular_exponent) * self.ks);
+            diffuse += radiance * Vec3::dot(light.direction, normal).max(0.0);
+        }
+        albedo * diffuse + specular
+    }
+}
+
+impl From<GlacierShaderOpts> for GlacierShader {
+    fn from(options: GlacierShaderOpts) -> GlacierShader {
+        let shapes = cache::shapes(&options.data).unwrap();
+        GlacierShader::new(
+            options.wraps.index(),
+            shapes,
+            options.lights,
+            (options.elevation[0], options.elevation[1]),
+            options.crevasse_spacing,
+            options.crevasse_width,
+            options.ks,
+            options.specular_exponent,
+            options.bias,
+        )
+    }
+}
+
+impl Shader for GlacierShader {
+    fn shade(&self, tracer: &Tracer, info: &TraceInfo) -> Vec3 {
+        let point = info.position();
+        let base = || match tracer.shader(self.wraps) {
+            Some(shader) => shader.shade(tracer, info),
+            None => Vec3::zeros(),
+        };
+
+        let footprint = info.footprint();
+        for shape in self.shapes.iter() {
+            if !shape.bbox().contains(point) {
+                continue;
+            }
+            let inside = coverage(shape.distance(point), 0.0, footprint);
+            if inside <= 0.0 {
+                continue;
+            }
+
+            let albedo = self.albedo(point, info.intersection.normal);
+            let ice = self.ice(tracer, info, albedo);
+            if inside >= 1.0 {
+                return ice;
+            }
+            return ice * inside + base() * (1.0 - inside);
+        }
+
+        base()
+    }
+
+    fn shapes(&self) -> Vec<Arc<Vec<Shape>>> {
+        vec![self.shapes.clone()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ice_color() {
+        let shader = GlacierShader::new(
+            0,
+            Arc::new(vec![]),
+            vec![],
+            (2000.0, 3000.0),
+            10.0,
+            2.0,
+            0.5,
+            40.0,
+            0.0,
+        );
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let low = shader.albedo(Vec3::new(5.0, 2000.0, 5.0), up);
+        let high = shader.albedo(Vec3::new(5.0, 3000.0, 5.0), up);
+        assert!(low.z > low.x && high.x > low.x);
+
+        // Sloping east, crevasses run north to south
+        let slope = Vec3::normalize(Vec3::new(0.5, 1.0, 0.0));
+        let open = shader.albedo(Vec3::new(11.0, 3000.0, 0.0), slope);
+        let solid = shader.albedo(Vec3::new(15.0, 3000.0, 0.0), slope);
+        assert_eq!(open, high * CREVASSE_SHADE);
+        assert_eq!(solid, high);
+        assert_eq!(shader.albedo(Vec3::new(11.0, 3000.0, 7.0), slope), open);
+    }
+}
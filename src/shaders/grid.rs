@@ -0,0 +1,137 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use super::depth_cue::Falloff;
+use super::shader::{Shader, TraceInfo, Tracer};
+use math::Vec3;
+use options::GridShaderOpts;
+
+/// Return the fraction of a pixel, centered at a distance from a line, that
+/// the line covers
+fn coverage(distance: f64, width: f64, footprint: f64) -> f64 {
+    let half = width / 2.0;
+    if footprint <= 0.0 {
+        return if distance < half { 1.0 } else { 0.0 };
+    }
+    let low = (distance - footprint / 2.0).max(-half);
+    let high = (distance + footprint / 2.0).min(half);
+    ((high - low) / footprint).max(0.0).min(1.0)
+}
+
+/// Colors the `x` and `z` plane with lines around square cells, in map
+/// units, optionally filling alternate cells as a checkerboard
+#[derive(Copy, Clone, Debug)]
+pub struct GridShader {
+    color: Vec3,
+    checker: Option<Vec3>,
+    cell_size: f64,
+    line_color: Vec3,
+    line_width: f64,
+    fade: Option<Falloff>,
+}
+
+impl GridShader {
+    pub fn new(
+        color: Vec3,
+        checker: Option<Vec3>,
+        cell_size: f64,
+        line_color: Vec3,
+        line_width: f64,
+        fade: Option<Falloff>,
+    ) -> GridShader {
+        GridShader {
+            color,
+            checker,
+            cell_size,
+            line_color,
+            line_width,
+            fade,
+        }
+    }
+
+    /// Return the color at a point seen from a distance, where a pixel spans
+    /// a footprint in map units
+    pub fn color(&self, point: Vec3, distance: f64, footprint: f64) -> Vec3 {
+        let (x, z) = (point.x / self.cell_size, point.z / self.cell_size);
+        let fade = match self.fade {
+            Some(falloff) => falloff.amount(distance),
+            None => 0.0,
+        };
+
+        // Checks fade to their average, so distant cells do not alias
+        let base = match self.checker {
+            Some(checker) => {
+                let cell = (x.floor() + z.floor()).rem_euclid(2.0);
+                let check = if cell < 1.0 { self.color } else { checker };
+                let average = (self.color + checker) * 0.5;
+                check * (1.0 - fade) + average * fade
+            }
+            None => self.color,
+        };
+
+        let across = |v: f64| (v - v.round()).abs() * self.cell_size;
+        let nearest = across(x).min(across(z));
+        let line = coverage(nearest, self.line_width, footprint) * (1.0 - fade);
+        base * (1.0 - line) + self.line_color * line
+    }
+}
+
+impl From<GridShaderOpts> for GridShader {
+    fn from(options: GridShaderOpts) -> GridShader {
+        GridShader::new(
+            From::from(options.color),
+            options.checker.map(From::from),
+            options.cell_size,
+            From::from(options.line_color),
+            options.line_width,
+            options.fade.map(From::from),
+        )
+    }
+}
+
+impl Shader for GridShader {
+    fn shade(&self, _: &Tracer, info: &TraceInfo) -> Vec3 {
+        self.color(info.position(), info.intersection.t, info.footprint())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_lines_and_checks() {
+        let (white, black, red) = (
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::zeros(),
+            Vec3::new(1.0, 0.0, 0.0),
+        );
+        let fade = Some(Falloff::Linear(100.0, 200.0));
+        let grid = GridShader::new(white, Some(black), 10.0, red, 2.0, fade);
+
+        assert_eq!(grid.color(Vec3::new(5.0, 0.0, 5.0), 0.0, 0.0), white);
+        assert_eq!(grid.color(Vec3::new(15.0, 0.0, 5.0), 0.0, 0.0), black);
+        assert_eq!(grid.color(Vec3::new(-5.0, 0.0, 5.0), 0.0, 0.0), black);
+        assert_eq!(grid.color(Vec3::new(10.5, 0.0, 5.0), 0.0, 0.0), red);
+        assert_eq!(grid.color(Vec3::new(5.0, 0.0, -9.5), 0.0, 0.0), red);
+
+        // A pixel twice the width of a line is half covered by it
+        let color = grid.color(Vec3::new(0.0, 0.0, 5.0), 0.0, 4.0);
+        assert_eq!(color, Vec3::new(1.0, 0.5, 0.5));
+
+        let gray = Vec3::new(0.5, 0.5, 0.5);
+        assert_eq!(grid.color(Vec3::new(10.0, 0.0, 5.0), 500.0, 0.0), gray);
+    }
+}
@@ -0,0 +1,178 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use super::shader::{shadowed, Shader, TraceInfo, Tracer};
+use lights::Light;
+use math::Vec3;
+use options::MatteShaderOpts;
+
+/// Shades a surface white where it is lit, and with a shadow color where it
+/// is not, so the render leaves imagery unchanged where it is multiplied over
+/// it except in shadow
+#[derive(Clone, Debug)]
+pub struct MatteShader {
+    lights: Vec<usize>,
+    bias: f64,
+    color: Vec3,
+    opacity: f64,
+    shading: bool,
+}
+
+impl MatteShader {
+    pub fn new(
+        lights: Vec<usize>,
+        bias: f64,
+        color: Vec3,
+        opacity: f64,
+        shading: bool,
+    ) -> MatteShader {
+        MatteShader {
+            lights,
+            bias,
+            color,
+            opacity,
+            shading,
+        }
+    }
+
+    /// Return the fraction of the intensity of the lights reaching a point
+    fn lit(&self, tracer: &Tracer, info: &TraceInfo) -> f64 {
+        let normal = info.intersection.normal;
+        let mut lit = 0.0;
+        let mut total = 0.0;
+        for index in &self.lights {
+            let light = match tracer.light(*index) {
+                Some(Light::Directional(ref light)) => light,
+                _ => continue,
+            };
+            total += light.intensity;
+            if shadowed(tracer, info, light, self.bias) {
+                continue;
+            }
+            lit += if self.shading {
+                light.intensity * Vec3::dot(light.direction, normal).max(0.0)
+            } else {
+                light.intensity
+            };
+        }
+        if total > 0.0 {
+            lit / total
+        } else {
+            1.0
+        }
+    }
+}
+
+impl From<MatteShaderOpts> for MatteShader {
+    fn from(options: MatteShaderOpts) -> MatteShader {
+        MatteShader::new(
+            options.lights,
+            options.bias,
+            From::from(options.color),
+            options.opacity,
+            options.shading,
+        )
+    }
+}
+
+impl Shader for MatteShader {
+    fn shade(&self, tracer: &Tracer, info: &TraceInfo) -> Vec3 {
+        let shadow = (1.0 - self.lit(tracer, info)) * self.opacity;
+        Vec3::new(1.0, 1.0, 1.0) * (1.0 - shadow) + self.color * shadow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lights::DirectionalLight;
+    use math::Ray;
+    use primitives::Intersection;
+    use shaders::RayType;
+
+    /// A tracer whose shadow rays always hit, or never hit, an occluder
+    struct ShadowTracer {
+        lights: Vec<Light>,
+        occluded: bool,
+    }
+
+    impl Tracer for ShadowTracer {
+        fn trace_pixel(&self, _: RayType, _: f64, _: f64) -> Option<TraceInfo> {
+            None
+        }
+
+        fn trace_ray(
+            &self,
+            _: RayType,
+            ray: Ray,
+            x: f64,
+            y: f64,
+        ) -> Option<TraceInfo> {
+            if !self.occluded {
+                return None;
+            }
+            Some(TraceInfo {
+                ray,
+                intersection: Intersection::new(1.0, -ray.direction),
+                primitive: 0,
+                x,
+                y,
+            })
+        }
+
+        fn secondary_ray(&self, _: &TraceInfo, direction: Vec3) -> Ray {
+            Ray::new(Vec3::zeros(), direction)
+        }
+
+        fn shader(&self, _: usize) -> Option<&Shader> {
+            None
+        }
+
+        fn light(&self, index: usize) -> Option<&Light> {
+            self.lights.get(index)
+        }
+    }
+
+    #[test]
+    fn shadows_over_white() {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let sun = Light::Directional(DirectionalLight::new(
+            up,
+            Vec3::new(1.0, 1.0, 1.0),
+            1.0,
+        ));
+        let info = TraceInfo {
+            ray: Ray::new(Vec3::zeros(), -up),
+            intersection: Intersection::new(1.0, up),
+            primitive: 0,
+            x: 0.0,
+            y: 0.0,
+        };
+        let shader =
+            MatteShader::new(vec![0], 0.0, Vec3::new(0.2, 0.2, 0.3), 0.5, true);
+
+        let lit = ShadowTracer {
+            lights: vec![sun.clone()],
+            occluded: false,
+        };
+        assert_eq!(shader.shade(&lit, &info), Vec3::new(1.0, 1.0, 1.0));
+
+        let shaded = ShadowTracer {
+            lights: vec![sun],
+            occluded: true,
+        };
+        assert_eq!(shader.shade(&shaded, &info), Vec3::new(0.6, 0.6, 0.65));
+    }
+}
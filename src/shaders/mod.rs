@@ -14,17 +14,30 @@
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
 mod constant;
+mod depth_cue;
+mod detail_normal;
 mod feature_lines;
+mod glacier;
+mod grid;
+mod matte;
 mod normal;
+mod pattern;
 mod phong;
 mod sdf;
 mod shader;
 mod texture;
+mod vector_layer;
 
 pub use self::constant::ConstantShader;
+pub use self::depth_cue::DepthCueShader;
+pub use self::detail_normal::DetailNormalShader;
 pub use self::feature_lines::FeatureLineShader;
+pub use self::glacier::GlacierShader;
+pub use self::grid::GridShader;
+pub use self::matte::MatteShader;
 pub use self::normal::NormalShader;
 pub use self::phong::PhongShader;
 pub use self::sdf::SdfShader;
-pub use self::shader::{Shader, TraceInfo, Tracer};
+pub use self::shader::{RayType, Shader, TraceInfo, Tracer};
 pub use self::texture::TextureShader;
+pub use self::vector_layer::VectorLayerShader;
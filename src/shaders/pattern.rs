@@ -0,0 +1,112 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use super::texture::TextureShader;
+use math::Vec3;
+use options::FillPatternOpts;
+
+/// A pattern used to fill the interior of shapes, specified in map units
+#[derive(Clone, Debug)]
+pub enum FillPattern {
+    /// Parallel lines rotated by an angle (in degrees) from the x axis
+    Hatch {
+        angle: f64,
+        spacing: f64,
+        width: f64,
+    },
+    /// A regular grid of dots
+    Dots { spacing: f64, radius: f64 },
+    /// A texture repeated across the shape
+    Texture(TextureShader),
+}
+
+impl FillPattern {
+    /// Return the color of the pattern at a point or `None` where it is clear
+    pub fn sample(&self, point: Vec3, color: Vec3) -> Option<Vec3> {
+        match *self {
+            FillPattern::Hatch {
+                angle,
+                spacing,
+                width,
+            } => {
+                let angle = angle.to_radians();
+                let across = point.z * angle.cos() - point.x * angle.sin();
+                if across.rem_euclid(spacing) < width {
+                    Some(color)
+                } else {
+                    None
+                }
+            }
+            FillPattern::Dots { spacing, radius } => {
+                let center = Vec3::new(
+                    (point.x / spacing).round() * spacing,
+                    point.y,
+                    (point.z / spacing).round() * spacing,
+                );
+                if Vec3::distance(center, point) < radius {
+                    Some(color)
+                } else {
+                    None
+                }
+            }
+            FillPattern::Texture(ref texture) => {
+                Some(texture.tiled(point.x, point.z))
+            }
+        }
+    }
+}
+
+impl From<FillPatternOpts> for FillPattern {
+    fn from(options: FillPatternOpts) -> FillPattern {
+        match options {
+            FillPatternOpts::Hatch(opts) => FillPattern::Hatch {
+                angle: opts.angle,
+                spacing: opts.spacing,
+                width: opts.width,
+            },
+            FillPatternOpts::Dots(opts) => FillPattern::Dots {
+                spacing: opts.spacing,
+                radius: opts.radius,
+            },
+            FillPatternOpts::Texture(opts) => {
+                FillPattern::Texture(From::from(opts))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hatch_pattern() {
+        let pattern = FillPattern::Hatch {
+            angle: 0.0,
+            spacing: 10.0,
+            width: 2.0,
+        };
+        let color = Vec3::new(1.0, 1.0, 1.0);
+        assert_eq!(
+            pattern.sample(Vec3::new(5.0, 0.0, 1.0), color),
+            Some(color)
+        );
+        assert_eq!(
+            pattern.sample(Vec3::new(5.0, 0.0, -9.0), color),
+            Some(color)
+        );
+        assert_eq!(pattern.sample(Vec3::new(5.0, 0.0, 5.0), color), None);
+    }
+}
@@ -13,50 +13,215 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
-use super::shader::{Shader, TraceInfo, Tracer};
-use math::{Ray, Vec3};
-use options::PhongShaderOpts;
+use super::shader::{shadowed, RayType, Shader, TraceInfo, Tracer};
+use io::cache::{self, Raster};
+use lights::{DirectionalLight, EnvironmentLight, Light};
+use math::Vec3;
+use options::{BakedLightingOpts, PhongShaderOpts};
+
+use std::f64::consts::PI;
+use std::f64::EPSILON;
+use std::sync::Arc;
+
+/// Light received by a terrain, baked into rasters in world space
+#[derive(Clone)]
+pub struct BakedLighting {
+    diffuse: Arc<Raster>,
+    occlusion: Arc<Raster>,
+    /// Index of the height map primitive the light was baked for
+    primitive: usize,
+}
+
+/// Return the bilinearly filtered value of a raster at a point in world
+/// space, or nothing outside of it
+fn lookup(raster: &Raster, point: Vec3) -> Option<f64> {
+    let (_, ref transform, ref texture) = *raster;
+    let (x, y) = transform.inverse(point.x, point.z);
+    let (width, height) = (texture.width, texture.height);
+    if x < 0.0 || y < 0.0 || x > (width - 1) as f64 || y > (height - 1) as f64 {
+        return None;
+    }
+    Some(texture.bilinear_clamped(x, y))
+}
+
+impl BakedLighting {
+    pub fn new(
+        diffuse: Arc<Raster>,
+        occlusion: Arc<Raster>,
+        primitive: usize,
+    ) -> BakedLighting {
+        BakedLighting {
+            diffuse,
+            occlusion,
+            primitive,
+        }
+    }
+
+    /// Return the diffuse light and the fraction of the sky reaching an
+    /// intersection, or nothing if it is not with the baked terrain
+    fn lookup(&self, tracer: &Tracer, info: &TraceInfo) -> Option<(f64, f64)> {
+        let object = tracer.object(info.primitive)?;
+        if object.primitive != self.primitive {
+            return None;
+        }
+        let point = info.position();
+        match (lookup(&self.diffuse, point), lookup(&self.occlusion, point)) {
+            (Some(diffuse), Some(occlusion)) => Some((diffuse, occlusion)),
+            _ => None,
+        }
+    }
+}
+
+impl From<BakedLightingOpts> for BakedLighting {
+    fn from(options: BakedLightingOpts) -> BakedLighting {
+        BakedLighting::new(
+            cache::heights(&options.diffuse).unwrap(),
+            cache::heights(&options.occlusion).unwrap(),
+            options.primitive,
+        )
+    }
+}
 
 #[derive(Clone, Default)]
 pub struct PhongShader {
     wraps: usize,
-    directional_lights: Vec<usize>,
+    lights: Vec<usize>,
     bias: f64,
     ambient_color: Vec3,
     specular_color: Vec3,
     specular_exponent: f64,
     ks: f64,
     cel_shading: Option<(usize, f64)>,
+    normalize: bool,
+    baked: Option<BakedLighting>,
+}
+
+/// Clamp each component of a color between zero and one
+fn saturate(color: Vec3) -> Vec3 {
+    Vec3::new(
+        color.x.max(0.0).min(1.0),
+        color.y.max(0.0).min(1.0),
+        color.z.max(0.0).min(1.0),
+    )
 }
 
 impl PhongShader {
     pub fn new(
         wraps: usize,
-        directional_lights: Vec<usize>,
+        lights: Vec<usize>,
         bias: f64,
         ambient_color: Vec3,
         specular_color: Vec3,
         specular_exponent: f64,
         ks: f64,
         cel_shading: Option<(usize, f64)>,
+        normalize: bool,
     ) -> PhongShader {
         PhongShader {
             wraps,
-            directional_lights,
+            lights,
             bias,
             ambient_color,
             specular_color,
             specular_exponent,
             ks,
             cel_shading,
+            normalize,
+            baked: None,
+        }
+    }
+}
+
+impl PhongShader {
+    /// Return the distance to a surface shadowing a point in a direction
+    fn shadow(
+        &self,
+        tracer: &Tracer,
+        info: &TraceInfo,
+        dir: Vec3,
+    ) -> Option<f64> {
+        let mut secondary = tracer.secondary_ray(info, dir);
+        secondary.origin += info.intersection.normal * self.bias;
+        tracer
+            .trace_ray(RayType::Shadow, secondary, info.x, info.y)
+            .map(|shadow| shadow.intersection.t)
+    }
+
+    /// Return whether a point of the baked terrain is shadowed from a light
+    /// by another primitive, whose shadows were not baked
+    fn shadowed_by_others(
+        &self,
+        tracer: &Tracer,
+        info: &TraceInfo,
+        light: &DirectionalLight,
+    ) -> bool {
+        let terrain = self.baked.as_ref().map(|baked| baked.primitive);
+        let mut secondary = tracer.secondary_ray(info, light.direction);
+        secondary.origin += info.intersection.normal * self.bias;
+        tracer
+            .trace_ray(RayType::Shadow, secondary, info.x, info.y)
+            .and_then(|shadow| tracer.object(shadow.primitive))
+            .map_or(false, |object| Some(object.primitive) != terrain)
+    }
+
+    /// Return the diffuse light received from an environment, estimated by
+    /// sampling directions in proportion to their brightness
+    fn environment(
+        &self,
+        tracer: &Tracer,
+        info: &TraceInfo,
+        light: &EnvironmentLight,
+        occlusion: Option<f64>,
+    ) -> Vec3 {
+        let normal = info.intersection.normal;
+        if let Some(occlusion) = occlusion {
+            let mut sum = Vec3::zeros();
+            for i in 0..light.samples {
+                let (direction, radiance, pdf) =
+                    light.sample(i, info.x, info.y);
+                let cosine = Vec3::dot(direction, normal);
+                if cosine > 0.0 && pdf > 0.0 {
+                    sum += radiance * (cosine / pdf);
+                }
+            }
+            let samples = light.samples.max(1) as f64;
+            return sum * (occlusion / (samples * PI));
+        }
+
+        let compute = || {
+            let mut sum = Vec3::zeros();
+            // Harmonic mean distance to occluding surfaces, which bounds how
+            // far the estimate may be reused
+            let mut inverse = 0.0;
+            for i in 0..light.samples {
+                let (direction, radiance, pdf) =
+                    light.sample(i, info.x, info.y);
+                let cosine = Vec3::dot(direction, normal);
+                if cosine <= 0.0 || pdf <= 0.0 {
+                    continue;
+                }
+                match self.shadow(tracer, info, direction) {
+                    Some(t) => inverse += 1.0 / t.max(EPSILON),
+                    None => sum += radiance * (cosine / pdf),
+                }
+            }
+            let samples = light.samples.max(1) as f64;
+            (sum / (samples * PI), samples / inverse)
+        };
+
+        match light.cache {
+            Some(ref cache) => {
+                cache.irradiance(info.position(), normal, compute)
+            }
+            None => compute().0,
         }
     }
 }
 
 impl From<PhongShaderOpts> for PhongShader {
     fn from(options: PhongShaderOpts) -> PhongShader {
-        PhongShader::new(
-            options.wraps,
+        let mut shader = PhongShader::new(
+            options.wraps.index(),
             options.lights,
             options.bias,
             From::from(options.ambient),
@@ -64,46 +229,110 @@ impl From<PhongShaderOpts> for PhongShader {
             options.specular_exponent,
             options.ks,
             options.cel_shading,
-        )
+            options.normalize,
+        );
+        shader.baked = options.baked.map(From::from);
+        shader
     }
 }
 
 impl Shader for PhongShader {
-    fn shade(&self, tracer: &Tracer, info: &TraceInfo) -> Vec3 {
-        let point = info.ray.origin + info.ray.direction * info.intersection.t;
-        let point = point + info.intersection.normal * self.bias;
+    fn memory(&self) -> usize {
+        match self.baked {
+            Some(ref baked) => {
+                baked.diffuse.2.memory() + baked.occlusion.2.memory()
+            }
+            None => 0,
+        }
+    }
 
+    fn shade(&self, tracer: &Tracer, info: &TraceInfo) -> Vec3 {
         let normal = info.intersection.normal;
         let eye = info.ray.direction;
 
-        let mut diffuse = 0.0;
-        let mut specular = 0.0;
+        let mut diffuse = Vec3::zeros();
+        let mut specular = Vec3::zeros();
+        let mut total = 0.0;
 
-        for index in &self.directional_lights {
-            let light = tracer.light(*index).unwrap();
+        let baked = self
+            .baked
+            .as_ref()
+            .and_then(|baked| baked.lookup(tracer, info));
+        // Directional light received without shadows, which the baked light
+        // is a fraction of
+        let (mut direct, mut received) = (Vec3::zeros(), 0.0);
+
+        for index in &self.lights {
+            let light = match *tracer.light(*index).unwrap() {
+                Light::Directional(ref light) => light,
+                Light::Environment(ref light) => {
+                    total += light.intensity;
+                    let occlusion = baked.map(|(_, occlusion)| occlusion);
+                    diffuse += self.environment(tracer, info, light, occlusion)
+                        * light.intensity;
+                    continue;
+                }
+            };
             let light_dir = light.direction;
-            let secondary = Ray::new(point, light_dir);
-            if tracer.trace_ray(secondary, info.x, info.y).is_some() {
+            total += light.intensity;
+            if baked.is_none() && shadowed(tracer, info, light, self.bias) {
                 continue;
             }
 
+            // Lights facing away contribute nothing, rather than darkening
+            // the contribution of other lights
+            let radiance = light.color * light.intensity;
             let reflection = Vec3::reflect(light_dir, normal);
-            specular += Vec3::dot(reflection, eye).powf(self.specular_exponent)
-                * self.ks;
-            diffuse += Vec3::dot(light_dir, normal);
+            let highlight = Vec3::dot(reflection, eye).max(0.0);
+            let cosine = Vec3::dot(light_dir, normal).max(0.0);
+            if baked.is_some() {
+                // Light blocked by other primitives is taken out of the light
+                // baked for the terrain alone
+                received += light.intensity * cosine;
+                if self.shadowed_by_others(tracer, info, light) {
+                    continue;
+                }
+                direct += radiance * cosine;
+                specular += radiance
+                    * (highlight.powf(self.specular_exponent) * self.ks);
+                continue;
+            }
+            specular +=
+                radiance * (highlight.powf(self.specular_exponent) * self.ks);
+            diffuse += radiance * cosine;
         }
 
-        diffuse = diffuse.max(0.0).min(1.0);
-        specular = specular.max(0.0).min(1.0);
-
-        if let Some((bands, specular_threshold)) = self.cel_shading {
-            let interval = 1.0 / bands as f64;
-            diffuse = (diffuse / interval).round() * interval;
-            specular = if specular > specular_threshold {
-                1.0
+        // Scale the light that would be received by the fraction of it that
+        // was baked, keeping the colors of the lights
+        if let Some((light, _)) = baked {
+            let lit = if received > 0.0 {
+                (light / received).min(1.0)
             } else {
                 0.0
             };
+            diffuse += direct * lit;
+            specular = specular * lit;
+        }
+
+        if self.normalize && total > 0.0 {
+            diffuse = diffuse / total;
+            specular = specular / total;
+        }
+        diffuse = saturate(diffuse);
+        specular = saturate(specular);
+
+        if let Some((bands, specular_threshold)) = self.cel_shading {
+            let interval = 1.0 / bands as f64;
+            let band = |value: f64| (value / interval).round() * interval;
+            let threshold =
+                |value: f64| if value > specular_threshold { 1.0 } else { 0.0 };
+            diffuse =
+                Vec3::new(band(diffuse.x), band(diffuse.y), band(diffuse.z));
+            specular = Vec3::new(
+                threshold(specular.x),
+                threshold(specular.y),
+                threshold(specular.z),
+            );
         }
 
         let color = match tracer.shader(self.wraps) {
@@ -116,3 +345,223 @@ impl Shader for PhongShader {
             + (self.specular_color * specular)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cameras::OrthographicCamera;
+    use math::{AffineTransform, Ray};
+    use primitives::{Intersection, Plane, Sphere};
+    use render::Renderer;
+    use scene::{Background, Object, Scene};
+    use shaders::ConstantShader;
+    use textures::Texture;
+
+    /// Shades an unoccluded surface of a single object with a white shader
+    /// and a set of lights
+    struct LitTracer {
+        white: ConstantShader,
+        lights: Vec<Light>,
+        object: Object,
+    }
+
+    impl Tracer for LitTracer {
+        fn trace_pixel(&self, _: RayType, _: f64, _: f64) -> Option<TraceInfo> {
+            None
+        }
+
+        fn trace_ray(
+            &self,
+            _: RayType,
+            _: Ray,
+            _: f64,
+            _: f64,
+        ) -> Option<TraceInfo> {
+            None
+        }
+
+        fn secondary_ray(&self, _: &TraceInfo, direction: Vec3) -> Ray {
+            Ray::new(Vec3::zeros(), direction)
+        }
+
+        fn shader(&self, _: usize) -> Option<&Shader> {
+            Some(&self.white)
+        }
+
+        fn light(&self, index: usize) -> Option<&Light> {
+            self.lights.get(index)
+        }
+
+        fn object(&self, index: usize) -> Option<&Object> {
+            match index {
+                0 => Some(&self.object),
+                _ => None,
+            }
+        }
+    }
+
+    fn shade(lights: Vec<Light>, normalize: bool) -> Vec3 {
+        shade_baked(lights, normalize, None)
+    }
+
+    fn shade_baked(
+        lights: Vec<Light>,
+        normalize: bool,
+        baked: Option<BakedLighting>,
+    ) -> Vec3 {
+        let indices = (0..lights.len()).collect();
+        let tracer = LitTracer {
+            white: ConstantShader::new(Vec3::new(1.0, 1.0, 1.0)),
+            lights,
+            object: Object::new(0, 0),
+        };
+        let mut shader = PhongShader::new(
+            0,
+            indices,
+            0.0,
+            Vec3::zeros(),
+            Vec3::zeros(),
+            1.0,
+            0.0,
+            None,
+            normalize,
+        );
+        shader.baked = baked;
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let info = TraceInfo {
+            ray: Ray::new(Vec3::zeros(), -up),
+            intersection: Intersection::new(1.0, up),
+            primitive: 0,
+            x: 0.0,
+            y: 0.0,
+        };
+        shader.shade(&tracer, &info)
+    }
+
+    #[test]
+    fn colored_lights() {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let light = |direction, color, intensity| {
+            Light::Directional(DirectionalLight::new(
+                direction, color, intensity,
+            ))
+        };
+        let red = light(up, Vec3::new(1.0, 0.0, 0.0), 0.5);
+        let blue = light(up, Vec3::new(0.0, 0.0, 1.0), 0.25);
+        let below = light(-up, Vec3::new(1.0, 1.0, 1.0), 1.0);
+
+        let color = shade(vec![red.clone(), blue.clone(), below], false);
+        assert_eq!(color, Vec3::new(0.5, 0.0, 0.25));
+
+        let color = shade(vec![red, blue], true);
+        assert!(Vec3::distance(color, Vec3::new(2.0, 0.0, 1.0) / 3.0) < 1e-9);
+    }
+
+    #[test]
+    fn environment_lights() {
+        // A uniform sky lights an upward facing surface by its radiance
+        let sky = vec![Vec3::new(1.0, 1.0, 1.0); 16 * 256];
+        let light =
+            EnvironmentLight::new(Texture::new(16, 256, sky), 0.5, 1024, 0.0);
+        let color = shade(vec![Light::Environment(light)], false);
+        assert!(Vec3::distance(color, Vec3::new(0.5, 0.5, 0.5)) < 0.02);
+    }
+
+    /// A raster of a constant value, around the origin in world space
+    fn raster(value: f64) -> Arc<Raster> {
+        let transform = AffineTransform::new(-4.0, -4.0, 1.0, 1.0);
+        let texture = Texture::new(9, 9, vec![value; 81]);
+        Arc::new((String::new(), transform, texture))
+    }
+
+    #[test]
+    fn baked_lighting() {
+        let baked = BakedLighting::new(raster(0.25), raster(0.5), 0);
+
+        // The baked light is a fraction of the light of the sun, in its color
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let sun = Light::Directional(DirectionalLight::new(
+            up,
+            Vec3::new(1.0, 0.5, 0.0),
+            0.5,
+        ));
+        let color = shade_baked(vec![sun], false, Some(baked.clone()));
+        assert_eq!(color, Vec3::new(0.25, 0.125, 0.0));
+
+        // Environment lights are scaled by the sky reaching the surface
+        let sky = vec![Vec3::new(1.0, 1.0, 1.0); 16 * 256];
+        let light =
+            EnvironmentLight::new(Texture::new(16, 256, sky), 1.0, 1024, 0.0);
+        let color =
+            shade_baked(vec![Light::Environment(light)], false, Some(baked));
+        assert!(Vec3::distance(color, Vec3::new(0.5, 0.5, 0.5)) < 0.02);
+    }
+
+    /// Return the color of the first surface below a point, of a unit sphere
+    /// above the origin and a level terrain, lit from above with the light
+    /// baked for the terrain
+    fn shade_terrain(diffuse: f64, x: f64, y: f64) -> Vec3 {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let mut phong = PhongShader::new(
+            1,
+            vec![0],
+            1e-6,
+            Vec3::zeros(),
+            Vec3::zeros(),
+            1.0,
+            0.0,
+            None,
+            false,
+        );
+        phong.baked = Some(BakedLighting::new(raster(diffuse), raster(1.0), 1));
+        let scene = Scene {
+            background: Background::Color(Vec3::zeros()),
+            camera: Arc::new(OrthographicCamera::new(
+                4,
+                4,
+                Vec3::new(0.0, 10.0, 0.0),
+                Vec3::zeros(),
+                1.0,
+                Vec3::new(0.0, 0.0, 1.0),
+                4.0,
+            )),
+            shaders: vec![
+                Arc::new(phong),
+                Arc::new(ConstantShader::new(white)),
+            ],
+            primitives: vec![
+                Arc::new(Sphere::new(Vec3::new(0.0, 5.0, 0.0), 1.0)),
+                Arc::new(Plane::new(up, 0.0)),
+            ],
+            objects: vec![Object::new(0, 0), Object::new(1, 0)],
+            lights: vec![Arc::new(Light::Directional(DirectionalLight::new(
+                up, white, 1.0,
+            )))],
+            linework: vec![],
+            labels: vec![],
+            edges: None,
+            ray_epsilon: 1e-9,
+            normal_offset: 0.0,
+            face_forward: true,
+            shadow_cache: None,
+            clip_elevation: None,
+            water: None,
+        };
+        let renderer = Renderer::new(1, scene);
+        let ray = Ray::new(Vec3::new(x, y, 0.0), -up);
+        let info = renderer.trace_ray(RayType::Camera, ray, 0.0, 0.0).unwrap();
+        renderer.shader(0).unwrap().shade(&renderer, &info)
+    }
+
+    #[test]
+    fn sphere_above_baked_terrain() {
+        let (black, white) = (Vec3::zeros(), Vec3::new(1.0, 1.0, 1.0));
+        // The sphere is lit, whatever the light baked for the terrain below
+        assert_eq!(shade_terrain(0.0, 0.0, 10.0), white);
+        assert_eq!(shade_terrain(0.0, 2.0, 10.0), black);
+        // And shadows the terrain where it is lit
+        assert_eq!(shade_terrain(1.0, 0.0, 3.0), black);
+        assert_eq!(shade_terrain(1.0, 2.0, 3.0), white);
+    }
+}
@@ -13,16 +13,28 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
+use super::pattern::FillPattern;
 use super::shader::{Shader, TraceInfo, Tracer};
-use io::ogr;
+use io::cache;
 use math::Vec3;
-use options::{Loader, SdfShaderOpts};
+use options::SdfShaderOpts;
 use shapes::Shape;
 
-#[derive(Clone, Default)]
+use std::sync::Arc;
+
+/// Return the fraction of a pixel, with a width in world units, that falls
+/// inside an edge at a signed distance
+pub fn coverage(distance: f64, edge: f64, width: f64) -> f64 {
+    if width <= 0.0 {
+        return if distance < edge { 1.0 } else { 0.0 };
+    }
+    ((edge - distance) / width + 0.5).min(1.0).max(0.0)
+}
+
+#[derive(Clone)]
 pub struct SdfShader {
     wraps: usize,
-    shapes: Vec<Shape>,
+    shapes: Arc<Vec<Shape>>,
     tolerance: f64,
     color: Vec3,
     alpha: f64,
@@ -30,55 +42,33 @@ pub struct SdfShader {
     stroke_color: Vec3,
     stroke_alpha: f64,
     offset: f64,
+    pattern: Option<FillPattern>,
+    vertical_tolerance: Option<f64>,
 }
 
 impl SdfShader {
-    pub fn new(
-        wraps: usize,
-        shapes: Vec<Shape>,
-        tolerance: f64,
-        color: Vec3,
-        alpha: f64,
-        stroke_width: f64,
-        stroke_color: Vec3,
-        stroke_alpha: f64,
-        offset: f64,
-    ) -> SdfShader {
+    /// Return a shader drawing shapes with the style of its options
+    pub fn new(options: SdfShaderOpts, shapes: Arc<Vec<Shape>>) -> SdfShader {
         SdfShader {
-            wraps,
+            wraps: options.wraps.index(),
             shapes,
-            tolerance,
-            color,
-            alpha,
-            stroke_width,
-            stroke_color,
-            stroke_alpha,
-            offset,
+            tolerance: options.tolerance,
+            color: From::from(options.color),
+            alpha: options.alpha,
+            stroke_width: options.stroke_width,
+            stroke_color: From::from(options.stroke_color),
+            stroke_alpha: options.stroke_alpha,
+            offset: options.offset,
+            pattern: options.pattern.map(From::from),
+            vertical_tolerance: options.vertical_tolerance,
         }
     }
 }
 
 impl From<SdfShaderOpts> for SdfShader {
     fn from(options: SdfShaderOpts) -> SdfShader {
-        let shapes = match options.data {
-            Loader::Shp(opts) => {
-                let layers = ogr::import(opts.filepath, &[opts.layer]).unwrap();
-                layers[0].clone()
-            }
-            _ => panic!("Unsupported format"),
-        };
-
-        SdfShader::new(
-            options.wraps,
-            shapes,
-            options.tolerance,
-            From::from(options.color),
-            options.alpha,
-            options.stroke_width,
-            From::from(options.stroke_color),
-            From::from(options.stroke_alpha),
-            options.offset,
-        )
+        let shapes = cache::shapes(&options.data).unwrap();
+        SdfShader::new(options, shapes)
     }
 }
 
@@ -90,22 +80,60 @@ impl Shader for SdfShader {
             None => Vec3::zeros(),
         };
 
-        for shape in &self.shapes {
+        let footprint = info.footprint();
+        for shape in self.shapes.iter() {
             if !shape.bbox().offset(self.offset).contains(point) {
                 continue;
             }
 
+            if let Some(tolerance) = self.vertical_tolerance {
+                if shape.vertical_offset(point) > tolerance {
+                    continue;
+                }
+            }
+
             let distance = shape.distance(point);
-            if distance < self.tolerance {
-                let (color, alpha) = if distance > self.tolerance - self.stroke_width {
-                    (self.stroke_color, self.stroke_alpha)
-                } else {
-                    (self.color, self.alpha)
-                };
-                return color * alpha + base * (1.0 - alpha);
+            let outer = coverage(distance, self.tolerance, footprint);
+            if outer <= 0.0 {
+                continue;
             }
+
+            let edge = self.tolerance - self.stroke_width;
+            let inner = coverage(distance, edge, footprint);
+
+            let (fill, fill_alpha) = match self.pattern {
+                Some(ref pattern) => match pattern.sample(point, self.color) {
+                    Some(color) => (color, self.alpha),
+                    None => (self.color, 0.0),
+                },
+                None => (self.color, self.alpha),
+            };
+
+            let fill_alpha = fill_alpha * inner;
+            let stroke_alpha = self.stroke_alpha * (outer - inner);
+            return fill * fill_alpha
+                + self.stroke_color * stroke_alpha
+                + base * (1.0 - fill_alpha - stroke_alpha);
         }
 
         base
     }
+
+    fn shapes(&self) -> Vec<Arc<Vec<Shape>>> {
+        vec![self.shapes.clone()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edge_coverage() {
+        assert_eq!(coverage(-1.0, 0.0, 1.0), 1.0);
+        assert_eq!(coverage(0.0, 0.0, 1.0), 0.5);
+        assert_eq!(coverage(0.25, 0.0, 1.0), 0.25);
+        assert_eq!(coverage(1.0, 0.0, 1.0), 0.0);
+        assert_eq!(coverage(-0.1, 0.0, 0.0), 1.0);
+    }
 }
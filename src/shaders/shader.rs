@@ -13,9 +13,25 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
-use lights::DirectionalLight;
+use lights::{DirectionalLight, Light};
 use math::{Ray, Vec3};
 use primitives::Intersection;
+use scene::Object;
+use shapes::Shape;
+
+use std::f64::EPSILON;
+use std::sync::Arc;
+
+/// The purpose of a ray, used to test against per object visibility
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RayType {
+    /// Primary rays cast from the camera
+    Camera,
+    /// Secondary rays cast towards a light
+    Shadow,
+    /// Rays cast around a primary ray when detecting feature lines
+    Stencil,
+}
 
 pub struct TraceInfo {
     /// The ray used to populate this object
@@ -30,18 +46,109 @@ pub struct TraceInfo {
     pub y: f64,
 }
 
+impl TraceInfo {
+    /// Return the world space position of the intersection
+    pub fn position(&self) -> Vec3 {
+        self.ray.origin + self.ray.direction * self.intersection.t
+    }
+
+    /// Return the change in position of the intersection for a one pixel
+    /// step in `x` and `y` on the view plane
+    pub fn differentials(&self) -> Option<(Vec3, Vec3)> {
+        let differentials = match self.ray.differentials {
+            Some(differentials) => differentials,
+            None => return None,
+        };
+
+        let point = self.position();
+        let normal = self.intersection.normal;
+
+        // Intersect the offset rays with the tangent plane at the point
+        let offset = |origin: Vec3, direction: Vec3| {
+            let denom = Vec3::dot(direction, normal);
+            if denom.abs() < EPSILON {
+                return None;
+            }
+            let t = Vec3::dot(point - origin, normal) / denom;
+            Some(origin + direction * t - point)
+        };
+
+        let dpdx = offset(differentials.rx_origin, differentials.rx_direction);
+        let dpdy = offset(differentials.ry_origin, differentials.ry_direction);
+        match (dpdx, dpdy) {
+            (Some(dpdx), Some(dpdy)) => Some((dpdx, dpdy)),
+            _ => None,
+        }
+    }
+
+    /// Return the world space width of a pixel at the intersection
+    pub fn footprint(&self) -> f64 {
+        match self.differentials() {
+            Some((dpdx, dpdy)) => Vec3::length(dpdx).max(Vec3::length(dpdy)),
+            None => 0.0,
+        }
+    }
+}
+
+/// Return whether a directional light is blocked from reaching the point of a
+/// trace, offset from the surface by a bias along its normal
+pub fn shadowed(
+    tracer: &Tracer,
+    info: &TraceInfo,
+    light: &DirectionalLight,
+    bias: f64,
+) -> bool {
+    let normal = info.intersection.normal;
+    let position = info.position() + normal * bias;
+    light
+        .shadow_map
+        .as_ref()
+        .and_then(|map| map.occluded(position))
+        .unwrap_or_else(|| {
+            let mut ray = tracer.secondary_ray(info, light.direction);
+            ray.origin += normal * bias;
+            tracer
+                .trace_ray(RayType::Shadow, ray, info.x, info.y)
+                .is_some()
+        })
+}
+
 pub trait Tracer {
     /// Returns information for tracing a ray specified in screen space
-    fn trace_pixel(&self, x: f64, y: f64) -> Option<TraceInfo>;
+    fn trace_pixel(&self, kind: RayType, x: f64, y: f64) -> Option<TraceInfo>;
     /// Returns information for a ray trace
-    fn trace_ray(&self, ray: Ray, x: f64, y: f64) -> Option<TraceInfo>;
+    fn trace_ray(
+        &self,
+        kind: RayType,
+        ray: Ray,
+        x: f64,
+        y: f64,
+    ) -> Option<TraceInfo>;
+    /// Return a ray leaving the intersection in a direction, moved off the
+    /// surface to avoid intersecting it again
+    fn secondary_ray(&self, info: &TraceInfo, direction: Vec3) -> Ray;
     /// Return a shader with a given index
     fn shader(&self, index: usize) -> Option<&Shader>;
     /// Return the light for a given index
-    fn light(&self, index: usize) -> Option<&DirectionalLight>;
+    fn light(&self, index: usize) -> Option<&Light>;
+    /// Return the object with a given index, as intersected by a trace
+    fn object(&self, _index: usize) -> Option<&Object> {
+        None
+    }
 }
 
 pub trait Shader {
     /// Return the resulting color for a ray trace
     fn shade(&self, tracer: &Tracer, info: &TraceInfo) -> Vec3;
+
+    /// Return an estimate of the number of bytes held by the shader, not
+    /// counting the shapes it shares
+    fn memory(&self) -> usize {
+        0
+    }
+
+    /// Return the shapes the shader shares with other resources of a scene
+    fn shapes(&self) -> Vec<Arc<Vec<Shape>>> {
+        vec![]
+    }
 }
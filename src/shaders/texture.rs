@@ -14,61 +14,328 @@
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
 use super::shader::{Shader, TraceInfo, Tracer};
-use math::{AffineTransform, Vec3};
-use options::TextureShaderOpts;
-use textures::{Bilinear, Texture};
+use math::{reproject, AffineTransform, Projection, Vec3};
+use options::{ReprojectOpts, TextureFilter, TextureShaderOpts};
+use textures::{Bilinear, Mipmaps, Texture};
 
-#[derive(Clone, Debug, Default)]
+/// Maximum number of probes taken along the major axis of the footprint
+const MAX_ANISOTROPY: f64 = 8.0;
+
+/// Number of points sampled along each edge of a texture when finding its
+/// footprint in the scene
+const EDGE_SAMPLES: usize = 16;
+
+/// Texture coordinates for a texture in a different coordinate reference
+/// system than the scene
+#[derive(Clone, Debug)]
+struct Reprojection {
+    scene: Projection,
+    texture: Projection,
+    /// Texture coordinates precomputed at regular positions in the scene
+    warp: Option<Warp>,
+}
+
+#[derive(Clone, Debug)]
+struct Warp {
+    x: f64,
+    z: f64,
+    spacing: f64,
+    grid: Texture<Vec3>,
+}
+
+#[derive(Clone, Debug)]
 pub struct TextureShader {
     transform: AffineTransform,
-    texture: Texture<Vec3>,
+    texture: Mipmaps<Vec3>,
+    filter: TextureFilter,
+    reprojection: Option<Reprojection>,
 }
 
 impl TextureShader {
     pub fn new(
         transform: AffineTransform,
         texture: Texture<Vec3>,
+        filter: TextureFilter,
     ) -> TextureShader {
-        TextureShader { transform, texture }
+        TextureShader {
+            transform,
+            texture: Mipmaps::new(texture),
+            filter,
+            reprojection: None,
+        }
     }
-}
 
-impl From<TextureShaderOpts> for TextureShader {
-    fn from(options: TextureShaderOpts) -> TextureShader {
-        match options.components {
-            1 => {
-                let data = options
-                    .data
-                    .into_iter()
-                    .map(|d| Vec3::new(d, d, d))
-                    .collect();
-                let texture = Texture::new(options.width, options.height, data);
-                TextureShader::new(From::from(options.transform), texture)
+    /// Sample the texture in a projection other than that of the scene,
+    /// optionally through a grid of texture coordinates with a spacing in
+    /// scene units
+    pub fn reproject(
+        mut self,
+        scene: Projection,
+        texture: Projection,
+        spacing: Option<f64>,
+    ) -> TextureShader {
+        self.reprojection = Some(Reprojection {
+            scene,
+            texture,
+            warp: None,
+        });
+        let warp = spacing
+            .filter(|spacing| *spacing > 0.0)
+            .map(|spacing| self.warp(spacing));
+        if let Some(ref mut reprojection) = self.reprojection {
+            reprojection.warp = warp;
+        }
+        self
+    }
+
+    /// Return the texture coordinates of a world position
+    pub fn locate(&self, x: f64, z: f64) -> (f64, f64) {
+        let reprojection = match self.reprojection {
+            Some(ref reprojection) => reprojection,
+            None => return self.transform.inverse(x, z),
+        };
+
+        if let Some(ref warp) = reprojection.warp {
+            let gx = (x - warp.x) / warp.spacing;
+            let gz = (z - warp.z) / warp.spacing;
+            let inside = gx >= 0.0
+                && gz >= 0.0
+                && gx + 1.0 < warp.grid.width as f64
+                && gz + 1.0 < warp.grid.height as f64;
+            if inside {
+                let uv = warp.grid.bilinear(gx, gz);
+                return (uv.x, uv.y);
             }
-            3 => {
-                assert_eq!(options.data.len() % 3, 0);
-                let mut data = Vec::with_capacity(options.data.len());
-                for i in 0..options.width * options.height {
-                    let pixel = &options.data[i * 3..i * 3 + 3];
-                    data.push(Vec3::new(pixel[0], pixel[1], pixel[2]));
-                }
-                TextureShader::new(
-                    From::from(options.transform),
-                    Texture::new(options.width, options.height, data),
-                )
+        }
+        self.reprojected(reprojection, x, z)
+    }
+
+    /// Return the texture coordinates of a world position, reprojected
+    /// exactly
+    fn reprojected(
+        &self,
+        reprojection: &Reprojection,
+        x: f64,
+        z: f64,
+    ) -> (f64, f64) {
+        let (x, y) =
+            reproject(&reprojection.scene, &reprojection.texture, x, -z);
+        self.transform.inverse(x, -y)
+    }
+
+    /// Precompute texture coordinates over the footprint of the texture
+    fn warp(&self, spacing: f64) -> Warp {
+        let reprojection = self.reprojection.as_ref().unwrap();
+        let base = &self.texture.levels[0];
+        let (width, height) = (base.width as f64, base.height as f64);
+
+        // Find the bounds of the texture in the scene from points along its
+        // edges, as straight edges may be curved after reprojection
+        let mut bounds = (
+            ::std::f64::INFINITY,
+            ::std::f64::INFINITY,
+            ::std::f64::NEG_INFINITY,
+            ::std::f64::NEG_INFINITY,
+        );
+        for i in 0..=EDGE_SAMPLES {
+            let t = i as f64 / EDGE_SAMPLES as f64;
+            let edges = [
+                (t * width, 0.0),
+                (t * width, height),
+                (0.0, t * height),
+                (width, t * height),
+            ];
+            for &(u, v) in &edges {
+                let (x, y) = self.transform.forward(u, v);
+                let (x, y) = reproject(
+                    &reprojection.texture,
+                    &reprojection.scene,
+                    x,
+                    -y,
+                );
+                bounds.0 = bounds.0.min(x);
+                bounds.1 = bounds.1.min(-y);
+                bounds.2 = bounds.2.max(x);
+                bounds.3 = bounds.3.max(-y);
+            }
+        }
+
+        let columns = ((bounds.2 - bounds.0) / spacing).ceil() as usize + 2;
+        let rows = ((bounds.3 - bounds.1) / spacing).ceil() as usize + 2;
+        let mut grid = Texture::blank(columns, rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let x = bounds.0 + column as f64 * spacing;
+                let z = bounds.1 + row as f64 * spacing;
+                let (u, v) = self.reprojected(reprojection, x, z);
+                grid.write1x1(column, row, Vec3::new(u, v, 0.0));
             }
-            _ => {
-                // FIXME: Return an error instead
-                TextureShader::new(Default::default(), Texture::blank(1, 1))
+        }
+
+        Warp {
+            x: bounds.0,
+            z: bounds.1,
+            spacing,
+            grid,
+        }
+    }
+
+    /// Return the texture at a world position, repeating it infinitely
+    pub fn tiled(&self, x: f64, z: f64) -> Vec3 {
+        let (u, v) = self.locate(x, z);
+        let width = (self.texture.levels[0].width - 1) as f64;
+        let height = (self.texture.levels[0].height - 1) as f64;
+        self.texture
+            .bilinear(u.rem_euclid(width), v.rem_euclid(height), 0)
+    }
+
+    /// Return the footprint of a pixel, as axes in texture space
+    fn texel_differentials(
+        &self,
+        info: &TraceInfo,
+    ) -> ((f64, f64), (f64, f64)) {
+        let point = info.position();
+        let (u, v) = self.locate(point.x, point.z);
+        let (dpdx, dpdy) = match info.differentials() {
+            Some(differentials) => differentials,
+            None => return ((0.0, 0.0), (0.0, 0.0)),
+        };
+        let (ux, vx) = self.locate(point.x + dpdx.x, point.z + dpdx.z);
+        let (uy, vy) = self.locate(point.x + dpdy.x, point.z + dpdy.z);
+        ((ux - u, vx - v), (uy - u, vy - v))
+    }
+}
+
+impl From<TextureShaderOpts> for TextureShader {
+    fn from(mut options: TextureShaderOpts) -> TextureShader {
+        let reprojection = options.reproject.take();
+        let shader = from_data(options);
+        match reprojection {
+            Some(ReprojectOpts {
+                scene,
+                texture,
+                grid,
+            }) => shader.reproject(scene.into(), texture.into(), grid),
+            None => shader,
+        }
+    }
+}
+
+/// Return a shader for the texture of some options, ignoring reprojection
+fn from_data(options: TextureShaderOpts) -> TextureShader {
+    match options.components {
+        1 => {
+            let data = options
+                .data
+                .into_iter()
+                .map(|d| Vec3::new(d, d, d))
+                .collect();
+            let texture = Texture::new(options.width, options.height, data);
+            TextureShader::new(
+                From::from(options.transform),
+                texture,
+                options.filter,
+            )
+        }
+        3 => {
+            assert_eq!(options.data.len() % 3, 0);
+            let mut data = Vec::with_capacity(options.data.len());
+            for i in 0..options.width * options.height {
+                let pixel = &options.data[i * 3..i * 3 + 3];
+                data.push(Vec3::new(pixel[0], pixel[1], pixel[2]));
             }
+            TextureShader::new(
+                From::from(options.transform),
+                Texture::new(options.width, options.height, data),
+                options.filter,
+            )
+        }
+        _ => {
+            // FIXME: Return an error instead
+            TextureShader::new(
+                Default::default(),
+                Texture::blank(1, 1),
+                options.filter,
+            )
         }
     }
 }
 
 impl Shader for TextureShader {
     fn shade(&self, _: &Tracer, info: &TraceInfo) -> Vec3 {
-        let point = info.ray.origin + info.ray.direction * info.intersection.t;
-        let (u, v) = self.transform.inverse(point.x, point.z);
-        self.texture.bilinear(u, v)
+        let point = info.position();
+        let (u, v) = self.locate(point.x, point.z);
+        let length = |(x, y): (f64, f64)| (x * x + y * y).sqrt();
+
+        match self.filter {
+            TextureFilter::Bilinear => self.texture.bilinear(u, v, 0),
+            TextureFilter::Trilinear => {
+                let (dx, dy) = self.texel_differentials(info);
+                let width = length(dx).max(length(dy));
+                self.texture.trilinear(u, v, width.max(1.0).log2())
+            }
+            TextureFilter::Anisotropic => {
+                let (dx, dy) = self.texel_differentials(info);
+                let (major, minor) = if length(dx) > length(dy) {
+                    (dx, dy)
+                } else {
+                    (dy, dx)
+                };
+
+                // Take several probes along the major axis, each filtered
+                // with the width of the minor axis
+                let ratio = length(major) / length(minor).max(1e-9);
+                let probes = ratio.min(MAX_ANISOTROPY).max(1.0).ceil();
+                let width = length(major) / probes;
+                let lod = width.max(1.0).log2();
+
+                let mut color = Vec3::zeros();
+                for i in 0..probes as usize {
+                    let t = (i as f64 + 0.5) / probes - 0.5;
+                    let (pu, pv) = (u + major.0 * t, v + major.1 * t);
+                    color += self.texture.trilinear(pu, pv, lod);
+                }
+                color / probes
+            }
+        }
+    }
+
+    fn memory(&self) -> usize {
+        self.texture.memory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warping_reprojected_coordinates() {
+        // A 256m web mercator texture near the origin of UTM zone 31
+        let (x, y) = Projection::WebMercator.forward(3.0, 45.0);
+        let transform = AffineTransform::new(x, -y, 1.0, 1.0);
+        let shader = TextureShader::new(
+            transform,
+            Texture::blank(256, 256),
+            TextureFilter::Bilinear,
+        );
+        let utm = Projection::Utm {
+            zone: 31,
+            south: false,
+        };
+
+        let exact =
+            shader.clone().reproject(utm, Projection::WebMercator, None);
+        let warped = shader.reproject(utm, Projection::WebMercator, Some(16.0));
+
+        let (x, y) = utm.forward(3.0, 45.0);
+        let (u, v) = exact.locate(x, -y);
+        assert!(u.abs() < 1e-3 && v.abs() < 1e-3);
+
+        for &(dx, dy) in &[(30.0, -40.0), (90.5, -120.25), (150.0, -10.0)] {
+            let (u1, v1) = exact.locate(x + dx, -y + dy);
+            let (u2, v2) = warped.locate(x + dx, -y + dy);
+            assert!((u1 - u2).abs() < 1e-3 && (v1 - v2).abs() < 1e-3);
+        }
     }
 }
@@ -0,0 +1,194 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use super::pattern::FillPattern;
+use super::sdf::coverage;
+use super::shader::{Shader, TraceInfo, Tracer};
+use io::cache;
+use math::Vec3;
+use options::{BlendMode, VectorLayerOpts, VectorLayerShaderOpts};
+use shapes::Shape;
+
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct VectorLayer {
+    shapes: Arc<Vec<Shape>>,
+    tolerance: f64,
+    fill: Option<Vec3>,
+    pattern: Option<FillPattern>,
+    stroke: Option<Vec3>,
+    stroke_width: f64,
+    dash: Option<(f64, f64)>,
+    opacity: f64,
+    blend: BlendMode,
+    offset: f64,
+}
+
+#[derive(Clone, Default)]
+pub struct VectorLayerShader {
+    wraps: usize,
+    layers: Vec<VectorLayer>,
+}
+
+/// Blend a color onto a base color
+fn blend(mode: BlendMode, base: Vec3, color: Vec3) -> Vec3 {
+    let overlay = |b: f64, c: f64| {
+        if b < 0.5 {
+            2.0 * b * c
+        } else {
+            1.0 - 2.0 * (1.0 - b) * (1.0 - c)
+        }
+    };
+
+    match mode {
+        BlendMode::Normal => color,
+        BlendMode::Multiply => base * color,
+        BlendMode::Screen => {
+            Vec3::new(1.0, 1.0, 1.0)
+                - (Vec3::new(1.0, 1.0, 1.0) - base)
+                    * (Vec3::new(1.0, 1.0, 1.0) - color)
+        }
+        BlendMode::Overlay => Vec3::new(
+            overlay(base.x, color.x),
+            overlay(base.y, color.y),
+            overlay(base.z, color.z),
+        ),
+    }
+}
+
+impl VectorLayer {
+    /// Return the color and coverage of the layer at a point, if any
+    fn color(&self, point: Vec3, footprint: f64) -> Option<(Vec3, f64)> {
+        for shape in self.shapes.iter() {
+            if !shape.bbox().offset(self.offset).contains(point) {
+                continue;
+            }
+
+            let distance = shape.distance(point);
+            let outer = coverage(distance, self.tolerance, footprint);
+            if outer <= 0.0 {
+                continue;
+            }
+
+            let edge = self.tolerance - self.stroke_width;
+            let inner = coverage(distance, edge, footprint);
+
+            let fill = match (self.fill, &self.pattern) {
+                (Some(fill), Some(pattern)) => pattern.sample(point, fill),
+                (fill, _) => fill,
+            };
+
+            // Gaps in the dash fall through to the fill
+            let stroke = match self.stroke {
+                Some(stroke) if self.dashed(shape, point) => Some(stroke),
+                _ => None,
+            };
+
+            let (fill_alpha, stroke_alpha) = match stroke {
+                Some(_) => (inner, outer - inner),
+                None => (outer, 0.0),
+            };
+
+            let mut color = Vec3::zeros();
+            let mut alpha = 0.0;
+            if let Some(fill) = fill {
+                color += fill * fill_alpha;
+                alpha += fill_alpha;
+            }
+            if let Some(stroke) = stroke {
+                color += stroke * stroke_alpha;
+                alpha += stroke_alpha;
+            }
+
+            if alpha > 0.0 {
+                return Some((color / alpha, alpha));
+            }
+        }
+
+        None
+    }
+
+    /// Return true if a point lies on a dash of the stroke
+    fn dashed(&self, shape: &Shape, point: Vec3) -> bool {
+        match self.dash {
+            Some((on, off)) => shape.measure(point) % (on + off) < on,
+            None => true,
+        }
+    }
+}
+
+impl From<VectorLayerOpts> for VectorLayer {
+    fn from(options: VectorLayerOpts) -> VectorLayer {
+        let shapes = cache::shapes(&options.data).unwrap();
+
+        VectorLayer {
+            shapes,
+            tolerance: options.tolerance,
+            fill: options.fill.map(From::from),
+            pattern: options.pattern.map(From::from),
+            stroke: options.stroke.map(From::from),
+            stroke_width: options.stroke_width,
+            dash: options.dash.map(|[on, off]| (on, off)),
+            opacity: options.opacity,
+            blend: options.blend,
+            offset: options.offset,
+        }
+    }
+}
+
+impl VectorLayerShader {
+    pub fn new(wraps: usize, layers: Vec<VectorLayer>) -> VectorLayerShader {
+        VectorLayerShader { wraps, layers }
+    }
+}
+
+impl From<VectorLayerShaderOpts> for VectorLayerShader {
+    fn from(options: VectorLayerShaderOpts) -> VectorLayerShader {
+        VectorLayerShader::new(
+            options.wraps.index(),
+            options.layers.into_iter().map(From::from).collect(),
+        )
+    }
+}
+
+impl Shader for VectorLayerShader {
+    fn shade(&self, tracer: &Tracer, info: &TraceInfo) -> Vec3 {
+        let point = info.ray.origin + info.ray.direction * info.intersection.t;
+        let mut base = match tracer.shader(self.wraps) {
+            Some(shader) => shader.shade(tracer, info),
+            None => Vec3::zeros(),
+        };
+
+        // Layers are composited bottom to top in the order they are declared
+        let footprint = info.footprint();
+        for layer in &self.layers {
+            if let Some((color, coverage)) = layer.color(point, footprint) {
+                let color = blend(layer.blend, base, color);
+                let alpha = layer.opacity * coverage;
+                base = color * alpha + base * (1.0 - alpha);
+            }
+        }
+
+        base
+    }
+
+    fn shapes(&self) -> Vec<Arc<Vec<Shape>>> {
+        self.layers
+            .iter()
+            .map(|layer| layer.shapes.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,181 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use math::{AffineTransform, Vec3};
+use textures::Texture;
+
+use std::f64::MAX;
+
+/// Distance a point may lie below the top of a shadow before it is shadowed
+const TOLERANCE: f64 = 1.0e-6;
+
+/// Shadows cast by a height map from a directional light, stored as the
+/// height of the top of the shadow above each of its points
+#[derive(Clone, Debug, Default)]
+pub struct ShadowMap {
+    /// A transform from raster space to world space
+    transform: AffineTransform,
+    shadows: Texture<f64>,
+}
+
+impl ShadowMap {
+    /// Sweep across a height map away from a light, lowering the top of the
+    /// shadow by the slope of the light at each step, so that every point is
+    /// visited once regardless of the length of the shadows
+    pub fn new(
+        transform: AffineTransform,
+        heights: &Texture<f64>,
+        direction: Vec3,
+    ) -> ShadowMap {
+        let (width, height) = (heights.width, heights.height);
+        let mut shadows = heights.clone();
+        let horizontal = (direction.x.powi(2) + direction.z.powi(2)).sqrt();
+
+        if direction.y <= 0.0 {
+            // A light at or below the horizon reaches no point, the top of
+            // the shadow is finite so that it still interpolates
+            for value in &mut shadows.buffer {
+                *value = MAX;
+            }
+            return ShadowMap { transform, shadows };
+        }
+        if horizontal == 0.0 || width == 0 || height == 0 {
+            return ShadowMap { transform, shadows };
+        }
+
+        // Direction towards the light in raster space, per world unit
+        let (rx, ry) = {
+            let (x0, z0) = transform.inverse(0.0, 0.0);
+            let (x1, z1) = transform
+                .inverse(direction.x / horizontal, direction.z / horizontal);
+            (x1 - x0, z1 - z0)
+        };
+
+        // Step one pixel at a time along the major axis, reading the previous
+        // line of the sweep at a fractional position along the minor axis
+        let transpose = ry.abs() > rx.abs();
+        let (major, minor) = if transpose { (ry, rx) } else { (rx, ry) };
+        let (lines, length) = if transpose {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        let index = |line: usize, i: usize| {
+            if transpose {
+                line * width + i
+            } else {
+                i * width + line
+            }
+        };
+        let slope = minor / major.abs();
+        let drop = direction.y / horizontal / major.abs();
+
+        for step in 1..lines {
+            // Lines nearest the light are swept first
+            let (line, previous) = if major > 0.0 {
+                (lines - 1 - step, lines - step)
+            } else {
+                (step, step - 1)
+            };
+            for i in 0..length {
+                let position = i as f64 + slope;
+                if position < 0.0 || position > (length - 1) as f64 {
+                    continue;
+                }
+                let i0 = (position.floor() as usize).min(length - 1);
+                let i1 = (i0 + 1).min(length - 1);
+                let t = position - i0 as f64;
+                let top = shadows.buffer[index(previous, i0)] * (1.0 - t)
+                    + shadows.buffer[index(previous, i1)] * t;
+                let value = &mut shadows.buffer[index(line, i)];
+                *value = value.max(top - drop);
+            }
+        }
+
+        ShadowMap { transform, shadows }
+    }
+
+    /// Return the number of bytes held by the map
+    pub fn memory(&self) -> usize {
+        self.shadows.memory()
+    }
+
+    /// Return whether a point is in shadow, or nothing if the point lies
+    /// outside of the height map
+    pub fn occluded(&self, point: Vec3) -> Option<bool> {
+        let (width, height) = (self.shadows.width, self.shadows.height);
+        let (x, y) = self.transform.inverse(point.x, point.z);
+        if x < 0.0 || y < 0.0 || x > width as f64 || y > height as f64 {
+            return None;
+        }
+
+        let x = x.min((width - 1) as f64);
+        let y = y.min((height - 1) as f64);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+        let lookup = |x, y| self.shadows.lookup1x1(x, y);
+        let top = lookup(x0, y0) * (1.0 - tx) * (1.0 - ty)
+            + lookup(x1, y0) * tx * (1.0 - ty)
+            + lookup(x0, y1) * (1.0 - tx) * ty
+            + lookup(x1, y1) * tx * ty;
+        Some(point.y + TOLERANCE < top)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A flat raster with a wall of a height across its middle column
+    fn wall(height: f64) -> Texture<f64> {
+        let mut heights = Texture::blank(9, 9);
+        for y in 0..9 {
+            heights.write1x1(4, y, height);
+        }
+        heights
+    }
+
+    #[test]
+    fn shadows_behind_walls() {
+        let transform = AffineTransform::new(0.0, 0.0, 2.0, 2.0);
+        // Light from +x at 45 degrees, so a 4 unit wall shadows 4 units
+        let light = Vec3::new(1.0, 1.0, 0.0);
+        let map = ShadowMap::new(transform, &wall(4.0), light);
+        let at = |x| map.occluded(Vec3::new(x, 0.0, 8.0));
+
+        assert_eq!(at(10.0), Some(false));
+        assert_eq!(at(6.0), Some(true));
+        assert_eq!(at(4.5), Some(true));
+        assert_eq!(at(3.5), Some(false));
+        assert_eq!(at(20.0), None);
+        // Points above the ground may rise out of the shadow
+        assert_eq!(map.occluded(Vec3::new(6.0, 3.0, 8.0)), Some(false));
+    }
+
+    #[test]
+    fn shadows_from_oblique_lights() {
+        let transform = AffineTransform::default();
+        let light = Vec3::new(-1.0, 1.0, -0.5);
+        let map = ShadowMap::new(transform, &wall(2.0), light);
+        assert_eq!(map.occluded(Vec3::new(5.0, 0.0, 4.0)), Some(true));
+        assert_eq!(map.occluded(Vec3::new(3.0, 0.0, 4.0)), Some(false));
+        assert_eq!(map.occluded(Vec3::new(7.0, 0.0, 4.0)), Some(false));
+
+        let below = Vec3::new(1.0, -0.1, 0.0);
+        let map = ShadowMap::new(transform, &wall(0.0), below);
+        assert_eq!(map.occluded(Vec3::new(1.0, 0.0, 1.0)), Some(true));
+    }
+}
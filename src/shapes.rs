@@ -15,6 +15,8 @@
 
 use math::Vec3;
 use std::f64::INFINITY;
+use std::mem;
+use std::slice;
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Point {
@@ -75,6 +77,128 @@ impl Shape {
             Shape::Polygon(ref shape) => shape.distance(point),
         }
     }
+
+    /// Return the vertical distance from a point to the edge of the shape
+    /// nearest to it in the XZ plane
+    pub fn vertical_offset(&self, point: Vec3) -> f64 {
+        let closest = match *self {
+            Shape::Point(shape) => shape.point,
+            Shape::LineString(ref shape) => shape.closest(point),
+            Shape::Ring(ref shape) => shape.line.closest(point),
+            Shape::Polygon(ref shape) => shape.closest(point),
+        };
+        (point.y - closest.y).abs()
+    }
+
+    /// Return the points of each line making up the shape
+    pub fn lines(&self) -> Vec<&[Vec3]> {
+        match *self {
+            Shape::Point(ref shape) => vec![slice::from_ref(&shape.point)],
+            Shape::LineString(ref shape) => vec![&shape.points],
+            Shape::Ring(ref shape) => vec![&shape.line.points],
+            Shape::Polygon(ref shape) => {
+                let mut lines = vec![&shape.exterior.line.points[..]];
+                for hole in &shape.holes {
+                    lines.push(&hole.line.points);
+                }
+                lines
+            }
+        }
+    }
+
+    /// Return the distance along the edge of the shape to its closest point
+    pub fn measure(&self, point: Vec3) -> f64 {
+        match *self {
+            Shape::Point(_) => 0.0,
+            Shape::LineString(ref shape) => shape.measure(point),
+            Shape::Ring(ref shape) => shape.measure(point),
+            Shape::Polygon(ref shape) => shape.measure(point),
+        }
+    }
+
+    /// Return the shape with vertices closer than a tolerance to the line
+    /// through their neighbours removed
+    pub fn simplify(&self, tolerance: f64) -> Shape {
+        match *self {
+            Shape::Point(shape) => Shape::Point(shape),
+            Shape::LineString(ref shape) => {
+                Shape::LineString(shape.simplify(tolerance))
+            }
+            Shape::Ring(ref shape) => Shape::Ring(shape.simplify(tolerance)),
+            Shape::Polygon(ref shape) => {
+                Shape::Polygon(shape.simplify(tolerance))
+            }
+        }
+    }
+
+    /// Return the shape with a function applied to each of its vertices
+    pub fn map<F: Fn(Vec3) -> Vec3>(&self, f: F) -> Shape {
+        let line = |line: &LineString| {
+            LineString::new(line.points.iter().map(|p| f(*p)).collect())
+        };
+        let ring = |ring: &Ring| Ring {
+            line: line(&ring.line),
+        };
+        match *self {
+            Shape::Point(shape) => Shape::Point(Point::new(f(shape.point))),
+            Shape::LineString(ref shape) => Shape::LineString(line(shape)),
+            Shape::Ring(ref shape) => Shape::Ring(ring(shape)),
+            Shape::Polygon(ref shape) => Shape::Polygon(Polygon::new(
+                ring(&shape.exterior),
+                shape.holes.iter().map(ring).collect(),
+            )),
+        }
+    }
+
+    /// Return the shape with vertices inserted so that no segment is longer
+    /// than a spacing in the XZ plane
+    pub fn densify(&self, spacing: f64) -> Shape {
+        match *self {
+            Shape::Point(shape) => Shape::Point(shape),
+            Shape::LineString(ref shape) => {
+                Shape::LineString(shape.densify(spacing))
+            }
+            Shape::Ring(ref shape) => Shape::Ring(shape.densify(spacing)),
+            Shape::Polygon(ref shape) => Shape::Polygon(shape.densify(spacing)),
+        }
+    }
+
+    /// Return an estimate of the number of bytes held by the shape
+    pub fn memory(&self) -> usize {
+        let heap = match *self {
+            Shape::Polygon(ref shape) => shape.memory(),
+            _ => {
+                let points: usize =
+                    self.lines().iter().map(|line| line.len()).sum();
+                points * mem::size_of::<Vec3>()
+            }
+        };
+        mem::size_of::<Shape>() + heap
+    }
+
+    /// Return the parts of the shape that lie inside a rectangle
+    pub fn clip(&self, rect: &Rect) -> Vec<Shape> {
+        match *self {
+            Shape::Point(shape) => {
+                if rect.contains(shape.point) {
+                    vec![Shape::Point(shape)]
+                } else {
+                    vec![]
+                }
+            }
+            Shape::LineString(ref shape) => shape
+                .clip(rect)
+                .into_iter()
+                .map(Shape::LineString)
+                .collect(),
+            Shape::Ring(ref shape) => {
+                shape.clip(rect).into_iter().map(Shape::Ring).collect()
+            }
+            Shape::Polygon(ref shape) => {
+                shape.clip(rect).into_iter().map(Shape::Polygon).collect()
+            }
+        }
+    }
 }
 
 impl Point {
@@ -82,6 +206,10 @@ impl Point {
         Point { point }
     }
 
+    pub fn position(&self) -> Vec3 {
+        self.point
+    }
+
     pub fn bbox(&self) -> Rect {
         Rect::new(self.point, self.point, self.point, self.point)
     }
@@ -101,6 +229,10 @@ impl Rect {
         }
     }
 
+    pub fn corners(&self) -> [Vec3; 4] {
+        [self.x0y0, self.x1y0, self.x1y1, self.x0y1]
+    }
+
     pub fn offset(&self, amount: f64) -> Rect {
         let minx = self.x0y0.x;
         let maxx = self.x1y0.x;
@@ -125,6 +257,37 @@ impl Rect {
 
         point.x >= minx && point.x <= maxx && point.z >= miny && point.z <= maxy
     }
+
+    /// Return true if two rectangles overlap
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.x0y0.x <= other.x1y0.x
+            && self.x1y0.x >= other.x0y0.x
+            && self.x0y0.z <= other.x0y1.z
+            && self.x0y1.z >= other.x0y0.z
+    }
+
+    /// Return the edges of the rectangle as an axis, its sign and position,
+    /// where points with `sign * (axis - position) >= 0` are inside
+    fn edges(&self) -> [(usize, f64, f64); 4] {
+        [
+            (0, 1.0, self.x0y0.x),
+            (0, -1.0, self.x1y0.x),
+            (2, 1.0, self.x0y0.z),
+            (2, -1.0, self.x0y1.z),
+        ]
+    }
+}
+
+/// Return the signed distance of a point inside one edge of a rectangle
+fn inside((axis, sign, position): (usize, f64, f64), point: Vec3) -> f64 {
+    let value = if axis == 0 { point.x } else { point.z };
+    sign * (value - position)
+}
+
+/// Return the point where a segment crosses an edge of a rectangle
+fn crossing(edge: (usize, f64, f64), a: Vec3, b: Vec3) -> Vec3 {
+    let (da, db) = (inside(edge, a), inside(edge, b));
+    a + (b - a) * (da / (da - db))
 }
 
 impl LineString {
@@ -167,9 +330,127 @@ impl LineString {
         minimum
     }
 
+    pub fn measure(&self, point: Vec3) -> f64 {
+        let mut minimum = INFINITY;
+        let mut measure = 0.0;
+        let mut length = 0.0;
+
+        let p3 = point;
+        for i in 0..self.points.len() - 1 {
+            let p1 = self.points[i];
+            let p2 = self.points[i + 1];
+            let u = Vec3::dot(p3 - p1, p2 - p1) / Vec3::dot(p2 - p1, p2 - p1);
+            let u = u.min(1.0).max(0.0);
+            let other = p1 + (p2 - p1) * u;
+            let distance = Vec3::distance(other, p3);
+            if distance < minimum {
+                minimum = distance;
+                measure = length + Vec3::distance(p1, other);
+            }
+            length += Vec3::distance(p1, p2);
+        }
+
+        measure
+    }
+
     pub fn bbox(&self) -> Rect {
         self.bounds
     }
+
+    /// Return the point on the line nearest to a point in the XZ plane, with
+    /// its height interpolated along the line
+    pub fn closest(&self, point: Vec3) -> Vec3 {
+        let flat = |p: Vec3| Vec3::new(p.x, 0.0, p.z);
+        let mut minimum = INFINITY;
+        let mut closest = self.points[0];
+
+        let p3 = flat(point);
+        for i in 0..self.points.len() - 1 {
+            let p1 = self.points[i];
+            let p2 = self.points[i + 1];
+            let (f1, f2) = (flat(p1), flat(p2));
+            let u = Vec3::dot(p3 - f1, f2 - f1) / Vec3::dot(f2 - f1, f2 - f1);
+            let u = if u.is_nan() { 0.0 } else { u.min(1.0).max(0.0) };
+            let distance = Vec3::distance(f1 + (f2 - f1) * u, p3);
+            if distance < minimum {
+                minimum = distance;
+                closest = p1 + (p2 - p1) * u;
+            }
+        }
+
+        closest
+    }
+
+    /// Simplify the line in the XZ plane (Douglas-Peucker)
+    pub fn simplify(&self, tolerance: f64) -> LineString {
+        let distance = |p: Vec3, a: Vec3, b: Vec3| {
+            perpendicular_distance((p.x, p.z), (a.x, a.z), (b.x, b.z))
+        };
+        LineString::new(simplify_by(&self.points, tolerance, &distance))
+    }
+
+    /// Return the line with evenly spaced vertices inserted along segments
+    /// longer than a spacing in the XZ plane
+    pub fn densify(&self, spacing: f64) -> LineString {
+        if spacing <= 0.0 || self.points.is_empty() {
+            return self.clone();
+        }
+
+        let mut points = vec![self.points[0]];
+        for pair in self.points.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let length = (b.x - a.x).hypot(b.z - a.z);
+            let steps = (length / spacing).ceil().max(1.0) as usize;
+            for i in 1..=steps {
+                points.push(a + (b - a) * (i as f64 / steps as f64));
+            }
+        }
+        LineString::new(points)
+    }
+
+    /// Return the pieces of the line that lie inside a rectangle
+    pub fn clip(&self, rect: &Rect) -> Vec<LineString> {
+        if !self.bounds.intersects(rect) {
+            return vec![];
+        }
+
+        let mut pieces = vec![];
+        let mut current: Vec<Vec3> = vec![];
+        for segment in self.points.windows(2) {
+            let (mut a, mut b) = (segment[0], segment[1]);
+            let mut visible = true;
+
+            // Cut the segment against each edge in turn
+            for &edge in &rect.edges() {
+                let (da, db) = (inside(edge, a), inside(edge, b));
+                if da < 0.0 && db < 0.0 {
+                    visible = false;
+                    break;
+                } else if da < 0.0 {
+                    a = crossing(edge, a, b);
+                } else if db < 0.0 {
+                    b = crossing(edge, a, b);
+                }
+            }
+
+            if !visible {
+                continue;
+            }
+
+            if current.last() != Some(&a) {
+                if current.len() > 1 {
+                    pieces.push(LineString::new(current));
+                }
+                current = vec![a];
+            }
+            current.push(b);
+        }
+
+        if current.len() > 1 {
+            pieces.push(LineString::new(current));
+        }
+        pieces
+    }
 }
 
 impl Ring {
@@ -188,6 +469,61 @@ impl Ring {
         self.line.distance(point) * sign
     }
 
+    pub fn measure(&self, point: Vec3) -> f64 {
+        self.line.measure(point)
+    }
+
+    /// Simplify the ring, keeping the original if it would collapse
+    pub fn simplify(&self, tolerance: f64) -> Ring {
+        let line = self.line.simplify(tolerance);
+        if line.points.len() < 4 {
+            return self.clone();
+        }
+        Ring { line }
+    }
+
+    pub fn densify(&self, spacing: f64) -> Ring {
+        Ring {
+            line: self.line.densify(spacing),
+        }
+    }
+
+    /// Return the ring cut to a rectangle (Sutherland–Hodgman)
+    pub fn clip(&self, rect: &Rect) -> Option<Ring> {
+        if !self.bbox().intersects(rect) {
+            return None;
+        }
+
+        let mut points = self.line.points.clone();
+        if points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+
+        for &edge in &rect.edges() {
+            let input = points;
+            points = Vec::with_capacity(input.len());
+            for (i, &b) in input.iter().enumerate() {
+                let a = input[(i + input.len() - 1) % input.len()];
+                let (da, db) = (inside(edge, a), inside(edge, b));
+                if db >= 0.0 {
+                    if da < 0.0 {
+                        points.push(crossing(edge, a, b));
+                    }
+                    points.push(b);
+                } else if da >= 0.0 {
+                    points.push(crossing(edge, a, b));
+                }
+            }
+        }
+
+        if points.len() < 3 {
+            return None;
+        }
+        let first = points[0];
+        points.push(first);
+        Some(Ring::new(points))
+    }
+
     pub fn contains(&self, point: Vec3) -> bool {
         let mut j = self.line.points.len() - 1;
         let mut signed = false;
@@ -216,6 +552,18 @@ impl Polygon {
         self.exterior.bbox()
     }
 
+    /// Return an estimate of the number of bytes held outside the polygon
+    pub fn memory(&self) -> usize {
+        let points: usize = self.exterior.line.points.len()
+            + self
+                .holes
+                .iter()
+                .map(|h| h.line.points.len())
+                .sum::<usize>();
+        points * mem::size_of::<Vec3>()
+            + self.holes.len() * mem::size_of::<Ring>()
+    }
+
     pub fn distance(&self, point: Vec3) -> f64 {
         let mut distance = self.exterior.distance(point);
         for hole in &self.holes {
@@ -224,7 +572,56 @@ impl Polygon {
         distance
     }
 
-    #[allow(dead_code)]
+    pub fn measure(&self, point: Vec3) -> f64 {
+        let mut closest = &self.exterior;
+        let mut minimum = self.exterior.line.distance(point);
+        for hole in &self.holes {
+            let distance = hole.line.distance(point);
+            if distance < minimum {
+                closest = hole;
+                minimum = distance;
+            }
+        }
+        closest.measure(point)
+    }
+
+    /// Return the point on the exterior or a hole nearest to a point in the
+    /// XZ plane
+    pub fn closest(&self, point: Vec3) -> Vec3 {
+        let flat = |p: Vec3| Vec3::new(p.x, 0.0, p.z);
+        let mut closest = self.exterior.line.closest(point);
+        for hole in &self.holes {
+            let other = hole.line.closest(point);
+            if Vec3::distance(flat(other), flat(point))
+                < Vec3::distance(flat(closest), flat(point))
+            {
+                closest = other;
+            }
+        }
+        closest
+    }
+
+    pub fn simplify(&self, tolerance: f64) -> Polygon {
+        Polygon::new(
+            self.exterior.simplify(tolerance),
+            self.holes.iter().map(|h| h.simplify(tolerance)).collect(),
+        )
+    }
+
+    pub fn densify(&self, spacing: f64) -> Polygon {
+        Polygon::new(
+            self.exterior.densify(spacing),
+            self.holes.iter().map(|h| h.densify(spacing)).collect(),
+        )
+    }
+
+    /// Return the polygon cut to a rectangle
+    pub fn clip(&self, rect: &Rect) -> Option<Polygon> {
+        let exterior = self.exterior.clip(rect)?;
+        let holes = self.holes.iter().filter_map(|h| h.clip(rect)).collect();
+        Some(Polygon::new(exterior, holes))
+    }
+
     pub fn contains(&self, point: Vec3) -> bool {
         if !self.exterior.contains(point) {
             return false;
@@ -238,6 +635,54 @@ impl Polygon {
     }
 }
 
+/// Return the perpendicular distance of a point to the line through `a` and
+/// `b`
+pub fn perpendicular_distance(
+    p: (f64, f64),
+    a: (f64, f64),
+    b: (f64, f64),
+) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = (dx * dx + dy * dy).sqrt();
+    if length == 0.0 {
+        return ((p.0 - a.0).powi(2) + (p.1 - a.1).powi(2)).sqrt();
+    }
+    (dy * p.0 - dx * p.1 + b.0 * a.1 - b.1 * a.0).abs() / length
+}
+
+/// Simplify a line of any kind of point, given the distance of a point from
+/// the line through two others
+pub fn simplify_by<T, F>(points: &[T], tolerance: f64, distance: &F) -> Vec<T>
+where
+    T: Copy,
+    F: Fn(T, T, T) -> f64,
+{
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let first = points[0];
+    let last = points[points.len() - 1];
+    let mut index = 0;
+    let mut maximum = 0.0;
+    for (i, point) in points.iter().enumerate().take(points.len() - 1).skip(1) {
+        let offset = distance(*point, first, last);
+        if offset > maximum {
+            index = i;
+            maximum = offset;
+        }
+    }
+
+    if maximum <= tolerance {
+        return vec![first, last];
+    }
+
+    let mut output = simplify_by(&points[..=index], tolerance, distance);
+    output.pop();
+    output.extend(simplify_by(&points[index..], tolerance, distance));
+    output
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -261,6 +706,17 @@ mod tests {
         assert_eq!(line_string.distance(Vec3::new(1.0, 0.0, 0.5)), 0.5);
     }
 
+    #[test]
+    fn test_line_string_measure() {
+        let line_string = LineString::new(vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.5, 0.0, 0.0),
+            Vec3::new(0.5, 0.0, 1.0),
+        ]);
+        assert_eq!(line_string.measure(Vec3::new(0.25, 0.0, -1.0)), 0.25);
+        assert_eq!(line_string.measure(Vec3::new(1.0, 0.0, 0.5)), 1.0);
+    }
+
     #[test]
     fn test_simple_polygon() {
         let polygon = Polygon::new(
@@ -276,4 +732,117 @@ mod tests {
         assert_eq!(polygon.distance(Vec3::new(0.5, 0.0, 0.5)), -0.5);
         assert_eq!(polygon.distance(Vec3::new(1.5, 0.0, 0.5)), 0.5);
     }
+
+    #[test]
+    fn test_vertical_offset() {
+        let line = Shape::LineString(LineString::new(vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 10.0, 0.0),
+        ]));
+        assert_eq!(line.vertical_offset(Vec3::new(1.0, 5.0, 1.0)), 0.0);
+        assert_eq!(line.vertical_offset(Vec3::new(1.0, 50.0, 0.0)), 45.0);
+        assert_eq!(line.vertical_offset(Vec3::new(-1.0, 3.0, 0.0)), 3.0);
+    }
+
+    #[test]
+    fn test_densifying_shapes() {
+        let line = LineString::new(vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(3.0, 6.0, 0.0),
+            Vec3::new(3.0, 6.0, 0.5),
+        ]);
+        assert_eq!(
+            line.densify(1.0),
+            LineString::new(vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 2.0, 0.0),
+                Vec3::new(2.0, 4.0, 0.0),
+                Vec3::new(3.0, 6.0, 0.0),
+                Vec3::new(3.0, 6.0, 0.5),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_simplifying_shapes() {
+        let line = Shape::LineString(LineString::new(vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 5.0, 0.1),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 2.0),
+        ]));
+        assert_eq!(
+            line.simplify(0.5),
+            Shape::LineString(LineString::new(vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(2.0, 0.0, 2.0),
+            ]))
+        );
+
+        let triangle = Ring::new(vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, 0.0),
+        ]);
+        assert_eq!(triangle.simplify(10.0), triangle);
+    }
+
+    #[test]
+    fn test_clipping_shapes() {
+        let rect = Rect::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+
+        // A line leaving and re-entering the rectangle is cut in two
+        let line = Shape::LineString(LineString::new(vec![
+            Vec3::new(0.5, 0.0, 0.5),
+            Vec3::new(2.0, 0.0, 0.5),
+            Vec3::new(2.0, 0.0, 0.75),
+            Vec3::new(0.5, 0.0, 0.75),
+        ]));
+        assert_eq!(
+            line.clip(&rect),
+            vec![
+                Shape::LineString(LineString::new(vec![
+                    Vec3::new(0.5, 0.0, 0.5),
+                    Vec3::new(1.0, 0.0, 0.5),
+                ])),
+                Shape::LineString(LineString::new(vec![
+                    Vec3::new(1.0, 0.0, 0.75),
+                    Vec3::new(0.5, 0.0, 0.75),
+                ])),
+            ]
+        );
+
+        let ring = Ring::new(vec![
+            Vec3::new(0.5, 0.0, 0.5),
+            Vec3::new(2.0, 0.0, 0.5),
+            Vec3::new(2.0, 0.0, 2.0),
+            Vec3::new(0.5, 0.0, 2.0),
+            Vec3::new(0.5, 0.0, 0.5),
+        ]);
+        let clipped = ring.clip(&rect).unwrap();
+        assert_eq!(
+            clipped.bbox(),
+            Rect::new(
+                Vec3::new(0.5, 0.0, 0.5),
+                Vec3::new(1.0, 0.0, 0.5),
+                Vec3::new(1.0, 0.0, 1.0),
+                Vec3::new(0.5, 0.0, 1.0),
+            )
+        );
+        assert!(clipped.contains(Vec3::new(0.75, 0.0, 0.75)));
+        let corner = Rect::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.4, 0.0, 0.0),
+            Vec3::new(0.4, 0.0, 0.4),
+            Vec3::new(0.0, 0.0, 0.4),
+        );
+        assert!(ring.clip(&corner).is_none());
+    }
 }
@@ -13,7 +13,10 @@
 // You should have received a copy of the GNU General Public License
 // along with Peaks. If not, see <https://www.gnu.org/licenses/>.
 
+use ops::{downsample, Average};
+
 use std::cmp;
+use std::mem;
 use std::ops::{Add, Mul};
 
 pub trait Bilinear {
@@ -125,6 +128,11 @@ where
         }
     }
 
+    /// Return the number of bytes held by the texture buffer
+    pub fn memory(&self) -> usize {
+        self.buffer.len() * mem::size_of::<T>()
+    }
+
     /// Write a single value to the texture
     pub fn write1x1(&mut self, x: usize, y: usize, value: T) {
         let i = self.width * y + x;
@@ -194,6 +202,153 @@ where
     }
 }
 
+impl<T> Texture<T>
+where
+    T: Mul<f64, Output = T> + Add<Output = T> + Copy + Default,
+{
+    /// Return a bilinearly filtered value from the texture, at a position
+    /// within it, repeating its last row and column
+    pub fn bilinear_clamped(&self, x: f64, y: f64) -> T {
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let x1 = (x0 + 1).min(self.width - 1);
+        let y1 = (y0 + 1).min(self.height - 1);
+        let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+        let top =
+            self.lookup1x1(x0, y0) * (1.0 - tx) + self.lookup1x1(x1, y0) * tx;
+        let bottom =
+            self.lookup1x1(x0, y1) * (1.0 - tx) + self.lookup1x1(x1, y1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+/// A floating point texture stored as 16 bit steps above a minimum, rounded
+/// up so decoded values are never below the originals
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QuantizedTexture {
+    pub width: usize,
+    pub height: usize,
+    min: f64,
+    step: f64,
+    buffer: Vec<u16>,
+}
+
+impl QuantizedTexture {
+    /// Quantize a texture whose values lie between a minimum and maximum
+    pub fn new(texture: &Texture<f64>, min: f64, max: f64) -> QuantizedTexture {
+        let step = (max - min) / f64::from(u16::MAX);
+        let buffer = texture
+            .buffer
+            .iter()
+            .map(|&value| {
+                if step <= 0.0 {
+                    return 0;
+                }
+                let steps = ((value - min) / step).ceil();
+                let mut q = steps.max(0.0).min(f64::from(u16::MAX)) as u16;
+                if min + f64::from(q) * step < value && q < u16::MAX {
+                    q += 1;
+                }
+                q
+            })
+            .collect();
+
+        QuantizedTexture {
+            width: texture.width,
+            height: texture.height,
+            min,
+            step,
+            buffer,
+        }
+    }
+
+    /// Return the number of bytes held by the texture buffer
+    pub fn memory(&self) -> usize {
+        self.buffer.len() * mem::size_of::<u16>()
+    }
+
+    /// Read a single value from the texture
+    pub fn lookup1x1(&self, x: usize, y: usize) -> f64 {
+        let i = self.width * y + x;
+        self.min + f64::from(self.buffer[i]) * self.step
+    }
+}
+
+/// A texture and a chain of successively halved, averaged, copies
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mipmaps<T>
+where
+    T: Copy + Clone + Default,
+{
+    pub levels: Vec<Texture<T>>,
+}
+
+impl<T> Mipmaps<T>
+where
+    T: Mul<f64, Output = T> + Add<Output = T> + Average,
+{
+    pub fn new(texture: Texture<T>) -> Mipmaps<T> {
+        let mut levels = vec![texture];
+        loop {
+            let (width, height) = {
+                let last = &levels[levels.len() - 1];
+                (last.width / 2, last.height / 2)
+            };
+            // Bilinear filtering requires at least a 2x2 texture
+            if width < 2 || height < 2 {
+                break;
+            }
+            let mut next = Texture::blank(width, height);
+            downsample(&levels[levels.len() - 1], &mut next);
+            levels.push(next);
+        }
+        Mipmaps { levels }
+    }
+
+    /// Return the number of bytes held by all levels
+    pub fn memory(&self) -> usize {
+        self.levels.iter().map(|level| level.memory()).sum()
+    }
+
+    /// Return a bilinearly filtered value from a level, where `x` and `y` are
+    /// specified in the coordinates of the first level
+    pub fn bilinear(&self, x: f64, y: f64, level: usize) -> T {
+        let base = &self.levels[0];
+        if x < 0.0
+            || x + 1.0 >= base.width as f64
+            || y < 0.0
+            || y + 1.0 >= base.height as f64
+        {
+            return Default::default();
+        }
+
+        let level = level.min(self.levels.len() - 1);
+        let texture = &self.levels[level];
+        let scale = 2_usize.pow(level as u32) as f64;
+
+        // Keep lookups inside the level as its texels cover a larger area
+        let limit = |value: f64, size: usize| {
+            let max = size as f64 - 1.0 - 1e-9;
+            ((value + 0.5) / scale - 0.5).min(max).max(0.0)
+        };
+
+        texture.bilinear(limit(x, texture.width), limit(y, texture.height))
+    }
+
+    /// Return a value interpolated between the two nearest levels of detail
+    pub fn trilinear(&self, x: f64, y: f64, lod: f64) -> T {
+        let lod = lod.max(0.0);
+        let level = lod.floor();
+        let t = lod - level;
+
+        let a = self.bilinear(x, y, level as usize);
+        if t == 0.0 {
+            return a;
+        }
+        let b = self.bilinear(x, y, level as usize + 1);
+        a * (1.0 - t) + b * t
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -204,4 +359,27 @@ mod tests {
         let tiles = img.tiles(8);
         assert_eq!(tiles.count(), 64);
     }
+
+    #[test]
+    fn quantizing_values() {
+        let values = vec![-12.5, 0.0, 0.1, 3.3, 1000.0, 123.456];
+        let texture = Texture::new(3, 2, values.clone());
+        let quantized = QuantizedTexture::new(&texture, -12.5, 1000.0);
+        let step = 1012.5 / 65535.0;
+        for (i, value) in values.iter().enumerate() {
+            let decoded = quantized.lookup1x1(i % 3, i / 3);
+            assert!(decoded >= *value && decoded - value <= step);
+        }
+        assert_eq!(quantized.memory(), 12);
+    }
+
+    #[test]
+    fn mipmap_levels() {
+        let mipmaps = Mipmaps::new(Texture::new(8, 4, vec![1.0; 8 * 4]));
+        assert_eq!(mipmaps.levels.len(), 2);
+        assert_eq!(mipmaps.levels[1].width, 4);
+        assert_eq!(mipmaps.levels[1].height, 2);
+        assert_eq!(mipmaps.trilinear(6.5, 2.5, 0.5), 1.0);
+        assert_eq!(mipmaps.trilinear(7.5, 2.5, 0.5), 0.0);
+    }
 }
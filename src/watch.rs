@@ -0,0 +1,113 @@
+// This file is part of Peaks.
+//
+// Peaks is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Peaks is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Peaks. If not, see <https://www.gnu.org/licenses/>.
+
+use serde_json::Value;
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Return the paths of the files read by the loaders and lights of a scene
+pub fn scene_files(scene: &Value) -> Vec<String> {
+    let mut files = vec![];
+    collect_files(scene, &mut files);
+    files
+}
+
+fn collect_files(value: &Value, files: &mut Vec<String>) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                match field {
+                    Value::String(path) if name == "filepath" => {
+                        if !files.contains(path) {
+                            files.push(path.clone());
+                        }
+                    }
+                    _ => collect_files(field, files),
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                collect_files(item, files);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Polls the modification times of a set of files
+#[derive(Clone, Debug)]
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+impl FileWatcher {
+    pub fn new<I, P>(paths: I) -> FileWatcher
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let path = path.into();
+                let time = modified(&path);
+                (path, time)
+            })
+            .collect();
+        FileWatcher { files }
+    }
+
+    /// Return true if any of the files was modified, created or removed
+    /// since the watcher was created or last returned true
+    pub fn changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, time) in &mut self.files {
+            let now = modified(path);
+            if now != *time {
+                *time = now;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collecting_scene_files() {
+        let scene = json!({
+            "lights": [{"type": "environment", "filepath": "sky.hdr"}],
+            "primitives": [
+                {"type": "height_map", "data": {"filepath": "dem.tif"}},
+                {"type": "marker", "data": {"filepath": "peaks.shp"},
+                 "terrain": {"data": {"filepath": "dem.tif"}}},
+            ],
+        });
+        assert_eq!(
+            scene_files(&scene),
+            vec!["sky.hdr", "dem.tif", "peaks.shp"]
+        );
+    }
+}